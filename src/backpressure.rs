@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, MutexGuard};
+use std::time::Duration;
+
+use crate::pending::PendingChanges;
+
+type PendingGuard<'a> = MutexGuard<'a, PendingChanges>;
+
+/// Bounds the number of pending changes, blocking the watcher's event handler while the buffer is full
+/// rather than dropping changes.
+#[derive(Debug)]
+pub(crate) struct Backpressure {
+    max_pending: usize,
+    timeout: Duration,
+    drained: Condvar,
+    // set when the watcher is closed so waiting event handlers don't delay it shutting down
+    closed: AtomicBool,
+}
+
+impl Backpressure {
+    pub fn new(max_pending: usize, timeout: Duration) -> Self {
+        Backpressure {
+            max_pending,
+            timeout,
+            drained: Condvar::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Wait until there's room in `changes` for another change, returns `Err` with the guard if the buffer
+    /// is still full after the timeout.
+    pub fn wait_for_room<'a>(&self, changes: PendingGuard<'a>) -> Result<PendingGuard<'a>, PendingGuard<'a>> {
+        let max_pending = self.max_pending;
+        let (changes, result) = self
+            .drained
+            .wait_timeout_while(changes, self.timeout, |changes| {
+                changes.len() >= max_pending && !self.closed.load(Ordering::Relaxed)
+            })
+            .unwrap();
+        match result.timed_out() {
+            true => Err(changes),
+            false => Ok(changes),
+        }
+    }
+
+    /// Wake any event handlers waiting for room, call after taking changes from the buffer.
+    pub fn notify_drained(&self) {
+        self.drained.notify_all();
+    }
+
+    /// Stop blocking, waiting event handlers are woken and no longer wait for room.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.drained.notify_all();
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{current, spawn, JoinHandle};
+
+use pyo3::prelude::*;
+use pyo3::types::{IntoPyDict, PyCFunction, PyDict, PyTuple};
+
+use crate::RustNotify;
+
+// how often, while there are no changes, the thread checks the watcher hasn't been collected
+const WATCH_TIMEOUT_MS: f64 = 1_000.0;
+
+/// A thread calling `watch()` and passing each batch of changes to the callback given to `set_callback()`.
+#[derive(Debug)]
+pub(crate) struct CallbackThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl CallbackThread {
+    pub fn spawn(
+        slf: &Bound<RustNotify>,
+        callback: PyObject,
+        debounce_ms: f64,
+        step_ms: f64,
+        linger_ms: f64,
+    ) -> PyResult<Self> {
+        let py = slf.py();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        // `watch()` takes an event to check on each step
+        let is_set = PyCFunction::new_closure_bound(
+            py,
+            None,
+            None,
+            move |_args: &Bound<PyTuple>, _kwargs: Option<&Bound<PyDict>>| stop_clone.load(Ordering::Relaxed),
+        )?;
+        let stop_event: PyObject = py
+            .import_bound("types")?
+            .getattr("SimpleNamespace")?
+            .call((), Some(&[("is_set", is_set)].into_py_dict_bound(py)))?
+            .unbind();
+        // the thread only refers to the watcher while it's watching, so the watcher can be collected, and the
+        // thread stops, once nothing else refers to it
+        let watcher = py.import_bound("weakref")?.getattr("ref")?.call1((slf,))?.unbind();
+        let handle = spawn(move || loop {
+            // the GIL is released while `watch()` waits and between calls, `None` once the thread should stop and
+            // `Some(None)` when `watch()` timed out
+            let changes = Python::with_gil(|py| {
+                let watcher = watcher.call0(py).ok()?;
+                let watcher = watcher.downcast_bound::<RustNotify>(py).ok()?;
+                let result = RustNotify::watch(
+                    watcher,
+                    py,
+                    debounce_ms,
+                    step_ms,
+                    WATCH_TIMEOUT_MS,
+                    stop_event.clone_ref(py),
+                    linger_ms,
+                    false,
+                    false,
+                );
+                match result {
+                    Ok(changes) => match changes.extract::<&str>(py) {
+                        // outside the main thread, `watch()` can only return "timeout" or "stop"
+                        Ok("timeout") => Some(None),
+                        Ok(_) => None,
+                        Err(_) => Some(Some(changes)),
+                    },
+                    Err(err) => {
+                        err.write_unraisable_bound(py, Some(callback.bind(py)));
+                        None
+                    }
+                }
+            });
+            match changes {
+                Some(Some(changes)) => Python::with_gil(|py| {
+                    if let Err(err) = callback.call1(py, (changes,)) {
+                        err.write_unraisable_bound(py, Some(callback.bind(py)));
+                    }
+                }),
+                Some(None) => (),
+                None => return,
+            }
+        });
+        Ok(CallbackThread { stop, handle })
+    }
+
+    /// Stop calling the callback, returns once it has stopped, so must be called without the GIL held.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // when called from the callback itself, the thread stops once the callback returns
+        if self.handle.thread().id() != current().id() {
+            let _ = self.handle.join();
+        }
+    }
+
+    /// Stop calling the callback from the next step, without waiting.
+    pub fn stop_soon(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
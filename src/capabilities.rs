@@ -0,0 +1,102 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// Names of every backend, whether or not it's available on this platform.
+pub(crate) const BACKENDS: [&str; 6] = ["poll", "inotify", "fsevent", "kqueue", "windows", "fanotify"];
+
+/// What a backend is able to do, as surfaced via watchfiles.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Capabilities {
+    /// events are pushed by the OS rather than found by scanning
+    pub event_driven: bool,
+    /// a single OS watch covers a whole directory tree, rather than one watch per directory
+    pub native_recursion: bool,
+    /// both sides of a rename are linked by the OS (e.g. inotify's rename cookies)
+    pub rename_cookies: bool,
+    /// the OS reports when a file opened for writing is closed
+    pub close_write: bool,
+    /// symlinks to directories are followed when watching recursively
+    pub follow_symlinks: bool,
+    /// file contents can be compared, via `advanced_options={'compare_contents': True}`
+    pub compare_contents: bool,
+}
+
+impl Capabilities {
+    pub fn for_backend(backend: &str) -> PyResult<Self> {
+        let caps = match backend {
+            "poll" => Capabilities {
+                event_driven: false,
+                native_recursion: false,
+                rename_cookies: false,
+                close_write: false,
+                follow_symlinks: true,
+                compare_contents: true,
+            },
+            "inotify" => Capabilities {
+                event_driven: true,
+                native_recursion: false,
+                rename_cookies: true,
+                close_write: true,
+                follow_symlinks: true,
+                compare_contents: false,
+            },
+            "fsevent" => Capabilities {
+                event_driven: true,
+                native_recursion: true,
+                rename_cookies: false,
+                close_write: false,
+                follow_symlinks: false,
+                compare_contents: false,
+            },
+            "kqueue" => Capabilities {
+                event_driven: true,
+                native_recursion: false,
+                rename_cookies: false,
+                close_write: false,
+                follow_symlinks: true,
+                compare_contents: false,
+            },
+            "windows" => Capabilities {
+                event_driven: true,
+                native_recursion: true,
+                rename_cookies: false,
+                close_write: false,
+                follow_symlinks: false,
+                compare_contents: false,
+            },
+            "fanotify" => Capabilities {
+                event_driven: true,
+                native_recursion: true,
+                rename_cookies: false,
+                close_write: true,
+                follow_symlinks: false,
+                compare_contents: false,
+            },
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Invalid backend {:?}, should be one of {:?}",
+                    backend, BACKENDS
+                )))
+            }
+        };
+        Ok(caps)
+    }
+
+    pub fn to_py(self, py: Python) -> PyResult<PyObject> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("event_driven", self.event_driven)?;
+        dict.set_item("native_recursion", self.native_recursion)?;
+        dict.set_item("rename_cookies", self.rename_cookies)?;
+        dict.set_item("close_write", self.close_write)?;
+        dict.set_item("follow_symlinks", self.follow_symlinks)?;
+        dict.set_item("compare_contents", self.compare_contents)?;
+        Ok(dict.into())
+    }
+}
+
+/// Feature flags for `backend`, see `RustNotify.capabilities()` for those of the backend in use.
+#[pyfunction]
+pub(crate) fn backend_capabilities(py: Python, backend: &str) -> PyResult<PyObject> {
+    Capabilities::for_backend(backend)?.to_py(py)
+}
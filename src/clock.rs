@@ -0,0 +1,105 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use pyo3::prelude::*;
+
+use crate::wakeup::Wakeup;
+
+/// Source of time for the `watch()` loop.
+pub(crate) trait Clock: Debug + Send + Sync {
+    /// Time elapsed since an arbitrary fixed point.
+    fn now(&self) -> Duration;
+
+    fn sleep(&self, duration: Duration);
+
+    /// Sleep until a change arrives after `seen` arrivals (if given), `stop()` is called or `timeout` passes,
+    /// returns the number of arrivals.
+    fn wait(&self, wakeup: &Wakeup, seen: Option<u64>, timeout: Duration) -> u64;
+
+    /// Whether `wait` returns as soon as a change arrives, otherwise `watch()` has to wake on each step to find them.
+    fn wakes_on_change(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock { start: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        sleep(duration)
+    }
+
+    fn wait(&self, wakeup: &Wakeup, seen: Option<u64>, timeout: Duration) -> u64 {
+        wakeup.wait(seen, timeout)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ManualClockState {
+    now: Mutex<Duration>,
+}
+
+impl Clock for ManualClockState {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    // sleeping just moves time forward, so timeouts and debouncing happen instantly
+    fn sleep(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    // changes which arrive later in real time are found on the next step, as with sleeping
+    fn wait(&self, wakeup: &Wakeup, _seen: Option<u64>, timeout: Duration) -> u64 {
+        self.sleep(timeout);
+        wakeup.arrivals()
+    }
+
+    fn wakes_on_change(&self) -> bool {
+        false
+    }
+}
+
+/// A clock which only moves forward when `watch()` sleeps or `advance()` is called, for testing.
+#[pyclass(module = "watchfiles._rust_notify")]
+#[derive(Debug, Default)]
+pub(crate) struct ManualClock {
+    pub state: Arc<ManualClockState>,
+}
+
+#[pymethods]
+impl ManualClock {
+    #[new]
+    fn py_new() -> Self {
+        ManualClock::default()
+    }
+
+    #[getter]
+    fn now_ms(&self) -> f64 {
+        self.state.now().as_secs_f64() * 1000.0
+    }
+
+    fn advance(&self, ms: f64) -> PyResult<()> {
+        self.state.sleep(crate::duration_from_ms("ms", ms)?);
+        Ok(())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("ManualClock(now_ms={})", self.now_ms())
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::{CHANGE_DELETED, CHANGE_MODIFIED};
+
+/// Tracks the contents of cloud storage placeholders (OneDrive, Dropbox etc. on Windows), so modify events caused
+/// only by their sync state changing, e.g. being downloaded ("hydrated"), pinned or freed up, can be ignored.
+#[derive(Debug, Default)]
+pub(crate) struct CloudPlaceholders {
+    // size and modification time of placeholders we've seen
+    contents: Mutex<HashMap<String, (u64, Option<SystemTime>)>>,
+}
+
+impl CloudPlaceholders {
+    /// Whether `change` to `path` only reflects a placeholder's sync state changing, that's a modification where
+    /// the size and modification time are the same as when we last saw the file.
+    ///
+    /// The first event for each placeholder is always reported, since there's nothing to compare it to.
+    pub fn is_sync_only(&self, change: u8, path: &str) -> bool {
+        let mut contents = self.contents.lock().unwrap();
+        if change == CHANGE_DELETED {
+            contents.remove(path);
+            return false;
+        }
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) if is_placeholder(&metadata) => metadata,
+            _ => {
+                contents.remove(path);
+                return false;
+            }
+        };
+        let content = (metadata.len(), metadata.modified().ok());
+        let previous = contents.insert(path.to_string(), content);
+        change == CHANGE_MODIFIED && previous == Some(content)
+    }
+}
+
+#[cfg(windows)]
+fn is_placeholder(metadata: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    // cloud files are reparse points, with these attributes set depending on whether they're available locally
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+    const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+    const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+    const FILE_ATTRIBUTE_PINNED: u32 = 0x80000;
+    const FILE_ATTRIBUTE_UNPINNED: u32 = 0x100000;
+    const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+    const PLACEHOLDER_ATTRIBUTES: u32 = FILE_ATTRIBUTE_REPARSE_POINT
+        | FILE_ATTRIBUTE_OFFLINE
+        | FILE_ATTRIBUTE_RECALL_ON_OPEN
+        | FILE_ATTRIBUTE_PINNED
+        | FILE_ATTRIBUTE_UNPINNED
+        | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS;
+    // `fs::metadata` follows symlinks, so a reparse point here isn't a symlink
+    metadata.is_file() && metadata.file_attributes() & PLACEHOLDER_ATTRIBUTES != 0
+}
+
+// cloud placeholders are only recognised on Windows, elsewhere sync clients write ordinary files
+#[cfg(not(windows))]
+fn is_placeholder(_metadata: &fs::Metadata) -> bool {
+    false
+}
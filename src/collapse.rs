@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::memory::vec_bytes;
+use crate::pending::PendingChanges;
+use crate::{CHANGE_ADDED, CHANGE_DELETED, CHANGE_MODIFIED};
+
+/// Suffixes and names of the temporary files editors create while performing a "safe write".
+const EDITOR_TEMP_SUFFIXES: &[&str] = &["___jb_tmp___", "___jb_old___", "~", ".swp", ".swx", ".tmp"];
+// vim checks it can create files in a directory by creating and deleting "4913"
+const EDITOR_TEMP_NAMES: &[&str] = &["4913"];
+
+fn is_editor_temp_file(path: &str) -> bool {
+    if EDITOR_TEMP_SUFFIXES.iter().any(|suffix| path.ends_with(suffix)) {
+        return true;
+    }
+    match Path::new(path).file_name().and_then(|name| name.to_str()) {
+        Some(name) => EDITOR_TEMP_NAMES.contains(&name),
+        None => false,
+    }
+}
+
+/// Reduce the create/modify/rename/chmod sequences editors produce when saving a file to a single
+/// `MODIFIED` change per file.
+///
+/// * a path both added and deleted in the batch which still exists was replaced, e.g. by renaming a temporary
+///   file over it, so it becomes `MODIFIED`
+/// * editor temporary files which were added or deleted in the batch, and no longer exist, are dropped
+pub(crate) fn collapse_save_storms(changes: &mut PendingChanges) {
+    let added_and_deleted: Vec<String> = changes
+        .paths_with(CHANGE_ADDED)
+        .filter(|path| changes.contains(CHANGE_DELETED, path))
+        .cloned()
+        .collect();
+    for path in added_and_deleted {
+        if Path::new(&path).exists() {
+            changes.remove(CHANGE_ADDED, &path);
+            changes.remove(CHANGE_DELETED, &path);
+            changes.insert(CHANGE_MODIFIED, path);
+        }
+    }
+    changes.retain_paths(|path| !is_editor_temp_file(path) || Path::new(path).exists());
+}
+
+/// Renames reported with both paths (e.g. via inotify's rename cookies) since the last batch, in order.
+#[derive(Debug, Default)]
+pub(crate) struct RenameTracker {
+    renames: Mutex<Vec<(String, String)>>,
+}
+
+impl RenameTracker {
+    pub fn record(&self, from: &str, to: &str) {
+        self.renames.lock().unwrap().push((from.to_string(), to.to_string()));
+    }
+
+    pub fn take(&self) -> Vec<(String, String)> {
+        std::mem::take(&mut *self.renames.lock().unwrap())
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        let renames = self.renames.lock().unwrap();
+        vec_bytes(&renames, renames.capacity(), |(from, to)| {
+            from.capacity() + to.capacity()
+        })
+    }
+}
+
+/// Drop the intermediate names of files renamed more than once in the batch, e.g. for `A -> B -> C` only
+/// `A` being deleted and `C` being added are reported.
+///
+/// Intermediate names which exist again by the end of the batch are left alone since something else was
+/// created there.
+///
+/// Returns the renames with chains joined up, e.g. just `A -> C`.
+pub(crate) fn collapse_rename_chains(
+    changes: &mut PendingChanges,
+    renames: Vec<(String, String)>,
+) -> Vec<(String, String)> {
+    // renames with chains joined, and the index of each in `joined` by destination
+    let mut joined: Vec<(String, String)> = Vec::new();
+    let mut destinations: HashMap<String, usize> = HashMap::new();
+    let mut intermediates: Vec<String> = Vec::new();
+    for (from, to) in renames {
+        match destinations.remove(&from) {
+            Some(index) => {
+                intermediates.push(from);
+                joined[index].1 = to.clone();
+                destinations.insert(to, index);
+            }
+            None => {
+                destinations.insert(to.clone(), joined.len());
+                joined.push((from, to));
+            }
+        }
+    }
+    for path in intermediates {
+        if !Path::new(&path).exists() {
+            changes.remove(CHANGE_ADDED, &path);
+            changes.remove(CHANGE_DELETED, &path);
+        }
+    }
+    joined
+}
+
+/// Replace `from` being deleted and `to` being added with a single rename, for each rename where both
+/// changes are pending, returns the renames which were paired.
+pub(crate) fn pair_renames(changes: &mut PendingChanges, renames: Vec<(String, String)>) -> Vec<(String, String)> {
+    renames
+        .into_iter()
+        .filter(|(from, to)| {
+            if changes.contains(CHANGE_DELETED, from) && changes.contains(CHANGE_ADDED, to) {
+                changes.remove(CHANGE_DELETED, from);
+                changes.remove(CHANGE_ADDED, to);
+                true
+            } else {
+                false
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use notify::Config as NotifyConfig;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::filter::FileTypes;
+
+/// Filter options for one watch path, or the default for all of them.
+#[derive(Debug, Clone)]
+pub(crate) struct FilterConfig {
+    pub max_file_size: Option<u64>,
+    pub files_only: bool,
+    pub file_types: FileTypes,
+}
+
+impl FilterConfig {
+    pub fn to_py(&self, py: Python) -> PyResult<PyObject> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("max_file_size", self.max_file_size)?;
+        dict.set_item("files_only", self.files_only)?;
+        dict.set_item("file_types", self.file_types.as_str())?;
+        Ok(dict.into())
+    }
+}
+
+/// Configuration of a `RustNotify` instance after defaults and fallbacks have been applied.
+#[derive(Debug, Clone)]
+pub(crate) struct EffectiveConfig {
+    pub watch_paths: Vec<String>,
+    pub recursive: bool,
+    // `None` unless polling
+    pub poll_delay_ms: Option<f64>,
+    pub poll_delay_ms_overrides: HashMap<String, f64>,
+    pub rescan_interval_ms: u64,
+    pub filters: FilterConfig,
+    pub root_filters: Vec<(String, FilterConfig)>,
+    pub collapse_save_storms: bool,
+    pub collapse_renames: bool,
+    pub modify_kind: bool,
+    pub modify_sizes: bool,
+    pub depth: bool,
+    pub first_component: bool,
+    pub source: bool,
+    pub virtual_filesystems: String,
+    pub scan_new_dirs: bool,
+    pub ignore_permission_denied: bool,
+    pub partial_ok: bool,
+    pub watch_retries: u32,
+    pub watch_retry_delay_ms: u64,
+    pub stat_retries: Option<u32>,
+    pub stat_retry_delay_ms: Option<u64>,
+    pub root_deleted: String,
+    pub max_pending_changes: Option<usize>,
+    pub overflow: Option<String>,
+    pub backpressure_timeout_ms: Option<u64>,
+    pub spill_dir: Option<PathBuf>,
+    pub manual_clock: bool,
+    pub advanced_options: AdvancedOptions,
+    pub share: Option<PathBuf>,
+    // "host" or "client", `None` unless sharing
+    pub share_role: Option<&'static str>,
+    pub path_containment: String,
+    pub history_size: usize,
+    pub merge_aliases: bool,
+    pub ignore_cloud_sync: bool,
+    pub rename_pairs: bool,
+    pub gitignore: bool,
+    pub include_globs: Option<Vec<String>>,
+    pub exclude_globs: Option<Vec<String>>,
+    pub allow_regexes: Option<Vec<String>>,
+    pub deny_regexes: Option<Vec<String>>,
+    pub change_mask: u8,
+    pub timestamp: bool,
+    pub is_dir: bool,
+    pub ordered: bool,
+    pub keep_duplicates: bool,
+    pub close_write: bool,
+    pub metadata_changes: String,
+    pub fanotify: bool,
+    pub process: bool,
+    pub root_backends: BTreeMap<String, String>,
+    pub network_filesystems: String,
+    pub watchdog_interval_ms: u64,
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    pub canonical_paths: bool,
+    pub relative_paths: bool,
+    pub root: bool,
+    pub group_by_root: bool,
+    pub file_targets: bool,
+    pub wait_for_creation: bool,
+    pub handle_sigterm: bool,
+    pub interrupt_signals: Vec<i32>,
+    pub acknowledge: bool,
+    pub watch_limit: String,
+    pub lazy_depth: Option<usize>,
+    pub registration_threads: usize,
+    pub progress: bool,
+    pub compare_contents: Option<bool>,
+    pub poll_threads: Option<usize>,
+    pub max_poll_delay_ms: Option<f64>,
+    pub compare_contents_max_size: Option<u64>,
+}
+
+impl EffectiveConfig {
+    pub fn to_py(&self, py: Python, backend: &str) -> PyResult<PyObject> {
+        let root_filters = PyDict::new_bound(py);
+        for (root, filter) in self.root_filters.iter() {
+            root_filters.set_item(root, filter.to_py(py)?)?;
+        }
+        let dict = PyDict::new_bound(py);
+        dict.set_item("backend", backend)?;
+        dict.set_item("watch_paths", self.watch_paths.clone())?;
+        dict.set_item("recursive", self.recursive)?;
+        dict.set_item("poll_delay_ms", self.poll_delay_ms)?;
+        dict.set_item("poll_delay_ms_overrides", self.poll_delay_ms_overrides.clone())?;
+        dict.set_item("rescan_interval_ms", self.rescan_interval_ms)?;
+        dict.set_item("max_file_size", self.filters.max_file_size)?;
+        dict.set_item("files_only", self.filters.files_only)?;
+        dict.set_item("file_types", self.filters.file_types.as_str())?;
+        dict.set_item("root_filters", root_filters)?;
+        dict.set_item("collapse_save_storms", self.collapse_save_storms)?;
+        dict.set_item("collapse_renames", self.collapse_renames)?;
+        dict.set_item("modify_kind", self.modify_kind)?;
+        dict.set_item("modify_sizes", self.modify_sizes)?;
+        dict.set_item("depth", self.depth)?;
+        dict.set_item("first_component", self.first_component)?;
+        dict.set_item("source", self.source)?;
+        dict.set_item("virtual_filesystems", &self.virtual_filesystems)?;
+        dict.set_item("scan_new_dirs", self.scan_new_dirs)?;
+        dict.set_item("ignore_permission_denied", self.ignore_permission_denied)?;
+        dict.set_item("partial_ok", self.partial_ok)?;
+        dict.set_item("watch_retries", self.watch_retries)?;
+        dict.set_item("watch_retry_delay_ms", self.watch_retry_delay_ms)?;
+        dict.set_item("stat_retries", self.stat_retries)?;
+        dict.set_item("stat_retry_delay_ms", self.stat_retry_delay_ms)?;
+        dict.set_item("root_deleted", &self.root_deleted)?;
+        dict.set_item("max_pending_changes", self.max_pending_changes)?;
+        dict.set_item("overflow", &self.overflow)?;
+        dict.set_item("backpressure_timeout_ms", self.backpressure_timeout_ms)?;
+        dict.set_item("spill_dir", &self.spill_dir)?;
+        dict.set_item("clock", if self.manual_clock { "manual" } else { "system" })?;
+        dict.set_item("advanced_options", self.advanced_options.to_py(py)?)?;
+        dict.set_item("share", &self.share)?;
+        dict.set_item("share_role", self.share_role)?;
+        dict.set_item("path_containment", &self.path_containment)?;
+        dict.set_item("history_size", self.history_size)?;
+        dict.set_item("merge_aliases", self.merge_aliases)?;
+        dict.set_item("ignore_cloud_sync", self.ignore_cloud_sync)?;
+        dict.set_item("rename_pairs", self.rename_pairs)?;
+        dict.set_item("gitignore", self.gitignore)?;
+        dict.set_item("include_globs", self.include_globs.clone())?;
+        dict.set_item("exclude_globs", self.exclude_globs.clone())?;
+        dict.set_item("allow_regexes", self.allow_regexes.clone())?;
+        dict.set_item("deny_regexes", self.deny_regexes.clone())?;
+        dict.set_item("change_mask", self.change_mask)?;
+        dict.set_item("timestamp", self.timestamp)?;
+        dict.set_item("is_dir", self.is_dir)?;
+        dict.set_item("ordered", self.ordered)?;
+        dict.set_item("keep_duplicates", self.keep_duplicates)?;
+        dict.set_item("close_write", self.close_write)?;
+        dict.set_item("metadata_changes", &self.metadata_changes)?;
+        dict.set_item("fanotify", self.fanotify)?;
+        dict.set_item("process", self.process)?;
+        dict.set_item("root_backends", &self.root_backends)?;
+        dict.set_item("network_filesystems", &self.network_filesystems)?;
+        dict.set_item("watchdog_interval_ms", self.watchdog_interval_ms)?;
+        dict.set_item("max_depth", self.max_depth)?;
+        dict.set_item("follow_symlinks", self.follow_symlinks)?;
+        dict.set_item("canonical_paths", self.canonical_paths)?;
+        dict.set_item("relative_paths", self.relative_paths)?;
+        dict.set_item("root", self.root)?;
+        dict.set_item("group_by_root", self.group_by_root)?;
+        dict.set_item("file_targets", self.file_targets)?;
+        dict.set_item("wait_for_creation", self.wait_for_creation)?;
+        dict.set_item("handle_sigterm", self.handle_sigterm)?;
+        dict.set_item("interrupt_signals", self.interrupt_signals.clone())?;
+        dict.set_item("acknowledge", self.acknowledge)?;
+        dict.set_item("watch_limit", &self.watch_limit)?;
+        dict.set_item("lazy_depth", self.lazy_depth)?;
+        dict.set_item("registration_threads", self.registration_threads)?;
+        dict.set_item("progress", self.progress)?;
+        dict.set_item("compare_contents", self.compare_contents)?;
+        dict.set_item("poll_threads", self.poll_threads)?;
+        dict.set_item("max_poll_delay_ms", self.max_poll_delay_ms)?;
+        dict.set_item("compare_contents_max_size", self.compare_contents_max_size)?;
+        Ok(dict.into())
+    }
+}
+
+/// Options passed directly to notify's `Config`, applied after watchfiles' own configuration.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AdvancedOptions {
+    pub poll_interval: Option<Duration>,
+    pub compare_contents: Option<bool>,
+}
+
+impl AdvancedOptions {
+    pub fn from_py(options: Option<&Bound<PyDict>>) -> PyResult<Self> {
+        let mut advanced = AdvancedOptions::default();
+        let options = match options {
+            Some(options) => options,
+            None => return Ok(advanced),
+        };
+        for (key, value) in options.iter() {
+            match key.extract::<&str>()? {
+                "poll_interval_ms" => {
+                    advanced.poll_interval = Some(crate::duration_from_ms("poll_interval_ms", value.extract()?)?)
+                }
+                "compare_contents" => advanced.compare_contents = Some(value.extract()?),
+                key => {
+                    return Err(PyValueError::new_err(format!(
+                        "Invalid advanced_options key {:?}, supported options are: 'poll_interval_ms', \
+                         'compare_contents'",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(advanced)
+    }
+
+    pub fn apply(&self, config: NotifyConfig) -> NotifyConfig {
+        let mut config = config;
+        if let Some(poll_interval) = self.poll_interval {
+            config = config.with_poll_interval(poll_interval);
+        }
+        if let Some(compare_contents) = self.compare_contents {
+            config = config.with_compare_contents(compare_contents);
+        }
+        config
+    }
+
+    fn to_py(&self, py: Python) -> PyResult<PyObject> {
+        let dict = PyDict::new_bound(py);
+        if let Some(poll_interval) = self.poll_interval {
+            dict.set_item("poll_interval_ms", poll_interval.as_secs_f64() * 1000.0)?;
+        }
+        if let Some(compare_contents) = self.compare_contents {
+            dict.set_item("compare_contents", compare_contents)?;
+        }
+        Ok(dict.into())
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::memory::btree_map_bytes;
+use crate::pending::PendingChanges;
+
+/// What to do with changes to paths which aren't inside any watch path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ContainmentPolicy {
+    Off,
+    // remove the change from the batch
+    Drop,
+    // keep the change, but record the path in `escaped_paths`
+    Flag,
+}
+
+impl ContainmentPolicy {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "off" => Ok(ContainmentPolicy::Off),
+            "drop" => Ok(ContainmentPolicy::Drop),
+            "flag" => Ok(ContainmentPolicy::Flag),
+            _ => Err(format!(
+                "Invalid path_containment value {:?}, must be 'off', 'drop' or 'flag'",
+                s
+            )),
+        }
+    }
+}
+
+/// Checks changed paths are inside a watch path, both lexically and once symlinks are resolved.
+#[derive(Debug)]
+pub(crate) struct Containment {
+    policy: ContainmentPolicy,
+    roots: Vec<PathBuf>,
+    real_roots: Vec<PathBuf>,
+    // paths found outside the watch paths, mapped to why
+    escaped: Mutex<BTreeMap<String, String>>,
+}
+
+impl Containment {
+    pub fn new(policy: ContainmentPolicy, watch_paths: &[String]) -> Self {
+        let mut containment = Containment {
+            policy,
+            roots: Vec::new(),
+            real_roots: Vec::new(),
+            escaped: Mutex::new(BTreeMap::new()),
+        };
+        containment.set_watch_paths(watch_paths);
+        containment
+    }
+
+    /// Replace the watch paths changes must stay within, e.g. after `add_path()`.
+    pub fn set_watch_paths(&mut self, watch_paths: &[String]) {
+        self.roots = watch_paths.iter().map(|p| normalize(Path::new(p))).collect();
+        self.real_roots = self
+            .roots
+            .iter()
+            .map(|root| fs::canonicalize(root).unwrap_or_else(|_| root.clone()))
+            .collect();
+    }
+
+    /// Record, and with `ContainmentPolicy::Drop` remove, changes to paths which escape the watch paths.
+    pub fn check(&self, changes: &mut PendingChanges) {
+        let mut escaped = self.escaped.lock().unwrap();
+        changes.retain_paths(|path| match self.escape_reason(path) {
+            Some(reason) => {
+                escaped.insert(path.to_string(), reason.to_string());
+                self.policy != ContainmentPolicy::Drop
+            }
+            None => true,
+        });
+    }
+
+    pub fn escaped_paths(&self) -> BTreeMap<String, String> {
+        self.escaped.lock().unwrap().clone()
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        btree_map_bytes(&self.escaped.lock().unwrap(), |path, reason| {
+            path.capacity() + reason.capacity()
+        })
+    }
+
+    fn escape_reason(&self, path: &str) -> Option<&'static str> {
+        let path = normalize(Path::new(path));
+        if !self.roots.iter().any(|root| path.starts_with(root)) {
+            return Some("outside the watch paths");
+        }
+        match resolve(&path) {
+            Some(real_path) if !self.real_roots.iter().any(|root| real_path.starts_with(root)) => {
+                Some("resolves outside the watch paths via a symlink")
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Remove `.` and `..` components from `path` without reference to the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            // `..` at the start of a relative path is kept, so the path can't match any root below it
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Where `path` really is once symlinks are resolved, deleted paths are resolved via their directory.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    match fs::canonicalize(path) {
+        Ok(real_path) => Some(real_path),
+        Err(_) => {
+            let real_parent = fs::canonicalize(path.parent()?).ok()?;
+            Some(real_parent.join(path.file_name()?))
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{ErrorKind as IOErrorKind, Read};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::spawn;
+use std::time::{Duration, SystemTime};
+
+use notify::event::{DataChange, ModifyKind};
+use notify::{Event, EventKind};
+use walkdir::WalkDir;
+
+use crate::EventHandler;
+
+/// What's compared for each file, its contents are only hashed when it's no larger than the limit.
+#[derive(Debug, PartialEq, Eq)]
+struct FileState {
+    size: u64,
+    mtime: Option<SystemTime>,
+    hash: Option<u64>,
+}
+
+/// With `compare_contents_max_size`, compares the contents of polled files ourselves rather than the poll watcher
+/// hashing every file, so only files no larger than `max_size` are read, larger files are compared by size.
+///
+/// The poll watcher still reports files whose mtime has changed, only changes it can't see are reported from here.
+#[derive(Debug)]
+pub(crate) struct ContentCheck {
+    roots: Vec<String>,
+    recursive: bool,
+    max_size: u64,
+    hasher: RandomState,
+    files: HashMap<String, FileState>,
+}
+
+impl ContentCheck {
+    pub fn new(roots: Vec<String>, recursive: bool, max_size: u64) -> Self {
+        let mut check = ContentCheck {
+            roots,
+            recursive,
+            max_size,
+            hasher: RandomState::new(),
+            files: HashMap::new(),
+        };
+        check.files = check.scan();
+        check
+    }
+
+    /// Check every file again every `interval` until the returned sender is dropped, passing a modification event
+    /// to `handler` for each file whose size or contents changed without its mtime changing.
+    pub fn spawn(mut self, interval: Duration, handler: EventHandler, source: &'static str) -> Sender<()> {
+        let (stop_sender, stop_receiver) = channel::<()>();
+        spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                for path in self.changed() {
+                    let event = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Any))).add_path(path.into());
+                    handler(Ok(event), source);
+                }
+            }
+        });
+        stop_sender
+    }
+
+    fn changed(&mut self) -> Vec<String> {
+        let files = self.scan();
+        let changed = files
+            .iter()
+            .filter(|(path, new)| match self.files.get(*path) {
+                // the poll watcher reports files whose mtime has moved forward
+                Some(old) => new.mtime <= old.mtime && (new.size != old.size || new.hash != old.hash),
+                None => false,
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.files = files;
+        changed
+    }
+
+    fn scan(&self) -> HashMap<String, FileState> {
+        let max_depth = if self.recursive { usize::MAX } else { 1 };
+        let mut files = HashMap::new();
+        for root in &self.roots {
+            for dir_entry in WalkDir::new(root).max_depth(max_depth).into_iter().flatten() {
+                let Ok(metadata) = dir_entry.metadata() else {
+                    continue;
+                };
+                let Some(path) = dir_entry.path().to_str() else {
+                    continue;
+                };
+                if !metadata.is_file() {
+                    continue;
+                }
+                let size = metadata.len();
+                let hash = match size <= self.max_size {
+                    true => self.hash(path),
+                    false => None,
+                };
+                let state = FileState {
+                    size,
+                    mtime: metadata.modified().ok(),
+                    hash,
+                };
+                files.insert(path.to_string(), state);
+            }
+        }
+        files
+    }
+
+    fn hash(&self, path: &str) -> Option<u64> {
+        let mut file = File::open(path).ok()?;
+        let mut hasher = self.hasher.build_hasher();
+        let mut buf = [0; 64 * 1024];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => return Some(hasher.finish()),
+                Ok(n) => hasher.write(&buf[..n]),
+                Err(e) if e.kind() == IOErrorKind::Interrupted => (),
+                Err(_) => return None,
+            }
+        }
+    }
+}
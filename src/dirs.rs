@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+
+use crate::memory::map_bytes;
+use crate::pending::PendingChanges;
+use crate::CHANGE_DELETED;
+
+/// Records whether the path of each pending change is a directory.
+#[derive(Debug, Default)]
+pub(crate) struct DirTracker {
+    // whether paths we've seen are directories, so deleted paths can be classified after they're gone
+    known: Mutex<HashMap<String, bool>>,
+    // `None` where it couldn't be determined
+    pending: Mutex<HashMap<(u8, String), Option<bool>>>,
+}
+
+impl DirTracker {
+    /// `is_dir` should be provided where the caller already knows, e.g. from the event kind, otherwise the path
+    /// is checked. Only the first record of each change is kept.
+    pub fn record(&self, change: u8, path: &str, is_dir: Option<bool>) {
+        let mut pending = self.pending.lock().unwrap();
+        let key = (change, path.to_string());
+        if pending.contains_key(&key) {
+            return;
+        }
+        let mut known = self.known.lock().unwrap();
+        let is_dir = match is_dir {
+            Some(is_dir) => Some(is_dir),
+            None if change == CHANGE_DELETED => known.get(path).copied(),
+            None => fs::metadata(path).ok().map(|metadata| metadata.is_dir()),
+        };
+        match (change, is_dir) {
+            (CHANGE_DELETED, _) => {
+                known.remove(path);
+            }
+            (_, Some(is_dir)) => {
+                known.insert(path.to_string(), is_dir);
+            }
+            _ => (),
+        }
+        pending.insert(key, is_dir);
+    }
+
+    pub fn take(&self) -> HashMap<(u8, String), Option<bool>> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// As `take`, but only for `changes`.
+    pub fn take_for(&self, changes: &PendingChanges) -> HashMap<(u8, String), Option<bool>> {
+        changes.take_annotations(&mut self.pending.lock().unwrap())
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        map_bytes(&self.known.lock().unwrap(), |path, _| path.capacity())
+            + map_bytes(&self.pending.lock().unwrap(), |(_, path), _| path.capacity())
+    }
+}
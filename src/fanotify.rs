@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+
+use notify::event::{
+    AccessKind, AccessMode, CreateKind, DataChange, Flag, MetadataKind, ModifyKind, RemoveKind, RenameMode,
+};
+use notify::{Error as NotifyError, Event, EventKind, RecursiveMode, Result as NotifyResult};
+
+// the events we ask for, with `FAN_REPORT_DFID_NAME` each is reported with the directory and name of its subject
+const EVENT_MASK: u64 = libc::FAN_CREATE
+    | libc::FAN_DELETE
+    | libc::FAN_MOVED_FROM
+    | libc::FAN_MOVED_TO
+    | libc::FAN_MODIFY
+    | libc::FAN_ATTRIB
+    | libc::FAN_CLOSE_WRITE
+    | libc::FAN_ONDIR;
+const METADATA_VERSION: u8 = 3;
+const METADATA_LEN: usize = 24;
+// resolved directories are forgotten once there are this many, so watching a busy filesystem can't use unbounded memory
+const MAX_CACHED_DIRS: usize = 10_000;
+// how often the reading thread checks whether it should stop
+const POLL_TIMEOUT_MS: libc::c_int = 100;
+
+type Fsid = [i32; 2];
+
+/// A watch path, as given and with symlinks resolved, since fanotify reports resolved paths.
+#[derive(Debug)]
+struct Root {
+    path: PathBuf,
+    real_path: PathBuf,
+    recursive: bool,
+}
+
+impl Root {
+    /// Where `real_path` is relative to this watch path, if it's being watched.
+    fn report_path(&self, real_path: &Path) -> Option<PathBuf> {
+        let relative = real_path.strip_prefix(&self.real_path).ok()?;
+        match self.recursive || relative.components().count() <= 1 {
+            true => Some(self.path.join(relative)),
+            false => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    roots: Vec<Root>,
+    // a file descriptor on each marked filesystem, needed to open the directories events refer to
+    mount_fds: HashMap<Fsid, OwnedFd>,
+    // directory handles mapped to their paths, `None` for directories which couldn't be opened
+    dirs: HashMap<Vec<u8>, Option<PathBuf>>,
+}
+
+/// Watches whole filesystems with fanotify and reports changes below the watch paths, along with the ID of the
+/// process which made each change.
+///
+/// fanotify requires the `CAP_SYS_ADMIN` capability and Linux 5.9 or later.
+#[derive(Debug)]
+pub(crate) struct FanotifyWatcher {
+    fd: Arc<OwnedFd>,
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FanotifyWatcher {
+    pub fn new<F: FnMut(NotifyResult<Event>) + Send + 'static>(mut handler: F) -> NotifyResult<Self> {
+        let flags = libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC | libc::FAN_NONBLOCK | libc::FAN_REPORT_DFID_NAME;
+        let event_flags = (libc::O_RDONLY | libc::O_CLOEXEC) as libc::c_uint;
+        let fd = unsafe { libc::fanotify_init(flags, event_flags) };
+        if fd < 0 {
+            return Err(NotifyError::io(IOError::last_os_error()));
+        }
+        let fd = Arc::new(unsafe { OwnedFd::from_raw_fd(fd) });
+        let state: Arc<Mutex<State>> = Arc::default();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (thread_fd, thread_state, thread_stop) = (fd.clone(), state.clone(), stop.clone());
+        spawn(move || {
+            let mut buffer = vec![0u8; 64 * 1024];
+            while !thread_stop.load(Ordering::Relaxed) {
+                let mut poll_fd = libc::pollfd {
+                    fd: thread_fd.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                if unsafe { libc::poll(&mut poll_fd, 1, POLL_TIMEOUT_MS) } <= 0 {
+                    continue;
+                }
+                let read = unsafe { libc::read(thread_fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len()) };
+                if read < 0 {
+                    let error = IOError::last_os_error();
+                    match error.kind() {
+                        IOErrorKind::WouldBlock | IOErrorKind::Interrupted => continue,
+                        _ => {
+                            handler(Err(NotifyError::io(error)));
+                            return;
+                        }
+                    }
+                }
+                let mut state = thread_state.lock().unwrap();
+                for event in parse_events(&buffer[..read as usize], &mut state) {
+                    handler(Ok(event));
+                }
+            }
+        });
+        Ok(FanotifyWatcher { fd, state, stop })
+    }
+
+    pub fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> NotifyResult<()> {
+        let real_path = fs::canonicalize(path).map_err(|e| match e.kind() {
+            IOErrorKind::NotFound => NotifyError::path_not_found().add_path(path.to_path_buf()),
+            _ => NotifyError::io(e).add_path(path.to_path_buf()),
+        })?;
+        let c_path = CString::new(real_path.as_os_str().as_bytes())
+            .map_err(|_| NotifyError::generic("watch path contains a null byte").add_path(path.to_path_buf()))?;
+        // `open_by_handle_at` doesn't accept `O_PATH` file descriptors, non-blocking in case the path is a FIFO
+        let mount_fd = unsafe { libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_NONBLOCK | libc::O_CLOEXEC) };
+        if mount_fd < 0 {
+            return Err(NotifyError::io(IOError::last_os_error()).add_path(path.to_path_buf()));
+        }
+        let mount_fd = unsafe { OwnedFd::from_raw_fd(mount_fd) };
+        let fsid = filesystem_id(mount_fd.as_raw_fd()).map_err(|e| NotifyError::io(e).add_path(path.to_path_buf()))?;
+        let flags = libc::FAN_MARK_ADD | libc::FAN_MARK_FILESYSTEM;
+        let marked =
+            unsafe { libc::fanotify_mark(self.fd.as_raw_fd(), flags, EVENT_MASK, libc::AT_FDCWD, c_path.as_ptr()) };
+        if marked < 0 {
+            return Err(NotifyError::io(IOError::last_os_error()).add_path(path.to_path_buf()));
+        }
+        let mut state = self.state.lock().unwrap();
+        state.mount_fds.entry(fsid).or_insert(mount_fd);
+        state.roots.retain(|root| root.path != path);
+        state.roots.push(Root {
+            path: path.to_path_buf(),
+            real_path,
+            recursive: recursive_mode == RecursiveMode::Recursive,
+        });
+        Ok(())
+    }
+
+    /// Stop reporting changes below `path`, the filesystem stays marked while the watcher exists.
+    pub fn unwatch(&mut self, path: &Path) -> NotifyResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let count = state.roots.len();
+        state.roots.retain(|root| root.path != path);
+        match state.roots.len() < count {
+            true => Ok(()),
+            false => Err(NotifyError::watch_not_found().add_path(path.to_path_buf())),
+        }
+    }
+}
+
+impl Drop for FanotifyWatcher {
+    fn drop(&mut self) {
+        // the reading thread holds the other reference to the fanotify fd, so it's closed once the thread stops
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn filesystem_id(fd: RawFd) -> std::io::Result<Fsid> {
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::fstatfs(fd, stat.as_mut_ptr()) } < 0 {
+        return Err(IOError::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+    // `fsid_t`'s fields are private, but it's two ints, as fanotify reports it
+    Ok(unsafe { std::mem::transmute::<libc::fsid_t, Fsid>(stat.f_fsid) })
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_ne_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Convert a buffer of fanotify events to notify events for paths below the watch paths.
+fn parse_events(mut buffer: &[u8], state: &mut State) -> Vec<Event> {
+    let mut events = Vec::new();
+    while buffer.len() >= METADATA_LEN {
+        let event_len = read_u32(buffer, 0) as usize;
+        if event_len < METADATA_LEN || event_len > buffer.len() || buffer[4] != METADATA_VERSION {
+            break;
+        }
+        let (event, rest) = buffer.split_at(event_len);
+        buffer = rest;
+        let metadata_len = read_u16(event, 6) as usize;
+        let mask = read_u64(event, 8);
+        let pid = read_i32(event, 20);
+        if mask & libc::FAN_Q_OVERFLOW != 0 {
+            events.push(Event::new(EventKind::Other).set_flag(Flag::Rescan));
+            continue;
+        }
+        let Some(real_path) = event_path(&event[metadata_len.min(event.len())..], state) else {
+            continue;
+        };
+        let Some(path) = state.roots.iter().find_map(|root| root.report_path(&real_path)) else {
+            continue;
+        };
+        let is_dir = mask & libc::FAN_ONDIR != 0;
+        // directories moving or being deleted change the paths of everything below them
+        if is_dir && mask & (libc::FAN_MOVED_FROM | libc::FAN_MOVED_TO | libc::FAN_DELETE) != 0 {
+            state.dirs.clear();
+        }
+        for kind in event_kinds(mask) {
+            let mut event = Event::new(kind).add_path(path.clone());
+            if pid > 0 {
+                event = event.set_process_id(pid as u32);
+            }
+            events.push(event);
+        }
+    }
+    events
+}
+
+/// The notify event kinds for a fanotify event mask, fanotify merges events on the same path so there may be several.
+fn event_kinds(mask: u64) -> impl Iterator<Item = EventKind> {
+    let is_dir = mask & libc::FAN_ONDIR != 0;
+    [
+        (
+            libc::FAN_CREATE,
+            EventKind::Create(match is_dir {
+                true => CreateKind::Folder,
+                false => CreateKind::File,
+            }),
+        ),
+        (libc::FAN_MOVED_TO, EventKind::Modify(ModifyKind::Name(RenameMode::To))),
+        (libc::FAN_MODIFY, EventKind::Modify(ModifyKind::Data(DataChange::Any))),
+        (
+            libc::FAN_ATTRIB,
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)),
+        ),
+        (
+            libc::FAN_CLOSE_WRITE,
+            EventKind::Access(AccessKind::Close(AccessMode::Write)),
+        ),
+        (
+            libc::FAN_MOVED_FROM,
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+        ),
+        (
+            libc::FAN_DELETE,
+            EventKind::Remove(match is_dir {
+                true => RemoveKind::Folder,
+                false => RemoveKind::File,
+            }),
+        ),
+    ]
+    .into_iter()
+    .filter(move |(flag, _)| mask & flag != 0)
+    .map(|(_, kind)| kind)
+}
+
+/// The path an event refers to, from its directory handle and name.
+fn event_path(mut info: &[u8], state: &mut State) -> Option<PathBuf> {
+    // each info record is a header of type, padding and length, followed by the record
+    while info.len() >= 4 {
+        let info_type = info[0];
+        let len = read_u16(info, 2) as usize;
+        if len < 4 || len > info.len() {
+            return None;
+        }
+        let (record, rest) = info.split_at(len);
+        info = rest;
+        if info_type != libc::FAN_EVENT_INFO_TYPE_DFID_NAME || record.len() < 20 {
+            continue;
+        }
+        let fsid: Fsid = [read_i32(record, 4), read_i32(record, 8)];
+        // `struct file_handle`, its size and type followed by the handle itself
+        let handle_len = 8 + read_u32(record, 12) as usize;
+        let handle = record.get(12..12 + handle_len)?;
+        let name = record[12 + handle_len..].split(|b| *b == 0).next()?;
+        let dir = resolve_dir(state, fsid, handle)?;
+        return match name {
+            b"." | b"" => Some(dir),
+            name => Some(dir.join(std::ffi::OsStr::from_bytes(name))),
+        };
+    }
+    None
+}
+
+fn resolve_dir(state: &mut State, fsid: Fsid, handle: &[u8]) -> Option<PathBuf> {
+    let mut key = Vec::with_capacity(8 + handle.len());
+    key.extend_from_slice(&fsid[0].to_ne_bytes());
+    key.extend_from_slice(&fsid[1].to_ne_bytes());
+    key.extend_from_slice(handle);
+    if let Some(dir) = state.dirs.get(&key) {
+        return dir.clone();
+    }
+    let dir = state
+        .mount_fds
+        .get(&fsid)
+        .and_then(|mount_fd| open_by_handle(mount_fd.as_raw_fd(), handle));
+    if state.dirs.len() >= MAX_CACHED_DIRS {
+        state.dirs.clear();
+    }
+    state.dirs.insert(key, dir.clone());
+    dir
+}
+
+/// Open the directory `handle` refers to and find its path, this requires the `CAP_DAC_READ_SEARCH` capability.
+fn open_by_handle(mount_fd: RawFd, handle: &[u8]) -> Option<PathBuf> {
+    // copy the handle so it's aligned as `struct file_handle`
+    let mut aligned = vec![0u32; (handle.len() + 3) / 4];
+    unsafe { std::ptr::copy_nonoverlapping(handle.as_ptr(), aligned.as_mut_ptr().cast::<u8>(), handle.len()) };
+    let fd = unsafe { libc::syscall(libc::SYS_open_by_handle_at, mount_fd, aligned.as_ptr(), libc::O_PATH) };
+    if fd < 0 {
+        return None;
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+    let path = fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd())).ok()?;
+    // the directory itself has since been deleted
+    match path.as_os_str().as_bytes().ends_with(b" (deleted)") {
+        true => None,
+        false => Some(path),
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use notify::event::{CreateKind, EventKind, RemoveKind};
+use regex::RegexSet;
+
+use crate::gitignore::Gitignore;
+use crate::glob::Glob;
+use crate::memory::set_bytes;
+use crate::roots::Roots;
+use crate::targets::FileTargets;
+use crate::{CHANGE_ADDED, CHANGE_DELETED, CHANGE_RENAMED};
+
+/// Which types of file changes are reported for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum FileTypes {
+    #[default]
+    All,
+    // regular files only, not directories, symlinks, sockets, FIFOs or device nodes
+    Regular,
+    RegularAndSymlinks,
+}
+
+impl FileTypes {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "all" => Ok(FileTypes::All),
+            "regular" => Ok(FileTypes::Regular),
+            "regular_and_symlinks" => Ok(FileTypes::RegularAndSymlinks),
+            _ => Err(format!(
+                "Invalid file_types value {:?}, must be 'all', 'regular' or 'regular_and_symlinks'",
+                s
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileTypes::All => "all",
+            FileTypes::Regular => "regular",
+            FileTypes::RegularAndSymlinks => "regular_and_symlinks",
+        }
+    }
+
+    fn allows(&self, file_type: fs::FileType) -> bool {
+        match self {
+            FileTypes::All => true,
+            FileTypes::Regular => file_type.is_file(),
+            FileTypes::RegularAndSymlinks => file_type.is_file() || file_type.is_symlink(),
+        }
+    }
+}
+
+/// How modifications which only change a path's metadata, e.g. permissions, ownership or timestamps, are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MetadataChanges {
+    Modified,
+    // reported as `CHANGE_ATTRIBUTE`
+    Attribute,
+    Ignore,
+}
+
+impl MetadataChanges {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "modified" => Ok(MetadataChanges::Modified),
+            "attribute" => Ok(MetadataChanges::Attribute),
+            "ignore" => Ok(MetadataChanges::Ignore),
+            _ => Err(format!(
+                "Invalid metadata_changes value {:?}, must be 'modified', 'attribute' or 'ignore'",
+                s
+            )),
+        }
+    }
+}
+
+/// Rust-side filtering applied to each change before it's added to the pending set, so uninteresting
+/// changes never make it back to python.
+#[derive(Debug, Default)]
+pub(crate) struct ChangeFilter {
+    /// added and modified files larger than this many bytes are ignored
+    pub max_file_size: Option<u64>,
+    /// changes to directories are ignored
+    pub files_only: bool,
+    /// changes to other types of file are ignored
+    pub file_types: FileTypes,
+    /// directories we've seen, so deleted paths can be classified after they're gone
+    known_dirs: Mutex<HashSet<String>>,
+    /// paths we've seen which aren't one of `file_types`, likewise so deletions can be classified
+    excluded_paths: Mutex<HashSet<String>>,
+}
+
+impl ChangeFilter {
+    pub fn new(max_file_size: Option<u64>, files_only: bool, file_types: FileTypes) -> Self {
+        ChangeFilter {
+            max_file_size,
+            files_only,
+            file_types,
+            ..Default::default()
+        }
+    }
+
+    /// `is_dir` should be provided where the caller already knows, e.g. from the event kind.
+    pub fn allows(&self, change: u8, path: &str, is_dir: Option<bool>) -> bool {
+        if let Some(max_file_size) = self.max_file_size {
+            // deleted files can't be checked, and we want to know about them anyway
+            if change != CHANGE_DELETED {
+                if let Ok(metadata) = fs::metadata(path) {
+                    if metadata.is_file() && metadata.len() > max_file_size {
+                        return false;
+                    }
+                }
+            }
+        }
+        if self.files_only && self.is_dir(change, path, is_dir) {
+            return false;
+        }
+        if self.file_types != FileTypes::All && !self.file_type_allowed(change, path) {
+            return false;
+        }
+        true
+    }
+
+    /// Deleted paths are allowed unless we saw they were another type of file before they were deleted.
+    fn file_type_allowed(&self, change: u8, path: &str) -> bool {
+        let mut excluded_paths = self.excluded_paths.lock().unwrap();
+        if change == CHANGE_DELETED {
+            return !excluded_paths.remove(path);
+        }
+        // symlinks aren't followed, so a symlink to a regular file is a symlink
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if !self.file_types.allows(metadata.file_type()) => {
+                excluded_paths.insert(path.to_string());
+                false
+            }
+            _ => {
+                excluded_paths.remove(path);
+                true
+            }
+        }
+    }
+
+    fn is_dir(&self, change: u8, path: &str, is_dir: Option<bool>) -> bool {
+        let mut known_dirs = self.known_dirs.lock().unwrap();
+        let is_dir = match is_dir {
+            Some(is_dir) => is_dir,
+            None if change == CHANGE_DELETED => known_dirs.contains(path),
+            None => fs::metadata(path).map(|m| m.is_dir()).unwrap_or(false),
+        };
+        if change == CHANGE_DELETED {
+            known_dirs.remove(path);
+        } else if is_dir {
+            known_dirs.insert(path.to_string());
+        }
+        is_dir
+    }
+
+    /// Memory used remembering the types of paths, so deleted paths can be classified.
+    pub fn memory_bytes(&self) -> usize {
+        set_bytes(&self.known_dirs.lock().unwrap(), |path| path.capacity())
+            + set_bytes(&self.excluded_paths.lock().unwrap(), |path| path.capacity())
+    }
+}
+
+/// Include and exclude glob patterns, matched against paths relative to the watch path containing them.
+///
+/// Patterns without a `/` match the file name at any depth. A path is excluded if it or any directory containing
+/// it (below the watch path) matches an exclude pattern, with include patterns only the path itself is matched.
+#[derive(Debug)]
+pub(crate) struct GlobFilter {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    // sorted with the deepest roots first
+    roots: Vec<PathBuf>,
+}
+
+impl GlobFilter {
+    pub fn new(include: &[String], exclude: &[String], watch_paths: &[String]) -> Result<Self, String> {
+        let compile = |patterns: &[String]| -> Result<Vec<Glob>, String> {
+            patterns
+                .iter()
+                .map(|pattern| match pattern.contains('/') {
+                    true => Glob::new(pattern.trim_start_matches('/')),
+                    false => Glob::new(&format!("**/{}", pattern)),
+                })
+                .collect()
+        };
+        let mut roots: Vec<PathBuf> = watch_paths.iter().map(PathBuf::from).collect();
+        roots.sort_by_key(|root| std::cmp::Reverse(root.components().count()));
+        Ok(GlobFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+            roots,
+        })
+    }
+
+    fn allows(&self, path: &str) -> bool {
+        let path = Path::new(path);
+        let relative = self
+            .roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        let components: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+        let relative = components.join("/");
+        if !self.include.is_empty() && !self.include.iter().any(|glob| glob.is_match(&relative)) {
+            return false;
+        }
+        // check the path and each of its parent directories
+        let mut end = relative.len();
+        loop {
+            let prefix = &relative[..end];
+            if self.exclude.iter().any(|glob| glob.is_match(prefix)) {
+                return false;
+            }
+            match prefix.rfind('/') {
+                Some(slash) => end = slash,
+                None => return true,
+            }
+        }
+    }
+}
+
+/// Regular expressions searched for in the full path of each change.
+#[derive(Debug)]
+pub(crate) struct RegexFilter {
+    allow: Option<RegexSet>,
+    deny: Option<RegexSet>,
+}
+
+impl RegexFilter {
+    pub fn new(allow: Option<&[String]>, deny: Option<&[String]>) -> Result<Self, String> {
+        let compile = |name: &str, patterns: Option<&[String]>| -> Result<Option<RegexSet>, String> {
+            match patterns {
+                Some(patterns) => RegexSet::new(patterns)
+                    .map(Some)
+                    .map_err(|e| format!("Invalid {} pattern: {}", name, e)),
+                None => Ok(None),
+            }
+        };
+        Ok(RegexFilter {
+            allow: compile("allow_regexes", allow)?,
+            deny: compile("deny_regexes", deny)?,
+        })
+    }
+
+    /// Paths must match an allow pattern, if there are any, and mustn't match any deny patterns.
+    fn allows(&self, path: &str) -> bool {
+        self.allow.as_ref().map_or(true, |allow| allow.is_match(path))
+            && !self.deny.as_ref().map_or(false, |deny| deny.is_match(path))
+    }
+}
+
+/// The kinds of change to report, as a bitmask with bit `1 << change` set for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChangeMask(u8);
+
+impl Default for ChangeMask {
+    fn default() -> Self {
+        ChangeMask(Self::ALL)
+    }
+}
+
+impl ChangeMask {
+    const ALL: u8 = 0b1111110;
+
+    pub fn new(mask: u8) -> Result<Self, String> {
+        match mask != 0 && mask & !Self::ALL == 0 {
+            true => Ok(ChangeMask(mask)),
+            false => Err(format!(
+                "Invalid change_mask value {:#b}, must be a combination of `1 << change` for changes 1 to 6",
+                mask
+            )),
+        }
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn allows(self, change: u8) -> bool {
+        self.0 & (1 << change) != 0
+    }
+
+    /// Renames are paired from deletions and additions, so those must be kept until they've been paired.
+    pub fn before_pairing(self) -> Self {
+        match self.allows(CHANGE_RENAMED) {
+            true => ChangeMask(self.0 | 1 << CHANGE_ADDED | 1 << CHANGE_DELETED),
+            false => self,
+        }
+    }
+}
+
+/// A `ChangeFilter` for each watch path with its own configuration, and a default for everything else.
+#[derive(Debug, Default)]
+pub(crate) struct RootFilters {
+    default: ChangeFilter,
+    // sorted with the deepest roots first, so where roots are nested the most specific one is used
+    roots: Vec<(PathBuf, ChangeFilter)>,
+    // these apply to every watch path
+    gitignore: Option<Gitignore>,
+    globs: Option<GlobFilter>,
+    regexes: Option<RegexFilter>,
+    change_mask: ChangeMask,
+    // the most levels a change can be below the watch path containing it
+    max_depth: Option<(usize, Roots)>,
+    // set when symlinks to directories aren't followed
+    no_follow: Option<Roots>,
+    file_targets: Option<FileTargets>,
+}
+
+impl RootFilters {
+    pub fn new(default: ChangeFilter, roots: Vec<(String, ChangeFilter)>) -> Self {
+        let mut roots: Vec<(PathBuf, ChangeFilter)> = roots
+            .into_iter()
+            .map(|(root, filter)| (PathBuf::from(root), filter))
+            .collect();
+        roots.sort_by_key(|(root, _)| std::cmp::Reverse(root.components().count()));
+        RootFilters {
+            default,
+            roots,
+            gitignore: None,
+            globs: None,
+            regexes: None,
+            change_mask: ChangeMask::default(),
+            max_depth: None,
+            no_follow: None,
+            file_targets: None,
+        }
+    }
+
+    /// Also ignore changes to paths ignored by `.gitignore` files.
+    pub fn with_gitignore(self, gitignore: Gitignore) -> Self {
+        RootFilters {
+            gitignore: Some(gitignore),
+            ..self
+        }
+    }
+
+    /// The filter for the watch path `path` falls under.
+    pub fn for_path(&self, path: &str) -> &ChangeFilter {
+        let path = Path::new(path);
+        self.roots
+            .iter()
+            .find(|(root, _)| path.starts_with(root))
+            .map_or(&self.default, |(_, filter)| filter)
+    }
+
+    /// Also ignore changes to paths excluded by glob patterns.
+    pub fn with_globs(self, globs: GlobFilter) -> Self {
+        RootFilters {
+            globs: Some(globs),
+            ..self
+        }
+    }
+
+    /// Also ignore changes to paths excluded by regular expressions.
+    pub fn with_regexes(self, regexes: RegexFilter) -> Self {
+        RootFilters {
+            regexes: Some(regexes),
+            ..self
+        }
+    }
+
+    /// Also ignore changes of kinds not in `change_mask`.
+    pub fn with_change_mask(self, change_mask: ChangeMask) -> Self {
+        RootFilters { change_mask, ..self }
+    }
+
+    /// Also ignore changes more than `max_depth` levels below the watch path containing them.
+    pub fn with_max_depth(self, max_depth: usize, watch_paths: &[String]) -> Self {
+        RootFilters {
+            max_depth: Some((max_depth, Roots::new(watch_paths))),
+            ..self
+        }
+    }
+
+    /// Also ignore changes below symlinks inside the watch paths, as if symlinks weren't followed.
+    pub fn without_symlinks(self, watch_paths: &[String]) -> Self {
+        RootFilters {
+            no_follow: Some(Roots::new(watch_paths)),
+            ..self
+        }
+    }
+
+    /// Also ignore changes in the directories of file targets, other than to the files themselves.
+    pub fn with_file_targets(self, file_targets: FileTargets) -> Self {
+        RootFilters {
+            file_targets: Some(file_targets),
+            ..self
+        }
+    }
+
+    pub fn allows(&self, change: u8, path: &str, is_dir: Option<bool>) -> bool {
+        if !self.change_mask.allows(change) {
+            return false;
+        }
+        if let Some(file_targets) = &self.file_targets {
+            if !file_targets.allows(path) {
+                return false;
+            }
+        }
+        if let Some((max_depth, roots)) = &self.max_depth {
+            if roots.position(path).map_or(false, |(depth, _)| depth > *max_depth) {
+                return false;
+            }
+        }
+        if let Some(regexes) = &self.regexes {
+            if !regexes.allows(path) {
+                return false;
+            }
+        }
+        if let Some(globs) = &self.globs {
+            if !globs.allows(path) {
+                return false;
+            }
+        }
+        if let Some(gitignore) = &self.gitignore {
+            if gitignore.is_ignored(change, path, is_dir) {
+                return false;
+            }
+        }
+        if !self.for_path(path).allows(change, path, is_dir) {
+            return false;
+        }
+        // this needs a stat per directory between the watch path and `path`, so it's checked last
+        match &self.no_follow {
+            Some(roots) => !below_symlink(roots, path),
+            None => true,
+        }
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        self.default.memory_bytes()
+            + self.gitignore.as_ref().map_or(0, |gitignore| gitignore.memory_bytes())
+            + self
+                .roots
+                .iter()
+                .map(|(_, filter)| filter.memory_bytes())
+                .sum::<usize>()
+    }
+}
+
+/// Whether any directory between `path`'s watch path and `path` is a symlink, the watch path itself and `path`
+/// may be symlinks.
+fn below_symlink(roots: &Roots, path: &str) -> bool {
+    let path = Path::new(path);
+    let (root, parent) = match (roots.owning_root(path), path.parent()) {
+        (Some(root), Some(parent)) => (root, parent),
+        _ => return false,
+    };
+    parent
+        .ancestors()
+        .take_while(|ancestor| *ancestor != root && ancestor.starts_with(root))
+        .any(|ancestor| {
+            fs::symlink_metadata(ancestor)
+                .map(|metadata| metadata.file_type().is_symlink())
+                .unwrap_or(false)
+        })
+}
+
+/// Whether the subject of an event is a directory, if that can be determined from the event kind alone.
+pub(crate) fn event_kind_is_dir(kind: &EventKind) -> Option<bool> {
+    match kind {
+        EventKind::Create(CreateKind::Folder) | EventKind::Remove(RemoveKind::Folder) => Some(true),
+        EventKind::Create(CreateKind::File) | EventKind::Remove(RemoveKind::File) => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,156 @@
+use std::path::Path;
+
+/// Filesystems whose contents are generated by the kernel; their mtimes and sizes don't describe changes
+/// and native notifications are rarely emitted, so watching them isn't meaningful.
+const VIRTUAL_FILESYSTEMS: &[&str] = &[
+    "proc",
+    "sysfs",
+    "devtmpfs",
+    "devpts",
+    "debugfs",
+    "tracefs",
+    "securityfs",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "pstore",
+    "bpf",
+    "efivarfs",
+    "fusectl",
+    "mqueue",
+    "hugetlbfs",
+    "binfmt_misc",
+];
+
+/// Network, shared-folder and overlay filesystems, where changes made by other machines, the VM host or the lower
+/// layers of an overlay don't produce native notifications.
+const REMOTE_FILESYSTEMS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "virtiofs",
+    "vboxsf",
+    "fuse.sshfs",
+    "overlay",
+];
+
+/// What to do with watch paths on virtual filesystems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum VirtualFsPolicy {
+    Error,
+    Skip,
+    Allow,
+}
+
+impl VirtualFsPolicy {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "error" => Ok(VirtualFsPolicy::Error),
+            "skip" => Ok(VirtualFsPolicy::Skip),
+            "allow" => Ok(VirtualFsPolicy::Allow),
+            _ => Err(format!(
+                "Invalid virtual_filesystems value {:?}, must be 'error', 'skip' or 'allow'",
+                s
+            )),
+        }
+    }
+}
+
+/// The mount points in `/proc/self/mounts` and the type of filesystem mounted at each, read once when the watcher
+/// is created and used to check each watch path.
+#[derive(Debug, Default)]
+pub(crate) struct Mounts {
+    // in the order they were mounted
+    mounts: Vec<(String, String)>,
+}
+
+impl Mounts {
+    #[cfg(target_os = "linux")]
+    pub fn read() -> Self {
+        let content = std::fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+        let mounts = content
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let _device = parts.next()?;
+                let mount_point = unescape_mount_field(parts.next()?);
+                let fs_type = parts.next()?;
+                Some((mount_point, fs_type.to_string()))
+            })
+            .collect();
+        Mounts { mounts }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn read() -> Self {
+        Mounts::default()
+    }
+
+    /// The type of the filesystem `path` is on, e.g. "ext4" or "proc", found from the longest matching mount point.
+    pub fn filesystem_type(&self, path: &Path) -> Option<&str> {
+        let path = path.canonicalize().ok()?;
+        self.mounts
+            .iter()
+            .filter(|(mount_point, _)| path.starts_with(mount_point))
+            // later mounts over the same point hide earlier ones, `max_by_key` returns the last of equal elements
+            .max_by_key(|(mount_point, _)| mount_point.len())
+            .map(|(_, fs_type)| fs_type.as_str())
+    }
+
+    /// The filesystem type if `path` is on a virtual filesystem like procfs or sysfs.
+    pub fn virtual_filesystem_type(&self, path: &Path) -> Option<&str> {
+        self.filesystem_type(path)
+            .filter(|fs_type| VIRTUAL_FILESYSTEMS.contains(fs_type))
+    }
+
+    /// The filesystem type if `path` is on a network or overlay filesystem, where native notifications are
+    /// unreliable.
+    pub fn remote_filesystem_type(&self, path: &Path) -> Option<&str> {
+        self.filesystem_type(path)
+            .filter(|fs_type| REMOTE_FILESYSTEMS.contains(fs_type))
+    }
+}
+
+/// How to watch paths on network and overlay filesystems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RemoteFsPolicy {
+    Native,
+    Poll,
+}
+
+impl RemoteFsPolicy {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "native" => Ok(RemoteFsPolicy::Native),
+            "poll" => Ok(RemoteFsPolicy::Poll),
+            _ => Err(format!(
+                "Invalid network_filesystems value {:?}, must be 'native' or 'poll'",
+                s
+            )),
+        }
+    }
+}
+
+/// Spaces, tabs, newlines and backslashes in mount points are escaped as octal, e.g. `\040`.
+#[cfg(target_os = "linux")]
+fn unescape_mount_field(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut chars = field.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let octal: String = chars.clone().take(3).collect();
+            if octal.len() == 3 {
+                if let Ok(byte) = u8::from_str_radix(&octal, 8) {
+                    result.push(byte as char);
+                    chars.nth(2);
+                    continue;
+                }
+            }
+        }
+        result.push(c);
+    }
+    result
+}
@@ -0,0 +1,201 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyCFunction, PyDict, PyTuple};
+
+use crate::{RustNotify, Step, WatchSteps};
+
+/// The `watch_async()` call being awaited, if any, shared with the callback run in the event loop each time a
+/// batch starts.
+pub(crate) type Awaiting = Arc<Mutex<Option<Py<WatchFuture>>>>;
+
+/// A call to `watch_async()`, taking the steps `watch()` would as callbacks in the event loop's thread rather than
+/// in a thread of its own, then resolving its future with the result.
+#[pyclass]
+pub(crate) struct WatchFuture {
+    watcher: Py<RustNotify>,
+    awaiting: Awaiting,
+    event_loop: PyObject,
+    future: PyObject,
+    // taken while a step is being taken
+    steps: Option<WatchSteps>,
+    // the handle of the next step, cancelled if the first change of a batch arrives first
+    next_step: Option<PyObject>,
+    // once the batch has settled, the next step takes it
+    lingering: bool,
+}
+
+impl WatchFuture {
+    /// Start watching in `event_loop`, returning the future which is resolved with what `watch()` would return.
+    pub fn start(watcher: &Bound<RustNotify>, event_loop: &Bound<PyAny>, steps: WatchSteps) -> PyResult<PyObject> {
+        let py = watcher.py();
+        let awaiting = watcher.borrow().awaiting.clone();
+        if awaiting.lock().unwrap().is_some() {
+            return Err(PyRuntimeError::new_err(
+                "watch_async() is already being awaited, wait for it to finish or cancel it before calling it again",
+            ));
+        }
+        let registered = match &watcher.borrow().async_wakeups {
+            Some((registered_loop, _)) => registered_loop.bind(py).is(event_loop),
+            None => false,
+        };
+        if !registered {
+            let wakeups = {
+                let this = watcher.borrow();
+                let call_soon_threadsafe = event_loop.getattr("call_soon_threadsafe")?.unbind();
+                this.wakeup.call_soon_on_batch(
+                    call_soon_threadsafe,
+                    wake_callback(py, awaiting.clone())?,
+                    true,
+                    this.debug,
+                )
+            };
+            watcher.borrow_mut().async_wakeups = Some((event_loop.clone().unbind(), wakeups));
+        }
+
+        let future = event_loop.call_method0("create_future")?;
+        let wait_time = steps.wait_time(watcher.borrow().idles());
+        let watch_future = Bound::new(
+            py,
+            WatchFuture {
+                watcher: watcher.clone().unbind(),
+                awaiting: awaiting.clone(),
+                event_loop: event_loop.clone().unbind(),
+                future: future.clone().unbind(),
+                steps: Some(steps),
+                next_step: None,
+                lingering: false,
+            },
+        )?;
+        future.call_method1("add_done_callback", (watch_future.getattr("done")?,))?;
+        // as with `watch()`, the first step is taken once there's something to check, or a batch starts
+        Self::schedule(&watch_future, wait_time)?;
+        *awaiting.lock().unwrap() = Some(watch_future.unbind());
+        Ok(future.unbind())
+    }
+
+    fn schedule(slf: &Bound<Self>, delay: Duration) -> PyResult<()> {
+        let step = slf.getattr("step")?;
+        let mut this = slf.borrow_mut();
+        let handle = this
+            .event_loop
+            .call_method1(slf.py(), "call_later", (delay.as_secs_f64(), step))?;
+        this.next_step = Some(handle);
+        Ok(())
+    }
+
+    fn cancel_next_step(&mut self, py: Python) -> PyResult<()> {
+        if let Some(handle) = self.next_step.take() {
+            handle.call_method0(py, "cancel")?;
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl WatchFuture {
+    /// Take a step, as `watch()` does each time it wakes.
+    fn step(slf: &Bound<Self>, py: Python) -> PyResult<()> {
+        let (watcher, future, lingering, steps) = {
+            let mut this = slf.borrow_mut();
+            this.next_step = None;
+            (
+                this.watcher.clone_ref(py),
+                this.future.clone_ref(py),
+                this.lingering,
+                this.steps.take(),
+            )
+        };
+        // the future has been cancelled, or a step is already being taken
+        let Some(mut steps) = steps else {
+            return Ok(());
+        };
+        if future.call_method0(py, "done")?.is_truthy(py)? {
+            return Ok(());
+        }
+        let watcher = watcher.bind(py);
+        watcher.borrow().step_count.fetch_add(1, Ordering::Relaxed);
+        // `RustNotify` isn't borrowed while this runs, the GIL may be released while taking the batch
+        let step = match lingering {
+            true => steps.finish(watcher, py).map(Step::Return),
+            false => match steps.check(watcher, py) {
+                Ok(Some(result)) => Ok(Step::Return(result)),
+                Ok(None) => steps.settle(watcher, py),
+                Err(err) => Err(err),
+            },
+        };
+        let step = match step {
+            Ok(Step::Settled) if steps.linger_time.is_zero() => steps.finish(watcher, py).map(Step::Return),
+            step => step,
+        };
+        // as with `watch()`, while nothing is pending, this is woken as soon as a change arrives
+        let idle = steps.last_size == 0 && watcher.borrow().idles();
+        let (wait_time, linger_time) = (steps.wait_time(idle), steps.linger_time);
+        slf.borrow_mut().steps = Some(steps);
+        let (method, value) = match step {
+            Ok(Step::Wait) => return Self::schedule(slf, wait_time),
+            Ok(Step::Settled) => {
+                slf.borrow_mut().lingering = true;
+                return Self::schedule(slf, linger_time);
+            }
+            Ok(Step::Return(result)) => ("set_result", result),
+            Err(err) => ("set_exception", err.into_value(py).into_any()),
+        };
+        // another call can start as soon as this one has a result
+        slf.borrow().awaiting.lock().unwrap().take();
+        future.call_method1(py, method, (value,))?;
+        Ok(())
+    }
+
+    /// Called when the first change of a batch arrives, `stop()` is called or an error is reported, until a batch
+    /// has started, a step is taken straight away.
+    fn wake(slf: &Bound<Self>, py: Python) -> PyResult<()> {
+        let waiting = match &slf.borrow().steps {
+            Some(steps) => steps.last_size == 0,
+            None => false,
+        };
+        if waiting && !slf.borrow().lingering {
+            slf.borrow_mut().cancel_next_step(py)?;
+            Self::step(slf, py)?;
+        }
+        Ok(())
+    }
+
+    /// Called once the future is done, cancelling it stops watching as setting `stop_event` would.
+    fn done(slf: &Bound<Self>, py: Python, future: &Bound<PyAny>) -> PyResult<()> {
+        let mut this = slf.borrow_mut();
+        this.cancel_next_step(py)?;
+        this.steps = None;
+        let mut awaiting = this.awaiting.lock().unwrap();
+        if awaiting.as_ref().map_or(false, |current| current.is(slf)) {
+            *awaiting = None;
+        }
+        drop(awaiting);
+        if future.call_method0("cancelled")?.is_truthy()? {
+            this.watcher.borrow(py).clear();
+        }
+        Ok(())
+    }
+}
+
+/// The callback run in the event loop each time a batch starts, `stop()` is called or an error is reported, waking
+/// whichever call is being awaited.
+fn wake_callback(py: Python, awaiting: Awaiting) -> PyResult<PyObject> {
+    let callback = PyCFunction::new_closure_bound(
+        py,
+        None,
+        None,
+        move |args: &Bound<PyTuple>, _kwargs: Option<&Bound<PyDict>>| -> PyResult<()> {
+            let py = args.py();
+            let current = awaiting.lock().unwrap().as_ref().map(|current| current.clone_ref(py));
+            match current {
+                Some(current) => WatchFuture::wake(current.bind(py), py),
+                None => Ok(()),
+            }
+        },
+    )?;
+    Ok(callback.into_any().unbind())
+}
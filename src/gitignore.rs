@@ -0,0 +1,348 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+use crate::glob::Glob;
+use crate::memory::{map_bytes, set_bytes};
+use crate::CHANGE_DELETED;
+
+// as git, so include cycles end
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// One line of a `.gitignore` file.
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    // `!pattern`, re-includes paths excluded by an earlier rule
+    negated: bool,
+    // `pattern/`, only matches directories
+    dir_only: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // patterns with a slash other than at the end are relative to the `.gitignore`'s directory,
+        // others match at any depth below it
+        let pattern = match line.strip_prefix('/') {
+            Some(rest) => rest.to_string(),
+            None if line.contains('/') => line.to_string(),
+            None => format!("**/{}", line),
+        };
+        // invalid patterns are ignored, as git does
+        let glob = Glob::new(&pattern).ok()?;
+        Some(Rule {
+            glob,
+            negated,
+            dir_only,
+        })
+    }
+}
+
+/// `.gitignore` rules for the git repositories containing the watch paths, used to ignore changes to paths
+/// git would ignore.
+///
+/// Rules are read as they're needed and cached per directory, the cache is cleared for a directory when
+/// its `.gitignore` changes.
+#[derive(Debug)]
+pub(crate) struct Gitignore {
+    // the root of the repository containing each watch path, or the watch path itself if it isn't in a repository
+    tops: Vec<PathBuf>,
+    // the global excludes file for each of `tops` which is a repository
+    excludes_files: HashMap<PathBuf, PathBuf>,
+    rules: Mutex<HashMap<PathBuf, Arc<Vec<Rule>>>>,
+    // directories we've seen, so rules which only match directories can be applied to them once they're deleted
+    known_dirs: Mutex<HashSet<PathBuf>>,
+}
+
+impl Gitignore {
+    pub fn new(watch_paths: &[String]) -> Self {
+        let mut tops: Vec<PathBuf> = watch_paths.iter().map(|p| repo_root(Path::new(p))).collect();
+        // deepest first, so a path is matched against the innermost repository
+        tops.sort_by_key(|top| std::cmp::Reverse(top.components().count()));
+        tops.dedup();
+        let excludes_files = tops
+            .iter()
+            .filter(|top| top.join(".git").exists())
+            .filter_map(|top| Some((top.clone(), excludes_file(top)?)))
+            .collect();
+        Gitignore {
+            tops,
+            excludes_files,
+            rules: Mutex::new(HashMap::new()),
+            known_dirs: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Whether git would ignore `path`, either because it matches a rule or because a directory containing it does.
+    ///
+    /// `is_dir` should be provided where the caller already knows, e.g. from the event kind.
+    pub fn is_ignored(&self, change: u8, path: &str, is_dir: Option<bool>) -> bool {
+        let path = Path::new(path);
+        if path.file_name().map_or(false, |name| name == ".gitignore") {
+            if let Some(parent) = path.parent() {
+                self.rules.lock().unwrap().remove(parent);
+            }
+        }
+        let top = match self.tops.iter().find(|top| path.starts_with(top)) {
+            Some(top) => top,
+            None => return false,
+        };
+        if path.ends_with(".git/info/exclude") {
+            self.rules.lock().unwrap().remove(top);
+        }
+        let is_dir = self.is_dir(change, path, is_dir);
+        let mut candidate = top.clone();
+        let components: Vec<_> = path.strip_prefix(top).unwrap_or(path).components().collect();
+        for (index, component) in components.iter().enumerate() {
+            candidate.push(component);
+            let candidate_is_dir = index + 1 < components.len() || is_dir;
+            if self.matches(top, &candidate, candidate_is_dir) == Some(true) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        map_bytes(&self.rules.lock().unwrap(), |dir, rules| {
+            dir.capacity() + rules.capacity() * std::mem::size_of::<Rule>()
+        }) + set_bytes(&self.known_dirs.lock().unwrap(), |dir| dir.capacity())
+    }
+
+    fn is_dir(&self, change: u8, path: &Path, is_dir: Option<bool>) -> bool {
+        let mut known_dirs = self.known_dirs.lock().unwrap();
+        let is_dir = match is_dir {
+            Some(is_dir) => is_dir,
+            None if change == CHANGE_DELETED => known_dirs.contains(path),
+            None => path.is_dir(),
+        };
+        if change == CHANGE_DELETED {
+            known_dirs.remove(path);
+        } else if is_dir {
+            known_dirs.insert(path.to_path_buf());
+        }
+        is_dir
+    }
+
+    /// `Some(true)` if the deepest `.gitignore` with a rule matching `path` ignores it, `Some(false)` if it
+    /// re-includes it and `None` if no rules match.
+    fn matches(&self, top: &Path, path: &Path, is_dir: bool) -> Option<bool> {
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if !current.starts_with(top) {
+                break;
+            }
+            let relative: Vec<_> = path
+                .strip_prefix(current)
+                .ok()?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            let relative = relative.join("/");
+            // within a file the last matching rule wins
+            let rules = self.rules_for(current, current == top);
+            if let Some(rule) = rules
+                .iter()
+                .rev()
+                .find(|rule| (is_dir || !rule.dir_only) && rule.glob.is_match(&relative))
+            {
+                return Some(!rule.negated);
+            }
+            dir = current.parent();
+        }
+        None
+    }
+
+    fn rules_for(&self, dir: &Path, is_top: bool) -> Arc<Vec<Rule>> {
+        let mut cache = self.rules.lock().unwrap();
+        if let Some(rules) = cache.get(dir) {
+            return rules.clone();
+        }
+        let mut rules = Vec::new();
+        // the global excludes file and `.git/info/exclude` apply to the whole repository, in that order of priority,
+        // with a lower priority than `.gitignore` files
+        if is_top {
+            if let Some(excludes_file) = self.excludes_files.get(dir) {
+                rules.extend(read_rules(excludes_file));
+            }
+            rules.extend(read_rules(&dir.join(".git").join("info").join("exclude")));
+        }
+        rules.extend(read_rules(&dir.join(".gitignore")));
+        let rules = Arc::new(rules);
+        cache.insert(dir.to_path_buf(), rules.clone());
+        rules
+    }
+}
+
+fn read_rules(path: &Path) -> Vec<Rule> {
+    match fs::read_to_string(path) {
+        Ok(content) => content.lines().filter_map(Rule::parse).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// `line` without its trailing spaces, other than one escaped with `\`, as git reads `.gitignore` lines.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches(' ');
+    let backslashes = trimmed.len() - trimmed.trim_end_matches('\\').len();
+    match backslashes % 2 == 1 && trimmed.len() < line.len() {
+        // the glob matches `\ ` as a space
+        true => &line[..trimmed.len() + 1],
+        false => trimmed,
+    }
+}
+
+/// The file named by `core.excludesFile`, or when it isn't set, the default of `$XDG_CONFIG_HOME/git/ignore`, as
+/// git does.
+fn excludes_file(top: &Path) -> Option<PathBuf> {
+    let home = env::var_os("HOME").filter(|h| !h.is_empty()).map(PathBuf::from);
+    let xdg_config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".config")));
+    let configured = match git_config_path(top, "core.excludesFile") {
+        Ok(configured) => configured,
+        // without git, its config files are read directly, in order of priority, the repository's config
+        // overrides the user's
+        Err(_) => [
+            Some(top.join(".git").join("config")),
+            home.as_ref().map(|h| h.join(".gitconfig")),
+            xdg_config.as_ref().map(|d| d.join("git").join("config")),
+        ]
+        .iter()
+        .flatten()
+        .find_map(|config| config_value(config, "core", "excludesfile", 0))
+        .map(|file| expand_home(&file, home.as_deref())),
+    };
+    match configured {
+        // relative paths are relative to the working tree, as git runs from there
+        Some(file) => Some(top.join(file)),
+        None => xdg_config.map(|d| d.join("git").join("ignore")),
+    }
+}
+
+/// The path `key` is set to for the repository at `top`, as git itself reads it from the repository's config, the
+/// user's and the system's, in that order of priority. Git follows includes, applies quoting and escapes, and finds
+/// the user's config however it's configured, e.g. with `GIT_CONFIG_GLOBAL`.
+///
+/// An error if git can't be run.
+fn git_config_path(top: &Path, key: &str) -> IOResult<Option<PathBuf>> {
+    let local = top.join(".git").join("config");
+    let scopes = [
+        vec!["--file".as_ref(), local.as_os_str()],
+        vec!["--global".as_ref()],
+        vec!["--system".as_ref()],
+    ];
+    for scope in scopes {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(top)
+            .arg("config")
+            .args(scope)
+            .args(["--includes", "--path", "--get", key])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        // git exits with 1 when the key isn't set
+        if output.status.success() {
+            let value = String::from_utf8_lossy(&output.stdout);
+            return Ok(Some(PathBuf::from(value.trim_end_matches('\n'))));
+        }
+    }
+    Ok(None)
+}
+
+/// The last value of `key` in `[section]` of the git config file at `path`, for when git can't be run. `[include]`s
+/// are followed, `[includeIf]`s aren't since their conditions need git to evaluate them.
+fn config_value(path: &Path, section: &str, key: &str, depth: usize) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut current = String::new();
+    let mut value = None;
+    for line in content.lines().map(str::trim) {
+        if let Some(header) = line.strip_prefix('[') {
+            current = header.split(']').next().unwrap_or_default().trim().to_ascii_lowercase();
+            continue;
+        }
+        let Some((name, raw)) = line.split_once('=') else {
+            continue;
+        };
+        let name = name.trim();
+        if current == "include" && name.eq_ignore_ascii_case("path") && depth < MAX_INCLUDE_DEPTH {
+            let home = env::var_os("HOME").map(PathBuf::from);
+            // relative includes are relative to the file including them
+            let include = path
+                .parent()
+                .unwrap_or(path)
+                .join(expand_home(&parse_config_value(raw), home.as_deref()));
+            if let Some(included) = config_value(&include, section, key, depth + 1) {
+                value = Some(included);
+            }
+        } else if current == section && name.eq_ignore_ascii_case(key) {
+            value = Some(parse_config_value(raw));
+        }
+    }
+    value
+}
+
+/// A git config value as git reads it, quotes are removed, `\` escapes are applied, a `;` or `#` outside quotes
+/// starts a comment and whitespace outside quotes is trimmed from the ends.
+fn parse_config_value(raw: &str) -> String {
+    let mut value = String::new();
+    let mut quoted = false;
+    // the length of `value` without trailing whitespace which wasn't quoted or escaped
+    let mut end = 0;
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('b') => {
+                    value.pop();
+                }
+                Some(escaped) => value.push(escaped),
+                None => (),
+            },
+            ';' | '#' if !quoted => break,
+            c => value.push(c),
+        }
+        if quoted || !c.is_whitespace() {
+            end = value.len();
+        }
+    }
+    value.truncate(end.min(value.len()));
+    value
+}
+
+/// `value` with a leading `~/` replaced by the user's home directory.
+fn expand_home(value: &str, home: Option<&Path>) -> PathBuf {
+    match (value.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(value),
+    }
+}
+
+/// The nearest directory at or above `path` containing `.git`, or `path` itself if there isn't one.
+fn repo_root(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(path)
+        .to_path_buf()
+}
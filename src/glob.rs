@@ -0,0 +1,138 @@
+/// A compiled glob pattern, matched against whole `/` separated paths.
+///
+/// * `?` matches any one character except `/`, `*` any run of characters except `/`
+/// * as a whole path component, `**` matches any run of characters including `/`, `**/` matches zero or more whole
+///   directories, elsewhere `**` is the same as `*`
+/// * `[abc]`, `[a-z]` and `[!a-z]` match one character (not `/`) in, or not in, the set
+/// * `\` escapes the next character, including inside a set
+#[derive(Debug, Clone)]
+pub(crate) struct Glob {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Char(char),
+    AnyChar,
+    Star,
+    AnyDirs,
+    AnyPath,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut index = 0;
+        while index < chars.len() {
+            match chars[index] {
+                '\\' => {
+                    index += 1;
+                    match chars.get(index) {
+                        Some(c) => tokens.push(Token::Char(*c)),
+                        None => return Err(format!("Invalid glob {:?}, ends with an unfinished escape", pattern)),
+                    }
+                }
+                '?' => tokens.push(Token::AnyChar),
+                '*' if chars.get(index + 1) == Some(&'*') => {
+                    let starts_component = index == 0 || chars[index - 1] == '/';
+                    index += 1;
+                    match chars.get(index + 1) {
+                        Some('/') if starts_component => {
+                            index += 1;
+                            tokens.push(Token::AnyDirs);
+                        }
+                        None if starts_component => tokens.push(Token::AnyPath),
+                        // e.g. `a**b` or `foo/**bar`
+                        _ => tokens.push(Token::Star),
+                    }
+                }
+                '*' => tokens.push(Token::Star),
+                '[' => {
+                    let (class, end) = parse_class(&chars, index)
+                        .ok_or_else(|| format!("Invalid glob {:?}, unclosed character class", pattern))?;
+                    tokens.push(class);
+                    index = end;
+                }
+                c => tokens.push(Token::Char(c)),
+            }
+            index += 1;
+        }
+        Ok(Glob { tokens })
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        let text: Vec<char> = path.chars().collect();
+        let len = text.len();
+        // `matches[j]` is whether the tokens processed so far match `text[j..]`, tokens are processed last first
+        let mut matches = vec![false; len + 1];
+        matches[len] = true;
+        // every position is set for each token, so the two rows are swapped rather than allocated per token
+        let mut next = vec![false; len + 1];
+        for token in self.tokens.iter().rev() {
+            // whether the rest of the pattern matches after some `/` at or after each position
+            let mut after_slash = false;
+            for j in (0..=len).rev() {
+                let c = text.get(j).copied();
+                if c == Some('/') && matches[j + 1] {
+                    after_slash = true;
+                }
+                next[j] = match token {
+                    Token::Char(expected) => c == Some(*expected) && matches[j + 1],
+                    Token::AnyChar => c.map_or(false, |c| c != '/') && matches[j + 1],
+                    Token::Class { negated, ranges } => {
+                        c.map_or(false, |c| c != '/' && in_ranges(ranges, c) != *negated) && matches[j + 1]
+                    }
+                    // match nothing, or one more character then whatever the star matches after it
+                    Token::Star => matches[j] || (c.map_or(false, |c| c != '/') && next[j + 1]),
+                    Token::AnyPath => matches[j] || (c.is_some() && next[j + 1]),
+                    Token::AnyDirs => matches[j] || after_slash,
+                };
+            }
+            std::mem::swap(&mut matches, &mut next);
+        }
+        matches[0]
+    }
+}
+
+fn in_ranges(ranges: &[(char, char)], c: char) -> bool {
+    ranges.iter().any(|(low, high)| *low <= c && c <= *high)
+}
+
+/// Parse the character class starting at `chars[start]`, returns the class and the index of its closing `]`.
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut index = start + 1;
+    let negated = matches!(chars.get(index), Some('!') | Some('^'));
+    if negated {
+        index += 1;
+    }
+    let mut ranges = Vec::new();
+    // a `]` straight after the opening `[` is part of the class
+    let first = index;
+    loop {
+        if chars.get(index) == Some(&']') && index > first {
+            return Some((Token::Class { negated, ranges }, index));
+        }
+        let (low, after) = class_char(chars, index)?;
+        match (chars.get(after), chars.get(after + 1)) {
+            (Some('-'), Some(&next)) if next != ']' => {
+                let (high, after) = class_char(chars, after + 1)?;
+                ranges.push((low, high));
+                index = after;
+            }
+            _ => {
+                ranges.push((low, low));
+                index = after;
+            }
+        }
+    }
+}
+
+/// The character at `chars[index]` in a class, which may be escaped with `\`, and the index after it.
+fn class_char(chars: &[char], index: usize) -> Option<(char, usize)> {
+    match *chars.get(index)? {
+        '\\' => Some((*chars.get(index + 1)?, index + 2)),
+        c => Some((c, index + 1)),
+    }
+}
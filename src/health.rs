@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use notify::event::{EventKind, ModifyKind, RenameMode};
+
+/// Probe files are named with this prefix, events for them are never reported as changes.
+const PROBE_PREFIX: &str = ".watchfiles-health-check-";
+
+// how long after a check modify events for the directories probes were written in are still ignored, the poll
+// watcher reports the directory modification alongside the probe being deleted, possibly just after it
+const PROBE_DIR_GRACE: Duration = Duration::from_millis(200);
+
+pub(crate) fn is_probe(path: &str) -> bool {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| name.starts_with(PROBE_PREFIX))
+}
+
+/// Which events have been received for a probe file.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ProbeState {
+    pub created: bool,
+    pub deleted: bool,
+}
+
+/// Probe files written by `health_check()`, and the events received for them.
+#[derive(Debug, Default)]
+pub(crate) struct HealthProbes {
+    probes: Mutex<HashMap<String, ProbeState>>,
+    updated: Condvar,
+    next_id: AtomicU64,
+    // directories containing probes, mapped to when their modify events stop being ignored, `None` while the
+    // probe exists
+    probe_dirs: Mutex<HashMap<String, Option<Instant>>>,
+}
+
+impl HealthProbes {
+    /// A new, unique, path for a probe file in `dir`.
+    pub fn probe_path(&self, dir: &str) -> String {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{}{}-{}", PROBE_PREFIX, std::process::id(), id);
+        Path::new(dir).join(name).to_string_lossy().to_string()
+    }
+
+    pub fn register(&self, path: &str) {
+        self.probes
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), ProbeState::default());
+        if let Some(dir) = parent_dir(path) {
+            self.probe_dirs.lock().unwrap().insert(dir, None);
+        }
+    }
+
+    pub fn unregister(&self, path: &str) -> ProbeState {
+        self.release_dir(path);
+        self.probes.lock().unwrap().remove(path).unwrap_or_default()
+    }
+
+    /// Stop ignoring modify events for the directory `path` is in shortly, once the probe has been deleted but
+    /// is still registered to receive events.
+    pub fn release_dir(&self, path: &str) {
+        if let Some(dir) = parent_dir(path) {
+            let grace_end = Instant::now() + PROBE_DIR_GRACE;
+            self.probe_dirs.lock().unwrap().insert(dir, Some(grace_end));
+        }
+    }
+
+    /// Whether modify events for `path` should be ignored since they were caused by writing a probe.
+    pub fn is_probe_dir(&self, path: &str) -> bool {
+        match self.probe_dirs.lock().unwrap().get(path) {
+            Some(None) => true,
+            Some(Some(grace_end)) => Instant::now() < *grace_end,
+            None => false,
+        }
+    }
+
+    /// Record an event for a probe file, events for unknown probes (e.g. from a previous check) are ignored.
+    pub fn record(&self, kind: &EventKind, path: &str) {
+        let mut probes = self.probes.lock().unwrap();
+        if let Some(state) = probes.get_mut(path) {
+            match kind {
+                EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => state.deleted = true,
+                _ => state.created = true,
+            }
+            self.updated.notify_all();
+        }
+    }
+
+    /// Wait until `done` is true for all of `paths`, or `deadline` passes.
+    pub fn wait(&self, paths: &[String], deadline: Instant, done: impl Fn(&ProbeState) -> bool) {
+        let mut probes = self.probes.lock().unwrap();
+        loop {
+            let all_done = paths.iter().all(|p| probes.get(p).map_or(true, &done));
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if all_done || remaining == Duration::ZERO {
+                return;
+            }
+            probes = self.updated.wait_timeout(probes, remaining).unwrap().0;
+        }
+    }
+}
+
+fn parent_dir(path: &str) -> Option<String> {
+    Path::new(path)
+        .parent()
+        .and_then(|dir| dir.to_str())
+        .map(|dir| dir.to_string())
+}
@@ -0,0 +1,53 @@
+use std::collections::VecDeque;
+use std::mem::size_of;
+use std::sync::Mutex;
+
+use crate::timestamps::epoch_seconds;
+
+/// A change returned by `watch()`, with when it was returned in seconds since the epoch.
+pub(crate) type HistoryEntry = (f64, u8, String);
+
+/// The most recent changes returned by `watch()`, oldest first, once full the oldest are dropped.
+#[derive(Debug)]
+pub(crate) struct History {
+    max_len: usize,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl History {
+    pub fn new(max_len: usize) -> Self {
+        History {
+            max_len,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Record a batch of changes as returned now.
+    pub fn record<'a>(&self, changes: impl Iterator<Item = (u8, &'a str)>) {
+        let timestamp = epoch_seconds();
+        let mut entries = self.entries.lock().unwrap();
+        for (change, path) in changes {
+            if entries.len() == self.max_len {
+                entries.pop_front();
+            }
+            entries.push_back((timestamp, change, path.to_string()));
+        }
+    }
+
+    /// Changes returned between `start` and `end` inclusive, oldest first.
+    pub fn between(&self, start: f64, end: f64) -> Vec<HistoryEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(timestamp, _, _)| start <= *timestamp && *timestamp <= end)
+            .cloned()
+            .collect()
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        let entries = self.entries.lock().unwrap();
+        entries.capacity() * size_of::<HistoryEntry>()
+            + entries.iter().map(|(_, _, path)| path.capacity()).sum::<usize>()
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use walkdir::WalkDir;
+
+use crate::paths::watched_path;
+
+#[derive(Debug, Default)]
+struct LazyState {
+    // directories with their own non-recursive watch
+    registered: HashSet<PathBuf>,
+    // registered directories whose subdirectories are registered too
+    expanded: HashSet<PathBuf>,
+    // paths with events inside a registered directory since `take_due()` was last called
+    active: HashSet<PathBuf>,
+}
+
+/// With `lazy_depth`, watch paths are only registered to that depth when watching starts, deeper directories are
+/// registered once their parent reports activity, so watches aren't spent on parts of a tree which never change.
+#[derive(Debug)]
+pub(crate) struct LazyRegistration {
+    #[cfg_attr(not(feature = "native"), allow(dead_code))]
+    depth: usize,
+    state: Mutex<LazyState>,
+}
+
+impl LazyRegistration {
+    pub fn new(depth: usize) -> Self {
+        LazyRegistration {
+            depth,
+            state: Mutex::new(LazyState::default()),
+        }
+    }
+
+    /// The directories to register for the watch path `path` once it's been registered itself, those within
+    /// `depth` levels of it.
+    #[cfg_attr(not(feature = "native"), allow(dead_code))]
+    pub fn initial(&self, path: &str) -> Vec<PathBuf> {
+        let mut state = self.state.lock().unwrap();
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        for entry in WalkDir::new(watched_path(Path::new(path)))
+            .follow_links(true)
+            .max_depth(self.depth)
+            .into_iter()
+            .flatten()
+        {
+            if !entry.file_type().is_dir() {
+                continue;
+            }
+            if entry.depth() < self.depth {
+                state.expanded.insert(entry.path().to_path_buf());
+            }
+            state.registered.insert(entry.path().to_path_buf());
+            if entry.depth() > 0 {
+                dirs.push(entry.into_path());
+            }
+        }
+        dirs
+    }
+
+    /// Note an event for `path`, called for every event before it's filtered.
+    pub fn record(&self, path: &Path, removed: bool) {
+        let mut state = self.state.lock().unwrap();
+        // the OS removes the watch of a deleted directory, so it needs registering again if it's recreated
+        if removed {
+            state.registered.remove(path);
+            state.expanded.remove(path);
+        }
+        if path.parent().map_or(false, |parent| state.registered.contains(parent)) {
+            state.active.insert(path.to_path_buf());
+        }
+    }
+
+    /// Directories which should be registered now, either because their parent has just reported activity for
+    /// the first time, or because they've been created inside a directory which already has.
+    pub fn take_due(&self) -> Vec<PathBuf> {
+        let mut state = self.state.lock().unwrap();
+        let active = std::mem::take(&mut state.active);
+        let mut due: Vec<PathBuf> = Vec::new();
+        for path in active {
+            let Some(parent) = path.parent() else {
+                continue;
+            };
+            if !state.registered.contains(parent) {
+                continue;
+            }
+            if state.expanded.insert(parent.to_path_buf()) {
+                if let Ok(entries) = fs::read_dir(parent) {
+                    due.extend(entries.flatten().map(|entry| entry.path()).filter(|p| p.is_dir()));
+                }
+            } else if path.is_dir() {
+                due.push(path);
+            }
+        }
+        due.retain(|dir| !state.registered.contains(dir));
+        due.sort();
+        due.dedup();
+        state.registered.extend(due.iter().cloned());
+        due
+    }
+
+    /// Forget `dir` after registering it failed, e.g. because it's been deleted.
+    pub fn failed(&self, dir: &Path) {
+        let mut state = self.state.lock().unwrap();
+        state.registered.remove(dir);
+        state.expanded.remove(dir);
+    }
+}
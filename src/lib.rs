@@ -1,22 +1,126 @@
 extern crate notify;
 extern crate pyo3;
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::ErrorKind as IOErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::{Duration, SystemTime};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
 
 use pyo3::create_exception;
-use pyo3::exceptions::{PyFileNotFoundError, PyOSError, PyPermissionError, PyRuntimeError, PyTypeError};
+use pyo3::exceptions::{
+    PyFileNotFoundError, PyOSError, PyPermissionError, PyRuntimeError, PyRuntimeWarning, PyTypeError, PyValueError,
+};
 use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PySet, PyTuple};
+
+use notify::event::{AccessKind, AccessMode, Event, EventKind, ModifyKind, RenameMode};
+#[cfg(feature = "poll")]
+use notify::PollWatcher;
+use notify::{Config as NotifyConfig, ErrorKind as NotifyErrorKind, RecursiveMode, Result as NotifyResult};
+#[cfg(feature = "native")]
+use notify::{RecommendedWatcher, Watcher};
+
+#[cfg(not(any(feature = "native", feature = "poll")))]
+compile_error!("at least one of the \"native\" and \"poll\" features must be enabled");
+
+mod backpressure;
+mod callback;
+mod capabilities;
+mod clock;
+mod cloud;
+mod collapse;
+mod config;
+mod containment;
+mod contents;
+mod dirs;
+#[cfg(all(target_os = "linux", feature = "fanotify"))]
+mod fanotify;
+mod filter;
+mod fstype;
+mod future;
+mod gitignore;
+mod glob;
+mod health;
+mod history;
+mod lazy;
+mod loops;
+mod memory;
+mod parallel;
+mod partitions;
+mod paths;
+mod pending;
+mod poller;
+mod process;
+mod progress;
+mod ready;
+mod rescan_marker;
+mod result;
+mod roots;
+mod share;
+mod signals;
+mod sizes;
+mod snapshot;
+mod sources;
+mod spill;
+mod stat_retry;
+mod state;
+mod targets;
+mod timestamps;
+mod wakeup;
+mod watch_limits;
+mod watchdog;
 
-use notify::event::{Event, EventKind, ModifyKind, RenameMode};
-use notify::{
-    Config as NotifyConfig, ErrorKind as NotifyErrorKind, PollWatcher, RecommendedWatcher, RecursiveMode,
-    Result as NotifyResult, Watcher,
+use backpressure::Backpressure;
+use callback::CallbackThread;
+use capabilities::{backend_capabilities, Capabilities};
+use clock::{Clock, ManualClock, SystemClock};
+use cloud::CloudPlaceholders;
+use collapse::RenameTracker;
+use config::{AdvancedOptions, EffectiveConfig, FilterConfig};
+use containment::{Containment, ContainmentPolicy};
+use contents::ContentCheck;
+use dirs::DirTracker;
+#[cfg(all(target_os = "linux", feature = "fanotify"))]
+use fanotify::FanotifyWatcher;
+use filter::{
+    event_kind_is_dir, ChangeFilter, ChangeMask, FileTypes, GlobFilter, MetadataChanges, RegexFilter, RootFilters,
 };
+use fstype::{Mounts, RemoteFsPolicy, VirtualFsPolicy};
+use future::{Awaiting, WatchFuture};
+use gitignore::Gitignore;
+use health::{is_probe, HealthProbes};
+use history::{History, HistoryEntry};
+use lazy::LazyRegistration;
+use loops::{parse_loop_error, SymlinkLoops};
+use memory::MemoryStats;
+use parallel::map_parallel;
+use partitions::PollPartitions;
+use paths::Canonicalizer;
+use pending::{InsertHook, PendingChanges};
+use poller::AdaptivePolling;
+#[cfg(feature = "poll")]
+use poller::Poller;
+use process::{ProcessInfo, ProcessTracker};
+use progress::RegistrationProgress;
+use rescan_marker::RescanMarker;
+use result::WatchResult;
+use roots::{covering_path, find_aliases, RemovedRoots, RootDeletedPolicy, RootMonitor, Roots};
+use share::{ShareClient, ShareHost, ShareRole};
+use sizes::{SizeChange, SizeTracker};
+use snapshot::Snapshot;
+use sources::{SourceTracker, SOURCE_RESCAN, SOURCE_ROOT_CHECK, SOURCE_SCAN, SOURCE_SHARED, SOURCE_SWITCH};
+use spill::Spill;
+use stat_retry::StatRetry;
+use state::{RootState, WatchState};
+use targets::FileTargets;
+use timestamps::TimestampTracker;
+use wakeup::{LoopWakeups, Wakeup};
+use watch_limits::{WatchLimit, WatchLimitPolicy};
+use watchdog::Watchdog;
 
 create_exception!(
     _rust_notify,
@@ -24,37 +128,504 @@ create_exception!(
     PyRuntimeError,
     "Internal or filesystem error."
 );
+create_exception!(
+    _rust_notify,
+    WatchLimitError,
+    PyOSError,
+    "Raised when the OS limit on inotify watches or instances is reached."
+);
 
 // these need to match `watchfiles/main.py::Change`
 const CHANGE_ADDED: u8 = 1;
 const CHANGE_MODIFIED: u8 = 2;
 const CHANGE_DELETED: u8 = 3;
+// only reported with `rename_pairs`, the change tuple has the new path after the old one
+const CHANGE_RENAMED: u8 = 4;
+// only reported with `close_write`, a file opened for writing was closed
+const CHANGE_WRITTEN: u8 = 5;
+// only reported with `metadata_changes='attribute'`, only the path's metadata changed
+const CHANGE_ATTRIBUTE: u8 = 6;
+// changes under the path may have been missed, so it needs to be rescanned, never removed by `change_mask`
+const CHANGE_RESCAN: u8 = 7;
+
+type Changes = Arc<Mutex<PendingChanges>>;
+
+// number of change tuples created between releasing the GIL when building the result of `watch()`
+const RESULT_CHUNK_SIZE: usize = 10_000;
+// without a timeout, how long `watch()` waits while idle before waking to check anyway, anything happening wakes it
+const IDLE_WAIT: Duration = Duration::from_secs(3_600);
+
+/// An error reported by the underlying watcher, details are attached to the `WatchfilesRustInternalError`
+/// raised by `watch()`.
+#[derive(Debug, Clone)]
+struct WatcherError {
+    message: String,
+    errno: Option<i32>,
+    os_message: Option<String>,
+    paths: Vec<String>,
+}
+
+impl WatcherError {
+    fn new(message: String) -> Self {
+        WatcherError {
+            message,
+            errno: None,
+            os_message: None,
+            paths: Vec::new(),
+        }
+    }
+
+    fn from_notify(error: &notify::Error) -> Self {
+        let (errno, os_message) = match &error.kind {
+            NotifyErrorKind::Io(io_error) => (io_error.raw_os_error(), Some(io_error.to_string())),
+            _ => (None, None),
+        };
+        WatcherError {
+            message: format!("error in underlying watcher: {}", error),
+            errno,
+            os_message,
+            paths: error.paths.iter().map(|p| p.to_string_lossy().to_string()).collect(),
+        }
+    }
+
+    fn to_py(&self, py: Python, backend: &str) -> PyErr {
+        let err = WatchfilesRustInternalError::new_err(self.message.clone());
+        let value = err.value_bound(py);
+        let attrs = [
+            ("errno", self.errno.to_object(py)),
+            ("strerror", self.os_message.to_object(py)),
+            ("paths", PyTuple::new_bound(py, &self.paths).into()),
+            ("backend", backend.to_object(py)),
+        ];
+        for (name, attr) in attrs {
+            if let Err(e) = value.setattr(name, attr) {
+                return e;
+            }
+        }
+        err
+    }
+}
+
+/// Where the watcher's threads leave an error for `watch()` to raise, which is woken to raise it.
+#[derive(Debug, Clone)]
+struct WatcherErrorSlot {
+    error: Arc<Mutex<Option<WatcherError>>>,
+    wakeup: Arc<Wakeup>,
+}
+
+impl WatcherErrorSlot {
+    fn new(wakeup: Arc<Wakeup>) -> Self {
+        WatcherErrorSlot {
+            error: Arc::default(),
+            wakeup,
+        }
+    }
+
+    fn set(&self, error: WatcherError) {
+        *self.error.lock().unwrap() = Some(error);
+        self.wakeup.error();
+    }
+
+    fn get(&self) -> Option<WatcherError> {
+        self.error.lock().unwrap().clone()
+    }
+}
+
+/// What happens to new changes once `max_pending_changes` are buffered.
+#[derive(Debug)]
+enum Overflow {
+    // block the watcher until `watch()` takes the changes
+    Block(Backpressure),
+    // write changes to disk, then move them back into the buffer once `watch()` takes the changes
+    Spill(Spill),
+    // replace the buffered changes with a rescan change for each watch path, dropping further changes until
+    // `watch()` takes them
+    Rescan(RescanMarker),
+}
+
+impl Overflow {
+    /// Call after changes have been taken from the buffer.
+    fn drained(&self, changes: &mut PendingChanges) -> Result<(), String> {
+        match self {
+            Overflow::Block(backpressure) => backpressure.notify_drained(),
+            Overflow::Spill(spill) => spill
+                .refill(changes)
+                .map_err(|e| format!("error reading spilled changes: {}", e))?,
+            Overflow::Rescan(rescan_marker) => rescan_marker.reset(),
+        }
+        Ok(())
+    }
+
+    /// Discard any changes which haven't been added to the buffer.
+    fn clear(&self) {
+        match self {
+            Overflow::Block(backpressure) => backpressure.notify_drained(),
+            Overflow::Spill(spill) => {
+                let _ = spill.clear();
+            }
+            Overflow::Rescan(rescan_marker) => rescan_marker.reset(),
+        }
+    }
+
+    fn close(&self) {
+        match self {
+            Overflow::Block(backpressure) => backpressure.close(),
+            Overflow::Spill(spill) => spill.close(),
+            Overflow::Rescan(_) => (),
+        }
+    }
+}
+
+/// Add a change to the buffer, if `overflow` is set and the buffer is full, first wait for room, spill
+/// the change to disk or replace the buffer with rescan changes.
+fn push_change(changes: &Changes, overflow: &Option<Arc<Overflow>>, change: (u8, String)) -> Result<(), String> {
+    let (change, path) = change;
+    let mut changes = changes.lock().unwrap();
+    if changes.contains(change, &path) {
+        // nothing to wait for, but the change may still need to be recorded as a duplicate
+        changes.insert(change, path);
+        return Ok(());
+    }
+    match overflow.as_deref() {
+        Some(Overflow::Block(backpressure)) => {
+            changes = backpressure.wait_for_room(changes).map_err(|changes| {
+                format!(
+                    "pending changes buffer still full ({} changes) after waiting {:?}",
+                    changes.len(),
+                    backpressure.timeout()
+                )
+            })?;
+        }
+        Some(Overflow::Spill(spill)) if spill.is_full(&changes) => {
+            changes.run_insert_hook(change, &path);
+            return spill
+                .push(change, &path)
+                .map_err(|e| format!("error spilling changes to disk: {}", e));
+        }
+        Some(Overflow::Rescan(rescan_marker)) => {
+            rescan_marker.push(&mut changes, change, path);
+            return Ok(());
+        }
+        _ => (),
+    }
+    changes.insert(change, path);
+    Ok(())
+}
+
+/// Extra elements appended, in this order, to each change tuple returned by `watch()`.
+#[derive(Debug, Default, Clone, Copy)]
+struct ExtraFields {
+    modify_kind: bool,
+    modify_sizes: bool,
+    depth: bool,
+    first_component: bool,
+    source: bool,
+    timestamp: bool,
+    is_dir: bool,
+    process: bool,
+    root: bool,
+}
+
+impl ExtraFields {
+    fn any(&self) -> bool {
+        self.modify_kind
+            || self.modify_sizes
+            || self.depth
+            || self.first_component
+            || self.source
+            || self.timestamp
+            || self.is_dir
+            || self.process
+            || self.root
+    }
+}
+
+/// A change with the details needed for any extra fields, prepared without the GIL.
+#[derive(Debug, Clone)]
+struct PreparedChange {
+    change: u8,
+    path: String,
+    // for renames, where the path was renamed to
+    new_path: Option<String>,
+    size_change: Option<SizeChange>,
+    // depth below the owning watch path and first component below it
+    position: Option<(usize, Option<String>)>,
+    source: Option<&'static str>,
+    timestamp: Option<f64>,
+    is_dir: Option<bool>,
+    // ID of the process which made the change and the user it was running as
+    process: Option<ProcessInfo>,
+    // the innermost watch path containing the change
+    root: Option<String>,
+}
+
+/// Sort `batch` into the order its changes arrived in `order`, with a copy of each change for each time it
+/// arrived if `order` includes duplicates.
+///
+/// Renames are placed where the new path was added, changes which never arrived themselves (e.g. from
+/// `collapse_save_storms`) where their path first had a change, and any others at the end.
+fn arrival_order(batch: Vec<PreparedChange>, order: Vec<(u8, String)>) -> Vec<PreparedChange> {
+    let mut arrivals: HashMap<(u8, String), Vec<usize>> = HashMap::new();
+    let mut first_arrival: HashMap<String, usize> = HashMap::new();
+    for (position, (change, path)) in order.into_iter().enumerate() {
+        first_arrival.entry(path.clone()).or_insert(position);
+        arrivals.entry((change, path)).or_default().push(position);
+    }
+    let mut positioned: Vec<(usize, PreparedChange)> = Vec::with_capacity(batch.len());
+    for prepared in batch {
+        let key = match &prepared.new_path {
+            Some(new_path) => (CHANGE_ADDED, new_path.clone()),
+            None => (prepared.change, prepared.path.clone()),
+        };
+        match arrivals.remove(&key) {
+            Some(positions) => {
+                let (last, others) = positions.split_last().unwrap();
+                positioned.extend(others.iter().map(|position| (*position, prepared.clone())));
+                positioned.push((*last, prepared));
+            }
+            None => {
+                let position = first_arrival.get(&key.1).copied().unwrap_or(usize::MAX);
+                positioned.push((position, prepared));
+            }
+        }
+    }
+    // stable, so changes without an arrival stay in the same order as each other
+    positioned.sort_by_key(|(position, _)| *position);
+    positioned.into_iter().map(|(_, prepared)| prepared).collect()
+}
+
+#[cfg_attr(not(feature = "poll"), allow(dead_code))]
+const POLL_BACKEND: &str = "poll";
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+const NATIVE_BACKEND: &str = "inotify";
+#[cfg(all(target_os = "macos", not(feature = "macos_kqueue")))]
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+const NATIVE_BACKEND: &str = "fsevent";
+#[cfg(target_os = "windows")]
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+const NATIVE_BACKEND: &str = "windows";
+// with `macos_kqueue` notify uses kqueue rather than FSEvents on macOS, as on the BSDs
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    all(target_os = "macos", not(feature = "macos_kqueue")),
+    target_os = "windows"
+)))]
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+const NATIVE_BACKEND: &str = "kqueue";
+#[cfg(all(target_os = "linux", feature = "fanotify"))]
+const FANOTIFY_BACKEND: &str = "fanotify";
+
+/// Handles events from every watcher, along with the name of the backend which produced them.
+type EventHandler = Arc<dyn Fn(NotifyResult<Event>, &'static str) + Send + Sync>;
+
+/// Adapt an event handler which also takes the name of the backend which produced the event to what notify expects.
+fn with_source(handler: EventHandler, source: &'static str) -> impl FnMut(NotifyResult<Event>) + Send + 'static {
+    move |res| handler(res, source)
+}
+
+/// The name of a backend which can be used in this build, "native" means whichever backend is native to this
+/// platform.
+fn available_backend(name: &str) -> PyResult<&'static str> {
+    match name {
+        POLL_BACKEND if cfg!(feature = "poll") => Ok(POLL_BACKEND),
+        name if (name == "native" || name == NATIVE_BACKEND) && cfg!(feature = "native") => Ok(NATIVE_BACKEND),
+        // notify can only be built with one of FSEvents and kqueue on macOS
+        #[cfg(target_os = "macos")]
+        "kqueue" => Err(PyValueError::new_err(
+            "Backend \"kqueue\" is not available, on macOS watchfiles must be built with the \"macos_kqueue\" feature \
+             to use it",
+        )),
+        name => Err(PyValueError::new_err(format!(
+            "Backend {:?} is not available, should be 'poll', 'native' or {:?}",
+            name, NATIVE_BACKEND
+        ))),
+    }
+}
 
 #[allow(dead_code)]
 #[derive(Debug)]
 enum WatcherEnum {
     None,
-    Poll(PollWatcher),
+    #[cfg(feature = "poll")]
+    Poll(Poller),
+    // used when watch paths are polled at different intervals
+    #[cfg(feature = "poll")]
+    MultiPoll(Vec<Poller>),
+    #[cfg(feature = "native")]
     Recommended(RecommendedWatcher),
+    #[cfg(all(target_os = "linux", feature = "fanotify"))]
+    Fanotify(FanotifyWatcher),
+    // changes are received from another process's watcher
+    Shared(ShareClient),
+}
+
+impl WatcherEnum {
+    /// Name of the mechanism used to watch for changes.
+    fn backend(&self) -> &'static str {
+        match self {
+            WatcherEnum::None => "none",
+            #[cfg(feature = "poll")]
+            WatcherEnum::Poll(_) | WatcherEnum::MultiPoll(_) => POLL_BACKEND,
+            #[cfg(feature = "native")]
+            WatcherEnum::Recommended(_) => NATIVE_BACKEND,
+            #[cfg(all(target_os = "linux", feature = "fanotify"))]
+            WatcherEnum::Fanotify(_) => FANOTIFY_BACKEND,
+            WatcherEnum::Shared(client) => client.host_backend,
+        }
+    }
+
+    /// Stop watching `path`, with multiple poll watchers whichever was polling it.
+    fn unwatch(&mut self, path: &Path) -> NotifyResult<()> {
+        match self {
+            WatcherEnum::None => Ok(()),
+            #[cfg(feature = "poll")]
+            WatcherEnum::Poll(watcher) => watcher.unwatch(path),
+            #[cfg(feature = "poll")]
+            WatcherEnum::MultiPoll(watchers) => watchers
+                .iter_mut()
+                .find_map(|w| w.unwatch(path).ok())
+                .ok_or_else(notify::Error::watch_not_found),
+            #[cfg(feature = "native")]
+            WatcherEnum::Recommended(watcher) => watcher.unwatch(path),
+            #[cfg(all(target_os = "linux", feature = "fanotify"))]
+            WatcherEnum::Fanotify(watcher) => watcher.unwatch(path),
+            // the host watches its own paths
+            WatcherEnum::Shared(_) => Ok(()),
+        }
+    }
+
+    /// Whether a path can be unwatched then watched again with `rewatch`, multiple poll watchers only find which
+    /// watcher polls a path by unwatching it, and the host watches paths for shared watchers.
+    fn detachable(&self) -> bool {
+        match self {
+            WatcherEnum::None | WatcherEnum::Shared(_) => false,
+            #[cfg(feature = "poll")]
+            WatcherEnum::MultiPoll(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Watch `path` again, e.g. after it was deleted and recreated.
+    fn rewatch(&mut self, path: &Path, mode: RecursiveMode) -> NotifyResult<()> {
+        match self {
+            WatcherEnum::None => Ok(()),
+            #[cfg(feature = "poll")]
+            WatcherEnum::Poll(watcher) => {
+                let _ = watcher.unwatch(path);
+                watcher.watch(path, mode)
+            }
+            // keep the path with whichever watcher was polling it, so it keeps its poll interval
+            #[cfg(feature = "poll")]
+            WatcherEnum::MultiPoll(watchers) => match watchers.iter_mut().find_map(|w| w.unwatch(path).ok().map(|_| w))
+            {
+                Some(watcher) => watcher.watch(path, mode),
+                None => Ok(()),
+            },
+            #[cfg(feature = "native")]
+            WatcherEnum::Recommended(watcher) => {
+                let _ = watcher.unwatch(path);
+                watcher.watch(path, mode)
+            }
+            #[cfg(all(target_os = "linux", feature = "fanotify"))]
+            WatcherEnum::Fanotify(watcher) => {
+                let _ = watcher.unwatch(path);
+                watcher.watch(path, mode)
+            }
+            WatcherEnum::Shared(_) => Ok(()),
+        }
+    }
 }
 
-#[pyclass]
+#[pyclass(weakref)]
 struct RustNotify {
-    changes: Arc<Mutex<HashSet<(u8, String)>>>,
-    error: Arc<Mutex<Option<String>>>,
+    changes: Changes,
+    error: WatcherErrorSlot,
     debug: bool,
+    recursive: bool,
+    collapse_save_storms: bool,
+    collapse_renames: bool,
+    rename_pairs: bool,
+    rename_tracker: Option<Arc<RenameTracker>>,
+    change_mask: ChangeMask,
+    ordered: bool,
+    canonical_paths: bool,
+    relative_paths: bool,
+    group_by_root: bool,
+    extra_fields: ExtraFields,
+    source_tracker: Option<Arc<SourceTracker>>,
+    timestamp_tracker: Option<Arc<TimestampTracker>>,
+    dir_tracker: Option<Arc<DirTracker>>,
+    process_tracker: Option<Arc<ProcessTracker>>,
+    roots: Roots,
+    containment: Option<Containment>,
+    history: Option<History>,
+    size_tracker: Option<Arc<SizeTracker>>,
+    overflow: Option<Arc<Overflow>>,
+    health_probes: Arc<HealthProbes>,
+    effective_config: EffectiveConfig,
+    symlink_loops: SymlinkLoops,
+    clock: Arc<dyn Clock>,
+    wakeup: Arc<Wakeup>,
+    loop_wakeups: Option<LoopWakeups>,
+    // the loop `watch_async()` was last called from, woken as each batch starts
+    async_wakeups: Option<(PyObject, LoopWakeups)>,
+    awaiting: Awaiting,
+    callback_thread: Option<CallbackThread>,
+    watch_paths: Vec<String>,
+    registration: RegistrationReport,
+    root_monitor: RootMonitor,
+    filter: Arc<RootFilters>,
+    factory: Arc<WatcherFactory>,
     watcher: WatcherEnum,
+    // set when other processes can attach to this watcher via `share`
+    share_host: Option<Arc<ShareHost>>,
+    // watch paths moved to another backend by `switch_backend()`, each with its own watcher
+    switched_roots: HashMap<String, WatcherEnum>,
+    // with `watch_limit='poll'`, poll watchers for the subtrees of watch paths which couldn't be watched natively
+    limit_pollers: HashMap<String, WatcherEnum>,
+    // with `lazy_depth`, which directories the main watcher has registered and which it should register next
+    lazy: Option<Arc<LazyRegistration>>,
+    // with `poll_threads`, which poller in `partition_pollers` polls each subdirectory of the polled watch paths
+    partitions: Option<Arc<PollPartitions>>,
+    partition_pollers: Vec<WatcherEnum>,
+    stat_retry: Option<Arc<StatRetry>>,
+    // paths reported since the last rescan, and the size of the rescan thread's snapshot
+    rescan_seen: Option<Arc<Mutex<HashSet<String>>>>,
+    rescan_snapshot_bytes: Arc<AtomicUsize>,
+    // dropping this sender stops the rescan thread
+    rescan_stop: Option<Sender<()>>,
+    // with `compare_contents_max_size`, dropping this sender stops the thread comparing file contents
+    content_check_stop: Option<Sender<()>>,
+    watchdog: Option<Watchdog>,
+    // watch paths added by `add_path()` with a different `recursive` to the rest
+    recursive_overrides: HashMap<String, bool>,
+    // set by `pause()`, changes are dropped while set
+    paused: Arc<AtomicBool>,
+    // whether `watch()` returns "signal" on SIGTERM, closing the watcher first
+    handle_sigterm: bool,
+    // keep our handlers installed until the watcher is closed
+    signal_claims: Vec<signals::SignalClaim>,
+    // other signals which make `watch()` return "signal"
+    interrupt_signals: Vec<i32>,
+    // events received from the backend since `watch()` last returned
+    raw_events: Arc<AtomicU64>,
+    // how many times `watch()` has woken to check for changes
+    step_count: AtomicU64,
+    // with `acknowledge`, the latest batch returned by `watch()` and its token, kept until passed to `ack()`
+    acknowledge: bool,
+    unacked: Mutex<Option<(u64, PyObject)>>,
+    last_token: AtomicU64,
 }
 
 fn map_watch_error(error: notify::Error) -> PyErr {
     let err_string = error.to_string();
     match error.kind {
         NotifyErrorKind::PathNotFound => return PyFileNotFoundError::new_err(err_string),
-        NotifyErrorKind::Generic(ref err) => {
-            // on Windows, we get a Generic with this message when the path does not exist
-            if err.as_str() == "Input watch path is neither a file nor a directory." {
-                return PyFileNotFoundError::new_err(err_string);
-            }
+        // on Windows, we get a Generic with this message when the path does not exist
+        NotifyErrorKind::Generic(ref err) if err.as_str() == "Input watch path is neither a file nor a directory." => {
+            return PyFileNotFoundError::new_err(err_string);
         }
         NotifyErrorKind::Io(ref io_error) => match io_error.kind() {
             IOErrorKind::NotFound => return PyFileNotFoundError::new_err(err_string),
@@ -66,24 +637,374 @@ fn map_watch_error(error: notify::Error) -> PyErr {
     PyOSError::new_err(format!("{} ({:?})", err_string, error))
 }
 
+/// Whether `error` is due to a path not existing, e.g. a watch path which has been deleted.
+fn is_missing_path_error(error: &notify::Error) -> bool {
+    match &error.kind {
+        NotifyErrorKind::PathNotFound => true,
+        // the poll watcher reports walkdir errors for a missing root as a message without a path
+        NotifyErrorKind::Generic(_) => error.paths.is_empty(),
+        NotifyErrorKind::Io(io_error) => io_error.kind() == IOErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+/// Like `map_watch_error`, but reaching the inotify limits raises `WatchLimitError` with details of the limit and
+/// how many directories watching `watch_paths` needs.
+fn map_watch_limit_error(error: notify::Error, watch_paths: &[String], recursive: bool) -> PyErr {
+    match WatchLimit::reached(&error) {
+        Some(limit) => Python::with_gil(|py| limit.to_py(py, watch_paths, recursive)),
+        None => map_watch_error(error),
+    }
+}
+
+/// Details of any problems registering watch paths.
+#[derive(Debug, Default)]
+struct RegistrationReport {
+    // watch paths which only registered successfully after retrying, and how many retries they needed
+    retried_paths: HashMap<String, u32>,
+    // watch paths which couldn't be watched but were ignored due to `ignore_permission_denied`, and the error
+    skipped_paths: HashMap<String, String>,
+    // watch paths inside another watch path, so not registered separately, and the path covering them
+    nested_paths: HashMap<String, String>,
+    // watch paths referring to the same directory as another watch path, so not registered separately, and
+    // that path, only set with `merge_aliases`
+    aliased_paths: HashMap<String, String>,
+    // watch paths which didn't exist yet with `wait_for_creation`, and the backend from `root_backends` to watch
+    // them with once they do
+    waiting_paths: HashMap<String, Option<&'static str>>,
+    // watch paths only partly watched natively because the inotify limit on watches was reached, with
+    // `watch_limit='poll'`, and the subtrees of each which are polled instead
+    polled_subtrees: HashMap<String, Vec<String>>,
+}
+
+/// What's needed to create another watcher after construction, e.g. for `switch_backend()`.
+struct WatcherFactory {
+    event_handler: EventHandler,
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    poll_delay: Duration,
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    poll_delay_overrides: HashMap<String, Duration>,
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    compare_contents: bool,
+    advanced_options: AdvancedOptions,
+    file_targets: FileTargets,
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    progress: Option<Arc<RegistrationProgress>>,
+    // with `max_poll_delay_ms`, how long each poll watcher waits between scans
+    adaptive: Option<Arc<AdaptivePolling>>,
+}
+
+impl WatcherFactory {
+    /// A new watcher using `backend`, watching only `path`.
+    fn create(&self, backend: &'static str, path: &str, recursive: bool) -> PyResult<WatcherEnum> {
+        let mode = match recursive {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
+        };
+        let (target, mode) = self.file_targets.registration(path, mode);
+        let handler = with_source(self.event_handler.clone(), backend);
+        let watcher = match backend {
+            #[cfg(feature = "poll")]
+            POLL_BACKEND => {
+                let config = poll_config(self.poll_delay(path), self.compare_contents, &self.advanced_options);
+                let mut watcher =
+                    new_poll_watcher(handler, config, &self.progress, &self.adaptive).map_err(map_watch_error)?;
+                watcher.watch(&target, mode).map_err(map_watch_error)?;
+                WatcherEnum::Poll(watcher)
+            }
+            #[cfg(feature = "native")]
+            _ => {
+                let config = self.advanced_options.apply(NotifyConfig::default());
+                let map_error = |error| map_watch_limit_error(error, &[path.to_string()], recursive);
+                let mut watcher = RecommendedWatcher::new(handler, config).map_err(map_error)?;
+                watcher.watch(&target, mode).map_err(map_error)?;
+                WatcherEnum::Recommended(watcher)
+            }
+            #[cfg(not(feature = "native"))]
+            _ => return Err(PyValueError::new_err(format!("Backend {:?} is not available", backend))),
+        };
+        Ok(watcher)
+    }
+
+    /// A poll watcher for `subtrees`, the parts of the watch path `path` which couldn't be watched natively once
+    /// the inotify limit on watches was reached.
+    fn create_subtree_poller(&self, path: &str, subtrees: &[String], recursive: bool) -> PyResult<WatcherEnum> {
+        #[cfg(feature = "poll")]
+        {
+            let mode = match recursive {
+                true => RecursiveMode::Recursive,
+                false => RecursiveMode::NonRecursive,
+            };
+            let config = poll_config(self.poll_delay(path), self.compare_contents, &self.advanced_options);
+            let mut watcher = new_poll_watcher(
+                subtree_event_handler(self.event_handler.clone()),
+                config,
+                &self.progress,
+                &self.adaptive,
+            )
+            .map_err(map_watch_error)?;
+            for subtree in subtrees {
+                watcher.watch(Path::new(subtree), mode).map_err(map_watch_error)?;
+            }
+            Ok(WatcherEnum::Poll(watcher))
+        }
+        #[cfg(not(feature = "poll"))]
+        {
+            let _ = (path, subtrees, recursive);
+            Err(WatchfilesRustInternalError::new_err(
+                "Polling is not available, watchfiles was built without the \"poll\" feature",
+            ))
+        }
+    }
+
+    /// Like `create`, but retrying as `retry` allows, and with `poll_on_limit`, polling `path` if the inotify limit
+    /// on watches is reached. Returns the number of retries needed and whether the limit was reached too.
+    ///
+    /// Called without the GIL held, so watchers can be created concurrently.
+    fn create_with_retry(
+        &self,
+        backend: &'static str,
+        path: &str,
+        recursive: bool,
+        retry: WatchRetry,
+        poll_on_limit: bool,
+    ) -> (PyResult<WatcherEnum>, u32, bool) {
+        let limit_reached = |err: &PyErr| Python::with_gil(|py| err.is_instance_of::<WatchLimitError>(py));
+        let mut result = self.create(backend, path, recursive);
+        let mut retries: u32 = 0;
+        for delay in retry.delays() {
+            match &result {
+                // retrying won't help if we've hit the OS limit on watches
+                Err(err) if !limit_reached(err) => (),
+                _ => break,
+            }
+            sleep(delay);
+            retries += 1;
+            result = self.create(backend, path, recursive);
+        }
+        match result {
+            Err(err) if poll_on_limit && backend != POLL_BACKEND && limit_reached(&err) => {
+                (self.create(POLL_BACKEND, path, recursive), retries, true)
+            }
+            result => (result, retries, false),
+        }
+    }
+
+    /// How often `path` is polled when it's polled.
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    fn poll_delay(&self, path: &str) -> Duration {
+        self.advanced_options
+            .poll_interval
+            .unwrap_or_else(|| self.poll_delay_overrides.get(path).copied().unwrap_or(self.poll_delay))
+    }
+}
+
+/// A new `PollWatcher`, with `progress` counting the directories found by the initial scan of each path it watches,
+/// and with `adaptive` polled when its scans allow rather than at the interval in `config`.
+#[cfg(feature = "poll")]
+fn new_poll_watcher<F: notify::EventHandler>(
+    event_handler: F,
+    config: NotifyConfig,
+    progress: &Option<Arc<RegistrationProgress>>,
+    adaptive: &Option<Arc<AdaptivePolling>>,
+) -> NotifyResult<Poller> {
+    let config = match adaptive {
+        Some(_) => config.with_manual_polling(),
+        None => config,
+    };
+    let watcher = match progress {
+        Some(progress) => {
+            let progress = progress.clone();
+            PollWatcher::with_initial_scan(event_handler, config, move |scanned: NotifyResult<PathBuf>| {
+                if let Ok(path) = scanned {
+                    progress.scanned(&path);
+                }
+            })?
+        }
+        None => PollWatcher::new(event_handler, config)?,
+    };
+    Ok(Poller::new(watcher, adaptive.as_ref()))
+}
+
+/// The event handler for a `PollWatcher` polling subtrees of a watch path, rather than the watch path itself.
+#[cfg(feature = "poll")]
+fn subtree_event_handler(event_handler: EventHandler) -> impl FnMut(NotifyResult<Event>) + Send + 'static {
+    move |res: NotifyResult<Event>| match res {
+        // a subtree which disappears is reported by the watcher of its parent
+        Err(e) if is_missing_path_error(&e) => (),
+        res => event_handler(res, POLL_BACKEND),
+    }
+}
+
+/// With `poll_threads`, the poll watchers sharing the subdirectories of `roots` between them, their initial scans
+/// run concurrently too.
+#[cfg(feature = "poll")]
+fn create_partition_pollers(
+    py: Python,
+    partitions: &PollPartitions,
+    roots: &[&String],
+    event_handler: &EventHandler,
+    config: NotifyConfig,
+    progress: &Option<Arc<RegistrationProgress>>,
+    adaptive: &Option<Arc<AdaptivePolling>>,
+) -> NotifyResult<Vec<WatcherEnum>> {
+    let mut shares: Vec<Vec<PathBuf>> = vec![Vec::new(); partitions.threads()];
+    for root in roots {
+        for (share, dirs) in shares.iter_mut().zip(partitions.split(Path::new(root))) {
+            share.extend(dirs);
+        }
+    }
+    let mut pollers: Vec<Mutex<(Poller, Vec<PathBuf>)>> = Vec::with_capacity(shares.len());
+    for share in shares {
+        let poller = new_poll_watcher(subtree_event_handler(event_handler.clone()), config, progress, adaptive)?;
+        pollers.push(Mutex::new((poller, share)));
+    }
+    py.allow_threads(|| {
+        map_parallel(&pollers, pollers.len(), |poller| {
+            let (poller, share) = &mut *poller.lock().unwrap();
+            for dir in share.iter() {
+                // a directory deleted since it was found is picked up again if it's recreated
+                let _ = poller.watch(dir, RecursiveMode::Recursive);
+            }
+        })
+    });
+    Ok(pollers
+        .into_iter()
+        .map(|poller| WatcherEnum::Poll(poller.into_inner().unwrap().0))
+        .collect())
+}
+
+/// The configuration of a `PollWatcher` polling every `interval`, with `advanced_options` applied last.
+#[cfg(feature = "poll")]
+fn poll_config(interval: Duration, compare_contents: bool, advanced_options: &AdvancedOptions) -> NotifyConfig {
+    let config = NotifyConfig::default()
+        .with_poll_interval(interval)
+        .with_compare_contents(compare_contents);
+    advanced_options.apply(config)
+}
+
+/// Convert a (possibly fractional) number of milliseconds from python into a `Duration`.
+fn duration_from_ms(name: &str, ms: f64) -> PyResult<Duration> {
+    if !ms.is_finite() || ms < 0.0 {
+        return Err(PyValueError::new_err(format!(
+            "{} must be a non-negative number, not {}",
+            name, ms
+        )));
+    }
+    Ok(Duration::from_secs_f64(ms / 1000.0))
+}
+
+const MAX_WATCH_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Bounded exponential backoff used when registering watch paths, or stat-ing paths while polling, fails.
+#[derive(Debug, Clone, Copy)]
+struct WatchRetry {
+    retries: u32,
+    initial_delay: Duration,
+}
+
+impl WatchRetry {
+    fn delays(&self) -> impl Iterator<Item = Duration> {
+        let initial_delay = self.initial_delay;
+        (0..self.retries).map(move |attempt| {
+            initial_delay
+                .checked_mul(2u32.saturating_pow(attempt))
+                .map_or(MAX_WATCH_RETRY_DELAY, |delay| delay.min(MAX_WATCH_RETRY_DELAY))
+        })
+    }
+}
+
 // macro to avoid duplicated code below
 macro_rules! watcher_paths {
-    ($watcher:ident, $paths:ident, $debug:ident, $recursive:ident, $ignore_permission_denied:ident) => {
+    (
+        $py:ident,
+        $watcher:ident,
+        $paths:ident,
+        $debug:ident,
+        $recursive:ident,
+        $ignore_permission_denied:ident,
+        $retry:ident,
+        $report:ident,
+        $file_targets:ident,
+        $poll_on_limit:ident
+    ) => {
         let mode = if $recursive {
             RecursiveMode::Recursive
         } else {
             RecursiveMode::NonRecursive
         };
-        for watch_path in $paths.into_iter() {
-            let result = $watcher.watch(Path::new(&watch_path), mode);
+        // outer paths are watched first so nested paths they already cover can be skipped
+        let mut ordered_paths: Vec<&String> = $paths.iter().collect();
+        ordered_paths.sort_by_key(|p| Path::new(p).components().count());
+        let mut watched_paths: Vec<String> = Vec::with_capacity(ordered_paths.len());
+        for watch_path in ordered_paths {
+            if let Some(target) = $report.aliased_paths.get(watch_path.as_str()) {
+                if $debug {
+                    eprintln!(
+                        "{:?} is an alias of {:?}, changes are reported via that path",
+                        watch_path, target
+                    );
+                }
+                continue;
+            }
+            if $recursive {
+                if let Some(outer) = covering_path(watch_path, &watched_paths) {
+                    if $debug {
+                        eprintln!("{:?} is already watched via {:?}", watch_path, outer);
+                    }
+                    // a path given twice isn't nested in itself
+                    if outer != watch_path {
+                        $report.nested_paths.insert(watch_path.to_string(), outer.clone());
+                    }
+                    continue;
+                }
+            }
+            let (target, target_mode) = $file_targets.registration(watch_path, mode);
+            // the GIL is released so `progress` can be reported while a large tree is registered
+            let mut result = $py.allow_threads(|| $watcher.watch(&target, target_mode));
+            let mut retries: u32 = 0;
+            for delay in $retry.delays() {
+                match &result {
+                    // retrying won't help if we've hit the OS limit on watches
+                    Err(err) if !matches!(err.kind, NotifyErrorKind::MaxFilesWatch) => (),
+                    _ => break,
+                }
+                if $debug {
+                    eprintln!("error watching {:?}, retrying in {:?}: {:?}", watch_path, delay, result);
+                }
+                $py.allow_threads(|| sleep(delay));
+                retries += 1;
+                result = $py.allow_threads(|| $watcher.watch(&target, target_mode));
+            }
+            if retries > 0 {
+                $report.retried_paths.insert(watch_path.to_string(), retries);
+            }
             match result {
+                // the directories watched before the limit was reached stay watched, the rest are polled
+                Err(err) if $poll_on_limit && WatchLimit::reached(&err) == Some(WatchLimit::Watches) => {
+                    let failed = err.paths.first().map(PathBuf::as_path);
+                    let subtrees = watch_limits::unwatched_subtrees(&target, failed, $recursive);
+                    if $debug {
+                        eprintln!(
+                            "inotify watch limit reached watching {:?}, polling {:?}",
+                            watch_path, subtrees
+                        );
+                    }
+                    $report.polled_subtrees.insert(watch_path.to_string(), subtrees);
+                    watched_paths.push(watch_path.to_string());
+                }
                 Err(err) => {
-                    let err = map_watch_error(err);
+                    let err_string = err.to_string();
+                    let err = map_watch_limit_error(err, &$paths, $recursive);
                     if !$ignore_permission_denied {
                         return Err(err);
                     }
+                    if $debug {
+                        eprintln!("ignoring error watching {:?}: {}", watch_path, err_string);
+                    }
+                    $report.skipped_paths.insert(watch_path.to_string(), err_string);
                 }
-                _ => (),
+                _ => watched_paths.push(watch_path.to_string()),
             }
         }
         if $debug {
@@ -105,249 +1026,3214 @@ macro_rules! wf_error {
 #[pymethods]
 impl RustNotify {
     #[new]
+    #[pyo3(signature = (
+        watch_paths,
+        debug,
+        force_polling,
+        poll_delay_ms,
+        recursive,
+        ignore_permission_denied,
+        *,
+        rescan_interval_ms = 0,
+        max_file_size = None,
+        files_only = false,
+        file_types = "all",
+        collapse_save_storms = false,
+        collapse_renames = false,
+        modify_kind = false,
+        modify_sizes = false,
+        poll_delay_ms_overrides = None,
+        virtual_filesystems = "allow",
+        scan_new_dirs = false,
+        watch_retries = 0,
+        watch_retry_delay_ms = 50,
+        stat_retries = 0,
+        stat_retry_delay_ms = 10,
+        partial_ok = false,
+        root_deleted = "ignore",
+        max_pending_changes = None,
+        overflow = "block",
+        backpressure_timeout_ms = 5_000,
+        spill_dir = None,
+        clock = None,
+        root_filters = None,
+        depth = false,
+        first_component = false,
+        source = false,
+        advanced_options = None,
+        share = None,
+        path_containment = "off",
+        history_size = 0,
+        merge_aliases = false,
+        ignore_cloud_sync = false,
+        rename_pairs = false,
+        gitignore = false,
+        include_globs = None,
+        exclude_globs = None,
+        allow_regexes = None,
+        deny_regexes = None,
+        change_mask = None,
+        timestamp = false,
+        is_dir = false,
+        ordered = false,
+        keep_duplicates = false,
+        close_write = false,
+        metadata_changes = "modified",
+        fanotify = false,
+        process = false,
+        backend = None,
+        root_backends = None,
+        network_filesystems = "native",
+        watchdog_interval_ms = 0,
+        max_depth = None,
+        follow_symlinks = None,
+        canonical_paths = false,
+        relative_paths = false,
+        root = false,
+        group_by_root = false,
+        file_targets = false,
+        wait_for_creation = false,
+        handle_sigterm = false,
+        interrupt_signals = None,
+        acknowledge = false,
+        watch_limit = "error",
+        lazy_depth = None,
+        registration_threads = 1,
+        progress = None,
+        compare_contents = false,
+        poll_threads = 1,
+        max_poll_delay_ms = None,
+        compare_contents_max_size = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
     fn py_new(
+        py: Python,
         watch_paths: Vec<String>,
         debug: bool,
         force_polling: bool,
-        poll_delay_ms: u64,
+        poll_delay_ms: f64,
         recursive: bool,
         ignore_permission_denied: bool,
+        rescan_interval_ms: u64,
+        max_file_size: Option<u64>,
+        files_only: bool,
+        file_types: &str,
+        collapse_save_storms: bool,
+        collapse_renames: bool,
+        modify_kind: bool,
+        modify_sizes: bool,
+        poll_delay_ms_overrides: Option<HashMap<String, f64>>,
+        virtual_filesystems: &str,
+        scan_new_dirs: bool,
+        watch_retries: u32,
+        watch_retry_delay_ms: u64,
+        stat_retries: u32,
+        stat_retry_delay_ms: u64,
+        partial_ok: bool,
+        root_deleted: &str,
+        max_pending_changes: Option<usize>,
+        overflow: &str,
+        backpressure_timeout_ms: u64,
+        spill_dir: Option<PathBuf>,
+        clock: Option<PyRef<ManualClock>>,
+        root_filters: Option<HashMap<String, Bound<PyDict>>>,
+        depth: bool,
+        first_component: bool,
+        source: bool,
+        advanced_options: Option<Bound<PyDict>>,
+        share: Option<PathBuf>,
+        path_containment: &str,
+        history_size: usize,
+        merge_aliases: bool,
+        ignore_cloud_sync: bool,
+        rename_pairs: bool,
+        gitignore: bool,
+        include_globs: Option<Vec<String>>,
+        exclude_globs: Option<Vec<String>>,
+        allow_regexes: Option<Vec<String>>,
+        deny_regexes: Option<Vec<String>>,
+        change_mask: Option<u8>,
+        timestamp: bool,
+        is_dir: bool,
+        ordered: bool,
+        keep_duplicates: bool,
+        close_write: bool,
+        metadata_changes: &str,
+        fanotify: bool,
+        process: bool,
+        backend: Option<&str>,
+        root_backends: Option<HashMap<String, String>>,
+        network_filesystems: &str,
+        watchdog_interval_ms: u64,
+        max_depth: Option<usize>,
+        follow_symlinks: Option<bool>,
+        canonical_paths: bool,
+        relative_paths: bool,
+        root: bool,
+        group_by_root: bool,
+        file_targets: bool,
+        wait_for_creation: bool,
+        handle_sigterm: bool,
+        interrupt_signals: Option<Vec<i32>>,
+        acknowledge: bool,
+        watch_limit: &str,
+        lazy_depth: Option<usize>,
+        registration_threads: usize,
+        progress: Option<PyObject>,
+        compare_contents: bool,
+        poll_threads: usize,
+        max_poll_delay_ms: Option<f64>,
+        compare_contents_max_size: Option<u64>,
     ) -> PyResult<Self> {
-        let changes: Arc<Mutex<HashSet<(u8, String)>>> = Arc::new(Mutex::new(HashSet::<(u8, String)>::new()));
-        let error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
-
-        let changes_clone = changes.clone();
-        let error_clone = error.clone();
-
-        let event_handler = move |res: NotifyResult<Event>| match res {
-            Ok(event) => {
-                if let Some(path_buf) = event.paths.first() {
-                    let path = match path_buf.to_str() {
-                        Some(s) => s.to_string(),
-                        None => {
-                            let msg = format!("Unable to decode path {:?} to string", path_buf);
-                            *error_clone.lock().unwrap() = Some(msg);
-                            return;
-                        }
-                    };
-                    let change = match event.kind {
-                        EventKind::Create(_) => CHANGE_ADDED,
-                        EventKind::Modify(ModifyKind::Metadata(_))
-                        | EventKind::Modify(ModifyKind::Data(_))
-                        | EventKind::Modify(ModifyKind::Other)
-                        | EventKind::Modify(ModifyKind::Any) => {
-                            // these events sometimes happen when creating files and deleting them, hence these checks
-                            let changes = changes_clone.lock().unwrap();
-                            if changes.contains(&(CHANGE_DELETED, path.clone()))
-                                || changes.contains(&(CHANGE_ADDED, path.clone()))
-                            {
-                                // file was already deleted or file was added in this batch, ignore this event
-                                return;
-                            } else {
-                                CHANGE_MODIFIED
-                            }
-                        }
-                        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => CHANGE_DELETED,
-                        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => CHANGE_ADDED,
-                        // RenameMode::Both duplicates RenameMode::From & RenameMode::To
-                        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => return,
-                        EventKind::Modify(ModifyKind::Name(_)) => {
-                            // On macOS the modify name event is triggered when a file is renamed,
-                            // but no information about whether it's the src or dst path is available.
-                            // Hence we have to check if the file exists instead.
-                            if Path::new(&path).exists() {
-                                CHANGE_ADDED
-                            } else {
-                                CHANGE_DELETED
-                            }
-                        }
-                        EventKind::Remove(_) => CHANGE_DELETED,
-                        event_kind => {
-                            if debug {
-                                eprintln!(
-                                    "raw-event={:?} event.kind={:?} no change detected",
-                                    event_kind, event_kind
-                                );
-                            }
-                            return;
-                        }
-                    };
-                    if debug {
-                        eprintln!("raw-event={:?} change={:?}", event, change);
-                    }
-                    changes_clone.lock().unwrap().insert((change, path));
-                } else if debug {
-                    eprintln!("raw-event={:?} no paths found", event);
-                }
-            }
-            Err(e) => {
-                *error_clone.lock().unwrap() = Some(format!("error in underlying watcher: {}", e));
+        if keep_duplicates && !ordered {
+            return Err(PyValueError::new_err("`keep_duplicates` requires `ordered=True`"));
+        }
+        // paths relative to their watch path are the same whether or not they're canonicalized
+        if canonical_paths && relative_paths {
+            return Err(PyValueError::new_err(
+                "`canonical_paths` and `relative_paths` can't be used together",
+            ));
+        }
+        if fanotify && force_polling {
+            return Err(PyValueError::new_err("`fanotify` can't be used with `force_polling`"));
+        }
+        // `force_polling` and `fanotify` are shorthands for choosing a backend
+        let (force_polling, fanotify) = match backend {
+            None => (force_polling, fanotify),
+            Some(_) if force_polling || fanotify => {
+                return Err(PyValueError::new_err(
+                    "`backend` can't be combined with `force_polling` or `fanotify`",
+                ))
             }
+            Some("fanotify") => (false, true),
+            Some(name) => (available_backend(name)? == POLL_BACKEND, false),
         };
-        macro_rules! create_poll_watcher {
-            ($msg_template:literal) => {{
-                if watch_paths.iter().any(|p| !Path::new(p).exists()) {
-                    return Err(PyFileNotFoundError::new_err("No such file or directory"));
-                }
-                let delay = Duration::from_millis(poll_delay_ms);
-                let config = NotifyConfig::default().with_poll_interval(delay);
-                let mut watcher = match PollWatcher::new(event_handler, config) {
-                    Ok(watcher) => watcher,
-                    Err(e) => return wf_error!($msg_template, e),
-                };
-                watcher_paths!(watcher, watch_paths, debug, recursive, ignore_permission_denied);
-                Ok(WatcherEnum::Poll(watcher))
-            }};
+        // deeper directories are registered by the native watcher as activity reaches them
+        if lazy_depth.is_some() && (!recursive || force_polling || fanotify || !cfg!(feature = "native")) {
+            return Err(PyValueError::new_err(
+                "`lazy_depth` requires `recursive=True` and native notifications, it can't be used with \
+                 `force_polling` or `fanotify`",
+            ));
         }
-
-        let watcher: WatcherEnum = match force_polling {
-            true => create_poll_watcher!("Error creating poll watcher: {}"),
-            false => {
-                match RecommendedWatcher::new(event_handler.clone(), NotifyConfig::default()) {
-                    Ok(watcher) => {
-                        let mut watcher = watcher;
-                        watcher_paths!(watcher, watch_paths, debug, recursive, ignore_permission_denied);
-                        Ok(WatcherEnum::Recommended(watcher))
+        if registration_threads == 0 {
+            return Err(PyValueError::new_err("`registration_threads` must be at least 1"));
+        }
+        // each watch path gets its own watcher, which only the poll and native backends support
+        if registration_threads > 1 && (fanotify || lazy_depth.is_some()) {
+            return Err(PyValueError::new_err(
+                "`registration_threads` can't be used with `fanotify` or `lazy_depth`",
+            ));
+        }
+        if poll_threads == 0 {
+            return Err(PyValueError::new_err("`poll_threads` must be at least 1"));
+        }
+        // the subdirectories of each watch path are shared between pollers, all polling at the same interval
+        if poll_threads > 1 && (!recursive || registration_threads > 1 || poll_delay_ms_overrides.is_some()) {
+            return Err(PyValueError::new_err(
+                "`poll_threads` requires `recursive=True`, it can't be used with `registration_threads` or \
+                 `poll_delay_ms_overrides`",
+            ));
+        }
+        let advanced_options = AdvancedOptions::from_py(advanced_options.as_ref())?;
+        let root_deleted_policy = RootDeletedPolicy::from_str(root_deleted).map_err(PyValueError::new_err)?;
+        let file_types = FileTypes::from_str(file_types).map_err(PyValueError::new_err)?;
+        let containment_policy = ContainmentPolicy::from_str(path_containment).map_err(PyValueError::new_err)?;
+        let metadata_changes_mode = MetadataChanges::from_str(metadata_changes).map_err(PyValueError::new_err)?;
+        let virtual_fs_policy = VirtualFsPolicy::from_str(virtual_filesystems).map_err(PyValueError::new_err)?;
+        let remote_fs_policy = RemoteFsPolicy::from_str(network_filesystems).map_err(PyValueError::new_err)?;
+        let watch_limit_policy = WatchLimitPolicy::from_str(watch_limit).map_err(PyValueError::new_err)?;
+        // subtrees can only be polled in builds which can poll
+        let poll_on_limit = cfg!(feature = "poll") && watch_limit_policy == WatchLimitPolicy::Poll;
+        // only read when it's needed, then shared by every check
+        let mounts = match virtual_fs_policy != VirtualFsPolicy::Allow || remote_fs_policy == RemoteFsPolicy::Poll {
+            true => Mounts::read(),
+            false => Mounts::default(),
+        };
+        let mut watch_paths = watch_paths;
+        if virtual_fs_policy != VirtualFsPolicy::Allow {
+            let mut checked_paths = Vec::with_capacity(watch_paths.len());
+            for watch_path in watch_paths {
+                match mounts.virtual_filesystem_type(Path::new(&watch_path)) {
+                    Some(fs_type) if virtual_fs_policy == VirtualFsPolicy::Error => {
+                        return Err(PyOSError::new_err(format!(
+                            "Unable to watch {:?}, it is on a virtual {:?} filesystem, \
+                             use `virtual_filesystems='allow'` to watch it anyway or 'skip' to ignore it",
+                            watch_path, fs_type
+                        )));
                     }
-                    Err(error) => {
-                        match &error.kind {
-                            NotifyErrorKind::Io(io_error) => {
-                                if io_error.raw_os_error() == Some(38) {
-                                    // see https://github.com/samuelcolvin/watchfiles/issues/167
-                                    // we callback to PollWatcher
-                                    if debug {
-                                        eprintln!(
-                                            "IO error using recommend watcher: {:?}, falling back to PollWatcher",
-                                            io_error
-                                        );
-                                    }
-                                    create_poll_watcher!("Error creating fallback poll watcher: {}")
-                                } else {
-                                    wf_error!("Error creating recommended watcher: {}", error)
-                                }
-                            }
-                            _ => {
-                                wf_error!("Error creating recommended watcher: {}", error)
-                            }
-                        }
+                    Some(fs_type) => {
+                        let message = format!(
+                            "Not watching {:?}, it is on a virtual {:?} filesystem, \
+                             use `virtual_filesystems='allow'` to watch it anyway",
+                            watch_path, fs_type
+                        );
+                        PyErr::warn_bound(py, &py.get_type_bound::<PyRuntimeWarning>(), &message, 1)?;
                     }
+                    None => checked_paths.push(watch_path),
                 }
             }
-        }?;
+            watch_paths = checked_paths;
+        }
+
+        let poll_delay = duration_from_ms("poll_delay_ms", poll_delay_ms)?;
+        let mut poll_delay_overrides: HashMap<String, Duration> = HashMap::new();
+        for (path, delay_ms) in poll_delay_ms_overrides.unwrap_or_default() {
+            poll_delay_overrides.insert(path, duration_from_ms("poll_delay_ms_overrides", delay_ms)?);
+        }
+        let mut unknown_overrides: Vec<&String> = poll_delay_overrides
+            .keys()
+            .filter(|p| !watch_paths.contains(p))
+            .collect();
+        if !unknown_overrides.is_empty() {
+            unknown_overrides.sort();
+            return Err(PyValueError::new_err(format!(
+                "poll_delay_ms_overrides contains paths which are not being watched: {:?}",
+                unknown_overrides
+            )));
+        }
+        if compare_contents_max_size.is_some() && !compare_contents {
+            return Err(PyValueError::new_err(
+                "`compare_contents_max_size` requires `compare_contents=True`",
+            ));
+        }
+        // with a maximum size, contents are compared by `ContentCheck` rather than by notify hashing every file
+        let compare_contents_max_size = compare_contents_max_size.filter(|_| compare_contents);
+        let notify_compare_contents = compare_contents && compare_contents_max_size.is_none();
+        // each poller backs off from the poll interval while its scans are slow
+        let adaptive_polling: Option<Arc<AdaptivePolling>> = match max_poll_delay_ms {
+            Some(ms) => {
+                let ceiling = duration_from_ms("max_poll_delay_ms", ms)?;
+                let base = advanced_options.poll_interval.unwrap_or(poll_delay);
+                if ceiling < base {
+                    return Err(PyValueError::new_err(
+                        "`max_poll_delay_ms` must be at least `poll_delay_ms`",
+                    ));
+                }
+                if !poll_delay_overrides.is_empty() {
+                    return Err(PyValueError::new_err(
+                        "`max_poll_delay_ms` can't be used with `poll_delay_ms_overrides`",
+                    ));
+                }
+                Some(Arc::new(AdaptivePolling::new(base, ceiling)))
+            }
+            None => None,
+        };
+
+        // the first process to use a `share` socket hosts the watcher, later processes receive its changes, so
+        // must filter them as the host does
+        let share_role = match &share {
+            Some(share_path) => {
+                let mut root_filters_key: Vec<String> = Vec::new();
+                for (path, filters) in root_filters.iter().flatten() {
+                    let mut items: Vec<String> = filters.iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+                    items.sort();
+                    root_filters_key.push(format!("{:?}:{:?}", path, items));
+                }
+                root_filters_key.sort();
+                let share_filters = [
+                    ("ignore_permission_denied", ignore_permission_denied.to_string()),
+                    ("max_file_size", format!("{:?}", max_file_size)),
+                    ("files_only", files_only.to_string()),
+                    ("file_types", file_types.as_str().to_string()),
+                    ("root_filters", format!("{:?}", root_filters_key)),
+                    ("scan_new_dirs", scan_new_dirs.to_string()),
+                    ("ignore_cloud_sync", ignore_cloud_sync.to_string()),
+                    ("gitignore", gitignore.to_string()),
+                    ("include_globs", format!("{:?}", include_globs)),
+                    ("exclude_globs", format!("{:?}", exclude_globs)),
+                    ("allow_regexes", format!("{:?}", allow_regexes)),
+                    ("deny_regexes", format!("{:?}", deny_regexes)),
+                    ("change_mask", format!("{:?}", change_mask)),
+                    ("close_write", close_write.to_string()),
+                    ("metadata_changes", metadata_changes.to_string()),
+                    ("max_depth", format!("{:?}", max_depth)),
+                    ("follow_symlinks", format!("{:?}", follow_symlinks)),
+                ];
+                Some(
+                    share::connect_or_host(share_path, &watch_paths, recursive, &share_filters).map_err(|e| {
+                        PyOSError::new_err(format!("Unable to share watcher via {:?}: {}", share_path, e))
+                    })?,
+                )
+            }
+            None => None,
+        };
+        let (share_host, share_client) = match share_role {
+            Some(ShareRole::Host(host)) => (Some(host), None),
+            Some(ShareRole::Client(client)) => (None, Some(client)),
+            None => (None, None),
+        };
+        // watch paths given their own backend each get a separate watcher, as with `switch_backend()`
+        let mut root_backend_names: BTreeMap<String, &'static str> = BTreeMap::new();
+        for (root, root_backend) in root_backends.unwrap_or_default() {
+            if !watch_paths.contains(&root) {
+                return Err(PyValueError::new_err(format!(
+                    "root_backends contains a path which is not being watched: {:?}",
+                    root
+                )));
+            }
+            root_backend_names.insert(root, available_backend(&root_backend)?);
+        }
+        // everything is polled anyway with `force_polling`, and clients don't watch paths themselves
+        if remote_fs_policy == RemoteFsPolicy::Poll && !force_polling && share_client.is_none() {
+            for watch_path in watch_paths.iter() {
+                if root_backend_names.contains_key(watch_path) {
+                    continue;
+                }
+                if let Some(fs_type) = mounts.remote_filesystem_type(Path::new(watch_path)) {
+                    if debug {
+                        eprintln!("polling {:?} on {:?} filesystem", watch_path, fs_type);
+                    }
+                    root_backend_names.insert(watch_path.clone(), available_backend(POLL_BACKEND)?);
+                }
+            }
+        }
+        if share_client.is_some() && !root_backend_names.is_empty() {
+            return Err(PyValueError::new_err(
+                "`root_backends` is not supported by clients of a shared watcher",
+            ));
+        }
+        if registration_threads > 1 && share_client.is_some() {
+            return Err(PyValueError::new_err(
+                "`registration_threads` is not supported by clients of a shared watcher",
+            ));
+        }
+        if poll_threads > 1 && share_client.is_some() {
+            return Err(PyValueError::new_err(
+                "`poll_threads` is not supported by clients of a shared watcher",
+            ));
+        }
+        if lazy_depth.is_some() && share_client.is_some() {
+            return Err(PyValueError::new_err(
+                "`lazy_depth` is not supported by clients of a shared watcher",
+            ));
+        }
+        if wait_for_creation && share_client.is_some() {
+            return Err(PyValueError::new_err(
+                "`wait_for_creation` is not supported by clients of a shared watcher",
+            ));
+        }
+        let mut signal_claims = Vec::new();
+        if handle_sigterm {
+            let claim = signals::claim(signals::SIGTERM)
+                .map_err(|e| PyOSError::new_err(format!("error installing SIGTERM handler: {}", e)))?;
+            signal_claims.push(claim);
+        }
+        let mut interrupt_signals = interrupt_signals.unwrap_or_default();
+        interrupt_signals.sort_unstable();
+        interrupt_signals.dedup();
+        for signal in &interrupt_signals {
+            let claim = signals::claim(*signal).map_err(|e| {
+                PyOSError::new_err(format!(
+                    "error installing handler for `interrupt_signals` {}: {}",
+                    signal, e
+                ))
+            })?;
+            signal_claims.push(claim);
+        }
+        let waiting_paths: HashMap<String, Option<&'static str>> = match wait_for_creation {
+            true => watch_paths
+                .iter()
+                .filter(|p| !Path::new(p).exists())
+                .map(|p| (p.clone(), root_backend_names.get(p).copied()))
+                .collect(),
+            false => HashMap::new(),
+        };
+        let main_paths: Vec<String> = watch_paths
+            .iter()
+            .filter(|p| !root_backend_names.contains_key(*p) && !waiting_paths.contains_key(*p))
+            .cloned()
+            .collect();
+        // changes are timestamped as they're added, however they were found
+        let timestamp_tracker: Option<Arc<TimestampTracker>> = match timestamp {
+            true => Some(Arc::new(TimestampTracker::default())),
+            false => None,
+        };
+        let dir_tracker: Option<Arc<DirTracker>> = match is_dir {
+            true => Some(Arc::new(DirTracker::default())),
+            false => None,
+        };
+        let mut insert_hooks: Vec<InsertHook> = Vec::new();
+        if let Some(timestamp_tracker) = &timestamp_tracker {
+            let timestamp_tracker = timestamp_tracker.clone();
+            insert_hooks.push(Arc::new(move |change, path| timestamp_tracker.record(change, path)));
+        }
+        // where the event handler knows whether a path is a directory it records it before adding the change
+        if let Some(dir_tracker) = &dir_tracker {
+            let dir_tracker = dir_tracker.clone();
+            insert_hooks.push(Arc::new(move |change, path| dir_tracker.record(change, path, None)));
+        }
+        let wakeup = Arc::new(Wakeup::default());
+        signals::wake_on_signal(&wakeup);
+        let wakeup_clone = wakeup.clone();
+        insert_hooks.push(Arc::new(move |_, _| wakeup_clone.notify()));
+        let mut pending = PendingChanges::with_insert_hook(Arc::new(move |change, path| {
+            insert_hooks.iter().for_each(|hook| hook(change, path))
+        }));
+        if ordered {
+            pending = pending.with_arrival_order(keep_duplicates);
+        }
+        let changes: Changes = Arc::new(Mutex::new(pending));
+        let error = WatcherErrorSlot::new(wakeup.clone());
+        // paths the watcher has reported since the last rescan, only tracked when rescanning is enabled
+        let seen: Option<Arc<Mutex<HashSet<String>>>> = match rescan_interval_ms {
+            0 => None,
+            _ => Some(Arc::new(Mutex::new(HashSet::new()))),
+        };
+
+        let mut filters_by_root: Vec<(String, ChangeFilter)> = Vec::new();
+        let mut root_filter_configs: Vec<(String, FilterConfig)> = Vec::new();
+        for (root, config) in root_filters.unwrap_or_default() {
+            if !watch_paths.contains(&root) {
+                return Err(PyValueError::new_err(format!(
+                    "root_filters contains a path which is not being watched: {:?}",
+                    root
+                )));
+            }
+            let mut root_max_file_size = max_file_size;
+            let mut root_files_only = files_only;
+            let mut root_file_types = file_types;
+            for (key, value) in config.iter() {
+                match key.extract::<&str>()? {
+                    "max_file_size" => root_max_file_size = value.extract()?,
+                    "files_only" => root_files_only = value.extract()?,
+                    "file_types" => {
+                        root_file_types = FileTypes::from_str(value.extract()?).map_err(PyValueError::new_err)?
+                    }
+                    key => {
+                        return Err(PyValueError::new_err(format!(
+                            "Invalid root_filters key {:?} for {:?}, must be 'max_file_size', 'files_only' or \
+                             'file_types'",
+                            key, root
+                        )))
+                    }
+                }
+            }
+            root_filter_configs.push((
+                root.clone(),
+                FilterConfig {
+                    max_file_size: root_max_file_size,
+                    files_only: root_files_only,
+                    file_types: root_file_types,
+                },
+            ));
+            filters_by_root.push((
+                root,
+                ChangeFilter::new(root_max_file_size, root_files_only, root_file_types),
+            ));
+        }
+        let mut filter = RootFilters::new(
+            ChangeFilter::new(max_file_size, files_only, file_types),
+            filters_by_root,
+        );
+        if gitignore {
+            filter = filter.with_gitignore(Gitignore::new(&watch_paths));
+        }
+        if include_globs.is_some() || exclude_globs.is_some() {
+            let globs = GlobFilter::new(
+                include_globs.as_deref().unwrap_or_default(),
+                exclude_globs.as_deref().unwrap_or_default(),
+                &watch_paths,
+            )
+            .map_err(PyValueError::new_err)?;
+            filter = filter.with_globs(globs);
+        }
+        if allow_regexes.is_some() || deny_regexes.is_some() {
+            let regexes =
+                RegexFilter::new(allow_regexes.as_deref(), deny_regexes.as_deref()).map_err(PyValueError::new_err)?;
+            filter = filter.with_regexes(regexes);
+        }
+        // notify can't limit how deep its watchers go, so changes below `max_depth` are filtered out instead
+        if let Some(max_depth) = max_depth {
+            filter = filter.with_max_depth(max_depth, &watch_paths);
+        }
+        let file_targets_enabled = file_targets;
+        let file_targets = match file_targets {
+            true => FileTargets::new(&watch_paths),
+            false => FileTargets::new(&[]),
+        };
+        // files are watched via their directory, so changes to anything else in it are filtered out
+        if !file_targets.is_empty() {
+            filter = filter.with_file_targets(file_targets.clone());
+        }
+        // notify follows symlinks wherever the OS allows, so not following them means ignoring changes below them
+        if follow_symlinks == Some(false) {
+            filter = filter.without_symlinks(&watch_paths);
+        }
+        let change_mask = match change_mask {
+            Some(mask) => ChangeMask::new(mask).map_err(PyValueError::new_err)?,
+            None => ChangeMask::default(),
+        };
+        // with `rename_pairs` the mask is applied again once renames have been paired
+        filter = filter.with_change_mask(match rename_pairs {
+            true => change_mask.before_pairing(),
+            false => change_mask,
+        });
+        let filter = Arc::new(filter);
+        let extra_fields = ExtraFields {
+            modify_kind,
+            modify_sizes,
+            depth,
+            first_component,
+            source,
+            timestamp,
+            is_dir,
+            process,
+            root,
+        };
+        let size_tracker: Option<Arc<SizeTracker>> = match modify_kind || modify_sizes {
+            true => Some(Arc::new(SizeTracker::default())),
+            false => None,
+        };
+
+        let overflow_mode = overflow.to_string();
+        let spill_dir = spill_dir.unwrap_or_else(std::env::temp_dir);
+        let overflow: Option<Arc<Overflow>> = match (max_pending_changes, overflow) {
+            (Some(0), _) => return Err(PyValueError::new_err("max_pending_changes must be greater than 0")),
+            (Some(max_pending), "block") => Some(Arc::new(Overflow::Block(Backpressure::new(
+                max_pending,
+                Duration::from_millis(backpressure_timeout_ms),
+            )))),
+            (Some(max_pending), "spill") => {
+                let spill = Spill::new(max_pending, &spill_dir).map_err(|e| {
+                    PyOSError::new_err(format!("Unable to create spill file in {:?}: {}", spill_dir, e))
+                })?;
+                Some(Arc::new(Overflow::Spill(spill)))
+            }
+            (Some(max_pending), "rescan") => Some(Arc::new(Overflow::Rescan(RescanMarker::new(
+                max_pending,
+                watch_paths.clone(),
+            )))),
+            (_, "block") | (_, "spill") | (_, "rescan") => None,
+            (_, overflow) => {
+                return Err(PyValueError::new_err(format!(
+                    "Invalid overflow value {:?}, must be 'block', 'spill' or 'rescan'",
+                    overflow
+                )))
+            }
+        };
+
+        let retry = WatchRetry {
+            retries: watch_retries,
+            initial_delay: Duration::from_millis(watch_retry_delay_ms),
+        };
+        let stat_retry: Option<Arc<StatRetry>> = match stat_retries {
+            0 => None,
+            _ => Some(Arc::new(StatRetry::new(WatchRetry {
+                retries: stat_retries,
+                initial_delay: Duration::from_millis(stat_retry_delay_ms),
+            }))),
+        };
+        let mut registration = RegistrationReport {
+            waiting_paths,
+            ..Default::default()
+        };
+        if merge_aliases {
+            registration.aliased_paths = find_aliases(&watch_paths);
+        }
+        let ignore_errors = ignore_permission_denied || partial_ok;
+        // with `registration_threads`, each watch path gets its own watcher, created concurrently below rather than
+        // registered with the main watcher, and with `poll_threads` each is polled in parts, either way watch paths
+        // nested inside another are found here rather than by `watcher_paths!`
+        let mut outer_paths: Vec<String> = Vec::new();
+        if registration_threads > 1 || poll_threads > 1 {
+            let mut ordered_paths: Vec<&String> = main_paths
+                .iter()
+                .filter(|p| !registration.aliased_paths.contains_key(*p))
+                .collect();
+            ordered_paths.sort_by_key(|p| Path::new(p).components().count());
+            for watch_path in ordered_paths {
+                if recursive {
+                    if let Some(outer) = covering_path(watch_path, &outer_paths) {
+                        if outer != watch_path {
+                            registration.nested_paths.insert(watch_path.clone(), outer.clone());
+                        }
+                        continue;
+                    }
+                }
+                outer_paths.push(watch_path.clone());
+            }
+        }
+        let parallel_roots: Vec<String> = match registration_threads {
+            1 => Vec::new(),
+            _ => outer_paths,
+        };
+        let main_paths: Vec<String> = main_paths
+            .into_iter()
+            .filter(|p| !parallel_roots.contains(p) && !registration.nested_paths.contains_key(p))
+            .collect();
+
+        let changes_clone = changes.clone();
+        let error_clone = error.clone();
+        let seen_clone = seen.clone();
+        let filter_clone = filter.clone();
+        let size_tracker_clone = size_tracker.clone();
+        let source_tracker: Option<Arc<SourceTracker>> = match source {
+            true => Some(Arc::new(SourceTracker::default())),
+            false => None,
+        };
+        let source_tracker_clone = source_tracker.clone();
+        let dir_tracker_clone = dir_tracker.clone();
+        // only some backends report which process made each change
+        let process_tracker: Option<Arc<ProcessTracker>> = match process {
+            true => Some(Arc::new(ProcessTracker::default())),
+            false => None,
+        };
+        let process_tracker_clone = process_tracker.clone();
+        let overflow_clone = overflow.clone();
+        let rename_tracker: Option<Arc<RenameTracker>> = match collapse_renames || rename_pairs {
+            true => Some(Arc::new(RenameTracker::default())),
+            false => None,
+        };
+        let rename_tracker_clone = rename_tracker.clone();
+        let health_probes = Arc::new(HealthProbes::default());
+        let health_probes_clone = health_probes.clone();
+        let paused = Arc::new(AtomicBool::new(false));
+        let paused_clone = paused.clone();
+        let raw_events = Arc::new(AtomicU64::new(0));
+        let raw_events_clone = raw_events.clone();
+        let symlink_loops = SymlinkLoops::default();
+        let symlink_loops_clone = symlink_loops.clone();
+        let stat_retry_clone = stat_retry.clone();
+        let cloud_placeholders: Option<CloudPlaceholders> = ignore_cloud_sync.then(CloudPlaceholders::default);
+        // when a root_deleted policy is set, errors about missing watch paths are handled by that instead
+        let root_paths: HashSet<String> = match root_deleted_policy {
+            RootDeletedPolicy::Ignore => HashSet::new(),
+            _ => watch_paths.iter().cloned().collect(),
+        };
+        let removed_roots = RemovedRoots::default();
+        let removed_roots_clone = removed_roots.clone();
+        let rescan_paths = watch_paths.clone();
+        let lazy: Option<Arc<LazyRegistration>> = lazy_depth.map(|depth| Arc::new(LazyRegistration::new(depth)));
+        let lazy_clone = lazy.clone();
+        let partitions: Option<Arc<PollPartitions>> =
+            (poll_threads > 1).then(|| Arc::new(PollPartitions::new(poll_threads)));
+        let partitions_clone = partitions.clone();
+
+        let event_handler: EventHandler = Arc::new(move |res: NotifyResult<Event>, source: &'static str| match res {
+            // the backend dropped events, e.g. inotify's queue overflowed, so changes under the paths are unknown
+            Ok(event) if event.need_rescan() => {
+                if paused_clone.load(Ordering::Relaxed) {
+                    return;
+                }
+                if debug {
+                    eprintln!("raw-event={:?} events were dropped, rescan needed", event);
+                }
+                let paths: Vec<String> = match event.paths.is_empty() {
+                    true => rescan_paths.clone(),
+                    false => event
+                        .paths
+                        .iter()
+                        .map(|path| path.to_string_lossy().to_string())
+                        .collect(),
+                };
+                for path in paths {
+                    if let Err(msg) = push_change(&changes_clone, &overflow_clone, (CHANGE_RESCAN, path)) {
+                        error_clone.set(WatcherError::new(msg));
+                    }
+                }
+            }
+            Ok(event) => {
+                if let Some(lazy) = &lazy_clone {
+                    for path in event.paths.iter() {
+                        lazy.record(path, matches!(event.kind, EventKind::Remove(_)));
+                    }
+                }
+                if let (Some(partitions), EventKind::Create(_)) = (&partitions_clone, &event.kind) {
+                    for path in event.paths.iter() {
+                        partitions.record(path);
+                    }
+                }
+                if let Some(path_buf) = event.paths.first() {
+                    let path = match path_buf.to_str() {
+                        Some(s) => s.to_string(),
+                        None => {
+                            let msg = format!("Unable to decode path {:?} to string", path_buf);
+                            let mut error = WatcherError::new(msg);
+                            error.paths.push(path_buf.to_string_lossy().to_string());
+                            error_clone.set(error);
+                            return;
+                        }
+                    };
+                    if is_probe(&path) {
+                        health_probes_clone.record(&event.kind, &path);
+                        return;
+                    }
+                    // events can still arrive while paused, from watchers which aren't detached or already queued
+                    if paused_clone.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    raw_events_clone.fetch_add(1, Ordering::Relaxed);
+                    // polling only sees modification times, so can't tell metadata changes from other modifications
+                    let metadata_changes = match source {
+                        POLL_BACKEND => MetadataChanges::Modified,
+                        _ => metadata_changes_mode,
+                    };
+                    let change = match event.kind {
+                        EventKind::Create(_) => CHANGE_ADDED,
+                        EventKind::Modify(ModifyKind::Metadata(_)) if metadata_changes == MetadataChanges::Ignore => {
+                            if debug {
+                                eprintln!("raw-event={:?} ignored, only metadata changed", event);
+                            }
+                            return;
+                        }
+                        EventKind::Modify(ModifyKind::Metadata(_))
+                            if metadata_changes == MetadataChanges::Attribute =>
+                        {
+                            // as with modifications, redundant once the path has been added or deleted in the batch
+                            if changes_clone.lock().unwrap().state(&path).supersedes_modified() {
+                                return;
+                            }
+                            CHANGE_ATTRIBUTE
+                        }
+                        EventKind::Modify(ModifyKind::Metadata(_))
+                        | EventKind::Modify(ModifyKind::Data(_))
+                        | EventKind::Modify(ModifyKind::Other)
+                        | EventKind::Modify(ModifyKind::Any) => {
+                            // these events sometimes happen when creating files and deleting them, hence these checks
+                            let changes = changes_clone.lock().unwrap();
+                            if changes.state(&path).supersedes_modified() {
+                                // file was already deleted or file was added in this batch, ignore this event
+                                return;
+                            } else {
+                                CHANGE_MODIFIED
+                            }
+                        }
+                        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => CHANGE_DELETED,
+                        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => CHANGE_ADDED,
+                        // RenameMode::Both duplicates RenameMode::From & RenameMode::To, but links the two paths
+                        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                            if let (Some(rename_tracker), [from, to]) = (&rename_tracker_clone, event.paths.as_slice())
+                            {
+                                if let (Some(from), Some(to)) = (from.to_str(), to.to_str()) {
+                                    rename_tracker.record(from, to);
+                                }
+                            }
+                            return;
+                        }
+                        EventKind::Modify(ModifyKind::Name(_)) => {
+                            // On macOS the modify name event is triggered when a file is renamed,
+                            // but no information about whether it's the src or dst path is available.
+                            // Hence we have to check if the file exists instead.
+                            if Path::new(&path).exists() {
+                                CHANGE_ADDED
+                            } else {
+                                CHANGE_DELETED
+                            }
+                        }
+                        EventKind::Remove(_) => CHANGE_DELETED,
+                        EventKind::Access(AccessKind::Close(AccessMode::Write)) if close_write => CHANGE_WRITTEN,
+                        event_kind => {
+                            if debug {
+                                eprintln!(
+                                    "raw-event={:?} event.kind={:?} no change detected",
+                                    event_kind, event_kind
+                                );
+                            }
+                            return;
+                        }
+                    };
+                    if let (Some(stat_retry), POLL_BACKEND) = (&stat_retry_clone, source) {
+                        let transient = match event.kind {
+                            EventKind::Remove(_) => !stat_retry.confirm_deleted(&path),
+                            EventKind::Create(_) => stat_retry.take_recovered(&path),
+                            _ => false,
+                        };
+                        if transient {
+                            if debug {
+                                eprintln!("raw-event={:?} ignored, caused by a transient stat failure", event);
+                            }
+                            return;
+                        }
+                    }
+                    // the watch on a deleted watch path is gone, even if the path is recreated before it's next checked
+                    if change == CHANGE_DELETED && root_paths.contains(&path) {
+                        removed_roots_clone.lock().unwrap().insert(path.clone());
+                    }
+                    if change == CHANGE_MODIFIED && health_probes_clone.is_probe_dir(&path) {
+                        if debug {
+                            eprintln!("raw-event={:?} ignored, caused by health check", event);
+                        }
+                        return;
+                    }
+                    if let Some(cloud_placeholders) = &cloud_placeholders {
+                        if cloud_placeholders.is_sync_only(change, &path) {
+                            if debug {
+                                eprintln!(
+                                    "raw-event={:?} ignored, only the cloud placeholder's state changed",
+                                    event
+                                );
+                            }
+                            return;
+                        }
+                    }
+                    let is_dir = event_kind_is_dir(&event.kind);
+                    // files written to a new directory before the watcher has started watching it are missed,
+                    // so we add everything found in the directory once we know about it
+                    let scan_new_dir = scan_new_dirs
+                        && recursive
+                        && matches!(event.kind, EventKind::Create(_))
+                        && is_dir.unwrap_or_else(|| path_buf.is_dir());
+                    if scan_new_dir {
+                        for (entry_path, entry_is_dir) in Snapshot::entries_below(&path) {
+                            if filter_clone.allows(CHANGE_ADDED, &entry_path, Some(entry_is_dir)) {
+                                if debug {
+                                    eprintln!("adding {:?} found in new directory {:?}", entry_path, path);
+                                }
+                                if let Some(seen) = &seen_clone {
+                                    seen.lock().unwrap().insert(entry_path.clone());
+                                }
+                                if let Some(source_tracker) = &source_tracker_clone {
+                                    source_tracker.record(CHANGE_ADDED, &entry_path, SOURCE_SCAN);
+                                }
+                                if let Some(dir_tracker) = &dir_tracker_clone {
+                                    dir_tracker.record(CHANGE_ADDED, &entry_path, Some(entry_is_dir));
+                                }
+                                let added = (CHANGE_ADDED, entry_path);
+                                if let Err(msg) = push_change(&changes_clone, &overflow_clone, added) {
+                                    error_clone.set(WatcherError::new(msg));
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    if !filter_clone.allows(change, &path, is_dir) {
+                        if debug {
+                            eprintln!("raw-event={:?} change={:?} ignored by filter", event, change);
+                        }
+                        return;
+                    }
+                    if debug {
+                        eprintln!("raw-event={:?} change={:?}", event, change);
+                    }
+                    if let Some(seen) = &seen_clone {
+                        seen.lock().unwrap().insert(path.clone());
+                    }
+                    if let Some(size_tracker) = &size_tracker_clone {
+                        size_tracker.record(change, &path);
+                    }
+                    if let Some(source_tracker) = &source_tracker_clone {
+                        source_tracker.record(change, &path, source);
+                    }
+                    if let Some(dir_tracker) = &dir_tracker_clone {
+                        dir_tracker.record(change, &path, is_dir);
+                    }
+                    if let (Some(process_tracker), Some(pid)) = (&process_tracker_clone, event.attrs.process_id()) {
+                        process_tracker.record(change, &path, pid);
+                    }
+                    if let Err(msg) = push_change(&changes_clone, &overflow_clone, (change, path)) {
+                        error_clone.set(WatcherError::new(msg));
+                    }
+                } else if debug {
+                    eprintln!("raw-event={:?} no paths found", event);
+                }
+            }
+            Err(e) => {
+                // symlink loops aren't followed, so they're not a reason to stop watching
+                if let NotifyErrorKind::Generic(message) = &e.kind {
+                    if let Some((symlink, ancestor)) = parse_loop_error(message) {
+                        if debug {
+                            eprintln!("symlink loop {:?} -> {:?} not followed", symlink, ancestor);
+                        }
+                        symlink_loops_clone.lock().unwrap().insert(symlink, ancestor);
+                        return;
+                    }
+                }
+                let root_missing = is_missing_path_error(&e)
+                    && match e.paths.is_empty() {
+                        true => root_paths.iter().any(|p| !Path::new(p).exists()),
+                        false => e
+                            .paths
+                            .iter()
+                            .all(|p| p.to_str().map_or(false, |p| root_paths.contains(p))),
+                    };
+                if root_missing {
+                    if debug {
+                        eprintln!("ignoring error for missing watch path: {}", e);
+                    }
+                    return;
+                }
+                // deletions caused by failing to stat paths while polling are confirmed before being reported
+                let stat_failure = matches!(e.kind, NotifyErrorKind::Generic(_) | NotifyErrorKind::Io(_));
+                if stat_failure && stat_retry_clone.is_some() && source == POLL_BACKEND {
+                    if debug {
+                        eprintln!("ignoring error while polling: {}", e);
+                    }
+                    return;
+                }
+                error_clone.set(WatcherError::from_notify(&e));
+            }
+        });
+        // with `progress` or `debug`, how many directories have been registered is reported while registering
+        let progress_enabled = progress.is_some();
+        let registration_progress: Option<Arc<RegistrationProgress>> =
+            (progress.is_some() || debug).then(|| Arc::new(RegistrationProgress::new(progress, debug)));
+        let progress_reporter = registration_progress.as_ref().map(|progress| progress.start());
+
+        #[cfg(feature = "poll")]
+        macro_rules! create_poll_watcher {
+            ($msg_template:literal) => {{
+                for watch_path in main_paths.iter() {
+                    let mut delays = retry.delays();
+                    let mut retries: u32 = 0;
+                    let mut exists = Path::new(watch_path).exists();
+                    while !exists {
+                        match delays.next() {
+                            Some(delay) => {
+                                py.allow_threads(|| sleep(delay));
+                                retries += 1;
+                                exists = Path::new(watch_path).exists();
+                            }
+                            None if partial_ok => break,
+                            None => return Err(PyFileNotFoundError::new_err("No such file or directory")),
+                        }
+                    }
+                    if retries > 0 {
+                        registration.retried_paths.insert(watch_path.to_string(), retries);
+                    }
+                    if !exists {
+                        let err_string = "No such file or directory".to_string();
+                        registration
+                            .skipped_paths
+                            .insert(watch_path.to_string(), err_string);
+                    }
+                }
+                // paths with different poll intervals each need their own PollWatcher
+                let mut groups: BTreeMap<Duration, Vec<String>> = BTreeMap::new();
+                groups.insert(poll_delay, Vec::new());
+                for watch_path in main_paths.iter() {
+                    if registration.skipped_paths.contains_key(watch_path) {
+                        continue;
+                    }
+                    let delay = poll_delay_overrides
+                        .get(watch_path)
+                        .copied()
+                        .unwrap_or(poll_delay);
+                    groups.entry(delay).or_default().push(watch_path.clone());
+                }
+                groups.retain(|delay, group_paths| *delay == poll_delay || !group_paths.is_empty());
+                let mut watchers = Vec::with_capacity(groups.len());
+                // with `poll_threads`, the main watcher polls only the watch paths themselves
+                let group_recursive = recursive && partitions.is_none();
+                for (delay, group_paths) in groups {
+                    let config = poll_config(delay, notify_compare_contents, &advanced_options);
+                    let handler = with_source(event_handler.clone(), POLL_BACKEND);
+                    let mut watcher = match new_poll_watcher(handler, config, &registration_progress, &adaptive_polling)
+                    {
+                        Ok(watcher) => watcher,
+                        Err(e) => return wf_error!($msg_template, e),
+                    };
+                    watcher_paths!(
+                        py,
+                        watcher,
+                        group_paths,
+                        debug,
+                        group_recursive,
+                        ignore_errors,
+                        retry,
+                        registration,
+                        file_targets,
+                        poll_on_limit
+                    );
+                    watchers.push(watcher);
+                }
+                if let Some(size_tracker) = &size_tracker {
+                    size_tracker.activate(&watch_paths, recursive);
+                }
+                match watchers.len() {
+                    1 => PyResult::Ok(WatcherEnum::Poll(watchers.remove(0))),
+                    _ => PyResult::Ok(WatcherEnum::MultiPoll(watchers)),
+                }
+            }};
+        }
+        #[cfg(not(feature = "poll"))]
+        macro_rules! create_poll_watcher {
+            ($msg_template:literal) => {{
+                let _ = (poll_delay, &poll_delay_overrides, &size_tracker);
+                wf_error!("Polling is not available, watchfiles was built without the \"poll\" feature")
+            }};
+        }
+
+        #[cfg(all(target_os = "linux", feature = "fanotify"))]
+        macro_rules! create_fanotify_watcher {
+            () => {{
+                match FanotifyWatcher::new(with_source(event_handler.clone(), FANOTIFY_BACKEND)) {
+                    Ok(watcher) => {
+                        let mut watcher = watcher;
+                        watcher_paths!(
+                            py,
+                            watcher,
+                            main_paths,
+                            debug,
+                            recursive,
+                            ignore_errors,
+                            retry,
+                            registration,
+                            file_targets,
+                            poll_on_limit
+                        );
+                        Ok(WatcherEnum::Fanotify(watcher))
+                    }
+                    // most likely a permission error, fanotify requires the `CAP_SYS_ADMIN` capability
+                    Err(error) => Err(map_watch_error(error)),
+                }
+            }};
+        }
+        #[cfg(not(all(target_os = "linux", feature = "fanotify")))]
+        macro_rules! create_fanotify_watcher {
+            () => {{
+                PyResult::Err(PyValueError::new_err(
+                    "fanotify is only available on Linux, in builds with the \"fanotify\" feature",
+                ))
+            }};
+        }
+
+        // without native notifications we always poll
+        #[cfg(not(feature = "native"))]
+        let watcher: WatcherEnum = match share_client {
+            Some(client) => WatcherEnum::Shared(client),
+            None if fanotify => create_fanotify_watcher!()?,
+            None => {
+                let _ = force_polling;
+                create_poll_watcher!("Error creating poll watcher: {}")?
+            }
+        };
+        #[cfg(feature = "native")]
+        let watcher: WatcherEnum = match (share_client, force_polling) {
+            (Some(client), _) => Ok(WatcherEnum::Shared(client)),
+            (None, true) => create_poll_watcher!("Error creating poll watcher: {}"),
+            (None, false) if fanotify => create_fanotify_watcher!(),
+            (None, false) => {
+                match RecommendedWatcher::new(
+                    with_source(event_handler.clone(), NATIVE_BACKEND),
+                    advanced_options.apply(NotifyConfig::default()),
+                ) {
+                    Ok(watcher) => {
+                        let mut watcher = watcher;
+                        // with `lazy_depth`, each watch path gets its own watch and directories below are
+                        // registered one at a time
+                        let native_recursive = recursive && lazy.is_none();
+                        watcher_paths!(
+                            py,
+                            watcher,
+                            main_paths,
+                            debug,
+                            native_recursive,
+                            ignore_errors,
+                            retry,
+                            registration,
+                            file_targets,
+                            poll_on_limit
+                        );
+                        if let Some(lazy) = &lazy {
+                            for watch_path in main_paths.iter() {
+                                if registration.skipped_paths.contains_key(watch_path)
+                                    || registration.aliased_paths.contains_key(watch_path)
+                                    || registration.polled_subtrees.contains_key(watch_path)
+                                {
+                                    continue;
+                                }
+                                for dir in lazy.initial(watch_path) {
+                                    if watcher.watch(&dir, RecursiveMode::NonRecursive).is_err() {
+                                        lazy.failed(&dir);
+                                    }
+                                }
+                            }
+                        }
+                        Ok(WatcherEnum::Recommended(watcher))
+                    }
+                    Err(error) => {
+                        match &error.kind {
+                            // a backend chosen explicitly isn't swapped for another
+                            NotifyErrorKind::Io(io_error)
+                                if io_error.raw_os_error() == Some(38) && backend.is_none() =>
+                            {
+                                // see https://github.com/samuelcolvin/watchfiles/issues/167
+                                // we callback to PollWatcher
+                                if debug {
+                                    eprintln!(
+                                        "IO error using recommend watcher: {:?}, falling back to PollWatcher",
+                                        io_error
+                                    );
+                                }
+                                create_poll_watcher!("Error creating fallback poll watcher: {}")
+                            }
+                            _ if WatchLimit::reached(&error).is_some() => {
+                                Err(map_watch_limit_error(error, &main_paths, recursive))
+                            }
+                            _ => {
+                                wf_error!("Error creating recommended watcher: {}", error)
+                            }
+                        }
+                    }
+                }
+            }
+        }?;
+
+        #[cfg(feature = "poll")]
+        let partition_pollers: Vec<WatcherEnum> = match &partitions {
+            Some(partitions) if watcher.backend() == POLL_BACKEND => {
+                let roots: Vec<&String> = main_paths
+                    .iter()
+                    .filter(|p| {
+                        !registration.skipped_paths.contains_key(*p)
+                            && !registration.aliased_paths.contains_key(*p)
+                            && !registration.nested_paths.contains_key(*p)
+                    })
+                    .collect();
+                let config = poll_config(poll_delay, notify_compare_contents, &advanced_options);
+                create_partition_pollers(
+                    py,
+                    partitions,
+                    &roots,
+                    &event_handler,
+                    config,
+                    &registration_progress,
+                    &adaptive_polling,
+                )
+                .map_err(map_watch_error)?
+            }
+            _ => Vec::new(),
+        };
+        #[cfg(not(feature = "poll"))]
+        let partition_pollers: Vec<WatcherEnum> = Vec::new();
+
+        if partial_ok && !ignore_permission_denied && !watch_paths.is_empty() {
+            let all_failed = watch_paths.iter().all(|p| registration.skipped_paths.contains_key(p));
+            if all_failed {
+                return Err(PyOSError::new_err(format!(
+                    "Unable to watch any of the watch paths: {:?}",
+                    registration.skipped_paths
+                )));
+            }
+        }
+
+        if follow_symlinks == Some(true) {
+            let backends = root_backend_names.values().copied().chain([watcher.backend()]);
+            for backend in backends {
+                if !Capabilities::for_backend(backend)?.follow_symlinks {
+                    return Err(PyValueError::new_err(format!(
+                        "`follow_symlinks=True` is not supported by the {:?} backend",
+                        backend
+                    )));
+                }
+            }
+        }
+
+        match &watcher {
+            WatcherEnum::Shared(client) => {
+                let changes = changes.clone();
+                let overflow = overflow.clone();
+                let filter = filter.clone();
+                let source_tracker = source_tracker.clone();
+                let error = error.clone();
+                let lost_error = error.clone();
+                let paused = paused.clone();
+                client
+                    .start(
+                        move |change, path| {
+                            if paused.load(Ordering::Relaxed) {
+                                return;
+                            }
+                            // the host's filters have already been applied, this instance's apply as well
+                            if !filter.allows(change, &path, None) {
+                                return;
+                            }
+                            if let Some(source_tracker) = &source_tracker {
+                                source_tracker.record(change, &path, SOURCE_SHARED);
+                            }
+                            if let Err(msg) = push_change(&changes, &overflow, (change, path)) {
+                                error.set(WatcherError::new(msg));
+                            }
+                        },
+                        move |msg| lost_error.set(WatcherError::new(msg)),
+                    )
+                    .map_err(|e| PyOSError::new_err(format!("Unable to receive shared changes: {}", e)))?;
+            }
+            _ => {
+                if let Some(host) = &share_host {
+                    host.start(watcher.backend());
+                }
+            }
+        }
+
+        let polling = (!matches!(watcher, WatcherEnum::Shared(_)) && watcher.backend() == "poll")
+            || root_backend_names.values().any(|b| *b == POLL_BACKEND);
+        let effective_config = EffectiveConfig {
+            watch_paths: watch_paths.clone(),
+            recursive,
+            poll_delay_ms: polling.then_some(poll_delay.as_secs_f64() * 1000.0),
+            poll_delay_ms_overrides: match polling {
+                true => poll_delay_overrides
+                    .iter()
+                    .map(|(path, delay)| (path.clone(), delay.as_secs_f64() * 1000.0))
+                    .collect(),
+                false => HashMap::new(),
+            },
+            rescan_interval_ms,
+            filters: FilterConfig {
+                max_file_size,
+                files_only,
+                file_types,
+            },
+            root_filters: root_filter_configs,
+            collapse_save_storms,
+            collapse_renames,
+            modify_kind,
+            modify_sizes,
+            depth,
+            first_component,
+            source,
+            virtual_filesystems: virtual_filesystems.to_string(),
+            scan_new_dirs,
+            ignore_permission_denied,
+            partial_ok,
+            watch_retries,
+            watch_retry_delay_ms,
+            stat_retries: polling.then_some(stat_retries),
+            stat_retry_delay_ms: polling.then_some(stat_retry_delay_ms),
+            root_deleted: root_deleted.to_string(),
+            max_pending_changes,
+            overflow: max_pending_changes.map(|_| overflow_mode.clone()),
+            backpressure_timeout_ms: match overflow.as_deref() {
+                Some(Overflow::Block(_)) => Some(backpressure_timeout_ms),
+                _ => None,
+            },
+            spill_dir: match overflow.as_deref() {
+                Some(Overflow::Spill(_)) => Some(spill_dir),
+                _ => None,
+            },
+            manual_clock: clock.is_some(),
+            advanced_options: advanced_options.clone(),
+            share_role: match (&share, &watcher) {
+                (None, _) => None,
+                (Some(_), WatcherEnum::Shared(_)) => Some("client"),
+                (Some(_), _) => Some("host"),
+            },
+            share,
+            path_containment: path_containment.to_string(),
+            history_size,
+            merge_aliases,
+            ignore_cloud_sync,
+            rename_pairs,
+            gitignore,
+            include_globs,
+            exclude_globs,
+            allow_regexes,
+            deny_regexes,
+            change_mask: change_mask.bits(),
+            timestamp,
+            is_dir,
+            ordered,
+            keep_duplicates,
+            close_write,
+            metadata_changes: metadata_changes.to_string(),
+            fanotify,
+            process,
+            root_backends: root_backend_names
+                .iter()
+                .map(|(root, backend)| (root.clone(), backend.to_string()))
+                .collect(),
+            network_filesystems: network_filesystems.to_string(),
+            watchdog_interval_ms,
+            max_depth,
+            follow_symlinks: match follow_symlinks {
+                Some(follow_symlinks) => follow_symlinks,
+                None => Capabilities::for_backend(watcher.backend())?.follow_symlinks,
+            },
+            canonical_paths,
+            relative_paths,
+            root,
+            group_by_root,
+            file_targets: file_targets_enabled,
+            wait_for_creation,
+            handle_sigterm,
+            interrupt_signals: interrupt_signals.clone(),
+            acknowledge,
+            watch_limit: watch_limit.to_string(),
+            lazy_depth,
+            registration_threads,
+            progress: progress_enabled,
+            compare_contents: polling.then_some(compare_contents),
+            poll_threads: polling.then_some(poll_threads),
+            max_poll_delay_ms: max_poll_delay_ms.filter(|_| polling),
+            compare_contents_max_size: compare_contents_max_size.filter(|_| polling),
+        };
+        let content_check_stop = match compare_contents_max_size {
+            Some(max_size) if polling => {
+                let main_backend = match &watcher {
+                    WatcherEnum::Shared(_) => None,
+                    watcher => Some(watcher.backend()),
+                };
+                let polled_roots: Vec<String> = watch_paths
+                    .iter()
+                    .filter(|p| root_backend_names.get(*p).copied().or(main_backend) == Some(POLL_BACKEND))
+                    .cloned()
+                    .collect();
+                let interval = advanced_options.poll_interval.unwrap_or(poll_delay);
+                let check = py.allow_threads(|| ContentCheck::new(polled_roots, recursive, max_size));
+                Some(check.spawn(interval, event_handler.clone(), POLL_BACKEND))
+            }
+            _ => None,
+        };
+        let factory = WatcherFactory {
+            event_handler,
+            poll_delay,
+            poll_delay_overrides,
+            compare_contents: notify_compare_contents,
+            advanced_options,
+            file_targets,
+            progress: registration_progress,
+            adaptive: adaptive_polling.clone(),
+        };
+        let mut separate_roots: Vec<(String, &'static str)> = root_backend_names
+            .into_iter()
+            .filter(|(root, _)| !registration.waiting_paths.contains_key(root))
+            .collect();
+        separate_roots.extend(parallel_roots.into_iter().map(|root| (root, watcher.backend())));
+        let created = py.allow_threads(|| {
+            map_parallel(&separate_roots, registration_threads, |(root, root_backend)| {
+                factory.create_with_retry(root_backend, root, recursive, retry, poll_on_limit)
+            })
+        });
+        let mut switched_roots: HashMap<String, WatcherEnum> = HashMap::new();
+        for ((root, root_backend), (result, retries, limit_reached)) in separate_roots.into_iter().zip(created) {
+            if retries > 0 {
+                registration.retried_paths.insert(root.clone(), retries);
+            }
+            if limit_reached && result.is_ok() {
+                registration.polled_subtrees.insert(root.clone(), vec![root.clone()]);
+            }
+            match result {
+                Ok(root_watcher) => {
+                    switched_roots.insert(root, root_watcher);
+                }
+                Err(err) if ignore_errors => {
+                    if debug {
+                        eprintln!("ignoring error watching {:?} with {}: {}", root, root_backend, err);
+                    }
+                    registration.skipped_paths.insert(root, err.to_string());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let mut limit_pollers: HashMap<String, WatcherEnum> = HashMap::new();
+        for (watch_path, subtrees) in registration.polled_subtrees.iter() {
+            let poller = factory.create_subtree_poller(watch_path, subtrees, recursive)?;
+            limit_pollers.insert(watch_path.clone(), poller);
+        }
+        if let Some(progress_reporter) = progress_reporter {
+            progress_reporter.finish(py);
+        }
+
+        let rescan_snapshot_bytes = Arc::new(AtomicUsize::new(0));
+        let rescan_stop = seen.clone().map(|seen| {
+            let interval = Duration::from_millis(rescan_interval_ms);
+            spawn_rescan(
+                watch_paths.clone(),
+                recursive,
+                interval,
+                changes.clone(),
+                overflow.clone(),
+                error.clone(),
+                seen,
+                filter.clone(),
+                source_tracker.clone(),
+                rescan_snapshot_bytes.clone(),
+                paused.clone(),
+                debug,
+            )
+        });
+
+        Ok(RustNotify {
+            changes,
+            error,
+            debug,
+            recursive,
+            collapse_save_storms,
+            collapse_renames,
+            rename_pairs,
+            rename_tracker,
+            change_mask,
+            ordered,
+            canonical_paths,
+            relative_paths,
+            group_by_root,
+            extra_fields,
+            source_tracker,
+            timestamp_tracker,
+            dir_tracker,
+            process_tracker,
+            roots: Roots::new(&watch_paths),
+            containment: match containment_policy {
+                ContainmentPolicy::Off => None,
+                policy => Some(Containment::new(policy, &watch_paths)),
+            },
+            history: match history_size {
+                0 => None,
+                _ => Some(History::new(history_size)),
+            },
+            size_tracker,
+            overflow,
+            health_probes,
+            effective_config,
+            symlink_loops,
+            wakeup,
+            loop_wakeups: None,
+            async_wakeups: None,
+            awaiting: Arc::new(Mutex::new(None)),
+            callback_thread: None,
+            clock: match clock {
+                Some(clock) => clock.state.clone(),
+                None => Arc::new(SystemClock::default()),
+            },
+            root_monitor: RootMonitor::new(root_deleted_policy, &watch_paths, removed_roots),
+            watch_paths,
+            registration,
+            filter,
+            factory: Arc::new(factory),
+            watcher,
+            share_host,
+            switched_roots,
+            limit_pollers,
+            partitions,
+            partition_pollers,
+            lazy,
+            stat_retry,
+            rescan_seen: seen,
+            rescan_snapshot_bytes,
+            rescan_stop,
+            content_check_stop,
+            watchdog: match watchdog_interval_ms {
+                0 => None,
+                ms => Some(Watchdog::new(Duration::from_millis(ms))),
+            },
+            recursive_overrides: HashMap::new(),
+            paused,
+            handle_sigterm,
+            signal_claims,
+            interrupt_signals,
+            raw_events,
+            step_count: AtomicU64::new(0),
+            acknowledge,
+            unacked: Mutex::new(None),
+            last_token: AtomicU64::new(0),
+        })
+    }
+
+    #[pyo3(signature = (
+        debounce_ms,
+        step_ms,
+        timeout_ms,
+        stop_event,
+        *,
+        linger_ms = 0.0,
+        flush_on_timeout = false,
+        structured = false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn watch(
+        slf: &Bound<Self>,
+        py: Python,
+        debounce_ms: f64,
+        step_ms: f64,
+        timeout_ms: f64,
+        stop_event: PyObject,
+        linger_ms: f64,
+        flush_on_timeout: bool,
+        structured: bool,
+    ) -> PyResult<PyObject> {
+        if matches!(slf.borrow().watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        let stop_event_is_set: Option<&PyAny> = match stop_event.is_none(py) {
+            true => None,
+            false => {
+                let event: &PyAny = stop_event.extract(py)?;
+                let func: &PyAny = event.getattr("is_set")?.extract()?;
+                if !func.is_callable() {
+                    return Err(PyTypeError::new_err("'stop_event.is_set' must be callable"));
+                }
+                Some(func)
+            }
+        };
+        if slf.borrow().acknowledge {
+            if !structured {
+                return Err(PyValueError::new_err(
+                    "`acknowledge=True` requires `structured=True`, for the token to acknowledge",
+                ));
+            }
+            // a batch which hasn't been acknowledged is delivered again until it is
+            if let Some((token, changes)) = slf.borrow().unacked.lock().unwrap().as_ref() {
+                let changes = changes.call_method0(py, "copy")?;
+                let result = WatchResult::new("changes", changes, 0.0, 0).with_token(*token);
+                return Ok(Py::new(py, result)?.into_py(py));
+            }
+        }
+
+        // SIGINT is only ours while this call runs, python's handler is restored when the claim is dropped on return
+        let _sigint_claim = signals::claim_if_handled(signals::SIGINT).ok().flatten();
+        // python replaces our handlers whenever `signal.signal()` is called, so they're reinstalled on each call,
+        // without them signals are still checked on each step
+        for claim in &slf.borrow().signal_claims {
+            let _ = claim.reinstall();
+        }
+        // until this is dropped, the signals we handle are recorded for this call rather than terminating the process
+        let mut watched_signals = slf.borrow().interrupt_signals.clone();
+        if slf.borrow().handle_sigterm {
+            watched_signals.push(signals::SIGTERM);
+        }
+        let _watching = signals::watching(&watched_signals);
+        // signals python handles itself only wake this call while python's wakeup fd is ours
+        let python_wakeup = signals::python_wakeup(py);
+        // otherwise they have to be checked for on each step, as does `stop_event`
+        let stepping = python_wakeup.is_none() || stop_event_is_set.is_some();
+
+        let clock = slf.borrow().clock.clone();
+        let wakeup = slf.borrow().wakeup.clone();
+        let mut arrivals = wakeup.arrivals();
+        let mut steps = WatchSteps::new(
+            clock.clone(),
+            debounce_ms,
+            step_ms,
+            timeout_ms,
+            linger_ms,
+            flush_on_timeout,
+            structured,
+        )?;
+        loop {
+            // until a change arrives there's no batch to wait for, so wake as soon as one does, after that sleep
+            // through each step to see whether more changes follow, either way `stop()` wakes us
+            let seen = match steps.last_size {
+                0 => Some(arrivals),
+                _ => None,
+            };
+            let idle = seen.is_some() && !stepping && slf.borrow().idles();
+            let wait_time = steps.wait_time(idle);
+            arrivals = py.allow_threads(|| clock.wait(&wakeup, seen, wait_time));
+            slf.borrow().step_count.fetch_add(1, Ordering::Relaxed);
+            // signals and errors are checked on every step anyway
+            wakeup.take_woken();
+            match py.check_signals() {
+                Ok(_) => (),
+                Err(_) => return steps.end(slf, py, "signal"),
+            };
+            // the process is being terminated, so stop the backend threads rather than leave them running
+            if slf.borrow().handle_sigterm && signals::take(signals::SIGTERM) {
+                slf.borrow().clear();
+                slf.borrow_mut().close();
+                return slf
+                    .borrow()
+                    .watch_result(py, structured, "signal", None, Duration::ZERO);
+            }
+            // every signal received is taken, so none is left to end the next call straight away
+            let interrupted = slf
+                .borrow()
+                .interrupt_signals
+                .iter()
+                .filter(|signal| signals::take(**signal))
+                .count();
+            if interrupted > 0 {
+                return steps.end(slf, py, "signal");
+            }
+
+            if let Some(result) = steps.check(slf, py)? {
+                return Ok(result);
+            }
+            if let Some(is_set) = stop_event_is_set {
+                if is_set.call0()?.is_truthy()? {
+                    if slf.borrow().debug {
+                        eprintln!("stop event set, stopping...");
+                    }
+                    return steps.end(slf, py, "stop");
+                }
+            }
+            match steps.settle(slf, py)? {
+                Step::Wait => (),
+                Step::Settled => break,
+                Step::Return(result) => return Ok(result),
+            }
+        }
+        // changes arriving just after the batch settled, e.g. the rename finishing a save, join this batch
+        if !steps.linger_time.is_zero() {
+            py.allow_threads(|| clock.sleep(steps.linger_time));
+        }
+        steps.finish(slf, py)
+    }
+
+    /// Like `watch()`, but returns an asyncio future which is resolved with the result, with each step taken
+    /// as a callback in the event loop rather than by a thread blocking. Cancelling the future stops watching.
+    #[pyo3(signature = (debounce_ms, step_ms, timeout_ms, *, linger_ms = 0.0, flush_on_timeout = false))]
+    pub fn watch_async(
+        slf: &Bound<Self>,
+        py: Python,
+        debounce_ms: f64,
+        step_ms: f64,
+        timeout_ms: f64,
+        linger_ms: f64,
+        flush_on_timeout: bool,
+    ) -> PyResult<PyObject> {
+        if matches!(slf.borrow().watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        if slf.borrow().acknowledge {
+            return Err(PyValueError::new_err(
+                "`acknowledge=True` requires `structured=True`, for the token to acknowledge",
+            ));
+        }
+        let event_loop = py.import_bound("asyncio")?.call_method0("get_running_loop")?;
+        let steps = WatchSteps::new(
+            slf.borrow().clock.clone(),
+            debounce_ms,
+            step_ms,
+            timeout_ms,
+            linger_ms,
+            flush_on_timeout,
+            false,
+        )?;
+        WatchFuture::start(slf, &event_loop, steps)
+    }
+
+    /// A file descriptor which is readable while changes are pending, for use with `select` or an event loop's
+    /// `add_reader`.
+    pub fn fileno(&self) -> PyResult<i64> {
+        self.ready_handle(cfg!(unix), "fileno() is only supported on unix")
+    }
+
+    /// A Win32 event handle which is signaled while changes are pending, for use with `WaitForMultipleObjects`.
+    pub fn event_handle(&self) -> PyResult<i64> {
+        self.ready_handle(cfg!(windows), "event_handle() is only supported on windows")
+    }
+
+    /// Call `event_loop.call_soon_threadsafe(callback)` from another thread each time changes start being pending,
+    /// replacing any loop registered before.
+    pub fn register_loop(&mut self, py: Python, event_loop: PyObject, callback: PyObject) -> PyResult<()> {
+        if matches!(self.watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        if !callback.bind(py).is_callable() {
+            return Err(PyTypeError::new_err("'callback' must be callable"));
+        }
+        let call_soon_threadsafe = event_loop.getattr(py, "call_soon_threadsafe")?;
+        // checked with the pending changes locked, so a batch can't start unnoticed meanwhile
+        let changes = self.changes.lock().unwrap();
+        self.wakeup.set_ready(changes.len() > 0);
+        self.loop_wakeups = Some(
+            self.wakeup
+                .call_soon_on_batch(call_soon_threadsafe, callback, false, self.debug),
+        );
+        drop(changes);
+        Ok(())
+    }
+
+    /// Call `callback` with each batch of changes from a thread owned by `RustNotify`, which calls `watch()`
+    /// until `set_callback(None)` or `close()` is called, replacing any callback set before.
+    #[pyo3(signature = (callback, *, debounce_ms = 1_600.0, step_ms = 50.0, linger_ms = 0.0))]
+    pub fn set_callback(
+        slf: &Bound<Self>,
+        py: Python,
+        callback: Option<PyObject>,
+        debounce_ms: f64,
+        step_ms: f64,
+        linger_ms: f64,
+    ) -> PyResult<()> {
+        if matches!(slf.borrow().watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        if let Some(callback) = &callback {
+            if !callback.bind(py).is_callable() {
+                return Err(PyTypeError::new_err("'callback' must be callable"));
+            }
+            // check these now, rather than in the thread
+            duration_from_ms("debounce_ms", debounce_ms)?;
+            duration_from_ms("step_ms", step_ms)?;
+            duration_from_ms("linger_ms", linger_ms)?;
+        }
+        // the old callback's `watch()` must have returned before another starts, or it could clear the changes
+        // the new one is waiting for as it stops
+        let previous = slf.borrow_mut().callback_thread.take();
+        if let Some(previous) = previous {
+            py.allow_threads(|| previous.stop());
+        }
+        if let Some(callback) = callback {
+            let callback_thread = CallbackThread::spawn(slf, callback, debounce_ms, step_ms, linger_ms)?;
+            slf.borrow_mut().callback_thread = Some(callback_thread);
+        }
+        Ok(())
+    }
+
+    /// Make `watch()` return `"stop"` straight away, from any thread, or the next call if it isn't running.
+    pub fn stop(&self) {
+        self.wakeup.stop();
+    }
+
+    /// Stop calling the callback passed to `register_loop()`.
+    pub fn unregister_loop(&mut self) {
+        self.loop_wakeups = None;
+    }
+
+    /// Check events are still being received by writing then deleting a probe file in each watched directory.
+    #[pyo3(signature = (timeout_ms = 1_000.0))]
+    pub fn health_check(slf: &Bound<Self>, py: Python, timeout_ms: f64) -> PyResult<HashMap<String, Option<bool>>> {
+        let this = slf.borrow();
+        match this.watcher {
+            WatcherEnum::None => return Err(PyRuntimeError::new_err("RustNotify watcher closed")),
+            // probe events are never passed on by the host
+            WatcherEnum::Shared(_) => {
+                return Err(PyRuntimeError::new_err(
+                    "health_check() is not supported by clients of a shared watcher",
+                ))
+            }
+            _ => (),
+        }
+        let health_probes = this.health_probes.clone();
+        let deadline = Instant::now() + duration_from_ms("timeout_ms", timeout_ms)?;
+
+        let mut report: HashMap<String, Option<bool>> = HashMap::new();
+        let mut probes: Vec<(String, String)> = Vec::new();
+        for watch_path in this.watch_paths.iter() {
+            // we can't write probes into files, or check paths which aren't being watched
+            if this.registration.skipped_paths.contains_key(watch_path) || !Path::new(watch_path).is_dir() {
+                report.insert(watch_path.clone(), None);
+                continue;
+            }
+            // events for an aliased path arrive via the path it's an alias of
+            let probe_dir = this.registration.aliased_paths.get(watch_path).unwrap_or(watch_path);
+            let probe_path = health_probes.probe_path(probe_dir);
+            health_probes.register(&probe_path);
+            match std::fs::File::create(&probe_path) {
+                Ok(_) => probes.push((watch_path.clone(), probe_path)),
+                Err(e) => {
+                    if this.debug {
+                        eprintln!("unable to create health check probe {:?}: {}", probe_path, e);
+                    }
+                    health_probes.unregister(&probe_path);
+                    report.insert(watch_path.clone(), Some(false));
+                }
+            }
+        }
+        drop(this);
+
+        let probe_paths: Vec<String> = probes.iter().map(|(_, probe_path)| probe_path.clone()).collect();
+        py.allow_threads(|| {
+            health_probes.wait(&probe_paths, deadline, |state| state.created);
+            for probe_path in probe_paths.iter() {
+                let _ = std::fs::remove_file(probe_path);
+            }
+            health_probes.wait(&probe_paths, deadline, |state| state.deleted);
+        });
+        for (watch_path, probe_path) in probes {
+            let state = health_probes.unregister(&probe_path);
+            report.insert(watch_path, Some(state.created && state.deleted));
+        }
+        Ok(report)
+    }
+
+    /// Move `path` to another backend, changes made while switching are found by comparing snapshots
+    /// of `path` from before and after the switch.
+    ///
+    /// `watch()` can keep running in another thread, `self` isn't borrowed while scanning or creating the watcher.
+    pub fn switch_backend(slf: &Bound<Self>, py: Python, path: String, backend: &str) -> PyResult<()> {
+        let (factory, backend, recursive) = {
+            let this = slf.borrow();
+            this.check_switchable(&path)?;
+            let backend = available_backend(backend)?;
+            if this.root_backend(&path) == backend {
+                return Ok(());
+            }
+            (this.factory.clone(), backend, this.path_recursive(&path))
+        };
+        let scan_paths = vec![path.clone()];
+        // the new watcher starts before the old one stops, so changes are only missed while both are running
+        let (before, watcher) = py.allow_threads(|| {
+            let before = Snapshot::scan(&scan_paths, recursive);
+            factory
+                .create(backend, &path, recursive)
+                .map(|watcher| (before, watcher))
+        })?;
+        {
+            let mut this = slf.borrow_mut();
+            // the watcher may have been closed while the new one was created
+            this.check_switchable(&path)?;
+            if this.switched_roots.insert(path.clone(), watcher).is_none() {
+                if let Some(target) = factory.file_targets.unregistration(&path) {
+                    let _ = this.watcher.unwatch(&target);
+                }
+            }
+            // the new watcher watches all of `path`, including any subtrees which were polled
+            this.limit_pollers.remove(&path);
+            this.registration.polled_subtrees.remove(&path);
+            this.remove_partitions(&path);
+        }
+        let after = py.allow_threads(|| Snapshot::scan(&scan_paths, recursive));
+
+        let this = slf.borrow();
+        let missed: Vec<(u8, String)> = before
+            .diff(&after)
+            .into_iter()
+            .filter(|(_, path, _)| !is_probe(path))
+            .filter(|(change, path, is_dir)| this.filter.allows(*change, path, Some(*is_dir)))
+            .map(|(change, path, _)| (change, path))
+            .collect();
+        if this.debug {
+            eprintln!(
+                "switched {:?} to {}, {} changes while switching",
+                path,
+                backend,
+                missed.len()
+            );
+        }
+        if let Some(source_tracker) = &this.source_tracker {
+            for (change, path) in missed.iter() {
+                source_tracker.record(*change, path, SOURCE_SWITCH);
+            }
+        }
+        this.push_own_changes(missed);
+        Ok(())
+    }
+
+    /// Start watching another path, this can be called while `watch()` is running in another thread.
+    #[pyo3(signature = (path, recursive = None))]
+    pub fn add_path(&mut self, path: String, recursive: Option<bool>) -> PyResult<()> {
+        match self.watcher {
+            WatcherEnum::None => return Err(PyRuntimeError::new_err("RustNotify watcher closed")),
+            WatcherEnum::Shared(_) => {
+                return Err(PyRuntimeError::new_err(
+                    "add_path() is not supported by clients of a shared watcher",
+                ))
+            }
+            _ => (),
+        }
+        if self.watch_paths.contains(&path) {
+            return Err(PyValueError::new_err(format!("{:?} is already a watch path", path)));
+        }
+        let recursive = recursive.unwrap_or(self.recursive);
+        self.register_path(&path, recursive)?;
+        if recursive != self.recursive {
+            self.recursive_overrides.insert(path.clone(), recursive);
+        }
+        if self.debug {
+            eprintln!("added watch path {:?}, recursive={}", path, recursive);
+        }
+        self.watch_paths.push(path);
+        self.watch_paths_changed();
+        Ok(())
+    }
+
+    /// Stop watching one of the watch paths, this can be called while `watch()` is running in another thread.
+    pub fn remove_path(&mut self, path: String) -> PyResult<()> {
+        if matches!(self.watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        if !self.watch_paths.contains(&path) {
+            return Err(PyValueError::new_err(format!("{:?} is not a watch path", path)));
+        }
+        // nested and aliased paths are covered by another watch path's watch, so there's nothing to unwatch
+        let covered = self.registration.nested_paths.contains_key(&path)
+            || self.registration.aliased_paths.contains_key(&path)
+            || self.registration.skipped_paths.contains_key(&path)
+            || self.registration.waiting_paths.contains_key(&path);
+        if self.switched_roots.remove(&path).is_none() && !covered {
+            if let Some(target) = self.factory.file_targets.unregistration(&path) {
+                let unwatched = self.watcher.unwatch(&target);
+                // if the limit was reached at the path itself, nothing was watched natively
+                if !self.registration.polled_subtrees.contains_key(&path) {
+                    unwatched.map_err(map_watch_error)?;
+                }
+            }
+        }
+        self.limit_pollers.remove(&path);
+        self.remove_partitions(&path);
+        // paths which were covered by this path's watch need their own now
+        let mut uncovered: Vec<String> = Vec::new();
+        for covering in [
+            &mut self.registration.nested_paths,
+            &mut self.registration.aliased_paths,
+        ] {
+            covering.retain(|covered, outer| match outer == &path {
+                true => {
+                    uncovered.push(covered.clone());
+                    false
+                }
+                false => true,
+            });
+        }
+        for covered in uncovered {
+            let recursive = self.path_recursive(&covered);
+            self.register_path(&covered, recursive)?;
+        }
+        if self.debug {
+            eprintln!("removed watch path {:?}", path);
+        }
+        self.watch_paths.retain(|p| p != &path);
+        self.recursive_overrides.remove(&path);
+        self.registration.retried_paths.remove(&path);
+        self.registration.skipped_paths.remove(&path);
+        self.registration.nested_paths.remove(&path);
+        self.registration.aliased_paths.remove(&path);
+        self.registration.waiting_paths.remove(&path);
+        self.registration.polled_subtrees.remove(&path);
+        self.watch_paths_changed();
+        Ok(())
+    }
+
+    /// Stop collecting changes and detach the OS watches until `resume()` is called, changes made meanwhile
+    /// are never returned.
+    ///
+    /// Multiple poll watchers and shared watchers keep running, with their changes dropped.
+    pub fn pause(&mut self) -> PyResult<()> {
+        if matches!(self.watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        if self.share_host.is_some() {
+            return Err(PyRuntimeError::new_err(
+                "pause() is not supported while sharing this watcher with other processes",
+            ));
+        }
+        if self.paused.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        for path in self.detachable_paths() {
+            let (target, _) = self.registration(&path);
+            let result = self.watcher_for(&path).unwatch(&target);
+            if self.debug {
+                eprintln!("paused, unwatched {:?}: {:?}", path, result);
+            }
+        }
+        // events for the last watchdog probes may have been missed while detaching
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            for (_, probe_path) in watchdog.take_probes() {
+                self.health_probes.unregister(&probe_path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Watch the watch paths again after `pause()`.
+    pub fn resume(&mut self) -> PyResult<()> {
+        if matches!(self.watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        if !self.paused.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        for path in self.detachable_paths() {
+            let (target, mode) = self.registration(&path);
+            // a watch path deleted while paused is left to the root_deleted policy
+            let result = self.watcher_for(&path).rewatch(&target, mode);
+            if self.debug {
+                eprintln!("resumed, watching {:?}: {:?}", path, result);
+            }
+        }
+        self.paused.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    #[getter]
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// How each watch path is being watched, along with the backend and filters, for debugging missing events.
+    pub fn watch_state(&self, py: Python) -> PyResult<PyObject> {
+        if matches!(self.watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        let registration = &self.registration;
+        let roots = self
+            .watch_paths
+            .iter()
+            .map(|path| {
+                let backend = self.root_backend(path);
+                let (status, reason) = if let Some(reason) = registration.skipped_paths.get(path) {
+                    ("skipped", Some(reason.clone()))
+                } else if let Some(outer) = registration.nested_paths.get(path) {
+                    ("nested", Some(outer.clone()))
+                } else if let Some(original) = registration.aliased_paths.get(path) {
+                    ("aliased", Some(original.clone()))
+                } else if registration.waiting_paths.contains_key(path) {
+                    ("waiting", None)
+                } else {
+                    ("watched", None)
+                };
+                // as with `RootFilters::for_path`, the deepest root filter containing the path applies
+                let filters = self
+                    .effective_config
+                    .root_filters
+                    .iter()
+                    .filter(|(root, _)| Path::new(path).starts_with(root))
+                    .max_by_key(|(root, _)| Path::new(root).components().count())
+                    .map_or(&self.effective_config.filters, |(_, filters)| filters);
+                let root = RootState {
+                    backend,
+                    recursive: self.path_recursive(path),
+                    // a shared watcher's host may poll at a different interval
+                    poll_delay_ms: (backend == POLL_BACKEND && !matches!(self.watcher, WatcherEnum::Shared(_)))
+                        .then(|| self.factory.poll_delay(path).as_secs_f64() * 1000.0),
+                    status,
+                    reason,
+                    filters: filters.clone(),
+                };
+                (path.clone(), root)
+            })
+            .collect();
+        WatchState {
+            backend: self.watcher.backend(),
+            paused: self.paused.load(Ordering::Relaxed),
+            pending_changes: self.changes.lock().unwrap().len(),
+            steps: self.step_count.load(Ordering::Relaxed),
+            roots,
+        }
+        .to_py(py, &self.effective_config)
+    }
+
+    /// How long pollers currently wait between scans, with `max_poll_delay_ms` the longest any has backed off to,
+    /// `None` if nothing is polled.
+    #[getter]
+    pub fn poll_delay_ms(&self) -> Option<f64> {
+        let poll_delay_ms = self.effective_config.poll_delay_ms?;
+        Some(match &self.factory.adaptive {
+            Some(adaptive) => adaptive.current().as_secs_f64() * 1000.0,
+            None => poll_delay_ms,
+        })
+    }
+
+    /// The backend watching each watch path.
+    #[getter]
+    pub fn root_backends(&self) -> HashMap<String, &'static str> {
+        self.watch_paths
+            .iter()
+            .map(|path| (path.clone(), self.root_backend(path)))
+            .collect()
+    }
+
+    /// Watch paths whose watcher has been rebuilt by the watchdog, mapped to how many times.
+    #[getter]
+    pub fn watchdog_restarts(&self) -> HashMap<String, u32> {
+        match &self.watchdog {
+            Some(watchdog) => watchdog.restarts(),
+            None => HashMap::new(),
+        }
+    }
+
+    pub fn capabilities(&self, py: Python) -> PyResult<PyObject> {
+        if matches!(self.watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        Capabilities::for_backend(self.watcher.backend())?.to_py(py)
+    }
+
+    #[getter]
+    pub fn effective_config(&self, py: Python) -> PyResult<PyObject> {
+        self.effective_config.to_py(py, self.watcher.backend())
+    }
+
+    #[getter]
+    pub fn symlink_loops(&self) -> BTreeMap<String, String> {
+        self.symlink_loops.lock().unwrap().clone()
+    }
+
+    /// Changes returned by `watch()` between `start` and `end`, in seconds since the epoch, from the history
+    /// kept with `history_size`.
+    pub fn changes_between(&self, start: f64, end: f64) -> PyResult<Vec<HistoryEntry>> {
+        match &self.history {
+            Some(history) => Ok(history.between(start, end)),
+            None => Err(PyRuntimeError::new_err(
+                "Change history isn't kept, set `history_size` to keep it",
+            )),
+        }
+    }
+
+    /// The changes currently pending, left for `watch()` to return.
+    pub fn peek(&self, py: Python) -> PyResult<PyObject> {
+        let changes: Vec<PyObject> = self
+            .changes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(change, path)| (change, path).to_object(py))
+            .collect();
+        Ok(PySet::new_bound(py, &changes)?.into())
+    }
+
+    /// Discard the pending changes, along with anything tracked about them, returns how many there were.
+    pub fn clear(&self) -> usize {
+        let mut changes = self.changes.lock().unwrap();
+        let discarded = changes.len();
+        changes.clear();
+        self.wakeup.set_ready(false);
+        drop(changes);
+        if let Some(overflow) = &self.overflow {
+            overflow.clear();
+        }
+        if let Some(size_tracker) = &self.size_tracker {
+            size_tracker.take_changes();
+        }
+        if let Some(source_tracker) = &self.source_tracker {
+            source_tracker.take();
+        }
+        if let Some(timestamp_tracker) = &self.timestamp_tracker {
+            timestamp_tracker.take();
+        }
+        if let Some(dir_tracker) = &self.dir_tracker {
+            dir_tracker.take();
+        }
+        if let Some(process_tracker) = &self.process_tracker {
+            process_tracker.take();
+        }
+        if let Some(rename_tracker) = &self.rename_tracker {
+            rename_tracker.take();
+        }
+        discarded
+    }
+
+    /// With `acknowledge`, mark the batch returned with `token` as processed, so it's not delivered again.
+    pub fn ack(&self, token: u64) -> PyResult<()> {
+        if !self.acknowledge {
+            return Err(PyRuntimeError::new_err(
+                "Batches aren't acknowledged, set `acknowledge=True` to acknowledge them",
+            ));
+        }
+        let mut unacked = self.unacked.lock().unwrap();
+        match unacked.as_ref() {
+            Some((unacked_token, _)) if *unacked_token == token => {
+                *unacked = None;
+                Ok(())
+            }
+            _ => Err(PyValueError::new_err(format!(
+                "`token` {} isn't the token of the batch awaiting acknowledgement",
+                token
+            ))),
+        }
+    }
+
+    /// How many changes are pending, and how many events the backend has reported since `watch()` last returned.
+    pub fn changes_pending(&self) -> HashMap<&'static str, u64> {
+        HashMap::from([
+            ("changes", self.changes.lock().unwrap().len() as u64),
+            ("raw_events", self.raw_events.load(Ordering::Relaxed)),
+        ])
+    }
+
+    /// Estimated bytes used by the structures this instance keeps in memory.
+    pub fn memory_stats(&self, py: Python) -> PyResult<PyObject> {
+        MemoryStats {
+            pending_changes: self.changes.lock().unwrap().memory_bytes(),
+            change_sources: self.source_tracker.as_ref().map_or(0, |t| t.memory_bytes()),
+            rescan_snapshot: self.rescan_snapshot_bytes.load(Ordering::Relaxed),
+            rescan_seen: self.rescan_seen.as_ref().map_or(0, |seen| {
+                memory::set_bytes(&seen.lock().unwrap(), |path| path.capacity())
+            }),
+            file_sizes: self.size_tracker.as_ref().map_or(0, |t| t.memory_bytes()),
+            renames: self.rename_tracker.as_ref().map_or(0, |t| t.memory_bytes()),
+            filter_cache: self.filter.memory_bytes(),
+            stat_retry: self.stat_retry.as_ref().map_or(0, |r| r.memory_bytes()),
+            escaped_paths: self.containment.as_ref().map_or(0, |c| c.memory_bytes()),
+            history: self.history.as_ref().map_or(0, |h| h.memory_bytes()),
+            timestamps: self.timestamp_tracker.as_ref().map_or(0, |t| t.memory_bytes()),
+            dirs: self.dir_tracker.as_ref().map_or(0, |t| t.memory_bytes()),
+            processes: self.process_tracker.as_ref().map_or(0, |t| t.memory_bytes()),
+        }
+        .to_py(py)
+    }
+
+    /// Changed paths found outside the watch paths by `path_containment`, mapped to why.
+    #[getter]
+    pub fn escaped_paths(&self) -> BTreeMap<String, String> {
+        match &self.containment {
+            Some(containment) => containment.escaped_paths(),
+            None => BTreeMap::new(),
+        }
+    }
+
+    #[getter]
+    pub fn retried_paths(&self) -> HashMap<String, u32> {
+        self.registration.retried_paths.clone()
+    }
 
-        Ok(RustNotify {
-            changes,
-            error,
-            debug,
-            watcher,
-        })
+    #[getter]
+    pub fn skipped_paths(&self) -> HashMap<String, String> {
+        self.registration.skipped_paths.clone()
     }
 
-    pub fn watch(
-        slf: &Bound<Self>,
-        py: Python,
-        debounce_ms: u64,
-        step_ms: u64,
-        timeout_ms: u64,
-        stop_event: PyObject,
-    ) -> PyResult<PyObject> {
-        if matches!(slf.borrow().watcher, WatcherEnum::None) {
-            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+    #[getter]
+    pub fn nested_paths(&self) -> HashMap<String, String> {
+        self.registration.nested_paths.clone()
+    }
+
+    #[getter]
+    pub fn aliased_paths(&self) -> HashMap<String, String> {
+        self.registration.aliased_paths.clone()
+    }
+
+    #[getter]
+    pub fn polled_subtrees(&self) -> HashMap<String, Vec<String>> {
+        self.registration.polled_subtrees.clone()
+    }
+
+    #[getter]
+    pub fn waiting_paths(&self) -> Vec<String> {
+        let mut waiting_paths: Vec<String> = self.registration.waiting_paths.keys().cloned().collect();
+        waiting_paths.sort();
+        waiting_paths
+    }
+
+    #[getter]
+    pub fn registration_report(&self) -> HashMap<String, Option<String>> {
+        self.watch_paths
+            .iter()
+            .map(|p| (p.clone(), self.registration.skipped_paths.get(p).cloned()))
+            .collect()
+    }
+
+    /// https://github.com/PyO3/pyo3/issues/1205#issuecomment-1164096251 for advice on `__enter__`
+    pub fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    pub fn close(&mut self) {
+        if let Some(overflow) = &self.overflow {
+            overflow.close();
         }
-        let stop_event_is_set: Option<&PyAny> = match stop_event.is_none(py) {
-            true => None,
-            false => {
-                let event: &PyAny = stop_event.extract(py)?;
-                let func: &PyAny = event.getattr("is_set")?.extract()?;
-                if !func.is_callable() {
-                    return Err(PyTypeError::new_err("'stop_event.is_set' must be callable"));
-                }
-                Some(func)
+        if let Some(share_host) = self.share_host.take() {
+            share_host.close();
+        }
+        self.watcher = WatcherEnum::None;
+        self.switched_roots.clear();
+        self.limit_pollers.clear();
+        self.partition_pollers.clear();
+        self.signal_claims.clear();
+        self.rescan_stop = None;
+        self.content_check_stop = None;
+        self.loop_wakeups = None;
+        self.async_wakeups = None;
+        if let Some(callback_thread) = self.callback_thread.take() {
+            callback_thread.stop_soon();
+        }
+    }
+
+    pub fn __exit__(&mut self, _exc_type: PyObject, _exc_value: PyObject, _traceback: PyObject) {
+        self.close();
+    }
+
+    pub fn __repr__(&self) -> PyResult<String> {
+        Ok(format!("RustNotify({:#?})", self.watcher))
+    }
+}
+
+impl RustNotify {
+    fn root_backend(&self, path: &str) -> &'static str {
+        match self.switched_roots.get(path) {
+            Some(watcher) => watcher.backend(),
+            None => self.watcher.backend(),
+        }
+    }
+
+    /// Whether `path` can be moved to another backend by `switch_backend()`.
+    fn check_switchable(&self, path: &str) -> PyResult<()> {
+        match self.watcher {
+            WatcherEnum::None => return Err(PyRuntimeError::new_err("RustNotify watcher closed")),
+            WatcherEnum::Shared(_) => {
+                return Err(PyRuntimeError::new_err(
+                    "switch_backend() is not supported by clients of a shared watcher",
+                ))
             }
+            _ => (),
+        }
+        if !self.watch_paths.iter().any(|p| p == path) {
+            return Err(PyValueError::new_err(format!("{:?} is not a watch path", path)));
+        }
+        Ok(())
+    }
+
+    /// Add changes found by the watcher itself rather than its backend, subject to the `overflow` policy like any
+    /// other change, except that they don't wait for room, they're added by the thread which would make it.
+    fn push_own_changes(&self, changes: impl IntoIterator<Item = (u8, String)>) {
+        let overflow = self
+            .overflow
+            .clone()
+            .filter(|overflow| !matches!(**overflow, Overflow::Block(_)));
+        for change in changes {
+            if let Err(msg) = push_change(&self.changes, &overflow, change) {
+                self.error.set(WatcherError::new(msg));
+                return;
+            }
+        }
+    }
+
+    /// Whether `path` is watched recursively, watch paths added by `add_path()` may differ from the rest.
+    fn path_recursive(&self, path: &str) -> bool {
+        self.recursive_overrides.get(path).copied().unwrap_or(self.recursive)
+    }
+
+    /// What's registered with the watcher for the watch path `path`, and how.
+    fn registration(&self, path: &str) -> (PathBuf, RecursiveMode) {
+        let partitioned = self.partitions.as_ref().map_or(false, |p| p.is_root(Path::new(path)));
+        let mode = match self.path_recursive(path) && !partitioned {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
         };
+        self.factory.file_targets.registration(path, mode)
+    }
 
-        let mut max_debounce_time: Option<SystemTime> = None;
-        let step_time = Duration::from_millis(step_ms);
-        let mut last_size: usize = 0;
-        let max_timeout_time: Option<SystemTime> = match timeout_ms {
-            0 => None,
-            _ => Some(SystemTime::now() + Duration::from_millis(timeout_ms)),
+    /// Watch `path` with the main watcher, or its own poll watcher if there are several poll watchers.
+    ///
+    /// The GIL is held throughout, releasing it while `self` is mutably borrowed would let a concurrent
+    /// `watch()` find it borrowed.
+    fn register_path(&mut self, path: &str, recursive: bool) -> PyResult<()> {
+        let mode = match recursive {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
         };
-        loop {
-            py.allow_threads(|| sleep(step_time));
-            match py.check_signals() {
-                Ok(_) => (),
-                Err(_) => {
-                    slf.borrow().clear();
-                    return Ok("signal".to_object(py));
-                }
-            };
+        let (target, mode) = self.factory.file_targets.registration(path, mode);
+        let added = match &mut self.watcher {
+            #[cfg(feature = "poll")]
+            WatcherEnum::Poll(watcher) => watcher.watch(&target, mode).map(|_| true),
+            #[cfg(feature = "native")]
+            WatcherEnum::Recommended(watcher) => watcher.watch(&target, mode).map(|_| true),
+            #[cfg(all(target_os = "linux", feature = "fanotify"))]
+            WatcherEnum::Fanotify(watcher) => watcher.watch(&target, mode).map(|_| true),
+            // with several poll watchers, there's no telling which polls at the default interval
+            _ => Ok(false),
+        };
+        if !added.map_err(map_watch_error)? {
+            let watcher = self.factory.create(POLL_BACKEND, path, recursive)?;
+            self.switched_roots.insert(path.to_string(), watcher);
+        }
+        Ok(())
+    }
 
-            if let Some(error) = slf.borrow().error.lock().unwrap().as_ref() {
-                slf.borrow().clear();
-                return wf_error!(error.clone());
-            }
+    /// The handle behind `fileno()` or `event_handle()`, whichever is `supported` on this platform.
+    fn ready_handle(&self, supported: bool, unsupported_msg: &str) -> PyResult<i64> {
+        if matches!(self.watcher, WatcherEnum::None) {
+            return Err(PyRuntimeError::new_err("RustNotify watcher closed"));
+        }
+        if !supported {
+            return Err(PyOSError::new_err(unsupported_msg.to_string()));
+        }
+        let changes = self.changes.lock().unwrap();
+        self.wakeup
+            .ready_handle(changes.len() > 0)
+            .map_err(|e| PyOSError::new_err(format!("Unable to create readiness signal: {}", e)))
+    }
 
-            if let Some(is_set) = stop_event_is_set {
-                if is_set.call0()?.is_truthy()? {
-                    if slf.borrow().debug {
-                        eprintln!("stop event set, stopping...");
+    /// The watcher watching `path`.
+    fn watcher_for(&mut self, path: &str) -> &mut WatcherEnum {
+        match self.switched_roots.get_mut(path) {
+            Some(watcher) => watcher,
+            None => &mut self.watcher,
+        }
+    }
+
+    /// Watch paths with their own watch which `pause()` detaches.
+    fn detachable_paths(&self) -> Vec<String> {
+        self.watch_paths
+            .iter()
+            .filter(|path| {
+                !self.registration.nested_paths.contains_key(*path)
+                    && !self.registration.aliased_paths.contains_key(*path)
+                    && !self.registration.skipped_paths.contains_key(*path)
+                    && !self.registration.waiting_paths.contains_key(*path)
+            })
+            .filter(|path| self.switched_roots.get(*path).unwrap_or(&self.watcher).detachable())
+            .cloned()
+            .collect()
+    }
+
+    /// Update everything derived from the watch paths after `add_path()` or `remove_path()`.
+    fn watch_paths_changed(&mut self) {
+        self.roots = Roots::new(&self.watch_paths);
+        if let Some(containment) = &mut self.containment {
+            containment.set_watch_paths(&self.watch_paths);
+        }
+        self.effective_config.watch_paths = self.watch_paths.clone();
+    }
+
+    /// With `watchdog_interval_ms`, rebuild the watcher for any natively watched path which didn't receive
+    /// events for the probe written by the last check, then write new probes.
+    ///
+    /// A path is first given a new native watcher, if that also stops receiving events or can't be created,
+    /// the path is polled instead.
+    fn check_watchdog(&mut self) {
+        let probes = match self.watchdog.as_mut().and_then(|watchdog| watchdog.due()) {
+            Some(probes) => probes,
+            None => return,
+        };
+        for (watch_path, probe_path) in probes {
+            let state = self.health_probes.unregister(&probe_path);
+            if state.created || self.root_backend(&watch_path) != NATIVE_BACKEND {
+                continue;
+            }
+            let restarts = self.watchdog.as_mut().map_or(0, |w| w.record_restart(&watch_path));
+            let recursive = self.path_recursive(&watch_path);
+            let native_watcher = match restarts {
+                1 => self.factory.create(NATIVE_BACKEND, &watch_path, recursive),
+                _ => Err(PyRuntimeError::new_err("native watcher stopped receiving events again")),
+            };
+            let watcher = native_watcher.or_else(|_| self.factory.create(POLL_BACKEND, &watch_path, recursive));
+            if self.debug {
+                eprintln!(
+                    "no events for watch path {:?}, rebuilt watcher: {:?}",
+                    watch_path, watcher
+                );
+            }
+            if let Ok(watcher) = watcher {
+                if self.switched_roots.insert(watch_path.clone(), watcher).is_none() {
+                    if let Some(target) = self.factory.file_targets.unregistration(&watch_path) {
+                        let _ = self.watcher.unwatch(&target);
                     }
-                    slf.borrow().clear();
-                    return Ok("stop".to_object(py));
                 }
             }
+        }
 
-            let size = slf.borrow().changes.lock().unwrap().len();
-            if size > 0 {
-                if size == last_size {
-                    break;
+        let mut probes: Vec<(String, String)> = Vec::new();
+        for watch_path in self.watch_paths.iter() {
+            // probes written and deleted straight away are missed by polling, and files can't contain probes
+            if self.registration.skipped_paths.contains_key(watch_path)
+                || self.root_backend(watch_path) != NATIVE_BACKEND
+                || !Path::new(watch_path).is_dir()
+            {
+                continue;
+            }
+            let probe_dir = self.registration.aliased_paths.get(watch_path).unwrap_or(watch_path);
+            let probe_path = self.health_probes.probe_path(probe_dir);
+            self.health_probes.register(&probe_path);
+            let written = std::fs::File::create(&probe_path).is_ok();
+            let _ = std::fs::remove_file(&probe_path);
+            self.health_probes.release_dir(&probe_path);
+            match written {
+                true => probes.push((watch_path.clone(), probe_path)),
+                false => {
+                    self.health_probes.unregister(&probe_path);
                 }
-                last_size = size;
+            }
+        }
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            watchdog.set_probes(probes);
+        }
+    }
 
-                let now = SystemTime::now();
-                if let Some(max_time) = max_debounce_time {
-                    if now > max_time {
-                        break;
-                    }
-                } else {
-                    max_debounce_time = Some(now + Duration::from_millis(debounce_ms));
+    /// Whether `watch()` can wait without stepping while no changes are pending, unless something has to be
+    /// checked periodically rather than waking it as it happens.
+    fn idles(&self) -> bool {
+        self.clock.wakes_on_change()
+            && self.changes.lock().unwrap().len() == 0
+            && self.registration.waiting_paths.is_empty()
+            && self.watchdog.is_none()
+            && self.root_monitor.policy == RootDeletedPolicy::Ignore
+            && self.lazy.is_none()
+            && self.partitions.is_none()
+    }
+
+    /// Apply the `root_deleted` policy to any watch paths which have been deleted or recreated since the
+    /// last check.
+    fn check_roots(&mut self) -> PyResult<()> {
+        let skipped_paths = &self.registration.skipped_paths;
+        let waiting_paths = &self.registration.waiting_paths;
+        let roots = self
+            .watch_paths
+            .iter()
+            .filter(|p| !skipped_paths.contains_key(*p) && !waiting_paths.contains_key(*p));
+        let root_changes = self.root_monitor.check(roots);
+        let policy = self.root_monitor.policy;
+        for path in root_changes.deleted {
+            if self.debug {
+                eprintln!("watch path {:?} deleted, root_deleted={:?}", path, policy);
+            }
+            if policy == RootDeletedPolicy::Raise {
+                return Err(PyFileNotFoundError::new_err(format!(
+                    "Watch path {:?} was deleted",
+                    path
+                )));
+            }
+            if let Some(source_tracker) = &self.source_tracker {
+                source_tracker.record(CHANGE_DELETED, &path, SOURCE_ROOT_CHECK);
+            }
+            self.push_own_changes([(CHANGE_DELETED, path)]);
+        }
+        if policy != RootDeletedPolicy::Reattach {
+            return Ok(());
+        }
+        for path in root_changes.recreated {
+            let (target, mode) = self.registration(&path);
+            // nested and aliased paths are covered by another path's watch, if this fails the path has probably
+            // been deleted again, which the next check will pick up
+            if !self.registration.nested_paths.contains_key(&path)
+                && !self.registration.aliased_paths.contains_key(&path)
+            {
+                let watcher = match self.switched_roots.get_mut(&path) {
+                    Some(watcher) => watcher,
+                    None => &mut self.watcher,
+                };
+                let result = watcher.rewatch(&target, mode);
+                if self.debug {
+                    eprintln!("watch path {:?} recreated, re-watching: {:?}", path, result);
                 }
-            } else if let Some(max_time) = max_timeout_time {
-                if SystemTime::now() > max_time {
-                    slf.borrow().clear();
-                    return Ok("timeout".to_object(py));
+            }
+            if let Some(source_tracker) = &self.source_tracker {
+                source_tracker.record(CHANGE_ADDED, &path, SOURCE_ROOT_CHECK);
+            }
+            self.push_own_changes([(CHANGE_ADDED, path)]);
+        }
+        Ok(())
+    }
+
+    /// Watch any watch paths waiting to be created with `wait_for_creation` which now exist, reporting each as added.
+    fn check_waiting(&mut self) {
+        let created: Vec<(String, Option<&'static str>)> = self
+            .registration
+            .waiting_paths
+            .iter()
+            .filter(|(path, _)| Path::new(path).exists())
+            .map(|(path, backend)| (path.clone(), *backend))
+            .collect();
+        for (path, backend) in created {
+            let recursive = self.path_recursive(&path);
+            // a path created inside another watch path is already covered by that path's watch
+            let registration = &self.registration;
+            let watched: Vec<String> = self
+                .watch_paths
+                .iter()
+                .filter(|p| {
+                    !registration.nested_paths.contains_key(*p)
+                        && !registration.aliased_paths.contains_key(*p)
+                        && !registration.skipped_paths.contains_key(*p)
+                        && !registration.waiting_paths.contains_key(*p)
+                        && self.path_recursive(p)
+                })
+                .cloned()
+                .collect();
+            let result = match covering_path(&path, &watched) {
+                Some(outer) => {
+                    self.registration.nested_paths.insert(path.clone(), outer.clone());
+                    Ok(())
                 }
+                None => match backend {
+                    Some(backend) => self
+                        .factory
+                        .create(backend, &path, recursive)
+                        .map(|watcher| drop(self.switched_roots.insert(path.clone(), watcher))),
+                    None => self.register_path(&path, recursive),
+                },
+            };
+            if self.debug {
+                eprintln!("watch path {:?} created, watching: {:?}", path, result);
+            }
+            // if this fails the path has probably been deleted again, so keep waiting for it
+            if result.is_err() {
+                continue;
+            }
+            self.registration.waiting_paths.remove(&path);
+            if let Some(source_tracker) = &self.source_tracker {
+                source_tracker.record(CHANGE_ADDED, &path, SOURCE_ROOT_CHECK);
             }
+            self.push_own_changes([(CHANGE_ADDED, path)]);
+        }
+    }
+
+    /// With `lazy_depth`, register the directories whose parents have reported activity since the last step.
+    fn check_lazy(&mut self) {
+        let Some(lazy) = &self.lazy else {
+            return;
+        };
+        let due = lazy.take_due();
+        for dir in due {
+            let result: NotifyResult<()> = match &mut self.watcher {
+                #[cfg(feature = "native")]
+                WatcherEnum::Recommended(watcher) => watcher.watch(&dir, RecursiveMode::NonRecursive),
+                _ => Ok(()),
+            };
+            if self.debug {
+                eprintln!("activity in the parent of {:?}, watching it: {:?}", dir, result);
+            }
+            // most likely the directory has been deleted again
+            if result.is_err() {
+                lazy.failed(&dir);
+            }
+        }
+    }
+
+    /// With `poll_threads`, poll the directories created inside polled watch paths since the last step.
+    fn check_partitions(&mut self) {
+        let Some(partitions) = &self.partitions else {
+            return;
+        };
+        for (dir, poller) in partitions.take_created() {
+            let result = self.partition_pollers[poller].rewatch(&dir, RecursiveMode::Recursive);
+            if self.debug {
+                eprintln!("{:?} created, polling it: {:?}", dir, result);
+            }
+        }
+    }
+
+    /// With `poll_threads`, stop polling the subdirectories of the watch path `path`.
+    fn remove_partitions(&mut self, path: &str) {
+        let Some(partitions) = &self.partitions else {
+            return;
+        };
+        for (dir, poller) in partitions.remove_root(Path::new(path)) {
+            let _ = self.partition_pollers[poller].unwatch(&dir);
+        }
+    }
+
+    /// What `watch()` returns, with `structured` a `WatchResult`, otherwise the changes or, if there are none, the
+    /// reason. Either way, raw events are counted from here for the next call.
+    fn watch_result(
+        &self,
+        py: Python,
+        structured: bool,
+        reason: &'static str,
+        changes: Option<PyObject>,
+        duration: Duration,
+    ) -> PyResult<PyObject> {
+        let raw_event_count = self.raw_events.swap(0, Ordering::Relaxed);
+        if !structured {
+            return Ok(changes.unwrap_or_else(|| reason.to_object(py)));
+        }
+        let duration_ms = duration.as_secs_f64() * 1000.0;
+        let result = match changes {
+            // the copy kept is left as it is, however the consumer modifies the one returned
+            Some(changes) if self.acknowledge => {
+                let token = self.last_token.fetch_add(1, Ordering::Relaxed) + 1;
+                let returned = changes.call_method0(py, "copy")?;
+                *self.unacked.lock().unwrap() = Some((token, changes));
+                WatchResult::new(reason, returned, duration_ms, raw_event_count).with_token(token)
+            }
+            Some(changes) => WatchResult::new(reason, changes, duration_ms, raw_event_count),
+            None => {
+                let changes: PyObject = if self.group_by_root {
+                    PyDict::new_bound(py).into()
+                } else if self.ordered {
+                    PyList::empty_bound(py).into()
+                } else {
+                    PySet::empty_bound(py)?.into()
+                };
+                WatchResult::new(reason, changes, duration_ms, raw_event_count)
+            }
+        };
+        Ok(Py::new(py, result)?.into_py(py))
+    }
+
+    /// Copy out what's needed to convert a batch of changes, see `changes_to_py`.
+    fn result_builder(&self) -> ResultBuilder {
+        let with_root = self.extra_fields.root || self.group_by_root;
+        let needs_roots = self.extra_fields.depth || self.extra_fields.first_component || with_root;
+        ResultBuilder {
+            extra_fields: self.extra_fields,
+            collapse_save_storms: self.collapse_save_storms,
+            collapse_renames: self.collapse_renames,
+            rename_pairs: self.rename_pairs,
+            change_mask: self.change_mask,
+            ordered: self.ordered,
+            group_by_root: self.group_by_root,
+            canonical_paths: self.canonical_paths,
+            relative_paths: self.relative_paths,
+            with_root,
+            rename_tracker: self.rename_tracker.clone(),
+            size_tracker: self.size_tracker.clone(),
+            source_tracker: self.source_tracker.clone(),
+            timestamp_tracker: self.timestamp_tracker.clone(),
+            dir_tracker: self.dir_tracker.clone(),
+            process_tracker: self.process_tracker.clone(),
+            spilling: matches!(self.overflow.as_deref(), Some(Overflow::Spill(_))),
+            roots: match needs_roots || self.relative_paths {
+                true => Some(self.roots.clone()),
+                false => None,
+            },
+        }
+    }
+
+    /// Convert a batch of changes to a python set, adding any extra details requested at construction
+    /// to each change tuple.
+    ///
+    /// Everything up to creating python objects happens without the GIL, and the GIL is briefly released between
+    /// chunks of objects, so huge batches don't block other python threads. `RustNotify` is only borrowed while
+    /// the GIL is held, so `add_path()`, `pause()` etc. can be called from other threads meanwhile.
+    fn changes_to_py(slf: &Bound<Self>, py: Python, changes: PendingChanges) -> PyResult<PyObject> {
+        let builder = slf.borrow().result_builder();
+        let batch = py.allow_threads(|| builder.prepare(changes));
+        let py_changes = builder.to_py(py, &batch)?;
+        if let Some(history) = &slf.borrow().history {
+            // renames are recorded with where the path was renamed to
+            history.record(batch.iter().map(|prepared| {
+                (
+                    prepared.change,
+                    prepared.new_path.as_ref().unwrap_or(&prepared.path).as_str(),
+                )
+            }));
         }
-        let py_changes = slf.borrow().changes.lock().unwrap().to_object(py);
-        slf.borrow().clear();
         Ok(py_changes)
     }
+}
 
-    /// https://github.com/PyO3/pyo3/issues/1205#issuecomment-1164096251 for advice on `__enter__`
-    pub fn __enter__(slf: Py<Self>) -> Py<Self> {
-        slf
+/// What's next after a step of `watch()`.
+enum Step {
+    /// Wait for another step.
+    Wait,
+    /// The batch has settled, it's taken once any `linger_ms` has passed.
+    Settled,
+    /// Return this without taking a batch.
+    Return(PyObject),
+}
+
+/// Where a call to `watch()` is up to between steps, so `watch_async()` can take the same steps from an event loop
+/// rather than a thread.
+#[derive(Debug)]
+struct WatchSteps {
+    clock: Arc<dyn Clock>,
+    debounce_time: Duration,
+    step_time: Duration,
+    linger_time: Duration,
+    flush_on_timeout: bool,
+    structured: bool,
+    max_timeout_time: Option<Duration>,
+    max_debounce_time: Option<Duration>,
+    last_size: usize,
+    // when the first change of the batch was seen, and why the batch ended
+    batch_start: Option<Duration>,
+    reason: &'static str,
+}
+
+impl WatchSteps {
+    fn new(
+        clock: Arc<dyn Clock>,
+        debounce_ms: f64,
+        step_ms: f64,
+        timeout_ms: f64,
+        linger_ms: f64,
+        flush_on_timeout: bool,
+        structured: bool,
+    ) -> PyResult<Self> {
+        let timeout_time = duration_from_ms("timeout_ms", timeout_ms)?;
+        let max_timeout_time: Option<Duration> = match timeout_time.is_zero() {
+            true => None,
+            false => Some(clock.now() + timeout_time),
+        };
+        Ok(WatchSteps {
+            debounce_time: duration_from_ms("debounce_ms", debounce_ms)?,
+            step_time: duration_from_ms("step_ms", step_ms)?,
+            linger_time: duration_from_ms("linger_ms", linger_ms)?,
+            clock,
+            flush_on_timeout,
+            structured,
+            max_timeout_time,
+            max_debounce_time: None,
+            last_size: 0,
+            batch_start: None,
+            reason: "changes",
+        })
     }
 
-    pub fn close(&mut self) {
-        self.watcher = WatcherEnum::None;
+    /// How long to wait for the next step, while `idle` nothing needs checking until the timeout, since a change
+    /// arriving, `stop()`, signals and errors all wake `watch()`.
+    fn wait_time(&self, idle: bool) -> Duration {
+        match (idle, self.max_timeout_time) {
+            (false, _) => self.step_time,
+            // `settle` only times out once the timeout has been exceeded
+            (true, Some(max_time)) => max_time.saturating_sub(self.clock.now()) + Duration::from_millis(1),
+            (true, None) => IDLE_WAIT,
+        }
     }
 
-    pub fn __exit__(&mut self, _exc_type: PyObject, _exc_value: PyObject, _traceback: PyObject) {
-        self.close();
+    /// Stop without a batch, discarding any pending changes.
+    fn end(&self, slf: &Bound<RustNotify>, py: Python, reason: &'static str) -> PyResult<PyObject> {
+        slf.borrow().clear();
+        slf.borrow()
+            .watch_result(py, self.structured, reason, None, Duration::ZERO)
     }
 
-    pub fn __repr__(&self) -> PyResult<String> {
-        Ok(format!("RustNotify({:#?})", self.watcher))
+    /// Check for errors and `stop()`, and whatever the watch paths need checking on each step, returns what to
+    /// return if that's the end of watching.
+    fn check(&self, slf: &Bound<RustNotify>, py: Python) -> PyResult<Option<PyObject>> {
+        let error = slf.borrow().error.get();
+        if let Some(error) = error {
+            slf.borrow().clear();
+            return Err(error.to_py(py, slf.borrow().watcher.backend()));
+        }
+
+        // while paused, watch paths aren't watched so there's nothing to check
+        if !slf.borrow().paused.load(Ordering::Relaxed) {
+            slf.borrow_mut().check_waiting();
+            let roots_result = slf.borrow_mut().check_roots();
+            if let Err(err) = roots_result {
+                slf.borrow().clear();
+                return Err(err);
+            }
+            slf.borrow_mut().check_watchdog();
+            slf.borrow_mut().check_lazy();
+            slf.borrow_mut().check_partitions();
+        }
+
+        if slf.borrow().wakeup.take_stopped() {
+            if slf.borrow().debug {
+                eprintln!("stop() called, stopping...");
+            }
+            return self.end(slf, py, "stop").map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Whether the batch has settled, from how many changes are pending compared to the last step.
+    fn settle(&mut self, slf: &Bound<RustNotify>, py: Python) -> PyResult<Step> {
+        let size = slf.borrow().changes.lock().unwrap().len();
+        // return whatever's been collected rather than waiting for the batch to settle, and leave any changes
+        // arriving from now on for the next call rather than clearing them
+        if self.flush_on_timeout
+            && self
+                .max_timeout_time
+                .map_or(false, |max_time| self.clock.now() > max_time)
+        {
+            self.reason = "timeout";
+            return match size {
+                0 => slf
+                    .borrow()
+                    .watch_result(py, self.structured, self.reason, None, Duration::ZERO)
+                    .map(Step::Return),
+                _ => Ok(Step::Settled),
+            };
+        }
+        if size > 0 {
+            if size == self.last_size {
+                return Ok(Step::Settled);
+            }
+            self.last_size = size;
+
+            let now = self.clock.now();
+            if let Some(max_time) = self.max_debounce_time {
+                if now > max_time {
+                    return Ok(Step::Settled);
+                }
+            } else {
+                self.max_debounce_time = Some(now + self.debounce_time);
+                self.batch_start = Some(now);
+            }
+        } else if let Some(max_time) = self.max_timeout_time {
+            if self.clock.now() > max_time {
+                return self.end(slf, py, "timeout").map(Step::Return);
+            }
+        }
+        Ok(Step::Wait)
+    }
+
+    /// Take the settled batch.
+    fn finish(&self, slf: &Bound<RustNotify>, py: Python) -> PyResult<PyObject> {
+        let changes = {
+            let this = slf.borrow();
+            let mut pending = this.changes.lock().unwrap();
+            let mut changes = pending.take();
+            if let Some(overflow) = &this.overflow {
+                overflow
+                    .drained(&mut pending)
+                    .map_err(WatchfilesRustInternalError::new_err)?;
+            }
+            this.wakeup.set_ready(pending.len() > 0);
+            if let Some(containment) = &this.containment {
+                containment.check(&mut changes);
+            }
+            // clients are sent the batch as it settled, they apply their own options to it
+            if let Some(host) = &this.share_host {
+                host.broadcast(changes.iter());
+            }
+            changes
+        };
+        let py_changes = RustNotify::changes_to_py(slf, py, changes)?;
+        let duration = self
+            .batch_start
+            .map_or(Duration::ZERO, |start| self.clock.now().saturating_sub(start));
+        slf.borrow()
+            .watch_result(py, self.structured, self.reason, Some(py_changes), duration)
     }
 }
 
-impl RustNotify {
-    fn clear(&self) {
-        self.changes.lock().unwrap().clear();
+/// What `RustNotify::changes_to_py` needs from `RustNotify`, copied out so no borrow of it is held while the GIL is
+/// released.
+struct ResultBuilder {
+    extra_fields: ExtraFields,
+    collapse_save_storms: bool,
+    collapse_renames: bool,
+    rename_pairs: bool,
+    change_mask: ChangeMask,
+    ordered: bool,
+    group_by_root: bool,
+    canonical_paths: bool,
+    relative_paths: bool,
+    with_root: bool,
+    rename_tracker: Option<Arc<RenameTracker>>,
+    size_tracker: Option<Arc<SizeTracker>>,
+    source_tracker: Option<Arc<SourceTracker>>,
+    timestamp_tracker: Option<Arc<TimestampTracker>>,
+    dir_tracker: Option<Arc<DirTracker>>,
+    process_tracker: Option<Arc<ProcessTracker>>,
+    // changes spilled to disk were annotated as they arrived, so their annotations are left until they're returned
+    spilling: bool,
+    roots: Option<Roots>,
+}
+
+impl ResultBuilder {
+    /// Collapse, pair and annotate `changes`, this doesn't need the GIL.
+    fn prepare(&self, mut changes: PendingChanges) -> Vec<PreparedChange> {
+        let order = changes.take_order();
+        let mut sources = match &self.source_tracker {
+            Some(source_tracker) if self.spilling => source_tracker.take_for(&changes),
+            Some(source_tracker) => source_tracker.take(),
+            None => HashMap::new(),
+        };
+        let mut timestamps = match &self.timestamp_tracker {
+            Some(timestamp_tracker) if self.spilling => timestamp_tracker.take_for(&changes),
+            Some(timestamp_tracker) => timestamp_tracker.take(),
+            None => HashMap::new(),
+        };
+        let mut dirs = match &self.dir_tracker {
+            Some(dir_tracker) if self.spilling => dir_tracker.take_for(&changes),
+            Some(dir_tracker) => dir_tracker.take(),
+            None => HashMap::new(),
+        };
+        let mut processes = match &self.process_tracker {
+            Some(process_tracker) if self.spilling => process_tracker.take_for(&changes),
+            Some(process_tracker) => process_tracker.take(),
+            None => HashMap::new(),
+        };
+        let mut renames = match &self.rename_tracker {
+            Some(rename_tracker) => rename_tracker.take(),
+            None => Vec::new(),
+        };
+        if self.collapse_renames {
+            renames = collapse::collapse_rename_chains(&mut changes, renames);
+        }
+        // without renamed in `change_mask`, renames are left as deletions and additions
+        let renamed = match self.rename_pairs && self.change_mask.allows(CHANGE_RENAMED) {
+            true => collapse::pair_renames(&mut changes, renames),
+            false => Vec::new(),
+        };
+        if self.collapse_save_storms {
+            collapse::collapse_save_storms(&mut changes);
+        }
+        let changes = changes
+            .into_changes()
+            .filter(|(change, _)| *change == CHANGE_RESCAN || self.change_mask.allows(*change));
+        let size_changes = match &self.size_tracker {
+            Some(size_tracker) => size_tracker.take_changes(),
+            None => HashMap::new(),
+        };
+        let mut batch: Vec<PreparedChange> = changes
+            .map(|(change, path)| {
+                let size_change = match change {
+                    CHANGE_MODIFIED => size_changes.get(&path).copied(),
+                    _ => None,
+                };
+                let position = self.roots.as_ref().and_then(|roots| roots.position(&path));
+                let root = self
+                    .roots
+                    .as_ref()
+                    .filter(|_| self.with_root)
+                    .and_then(|roots| roots.root_of(&path));
+                let source = sources.remove(&(change, path.clone()));
+                let timestamp = timestamps.remove(&(change, path.clone()));
+                let is_dir = dirs.remove(&(change, path.clone())).flatten();
+                let process = processes.remove(&(change, path.clone()));
+                PreparedChange {
+                    change,
+                    path,
+                    new_path: None,
+                    size_change,
+                    position,
+                    source,
+                    timestamp,
+                    is_dir,
+                    process,
+                    root,
+                }
+            })
+            .collect();
+        batch.extend(renamed.into_iter().map(|(from, to)| {
+            let position = self.roots.as_ref().and_then(|roots| roots.position(&to));
+            let root = self
+                .roots
+                .as_ref()
+                .filter(|_| self.with_root)
+                .and_then(|roots| roots.root_of(&to));
+            let source = sources.remove(&(CHANGE_ADDED, to.clone()));
+            let timestamp = timestamps.remove(&(CHANGE_ADDED, to.clone()));
+            let is_dir = dirs.remove(&(CHANGE_ADDED, to.clone())).flatten();
+            let process = processes.remove(&(CHANGE_ADDED, to.clone()));
+            PreparedChange {
+                change: CHANGE_RENAMED,
+                path: from,
+                new_path: Some(to),
+                size_change: None,
+                position,
+                source,
+                timestamp,
+                is_dir,
+                process,
+                root,
+            }
+        }));
+        let mut batch = match self.ordered {
+            true => arrival_order(batch, order),
+            false => batch,
+        };
+        // paths are only canonicalized once everything keyed by the path as reported has been looked up
+        if self.canonical_paths {
+            let mut canonicalizer = Canonicalizer::default();
+            for prepared in batch.iter_mut() {
+                prepared.path = canonicalizer.canonical(&prepared.path);
+                if let Some(new_path) = prepared.new_path.as_mut() {
+                    *new_path = canonicalizer.canonical(new_path);
+                }
+            }
+        }
+        // paths outside every watch path, e.g. those allowed by `path_containment`, are left absolute
+        if let Some(roots) = self.roots.as_ref().filter(|_| self.relative_paths) {
+            for prepared in batch.iter_mut() {
+                if let Some(relative) = roots.relative(&prepared.path) {
+                    prepared.path = relative;
+                }
+                if let Some(relative) = prepared.new_path.as_deref().and_then(|p| roots.relative(p)) {
+                    prepared.new_path = Some(relative);
+                }
+            }
+        }
+        batch
+    }
+
+    /// Create the python objects for a prepared batch, releasing the GIL briefly between chunks.
+    fn to_py(&self, py: Python, batch: &[PreparedChange]) -> PyResult<PyObject> {
+        let mut py_changes: Vec<PyObject> = Vec::with_capacity(batch.len());
+        for (index, chunk) in batch.chunks(RESULT_CHUNK_SIZE).enumerate() {
+            if index > 0 {
+                py.allow_threads(std::thread::yield_now);
+            }
+            for prepared in chunk {
+                if !self.extra_fields.any() {
+                    py_changes.push(match &prepared.new_path {
+                        Some(new_path) => (prepared.change, &prepared.path, new_path).to_object(py),
+                        None => (prepared.change, &prepared.path).to_object(py),
+                    });
+                    continue;
+                }
+                let size_change = prepared.size_change;
+                let mut items = vec![prepared.change.to_object(py), prepared.path.to_object(py)];
+                if let Some(new_path) = &prepared.new_path {
+                    items.push(new_path.to_object(py));
+                }
+                if self.extra_fields.modify_kind {
+                    items.push(size_change.and_then(|s| s.kind()).to_object(py));
+                }
+                if self.extra_fields.modify_sizes {
+                    items.push(size_change.map(|s| (s.old_size, s.new_size)).to_object(py));
+                }
+                if self.extra_fields.depth {
+                    items.push(prepared.position.as_ref().map(|(depth, _)| *depth).to_object(py));
+                }
+                if self.extra_fields.first_component {
+                    items.push(
+                        prepared
+                            .position
+                            .as_ref()
+                            .and_then(|(_, first)| first.clone())
+                            .to_object(py),
+                    );
+                }
+                if self.extra_fields.source {
+                    items.push(prepared.source.to_object(py));
+                }
+                if self.extra_fields.timestamp {
+                    items.push(prepared.timestamp.to_object(py));
+                }
+                if self.extra_fields.is_dir {
+                    items.push(prepared.is_dir.to_object(py));
+                }
+                if self.extra_fields.process {
+                    items.push(prepared.process.to_object(py));
+                }
+                if self.extra_fields.root {
+                    items.push(prepared.root.to_object(py));
+                }
+                py_changes.push(PyTuple::new_bound(py, items).to_object(py));
+            }
+        }
+        let collection = |py_changes: Vec<PyObject>| -> PyResult<PyObject> {
+            match self.ordered {
+                true => Ok(PyList::new_bound(py, py_changes).into()),
+                false => Ok(PySet::new_bound(py, &py_changes)?.into()),
+            }
+        };
+        if !self.group_by_root {
+            return collection(py_changes);
+        }
+        let mut groups: BTreeMap<Option<&String>, Vec<PyObject>> = BTreeMap::new();
+        for (prepared, py_change) in batch.iter().zip(py_changes) {
+            groups.entry(prepared.root.as_ref()).or_default().push(py_change);
+        }
+        let grouped = PyDict::new_bound(py);
+        for (root, py_changes) in groups {
+            grouped.set_item(root, collection(py_changes)?)?;
+        }
+        Ok(grouped.into())
     }
 }
+/// Periodically rescan `watch_paths` and inject any changes the watcher didn't report.
+///
+/// Paths in `seen` were reported by the watcher, so differences for them are not injected again. The
+/// previous round's `seen` is also checked to cover events which arrive between a scan and the swap.
+#[allow(clippy::too_many_arguments)]
+fn spawn_rescan(
+    watch_paths: Vec<String>,
+    recursive: bool,
+    interval: Duration,
+    changes: Changes,
+    overflow: Option<Arc<Overflow>>,
+    error: WatcherErrorSlot,
+    seen: Arc<Mutex<HashSet<String>>>,
+    filter: Arc<RootFilters>,
+    source_tracker: Option<Arc<SourceTracker>>,
+    snapshot_bytes: Arc<AtomicUsize>,
+    paused: Arc<AtomicBool>,
+    debug: bool,
+) -> Sender<()> {
+    // take the initial snapshot before returning so nothing after construction can be missed
+    let mut snapshot = Snapshot::scan(&watch_paths, recursive);
+    snapshot_bytes.store(snapshot.memory_bytes(), Ordering::Relaxed);
+    let (stop_sender, stop_receiver) = channel::<()>();
+    spawn(move || {
+        let mut previous_seen = HashSet::new();
+        while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+            let new_snapshot = Snapshot::scan(&watch_paths, recursive);
+            let current_seen = std::mem::take(&mut *seen.lock().unwrap());
+            let missed: Vec<(u8, String)> = snapshot
+                .diff(&new_snapshot)
+                .into_iter()
+                .filter(|_| !paused.load(Ordering::Relaxed))
+                .filter(|(_, path, _)| !current_seen.contains(path) && !previous_seen.contains(path))
+                .filter(|(_, path, _)| !is_probe(path))
+                .filter(|(change, path, is_dir)| filter.allows(*change, path, Some(*is_dir)))
+                .map(|(change, path, _)| (change, path))
+                .collect();
+            if debug {
+                eprintln!(
+                    "rescan of {} paths found {} missed changes",
+                    new_snapshot.len(),
+                    missed.len()
+                );
+            }
+            if let Some(source_tracker) = &source_tracker {
+                for (change, path) in missed.iter() {
+                    source_tracker.record(*change, path, SOURCE_RESCAN);
+                }
+            }
+            for change in missed {
+                if let Err(msg) = push_change(&changes, &overflow, change) {
+                    error.set(WatcherError::new(msg));
+                    break;
+                }
+            }
+            snapshot_bytes.store(new_snapshot.memory_bytes(), Ordering::Relaxed);
+            snapshot = new_snapshot;
+            previous_seen = current_seen;
+        }
+    });
+    stop_sender
+}
 
 #[pymodule]
 fn _rust_notify(py: Python, m: &Bound<PyModule>) -> PyResult<()> {
@@ -359,10 +4245,23 @@ fn _rust_notify(py: Python, m: &Bound<PyModule>) -> PyResult<()> {
     // it seems the dot after "alpha/beta" e.g. "-alpha.1" is not necessary, hence why this works
     version = version.replace("-alpha", "a").replace("-beta", "b");
     m.add("__version__", version)?;
-    m.add(
-        "WatchfilesRustInternalError",
-        py.get_type_bound::<WatchfilesRustInternalError>(),
-    )?;
+    let internal_error = py.get_type_bound::<WatchfilesRustInternalError>();
+    // details of errors from the underlying watcher, set on instances raised by `watch()`
+    internal_error.setattr("errno", py.None())?;
+    internal_error.setattr("strerror", py.None())?;
+    internal_error.setattr("paths", PyTuple::empty_bound(py))?;
+    internal_error.setattr("backend", py.None())?;
+    m.add("WatchfilesRustInternalError", internal_error)?;
+    let limit_error = py.get_type_bound::<WatchLimitError>();
+    // details of the limit reached, set on instances
+    limit_error.setattr("limit_name", py.None())?;
+    limit_error.setattr("limit", py.None())?;
+    limit_error.setattr("usage", py.None())?;
+    limit_error.setattr("directories", 0)?;
+    m.add("WatchLimitError", limit_error)?;
     m.add_class::<RustNotify>()?;
+    m.add_class::<ManualClock>()?;
+    m.add_class::<WatchResult>()?;
+    m.add_function(wrap_pyfunction!(backend_capabilities, m)?)?;
     Ok(())
 }
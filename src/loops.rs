@@ -0,0 +1,18 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Symlinks found pointing to one of their ancestors, mapped to that ancestor.
+pub(crate) type SymlinkLoops = Arc<Mutex<BTreeMap<String, String>>>;
+
+const LOOP_PREFIX: &str = "File system loop found: ";
+const LOOP_SEPARATOR: &str = " points to an ancestor ";
+
+/// Parse the error walkdir reports when it finds a symlink pointing to an ancestor directory (which it doesn't
+/// descend into), returns `(symlink, ancestor)`.
+///
+/// The poll watcher passes these on as generic errors with only the message, so this is all we have to go on.
+pub(crate) fn parse_loop_error(message: &str) -> Option<(String, String)> {
+    let rest = message.strip_prefix(LOOP_PREFIX)?;
+    let (child, ancestor) = rest.split_once(LOOP_SEPARATOR)?;
+    Some((child.to_string(), ancestor.to_string()))
+}
@@ -0,0 +1,69 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem::size_of;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+// estimates are of the memory allocated for each structure, they don't include allocator overhead
+
+/// Estimated bytes used by `map`, `heap` gives the bytes each entry owns separately, e.g. a key's string.
+pub(crate) fn map_bytes<K, V>(map: &HashMap<K, V>, heap: impl Fn(&K, &V) -> usize) -> usize {
+    // the table has one control byte per bucket as well as the entries themselves
+    map.capacity() * (size_of::<(K, V)>() + 1) + map.iter().map(|(k, v)| heap(k, v)).sum::<usize>()
+}
+
+pub(crate) fn set_bytes<T>(set: &HashSet<T>, heap: impl Fn(&T) -> usize) -> usize {
+    set.capacity() * (size_of::<T>() + 1) + set.iter().map(heap).sum::<usize>()
+}
+
+pub(crate) fn btree_map_bytes<K, V>(map: &BTreeMap<K, V>, heap: impl Fn(&K, &V) -> usize) -> usize {
+    map.len() * size_of::<(K, V)>() + map.iter().map(|(k, v)| heap(k, v)).sum::<usize>()
+}
+
+pub(crate) fn vec_bytes<T>(vec: &[T], capacity: usize, heap: impl Fn(&T) -> usize) -> usize {
+    capacity * size_of::<T>() + vec.iter().map(heap).sum::<usize>()
+}
+
+/// Estimated bytes used by each of the structures a `RustNotify` instance keeps in memory.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryStats {
+    pub pending_changes: usize,
+    pub change_sources: usize,
+    pub rescan_snapshot: usize,
+    pub rescan_seen: usize,
+    pub file_sizes: usize,
+    pub renames: usize,
+    pub filter_cache: usize,
+    pub stat_retry: usize,
+    pub escaped_paths: usize,
+    pub history: usize,
+    pub timestamps: usize,
+    pub dirs: usize,
+    pub processes: usize,
+}
+
+impl MemoryStats {
+    pub fn to_py(&self, py: Python) -> PyResult<PyObject> {
+        let stats = [
+            ("pending_changes", self.pending_changes),
+            ("change_sources", self.change_sources),
+            ("rescan_snapshot", self.rescan_snapshot),
+            ("rescan_seen", self.rescan_seen),
+            ("file_sizes", self.file_sizes),
+            ("renames", self.renames),
+            ("filter_cache", self.filter_cache),
+            ("stat_retry", self.stat_retry),
+            ("escaped_paths", self.escaped_paths),
+            ("history", self.history),
+            ("timestamps", self.timestamps),
+            ("dirs", self.dirs),
+            ("processes", self.processes),
+        ];
+        let dict = PyDict::new_bound(py);
+        for (name, bytes) in stats {
+            dict.set_item(name, bytes)?;
+        }
+        dict.set_item("total", stats.iter().map(|(_, bytes)| bytes).sum::<usize>())?;
+        Ok(dict.into())
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct PartitionState {
+    // watch paths which are split between the pollers, the main watcher polls only the paths themselves
+    roots: HashSet<PathBuf>,
+    // subdirectories of the roots, and which poller polls each
+    assigned: HashMap<PathBuf, usize>,
+    // how many subdirectories each poller polls
+    load: Vec<usize>,
+    // paths created directly inside a root since `take_created()` was last called
+    created: HashSet<PathBuf>,
+}
+
+impl PartitionState {
+    fn assign(&mut self, dir: PathBuf) -> usize {
+        let poller = (0..self.load.len()).min_by_key(|i| self.load[*i]).unwrap_or(0);
+        self.load[poller] += 1;
+        self.assigned.insert(dir, poller);
+        poller
+    }
+}
+
+/// With `poll_threads`, the subdirectories of each polled watch path are shared between several poll watchers,
+/// each of which scans its share on its own thread, so a poll of a large tree takes a fraction of the time.
+#[derive(Debug)]
+pub(crate) struct PollPartitions {
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    threads: usize,
+    state: Mutex<PartitionState>,
+}
+
+impl PollPartitions {
+    pub fn new(threads: usize) -> Self {
+        PollPartitions {
+            threads,
+            state: Mutex::new(PartitionState {
+                load: vec![0; threads],
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// How many pollers the subdirectories are shared between.
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Split the watch path `root` between the pollers, returning the subdirectories each should poll.
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    pub fn split(&self, root: &Path) -> Vec<Vec<PathBuf>> {
+        let mut dirs: Vec<PathBuf> = match fs::read_dir(root) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|p| p.is_dir())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        dirs.sort();
+        let mut state = self.state.lock().unwrap();
+        state.roots.insert(root.to_path_buf());
+        let mut shares: Vec<Vec<PathBuf>> = vec![Vec::new(); self.threads];
+        for dir in dirs {
+            let poller = state.assign(dir.clone());
+            shares[poller].push(dir);
+        }
+        shares
+    }
+
+    /// Whether the watch path `path` is split between the pollers.
+    pub fn is_root(&self, path: &Path) -> bool {
+        self.state.lock().unwrap().roots.contains(path)
+    }
+
+    /// Note a path which has been created, called for every creation event before it's filtered.
+    pub fn record(&self, path: &Path) {
+        let mut state = self.state.lock().unwrap();
+        let in_root = path.parent().map_or(false, |parent| state.roots.contains(parent));
+        if in_root && !state.assigned.contains_key(path) {
+            state.created.insert(path.to_path_buf());
+        }
+    }
+
+    /// Directories created inside a root since this was last called, and the poller which should poll each.
+    ///
+    /// Directories deleted later stay with their poller, which picks them up again if they're recreated.
+    pub fn take_created(&self) -> Vec<(PathBuf, usize)> {
+        let mut state = self.state.lock().unwrap();
+        let mut created: Vec<PathBuf> = std::mem::take(&mut state.created)
+            .into_iter()
+            .filter(|p| p.is_dir())
+            .collect();
+        created.sort();
+        created
+            .into_iter()
+            .map(|dir| {
+                let poller = state.assign(dir.clone());
+                (dir, poller)
+            })
+            .collect()
+    }
+
+    /// Stop splitting the watch path `root`, returning the subdirectories which were polled and by which poller.
+    pub fn remove_root(&self, root: &Path) -> Vec<(PathBuf, usize)> {
+        let mut state = self.state.lock().unwrap();
+        if !state.roots.remove(root) {
+            return Vec::new();
+        }
+        let dirs: Vec<(PathBuf, usize)> = state
+            .assigned
+            .iter()
+            .filter(|(dir, _)| dir.parent() == Some(root))
+            .map(|(dir, poller)| (dir.clone(), *poller))
+            .collect();
+        for (dir, poller) in dirs.iter() {
+            state.assigned.remove(dir);
+            state.load[*poller] -= 1;
+        }
+        state.created.retain(|p| p.parent() != Some(root));
+        dirs
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Canonicalizes the paths of a batch of changes, caching the directories resolved so each is only resolved once.
+///
+/// Only the directory containing a path is resolved, a changed symlink is reported as itself rather than its
+/// target, and deleted paths can still be canonicalized via whichever of their ancestors still exist.
+#[derive(Debug, Default)]
+pub(crate) struct Canonicalizer {
+    dirs: HashMap<PathBuf, Option<PathBuf>>,
+}
+
+impl Canonicalizer {
+    pub fn canonical(&mut self, path: &str) -> String {
+        let path = Path::new(path);
+        let mut missing: Vec<OsString> = Vec::new();
+        let mut current = path;
+        loop {
+            let (parent, name) = match (current.parent(), current.file_name()) {
+                (Some(parent), Some(name)) => (parent, name),
+                // e.g. a path ending in `..`, or none of its ancestors exist
+                _ => return path.to_string_lossy().to_string(),
+            };
+            missing.push(name.to_os_string());
+            if let Some(mut canonical) = self.canonical_dir(parent) {
+                canonical.extend(missing.iter().rev());
+                return canonical.to_string_lossy().to_string();
+            }
+            current = parent;
+        }
+    }
+
+    fn canonical_dir(&mut self, dir: &Path) -> Option<PathBuf> {
+        self.dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| fs::canonicalize(dir).ok().map(strip_verbatim))
+            .clone()
+    }
+}
+
+// canonical paths on windows are "verbatim" paths like `\\?\C:\foo`, which aren't what anyone passes as a watch path
+#[cfg(windows)]
+fn strip_verbatim(path: PathBuf) -> PathBuf {
+    match path.to_str().and_then(|s| s.strip_prefix(r"\\?\")) {
+        Some(stripped) if stripped.as_bytes().get(1) == Some(&b':') => PathBuf::from(stripped),
+        _ => path,
+    }
+}
+
+#[cfg(not(windows))]
+fn strip_verbatim(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// `path` as notify reports events under it, relative paths are made absolute when they're watched.
+pub(crate) fn watched_path(path: &Path) -> PathBuf {
+    match path.is_absolute() {
+        true => path.to_path_buf(),
+        false => env::current_dir().map_or_else(|_| path.to_path_buf(), |cwd| cwd.join(path)),
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::memory::{map_bytes, vec_bytes};
+use crate::{CHANGE_ADDED, CHANGE_ATTRIBUTE, CHANGE_DELETED, CHANGE_MODIFIED, CHANGE_RESCAN, CHANGE_WRITTEN};
+
+// the order changes for a path are listed in
+const CHANGES: [u8; 6] = [
+    CHANGE_RESCAN,
+    CHANGE_ADDED,
+    CHANGE_MODIFIED,
+    CHANGE_ATTRIBUTE,
+    CHANGE_WRITTEN,
+    CHANGE_DELETED,
+];
+
+/// The changes reported for one path in the current batch, a path can be e.g. both added and deleted within a
+/// batch, so this is a set of change types stored as bit flags.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChangeState(u8);
+
+impl ChangeState {
+    fn flag(change: u8) -> u8 {
+        1 << change
+    }
+
+    pub fn contains(self, change: u8) -> bool {
+        self.0 & Self::flag(change) != 0
+    }
+
+    /// A modification is redundant once a path has been added or deleted in the batch, editors and some
+    /// backends report modifications alongside creating and deleting files.
+    pub fn supersedes_modified(self) -> bool {
+        self.contains(CHANGE_ADDED) || self.contains(CHANGE_DELETED)
+    }
+
+    fn insert(&mut self, change: u8) -> bool {
+        let new = !self.contains(change);
+        self.0 |= Self::flag(change);
+        new
+    }
+
+    fn remove(&mut self, change: u8) -> bool {
+        let present = self.contains(change);
+        self.0 &= !Self::flag(change);
+        present
+    }
+
+    fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = u8> {
+        CHANGES.into_iter().filter(move |change| self.contains(*change))
+    }
+}
+
+/// Called with each new change added to `PendingChanges`.
+pub(crate) type InsertHook = Arc<dyn Fn(u8, &str) + Send + Sync>;
+
+/// Every change inserted, in the order they arrived.
+#[derive(Debug, Default)]
+struct ArrivalOrder {
+    log: Vec<(u8, String)>,
+    // whether changes which were already pending are logged again
+    duplicates: bool,
+}
+
+/// Changes waiting to be returned by `watch()`, keyed by path so each path is only stored once however
+/// many types of change it has.
+#[derive(Default)]
+pub(crate) struct PendingChanges {
+    paths: HashMap<String, ChangeState>,
+    // total number of `(change, path)` pairs, which is what limits such as `max_pending_changes` count
+    len: usize,
+    on_insert: Option<InsertHook>,
+    order: Option<ArrivalOrder>,
+}
+
+impl PendingChanges {
+    pub fn with_insert_hook(on_insert: InsertHook) -> Self {
+        PendingChanges {
+            on_insert: Some(on_insert),
+            ..Default::default()
+        }
+    }
+
+    /// Also record the order changes arrive in, see `take_order`.
+    pub fn with_arrival_order(self, duplicates: bool) -> Self {
+        PendingChanges {
+            order: Some(ArrivalOrder {
+                log: Vec::new(),
+                duplicates,
+            }),
+            ..self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The changes pending for `path`, empty if there are none.
+    pub fn state(&self, path: &str) -> ChangeState {
+        self.paths.get(path).copied().unwrap_or_default()
+    }
+
+    pub fn contains(&self, change: u8, path: &str) -> bool {
+        self.state(path).contains(change)
+    }
+
+    /// Returns whether the change was new.
+    pub fn insert(&mut self, change: u8, path: String) -> bool {
+        if !self.contains(change, &path) {
+            self.run_insert_hook(change, &path);
+        }
+        self.restore(change, path)
+    }
+
+    /// Call the insert hook for a change kept elsewhere until there's room for it, e.g. spilled to disk, so it's
+    /// annotated as it arrives.
+    pub fn run_insert_hook(&self, change: u8, path: &str) {
+        if let Some(on_insert) = &self.on_insert {
+            on_insert(change, path);
+        }
+    }
+
+    /// Insert a change whose insert hook has already been called by `run_insert_hook`, returns whether the change
+    /// was new.
+    pub fn restore(&mut self, change: u8, path: String) -> bool {
+        let new = self.paths.entry(path.clone()).or_default().insert(change);
+        if new {
+            self.len += 1;
+        }
+        if let Some(order) = &mut self.order {
+            if new || order.duplicates {
+                order.log.push((change, path));
+            }
+        }
+        new
+    }
+
+    /// Returns whether the change was pending.
+    pub fn remove(&mut self, change: u8, path: &str) -> bool {
+        let removed = match self.paths.get_mut(path) {
+            Some(state) => state.remove(change),
+            None => false,
+        };
+        if removed {
+            self.len -= 1;
+            if self.state(path).is_empty() {
+                self.paths.remove(path);
+            }
+        }
+        removed
+    }
+
+    /// Remove all changes for paths where `keep` returns `false`.
+    pub fn retain_paths(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        let mut removed = 0;
+        self.paths.retain(|path, state| match keep(path) {
+            true => true,
+            false => {
+                removed += state.len();
+                false
+            }
+        });
+        self.len -= removed;
+    }
+
+    /// Paths which have `change` pending.
+    pub fn paths_with(&self, change: u8) -> impl Iterator<Item = &String> {
+        self.paths
+            .iter()
+            .filter(move |(_, state)| state.contains(change))
+            .map(|(path, _)| path)
+    }
+
+    /// Changes in the order they arrived, including any since removed, empty unless arrival order is recorded.
+    pub fn take_order(&mut self) -> Vec<(u8, String)> {
+        match &mut self.order {
+            Some(order) => std::mem::take(&mut order.log),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        map_bytes(&self.paths, |path, _| path.capacity())
+            + self.order.as_ref().map_or(0, |order| {
+                vec_bytes(&order.log, order.log.capacity(), |(_, path)| path.capacity())
+            })
+    }
+
+    pub fn clear(&mut self) {
+        self.paths.clear();
+        self.len = 0;
+        self.take_order();
+    }
+
+    /// Take all the pending changes, leaving this empty.
+    pub fn take(&mut self) -> PendingChanges {
+        PendingChanges {
+            paths: std::mem::take(&mut self.paths),
+            len: std::mem::take(&mut self.len),
+            on_insert: None,
+            order: self.order.as_mut().map(|order| ArrivalOrder {
+                log: std::mem::take(&mut order.log),
+                duplicates: order.duplicates,
+            }),
+        }
+    }
+
+    /// The pending changes, without taking them.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &str)> {
+        self.paths
+            .iter()
+            .flat_map(|(path, state)| state.iter().map(move |change| (change, path.as_str())))
+    }
+
+    /// Take the entries of `annotations` for these changes, leaving those for changes still to be added, e.g.
+    /// spilled to disk.
+    pub fn take_annotations<V>(&self, annotations: &mut HashMap<(u8, String), V>) -> HashMap<(u8, String), V> {
+        self.iter()
+            .filter_map(|(change, path)| {
+                let key = (change, path.to_string());
+                annotations.remove(&key).map(|value| (key, value))
+            })
+            .collect()
+    }
+
+    pub fn into_changes(self) -> impl Iterator<Item = (u8, String)> {
+        self.paths
+            .into_iter()
+            .flat_map(|(path, state)| state.iter().map(move |change| (change, path.clone())))
+    }
+}
+
+impl Extend<(u8, String)> for PendingChanges {
+    fn extend<I: IntoIterator<Item = (u8, String)>>(&mut self, iter: I) {
+        for (change, path) in iter {
+            self.insert(change, path);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+#[cfg(feature = "poll")]
+use std::{
+    fmt,
+    path::Path,
+    sync::Condvar,
+    thread::{sleep, spawn},
+    time::Instant,
+};
+
+#[cfg(feature = "poll")]
+use notify::{PollWatcher, RecursiveMode, Result as NotifyResult, Watcher};
+
+// how long the poll thread is given to start a scan once it's been asked to, before waiting for the scan to finish
+#[cfg(feature = "poll")]
+const SCAN_START: Duration = Duration::from_millis(1);
+
+/// With `max_poll_delay_ms`, each poller backs off from the poll interval while its scans are slow.
+#[derive(Debug)]
+pub(crate) struct AdaptivePolling {
+    base: Duration,
+    ceiling: Duration,
+    // the current interval of each poller, in nanoseconds
+    intervals: Mutex<Vec<Weak<AtomicU64>>>,
+}
+
+impl AdaptivePolling {
+    pub fn new(base: Duration, ceiling: Duration) -> Self {
+        AdaptivePolling {
+            base,
+            ceiling,
+            intervals: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The interval after a scan which took `scan`, twice as long as the scan so no more than about a third of the
+    /// time is spent scanning, but no shorter than the poll interval or longer than the ceiling.
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    fn next_interval(&self, scan: Duration) -> Duration {
+        scan.saturating_mul(2).clamp(self.base, self.ceiling)
+    }
+
+    /// The longest interval any poller has backed off to, the poll interval if none have.
+    pub fn current(&self) -> Duration {
+        let mut intervals = self.intervals.lock().unwrap();
+        intervals.retain(|interval| interval.strong_count() > 0);
+        intervals
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|interval| Duration::from_nanos(interval.load(Ordering::Relaxed)))
+            .max()
+            .unwrap_or(self.base)
+    }
+
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    fn register(&self) -> Arc<AtomicU64> {
+        let interval = Arc::new(AtomicU64::new(self.base.as_nanos() as u64));
+        self.intervals.lock().unwrap().push(Arc::downgrade(&interval));
+        interval
+    }
+}
+
+/// A `PollWatcher`, which with `AdaptivePolling` is created with manual polling and polled from a thread of ours,
+/// timing each scan to decide when to poll next.
+#[cfg(feature = "poll")]
+pub(crate) struct Poller {
+    watcher: Arc<Mutex<PollWatcher>>,
+    stop: Arc<(Mutex<bool>, Condvar)>,
+}
+
+#[cfg(feature = "poll")]
+impl Poller {
+    pub fn new(watcher: PollWatcher, adaptive: Option<&Arc<AdaptivePolling>>) -> Self {
+        let poller = Poller {
+            watcher: Arc::new(Mutex::new(watcher)),
+            stop: Arc::new((Mutex::new(false), Condvar::new())),
+        };
+        if let Some(adaptive) = adaptive {
+            let watcher = Arc::downgrade(&poller.watcher);
+            let adaptive = adaptive.clone();
+            let stop = poller.stop.clone();
+            spawn(move || schedule(watcher, adaptive, stop));
+        }
+        poller
+    }
+
+    pub fn watch(&mut self, path: &Path, mode: RecursiveMode) -> NotifyResult<()> {
+        self.watcher.lock().unwrap().watch(path, mode)
+    }
+
+    pub fn unwatch(&mut self, path: &Path) -> NotifyResult<()> {
+        self.watcher.lock().unwrap().unwatch(path)
+    }
+}
+
+#[cfg(feature = "poll")]
+impl fmt::Debug for Poller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.watcher.lock().unwrap().fmt(f)
+    }
+}
+
+#[cfg(feature = "poll")]
+impl Drop for Poller {
+    fn drop(&mut self) {
+        let (stopped, condvar) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+}
+
+/// Poll `watcher` until it's dropped, waiting as long after each scan as `adaptive` decides.
+#[cfg(feature = "poll")]
+fn schedule(watcher: Weak<Mutex<PollWatcher>>, adaptive: Arc<AdaptivePolling>, stop: Arc<(Mutex<bool>, Condvar)>) {
+    let interval = adaptive.register();
+    let (stopped, condvar) = &*stop;
+    loop {
+        let delay = Duration::from_nanos(interval.load(Ordering::Relaxed));
+        let guard = stopped.lock().unwrap();
+        let (guard, _) = condvar.wait_timeout_while(guard, delay, |s| !*s).unwrap();
+        if *guard {
+            return;
+        }
+        drop(guard);
+        let Some(watcher) = watcher.upgrade() else {
+            return;
+        };
+        let scan = scan(&mut watcher.lock().unwrap());
+        interval.store(adaptive.next_interval(scan).as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Ask `watcher` to scan its paths, returning how long the scan took.
+#[cfg(feature = "poll")]
+fn scan(watcher: &mut PollWatcher) -> Duration {
+    let start = Instant::now();
+    if watcher.poll().is_err() {
+        return Duration::ZERO;
+    }
+    // the poll thread holds the lock on its watches while scanning, so unwatching a path which isn't watched only
+    // returns once the scan has finished, the poll thread might not have started it by the first attempt
+    let _ = watcher.unwatch(Path::new(""));
+    sleep(SCAN_START);
+    let _ = watcher.unwatch(Path::new(""));
+    start.elapsed()
+}
@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+
+use crate::memory::map_bytes;
+use crate::pending::PendingChanges;
+
+/// The ID of a process and, if it could be found, the ID of the user it was running as.
+pub(crate) type ProcessInfo = (u32, Option<u32>);
+
+/// Records the ID of the process which made each pending change, and the user it was running as, where the
+/// backend reports it.
+#[derive(Debug, Default)]
+pub(crate) struct ProcessTracker {
+    // the user ID is `None` where the process exited before it could be looked up
+    pending: Mutex<HashMap<(u8, String), ProcessInfo>>,
+}
+
+impl ProcessTracker {
+    /// Only the first record of each change is kept.
+    pub fn record(&self, change: u8, path: &str, pid: u32) {
+        self.pending
+            .lock()
+            .unwrap()
+            .entry((change, path.to_string()))
+            .or_insert_with(|| (pid, process_uid(pid)));
+    }
+
+    pub fn take(&self) -> HashMap<(u8, String), ProcessInfo> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// As `take`, but only for `changes`.
+    pub fn take_for(&self, changes: &PendingChanges) -> HashMap<(u8, String), ProcessInfo> {
+        changes.take_annotations(&mut self.pending.lock().unwrap())
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        map_bytes(&self.pending.lock().unwrap(), |(_, path), _| path.capacity())
+    }
+}
+
+/// The effective user ID of process `pid`, from the second field of the `Uid:` line in `/proc/<pid>/status`.
+fn process_uid(pid: u32) -> Option<u32> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let uids = status.lines().find_map(|line| line.strip_prefix("Uid:"))?;
+    uids.split_whitespace().nth(1)?.parse().ok()
+}
@@ -0,0 +1,140 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+use pyo3::prelude::*;
+
+use crate::watch_limits::WatchLimit;
+
+// how often progress is reported while watch paths are being registered
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Counts the directories registered while a `RustNotify` is constructed, reporting the count to the `progress`
+/// callback, and with `debug` to stderr, from a thread while registration is under way, then once it's finished.
+#[derive(Debug)]
+pub(crate) struct RegistrationProgress {
+    callback: Option<PyObject>,
+    debug: bool,
+    // directories found by the initial scans of poll watchers
+    scanned_dirs: AtomicU64,
+    // inotify watches this process already had, so aren't counted
+    baseline_watches: u64,
+    finished: AtomicBool,
+}
+
+impl RegistrationProgress {
+    pub fn new(callback: Option<PyObject>, debug: bool) -> Self {
+        RegistrationProgress {
+            callback,
+            debug,
+            scanned_dirs: AtomicU64::new(0),
+            baseline_watches: inotify_watches(),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Note a path found by a poll watcher's initial scan, once construction has finished this does nothing.
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    pub fn scanned(&self, path: &Path) {
+        if !self.finished.load(Ordering::Relaxed) && path.is_dir() {
+            self.scanned_dirs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// How many directories have been registered so far.
+    fn count(&self) -> u64 {
+        self.scanned_dirs.load(Ordering::Relaxed) + inotify_watches().saturating_sub(self.baseline_watches)
+    }
+
+    fn report(&self, py: Python, count: u64) {
+        if self.debug {
+            eprintln!("registered {} directories", count);
+        }
+        if let Some(callback) = &self.callback {
+            if let Err(err) = callback.call1(py, (count,)) {
+                err.write_unraisable_bound(py, Some(callback.bind(py)));
+            }
+        }
+    }
+
+    /// Start reporting progress, until `finish()` is called on the returned `ProgressReporter`.
+    pub fn start(self: &Arc<Self>) -> ProgressReporter {
+        let stop: Arc<(Mutex<bool>, Condvar)> = Arc::new((Mutex::new(false), Condvar::new()));
+        let stop_clone = stop.clone();
+        let progress = self.clone();
+        let handle = spawn(move || {
+            let (stopped, condvar) = &*stop_clone;
+            let mut last_count = 0;
+            loop {
+                let guard = stopped.lock().unwrap();
+                let (guard, _) = condvar.wait_timeout_while(guard, PROGRESS_INTERVAL, |s| !*s).unwrap();
+                if *guard {
+                    return;
+                }
+                drop(guard);
+                let count = progress.count();
+                if count != last_count {
+                    last_count = count;
+                    Python::with_gil(|py| {
+                        // construction may have finished while we waited for the GIL
+                        if !*stopped.lock().unwrap() {
+                            progress.report(py, count);
+                        }
+                    });
+                }
+            }
+        });
+        ProgressReporter {
+            progress: self.clone(),
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+/// Reports progress from a thread until finished or dropped, e.g. if construction fails.
+#[derive(Debug)]
+pub(crate) struct ProgressReporter {
+    progress: Arc<RegistrationProgress>,
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ProgressReporter {
+    fn stop(&self) {
+        let (stopped, condvar) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    /// Stop the thread, then report the final count.
+    pub fn finish(mut self, py: Python) {
+        self.stop();
+        if let Some(handle) = self.handle.take() {
+            // the thread may be waiting for the GIL
+            py.allow_threads(|| {
+                let _ = handle.join();
+            });
+        }
+        let count = self.progress.count();
+        self.progress.finished.store(true, Ordering::Relaxed);
+        self.progress.report(py, count);
+    }
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        self.stop();
+        self.progress.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+// inotify watches in use by this process, only counted on Linux
+fn inotify_watches() -> u64 {
+    match cfg!(target_os = "linux") {
+        true => WatchLimit::Watches.process_usage(Path::new("/proc/self")),
+        false => 0,
+    }
+}
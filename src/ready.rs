@@ -0,0 +1,126 @@
+pub(crate) use imp::ReadySignal;
+
+// a socket pair, with the reading end returned by `fileno()`
+#[cfg(unix)]
+mod imp {
+    use std::io::{Read, Result as IOResult, Write};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    /// A socket which is readable while changes are pending, for `fileno()`.
+    #[derive(Debug)]
+    pub(crate) struct ReadySignal {
+        reader: UnixStream,
+        writer: UnixStream,
+        ready: bool,
+    }
+
+    impl ReadySignal {
+        pub fn new() -> IOResult<Self> {
+            let (reader, writer) = UnixStream::pair()?;
+            reader.set_nonblocking(true)?;
+            writer.set_nonblocking(true)?;
+            Ok(ReadySignal {
+                reader,
+                writer,
+                ready: false,
+            })
+        }
+
+        pub fn handle(&self) -> i64 {
+            self.reader.as_raw_fd() as i64
+        }
+
+        /// Make the socket readable, or drain it so it isn't.
+        pub fn set(&mut self, ready: bool) {
+            if ready == self.ready {
+                return;
+            }
+            match ready {
+                true => {
+                    let _ = (&self.writer).write(&[1]);
+                }
+                false => {
+                    let mut buf = [0u8; 64];
+                    while matches!((&self.reader).read(&mut buf), Ok(n) if n > 0) {}
+                }
+            }
+            self.ready = ready;
+        }
+    }
+}
+
+// a manual-reset event, returned by `event_handle()`
+#[cfg(windows)]
+mod imp {
+    use std::io::{Error as IOError, Result as IOResult};
+    use std::ptr;
+
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::Threading::{CreateEventW, ResetEvent, SetEvent};
+
+    /// An event which is signaled while changes are pending, for `event_handle()`.
+    #[derive(Debug)]
+    pub(crate) struct ReadySignal {
+        event: HANDLE,
+        ready: bool,
+    }
+
+    impl ReadySignal {
+        pub fn new() -> IOResult<Self> {
+            let event = unsafe { CreateEventW(ptr::null(), 1, 0, ptr::null()) };
+            if event == 0 {
+                return Err(IOError::last_os_error());
+            }
+            Ok(ReadySignal { event, ready: false })
+        }
+
+        pub fn handle(&self) -> i64 {
+            self.event as i64
+        }
+
+        pub fn set(&mut self, ready: bool) {
+            if ready == self.ready {
+                return;
+            }
+            unsafe {
+                match ready {
+                    true => SetEvent(self.event),
+                    false => ResetEvent(self.event),
+                };
+            }
+            self.ready = ready;
+        }
+    }
+
+    impl Drop for ReadySignal {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.event);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::io::{Error as IOError, ErrorKind as IOErrorKind, Result as IOResult};
+
+    #[derive(Debug)]
+    pub(crate) struct ReadySignal;
+
+    impl ReadySignal {
+        pub fn new() -> IOResult<Self> {
+            Err(IOError::new(
+                IOErrorKind::Unsupported,
+                "readiness signaling is not supported on this platform",
+            ))
+        }
+
+        pub fn handle(&self) -> i64 {
+            unreachable!()
+        }
+
+        pub fn set(&mut self, _ready: bool) {}
+    }
+}
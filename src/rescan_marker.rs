@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::pending::PendingChanges;
+use crate::CHANGE_RESCAN;
+
+/// Bounds the number of pending changes by replacing them all with a rescan change for each watch path once the
+/// buffer is full, so a consumer which falls far behind rescans rather than the buffer growing without limit.
+#[derive(Debug)]
+pub(crate) struct RescanMarker {
+    max_pending: usize,
+    watch_paths: Vec<String>,
+    // set once the buffer has been collapsed, until `watch()` takes the changes, further changes are dropped
+    collapsed: AtomicBool,
+}
+
+impl RescanMarker {
+    pub fn new(max_pending: usize, watch_paths: Vec<String>) -> Self {
+        RescanMarker {
+            max_pending,
+            watch_paths,
+            collapsed: AtomicBool::new(false),
+        }
+    }
+
+    /// Add a change to `changes`, unless it's full, in which case they're replaced by rescan changes.
+    pub fn push(&self, changes: &mut PendingChanges, change: u8, path: String) {
+        if self.collapsed.load(Ordering::Relaxed) {
+            return;
+        }
+        if changes.len() < self.max_pending {
+            changes.insert(change, path);
+            return;
+        }
+        changes.clear();
+        for watch_path in &self.watch_paths {
+            changes.insert(CHANGE_RESCAN, watch_path.clone());
+        }
+        self.collapsed.store(true, Ordering::Relaxed);
+    }
+
+    /// Start collecting changes again, call after changes have been taken from the buffer or discarded.
+    pub fn reset(&self) {
+        self.collapsed.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,51 @@
+use pyo3::prelude::*;
+
+/// Returned by `watch(..., structured=True)` in place of either changes or a string giving why there are none.
+#[pyclass(module = "watchfiles._rust_notify", frozen)]
+#[derive(Debug)]
+pub(crate) struct WatchResult {
+    #[pyo3(get)]
+    reason: &'static str,
+    #[pyo3(get)]
+    changes: PyObject,
+    #[pyo3(get)]
+    duration_ms: f64,
+    #[pyo3(get)]
+    raw_event_count: u64,
+    #[pyo3(get)]
+    token: Option<u64>,
+}
+
+impl WatchResult {
+    pub fn new(reason: &'static str, changes: PyObject, duration_ms: f64, raw_event_count: u64) -> Self {
+        WatchResult {
+            reason,
+            changes,
+            duration_ms,
+            raw_event_count,
+            token: None,
+        }
+    }
+
+    /// With `acknowledge=True`, the token to pass to `ack()` once the changes have been processed.
+    pub fn with_token(self, token: u64) -> Self {
+        WatchResult {
+            token: Some(token),
+            ..self
+        }
+    }
+}
+
+#[pymethods]
+impl WatchResult {
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        Ok(format!(
+            "WatchResult(reason={:?}, changes={}, duration_ms={}, raw_event_count={}, token={:?})",
+            self.reason,
+            self.changes.bind(py).repr()?,
+            self.duration_ms,
+            self.raw_event_count,
+            self.token
+        ))
+    }
+}
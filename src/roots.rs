@@ -0,0 +1,196 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// What to do when a watch path is deleted while it's being watched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RootDeletedPolicy {
+    // leave it to the backend, some report the deletion, some raise an error, some do nothing
+    Ignore,
+    Raise,
+    Emit,
+    Reattach,
+}
+
+impl RootDeletedPolicy {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "ignore" => Ok(RootDeletedPolicy::Ignore),
+            "raise" => Ok(RootDeletedPolicy::Raise),
+            "emit" => Ok(RootDeletedPolicy::Emit),
+            "reattach" => Ok(RootDeletedPolicy::Reattach),
+            _ => Err(format!(
+                "Invalid root_deleted value {:?}, must be 'ignore', 'raise', 'emit' or 'reattach'",
+                s
+            )),
+        }
+    }
+}
+
+/// Watch paths which were deleted or recreated since the last check.
+#[derive(Debug, Default)]
+pub(crate) struct RootChanges {
+    pub deleted: Vec<String>,
+    pub recreated: Vec<String>,
+}
+
+/// Watch paths the backend reported as deleted since they were last checked.
+pub(crate) type RemovedRoots = Arc<Mutex<HashSet<String>>>;
+
+/// Tracks which watch paths currently exist.
+#[derive(Debug)]
+pub(crate) struct RootMonitor {
+    pub policy: RootDeletedPolicy,
+    missing: HashSet<String>,
+    // what each watch path referred to when last checked, so a path deleted and recreated between checks is
+    // still found, on unix at least
+    identities: HashMap<String, FileIdentity>,
+    removed: RemovedRoots,
+}
+
+impl RootMonitor {
+    pub fn new(policy: RootDeletedPolicy, watch_paths: &[String], removed: RemovedRoots) -> Self {
+        let identities = match policy {
+            RootDeletedPolicy::Ignore => HashMap::new(),
+            _ => watch_paths
+                .iter()
+                .filter_map(|path| Some((path.clone(), file_identity(path)?)))
+                .collect(),
+        };
+        RootMonitor {
+            policy,
+            missing: HashSet::new(),
+            identities,
+            removed,
+        }
+    }
+
+    pub fn check<'a>(&mut self, roots: impl Iterator<Item = &'a String>) -> RootChanges {
+        let mut changes = RootChanges::default();
+        if self.policy == RootDeletedPolicy::Ignore {
+            return changes;
+        }
+        let removed = std::mem::take(&mut *self.removed.lock().unwrap());
+        for root in roots {
+            let identity = file_identity(root);
+            let exists = identity.is_some();
+            // directories are often recreated with the same inode, so a deletion reported by the backend counts too
+            let replaced = exists
+                && (removed.contains(root)
+                    || matches!(
+                        (self.identities.get(root), &identity),
+                        (Some(previous), Some(identity)) if previous != identity
+                    ));
+            match identity {
+                Some(identity) => self.identities.insert(root.clone(), identity),
+                None => self.identities.remove(root),
+            };
+            if replaced && !self.missing.contains(root) {
+                changes.deleted.push(root.clone());
+                changes.recreated.push(root.clone());
+            } else if !exists && self.missing.insert(root.clone()) {
+                changes.deleted.push(root.clone());
+            } else if exists && self.missing.remove(root) {
+                changes.recreated.push(root.clone());
+            }
+        }
+        changes
+    }
+}
+
+/// Watch paths sorted deepest first, so the first root containing a path is the innermost one.
+#[derive(Debug, Clone)]
+pub(crate) struct Roots {
+    roots: Vec<PathBuf>,
+}
+
+impl Roots {
+    pub fn new(watch_paths: &[String]) -> Self {
+        let mut roots: Vec<PathBuf> = watch_paths.iter().map(PathBuf::from).collect();
+        roots.sort_by_key(|root| std::cmp::Reverse(root.components().count()));
+        Roots { roots }
+    }
+
+    /// The innermost watch path containing `path`.
+    pub fn owning_root(&self, path: &Path) -> Option<&Path> {
+        self.roots
+            .iter()
+            .find(|root| path.starts_with(root))
+            .map(|root| root.as_path())
+    }
+
+    /// The innermost watch path containing `path`, as it was given.
+    pub fn root_of(&self, path: &str) -> Option<String> {
+        self.owning_root(Path::new(path))
+            .map(|root| root.to_string_lossy().to_string())
+    }
+
+    /// `path` relative to the innermost watch path containing it, `"."` for a watch path itself.
+    pub fn relative(&self, path: &str) -> Option<String> {
+        let path = Path::new(path);
+        let relative = path.strip_prefix(self.owning_root(path)?).ok()?;
+        match relative.as_os_str().is_empty() {
+            true => Some(".".to_string()),
+            false => Some(relative.to_string_lossy().to_string()),
+        }
+    }
+
+    /// How many levels `path` is below its watch path, and the first component of `path` below it, e.g. for
+    /// `/root/foo/bar.py` watched via `/root` that's `(2, Some("foo"))`.
+    pub fn position(&self, path: &str) -> Option<(usize, Option<String>)> {
+        let path = Path::new(path);
+        let relative = path.strip_prefix(self.owning_root(path)?).ok()?;
+        let mut components = relative.components().filter(|c| matches!(c, Component::Normal(_)));
+        let first = components.next().map(|c| c.as_os_str().to_string_lossy().to_string());
+        let depth = first.iter().count() + components.count();
+        Some((depth, first))
+    }
+}
+
+/// The first of `watched` which `path` is inside of (or the same as).
+///
+/// When watching recursively `path` doesn't need registering separately, that path's watch already covers it.
+pub(crate) fn covering_path<'a>(path: &str, watched: &'a [String]) -> Option<&'a String> {
+    watched.iter().find(|w| Path::new(path).starts_with(w.as_str()))
+}
+
+// what identifies the directory a path refers to, so different paths to the same directory can be found
+#[cfg(unix)]
+type FileIdentity = (u64, u64);
+#[cfg(not(unix))]
+type FileIdentity = PathBuf;
+
+#[cfg(unix)]
+fn file_identity(path: &str) -> Option<FileIdentity> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+// without device and inode numbers, only aliases via symlinks and junctions can be found
+#[cfg(not(unix))]
+fn file_identity(path: &str) -> Option<FileIdentity> {
+    fs::canonicalize(path).ok()
+}
+
+/// Watch paths which refer to the same directory as an earlier watch path, e.g. via a symlink or bind mount,
+/// mapped to that earlier path.
+pub(crate) fn find_aliases(watch_paths: &[String]) -> HashMap<String, String> {
+    let mut first_paths: HashMap<FileIdentity, &String> = HashMap::new();
+    let mut aliases = HashMap::new();
+    for path in watch_paths {
+        if let Some(identity) = file_identity(path) {
+            match first_paths.get(&identity) {
+                // a path given twice isn't an alias of itself
+                Some(first) if *first != path => {
+                    aliases.insert(path.clone(), (*first).clone());
+                }
+                Some(_) => (),
+                None => {
+                    first_paths.insert(identity, path);
+                }
+            }
+        }
+    }
+    aliases
+}
@@ -0,0 +1,301 @@
+use std::io::{Error as IOError, ErrorKind as IOErrorKind, Result as IOResult};
+use std::path::Path;
+use std::sync::Arc;
+
+pub(crate) use imp::{ShareClient, ShareHost};
+
+/// Whether this instance hosts the shared watcher, or is a client of another process's watcher.
+pub(crate) enum ShareRole {
+    Host(Arc<ShareHost>),
+    Client(ShareClient),
+}
+
+/// What a host and its clients must agree on, the watch paths, whether they're watched recursively and the
+/// options deciding which changes the host reports, as `(name, value)` pairs.
+fn handshake_key(watch_paths: &[String], recursive: bool, filters: &[(&str, String)]) -> String {
+    let mut paths = watch_paths.to_vec();
+    paths.sort();
+    paths.dedup();
+    let filters: Vec<String> = filters
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    format!("{}\0{}\0\0{}", recursive, paths.join("\0"), filters.join("\0"))
+}
+
+/// Connect to the host listening at `path`, or become the host if there isn't one.
+pub(crate) fn connect_or_host(
+    path: &Path,
+    watch_paths: &[String],
+    recursive: bool,
+    filters: &[(&str, String)],
+) -> IOResult<ShareRole> {
+    let key = handshake_key(watch_paths, recursive, filters);
+    // a second attempt covers another process becoming the host at the same time
+    for _ in 0..2 {
+        if let Some(client) = ShareClient::connect(path, &key)? {
+            return Ok(ShareRole::Client(client));
+        }
+        if let Some(host) = ShareHost::bind(path, key.clone())? {
+            return Ok(ShareRole::Host(Arc::new(host)));
+        }
+    }
+    Err(IOError::new(
+        IOErrorKind::AddrInUse,
+        "unable to either connect to or host the shared watcher",
+    ))
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::fs::remove_file;
+    use std::io::{BufReader, Error as IOError, ErrorKind as IOErrorKind, Result as IOResult, Write};
+    use std::net::Shutdown;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::thread::spawn;
+    use std::time::Duration;
+
+    use crate::capabilities::BACKENDS;
+    use crate::spill::{read_record, write_record};
+
+    // clients which can't keep up are disconnected rather than blocking the host's watcher
+    const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+    // the change type used for the handshake, and the replies to it
+    const HANDSHAKE: u8 = 0;
+    const ACCEPTED: u8 = 1;
+    const REJECTED: u8 = 2;
+
+    #[derive(Debug, Default)]
+    struct HostState {
+        clients: Mutex<Vec<UnixStream>>,
+        closed: AtomicBool,
+    }
+
+    /// Listens on a unix socket for clients, and forwards each batch of changes `watch()` returns to them.
+    #[derive(Debug)]
+    pub(crate) struct ShareHost {
+        path: PathBuf,
+        key: String,
+        // taken when the host starts accepting clients
+        listener: Mutex<Option<UnixListener>>,
+        state: Arc<HostState>,
+        // batches are written to clients by a thread of their own, so a slow client can't hold up `watch()`
+        batches: Mutex<Option<Sender<Vec<u8>>>>,
+    }
+
+    impl ShareHost {
+        /// Returns `None` if another host is already listening at `path`.
+        pub fn bind(path: &Path, key: String) -> IOResult<Option<Self>> {
+            match UnixListener::bind(path) {
+                Ok(listener) => Ok(Some(ShareHost {
+                    path: path.to_path_buf(),
+                    key,
+                    listener: Mutex::new(Some(listener)),
+                    state: Arc::default(),
+                    batches: Mutex::new(None),
+                })),
+                Err(e) if e.kind() == IOErrorKind::AddrInUse => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+
+        /// Start accepting clients, telling them the host is watching with `backend`.
+        pub fn start(&self, backend: &'static str) {
+            let listener = match self.listener.lock().unwrap().take() {
+                Some(listener) => listener,
+                None => return,
+            };
+            let (sender, receiver) = channel::<Vec<u8>>();
+            *self.batches.lock().unwrap() = Some(sender);
+            let state = self.state.clone();
+            spawn(move || {
+                for records in receiver {
+                    // clients which have gone away, or timed out, are dropped
+                    let mut clients = state.clients.lock().unwrap();
+                    clients.retain_mut(|client| client.write_all(&records).is_ok());
+                }
+            });
+
+            let state = self.state.clone();
+            let key = self.key.clone();
+            spawn(move || {
+                for stream in listener.incoming() {
+                    if state.closed.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if let Ok(stream) = stream {
+                        if let Ok(Some(stream)) = accept(stream, &key, backend) {
+                            state.clients.lock().unwrap().push(stream);
+                        }
+                    }
+                }
+            });
+        }
+
+        /// Queue a batch to be sent to every client, returns straight away.
+        pub fn broadcast<'a>(&self, changes: impl Iterator<Item = (u8, &'a str)>) {
+            let batches = self.batches.lock().unwrap();
+            let Some(sender) = batches.as_ref() else {
+                return;
+            };
+            let mut records = Vec::new();
+            for (change, path) in changes {
+                if write_record(&mut records, change, path).is_err() {
+                    return;
+                }
+            }
+            if !records.is_empty() {
+                let _ = sender.send(records);
+            }
+        }
+
+        pub fn close(&self) {
+            if self.state.closed.swap(true, Ordering::Relaxed) {
+                return;
+            }
+            // the sender thread stops once its channel is closed
+            self.batches.lock().unwrap().take();
+            // dropping the streams disconnects the clients
+            self.state.clients.lock().unwrap().clear();
+            // wake the accept thread so it sees the host is closed
+            let _ = UnixStream::connect(&self.path);
+            let _ = remove_file(&self.path);
+        }
+    }
+
+    impl Drop for ShareHost {
+        fn drop(&mut self) {
+            self.close();
+        }
+    }
+
+    fn accept(mut stream: UnixStream, key: &str, backend: &'static str) -> IOResult<Option<UnixStream>> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let (_, client_key) = read_record(&mut stream)?;
+        if client_key != key {
+            write_record(
+                &mut stream,
+                REJECTED,
+                "watch paths, recursive or the options filtering changes don't match the host",
+            )?;
+            return Ok(None);
+        }
+        write_record(&mut stream, ACCEPTED, backend)?;
+        Ok(Some(stream))
+    }
+
+    /// Connection to another process's shared watcher, changes are received on a background thread.
+    #[derive(Debug)]
+    pub(crate) struct ShareClient {
+        stream: UnixStream,
+        /// the backend the host is watching with
+        pub host_backend: &'static str,
+        closed: Arc<AtomicBool>,
+    }
+
+    impl ShareClient {
+        /// Returns `None` if there's no host listening at `path`.
+        pub fn connect(path: &Path, key: &str) -> IOResult<Option<Self>> {
+            let mut stream = match UnixStream::connect(path) {
+                Ok(stream) => stream,
+                // the socket was left behind by a host which has exited
+                Err(e) if e.kind() == IOErrorKind::ConnectionRefused => {
+                    let _ = remove_file(path);
+                    return Ok(None);
+                }
+                Err(e) if e.kind() == IOErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            write_record(&mut stream, HANDSHAKE, key)?;
+            let (reply, message) = read_record(&mut stream)?;
+            if reply != ACCEPTED {
+                return Err(IOError::new(IOErrorKind::InvalidInput, message));
+            }
+            Ok(Some(ShareClient {
+                stream,
+                host_backend: BACKENDS.iter().copied().find(|b| *b == message).unwrap_or("shared"),
+                closed: Arc::default(),
+            }))
+        }
+
+        /// Call `on_change` with each change the host sends, or `on_lost` if the connection is lost other than
+        /// by this client closing.
+        pub fn start<C, L>(&self, on_change: C, on_lost: L) -> IOResult<()>
+        where
+            C: Fn(u8, String) + Send + 'static,
+            L: FnOnce(String) + Send + 'static,
+        {
+            let mut reader = BufReader::new(self.stream.try_clone()?);
+            let closed = self.closed.clone();
+            spawn(move || loop {
+                match read_record(&mut reader) {
+                    Ok((change, path)) => on_change(change, path),
+                    Err(e) => {
+                        if !closed.load(Ordering::Relaxed) {
+                            on_lost(format!("lost connection to the shared watcher's host: {}", e));
+                        }
+                        return;
+                    }
+                }
+            });
+            Ok(())
+        }
+    }
+
+    impl Drop for ShareClient {
+        fn drop(&mut self) {
+            self.closed.store(true, Ordering::Relaxed);
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// sharing uses unix sockets, elsewhere there's never a host to connect to, nor can one be created
+#[cfg(not(unix))]
+mod imp {
+    use std::io::{Error as IOError, ErrorKind as IOErrorKind, Result as IOResult};
+    use std::path::Path;
+
+    #[derive(Debug)]
+    pub(crate) struct ShareHost;
+
+    impl ShareHost {
+        pub fn bind(_path: &Path, _key: String) -> IOResult<Option<Self>> {
+            Err(IOError::new(
+                IOErrorKind::Unsupported,
+                "sharing a watcher is only supported on unix",
+            ))
+        }
+
+        pub fn start(&self, _backend: &'static str) {}
+
+        pub fn broadcast<'a>(&self, _changes: impl Iterator<Item = (u8, &'a str)>) {}
+
+        pub fn close(&self) {}
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    pub(crate) struct ShareClient {
+        pub host_backend: &'static str,
+    }
+
+    impl ShareClient {
+        pub fn connect(_path: &Path, _key: &str) -> IOResult<Option<Self>> {
+            Ok(None)
+        }
+
+        pub fn start<C, L>(&self, _on_change: C, _on_lost: L) -> IOResult<()>
+        where
+            C: Fn(u8, String) + Send + 'static,
+            L: FnOnce(String) + Send + 'static,
+        {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,397 @@
+pub(crate) use imp::{
+    claim, claim_if_handled, python_wakeup, take, wake_on_signal, watching, SignalClaim, SIGINT, SIGTERM,
+};
+
+// the handler only sets a bit for the signal and writes to a socket, which a thread reads to wake `watch()`,
+// then calls whichever handler was installed before it, e.g. python's, while no `watch()` is waiting for the signal
+// and there's no handler before ours, the signal is handled as if ours had never been installed
+#[cfg(all(unix, feature = "signals"))]
+mod imp {
+    use std::io::{Error as IOError, ErrorKind as IOErrorKind, Read, Result as IOResult};
+    use std::mem;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex, Weak};
+    use std::thread::spawn;
+
+    use pyo3::prelude::*;
+
+    use crate::wakeup::Wakeup;
+
+    pub(crate) const SIGINT: i32 = libc::SIGINT;
+    pub(crate) const SIGTERM: i32 = libc::SIGTERM;
+
+    const MAX_SIGNAL: usize = 64;
+
+    // a bit for each signal received and not yet taken
+    static RECEIVED: AtomicU64 = AtomicU64::new(0);
+    // held while installing, so the previous handler recorded is the one replaced
+    static INSTALLING: Mutex<Installed> = Mutex::new(Installed {
+        claims: [0; MAX_SIGNAL],
+        replaced: [None; MAX_SIGNAL],
+    });
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_HANDLER: AtomicUsize = AtomicUsize::new(libc::SIG_DFL);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_SIGINFO: AtomicBool = AtomicBool::new(false);
+    // the handler each signal had before ours, and whether it takes `siginfo_t`
+    static PREVIOUS: [AtomicUsize; MAX_SIGNAL] = [NO_HANDLER; MAX_SIGNAL];
+    static PREVIOUS_SIGINFO: [AtomicBool; MAX_SIGNAL] = [NO_SIGINFO; MAX_SIGNAL];
+    // the writing end of the socket, or -1 until `wake_on_signal` is first called
+    static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+    static LISTENERS: Mutex<Vec<Weak<Wakeup>>> = Mutex::new(Vec::new());
+    // python's wakeup fd before ours replaced it, what's written to ours is passed on to it, or -1
+    static FORWARD_FD: AtomicI32 = AtomicI32::new(-1);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NOT_WATCHING: AtomicUsize = AtomicUsize::new(0);
+    // how many calls to `watch()` are waiting for each signal
+    static WATCHING: [AtomicUsize; MAX_SIGNAL] = [NOT_WATCHING; MAX_SIGNAL];
+
+    struct Installed {
+        // how many `SignalClaim`s are held for each signal, ours is installed while any are
+        claims: [usize; MAX_SIGNAL],
+        // the action ours replaced, restored once the last claim is released
+        replaced: [Option<libc::sigaction>; MAX_SIGNAL],
+    }
+
+    extern "C" fn handler(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+        let index = signal as usize;
+        if index >= MAX_SIGNAL {
+            return;
+        }
+        let previous = PREVIOUS[index].load(Ordering::SeqCst);
+        let default_action = previous == libc::SIG_DFL || previous == libc::SIG_IGN;
+        if default_action && WATCHING[index].load(Ordering::SeqCst) == 0 {
+            // nothing is waiting for the signal, so it terminates the process, or is ignored, as it would without us,
+            // the signal is blocked until we return, so raising it again terminates the process once we have
+            if previous == libc::SIG_DFL {
+                unsafe {
+                    libc::signal(signal, libc::SIG_DFL);
+                    libc::raise(signal);
+                }
+            }
+            return;
+        }
+        RECEIVED.fetch_or(1 << index, Ordering::SeqCst);
+        let wake_fd = WAKE_FD.load(Ordering::SeqCst);
+        if wake_fd >= 0 {
+            // the write can't block, if the socket is full the reading thread is already due to wake listeners
+            let errno = errno_location();
+            let saved_errno = errno.map(|errno| unsafe { *errno });
+            unsafe {
+                libc::write(wake_fd, [signal as u8].as_ptr() as *const libc::c_void, 1);
+            }
+            if let (Some(errno), Some(saved_errno)) = (errno, saved_errno) {
+                unsafe { *errno = saved_errno };
+            }
+        }
+        // the default action for the signals we handle is to terminate, which is what `watch()` is avoiding
+        if default_action {
+            return;
+        }
+        unsafe {
+            match PREVIOUS_SIGINFO[index].load(Ordering::SeqCst) {
+                true => {
+                    let previous: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                        mem::transmute(previous);
+                    previous(signal, info, context);
+                }
+                false => {
+                    let previous: extern "C" fn(libc::c_int) = mem::transmute(previous);
+                    previous(signal);
+                }
+            }
+        }
+    }
+
+    // the code interrupted by the handler may be about to read `errno`, so writing to the socket mustn't change it
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn errno_location() -> Option<*mut libc::c_int> {
+        Some(unsafe { libc::__errno_location() })
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    fn errno_location() -> Option<*mut libc::c_int> {
+        Some(unsafe { libc::__error() })
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd"
+    )))]
+    fn errno_location() -> Option<*mut libc::c_int> {
+        None
+    }
+
+    /// Keeps our handler installed for a signal, once every claim for the signal has been dropped the action ours
+    /// replaced is restored.
+    #[derive(Debug)]
+    pub(crate) struct SignalClaim {
+        signal: i32,
+    }
+
+    impl SignalClaim {
+        /// Install our handler again if it's been replaced, python replaces it whenever `signal.signal()` is called
+        /// for the same signal, e.g. by asyncio, so this should be called before relying on it.
+        pub fn reinstall(&self) -> IOResult<()> {
+            install_locked(&mut INSTALLING.lock().unwrap(), self.signal, false).map(|_| ())
+        }
+    }
+
+    impl Drop for SignalClaim {
+        fn drop(&mut self) {
+            let index = self.signal as usize;
+            let mut installed = INSTALLING.lock().unwrap();
+            installed.claims[index] -= 1;
+            if installed.claims[index] > 0 {
+                return;
+            }
+            let Some(replaced) = installed.replaced[index].take() else {
+                return;
+            };
+            unsafe {
+                // if something else has replaced ours since, it's left alone
+                let mut current: libc::sigaction = mem::zeroed();
+                if libc::sigaction(self.signal, ptr::null(), &mut current) == 0
+                    && current.sa_sigaction == handler as *const () as usize
+                {
+                    libc::sigaction(self.signal, &replaced, ptr::null_mut());
+                }
+            }
+        }
+    }
+
+    /// Install our handler for `signal`, keeping it installed until the returned claim, and any others for the
+    /// same signal, are dropped.
+    pub(crate) fn claim(signal: i32) -> IOResult<SignalClaim> {
+        let mut installed = INSTALLING.lock().unwrap();
+        install_locked(&mut installed, signal, false)?;
+        installed.claims[signal as usize] += 1;
+        Ok(SignalClaim { signal })
+    }
+
+    /// Like `claim`, but only if a handler function is already installed, e.g. python's for SIGINT, so a signal
+    /// which would terminate the process or be ignored still does.
+    pub(crate) fn claim_if_handled(signal: i32) -> IOResult<Option<SignalClaim>> {
+        let mut installed = INSTALLING.lock().unwrap();
+        if !install_locked(&mut installed, signal, true)? {
+            return Ok(None);
+        }
+        installed.claims[signal as usize] += 1;
+        Ok(Some(SignalClaim { signal }))
+    }
+
+    /// Install our handler for `signal` if it isn't already, returns whether it's installed.
+    fn install_locked(installed: &mut Installed, signal: i32, only_if_handled: bool) -> IOResult<bool> {
+        let index = signal as usize;
+        if index >= MAX_SIGNAL {
+            return Err(IOError::from_raw_os_error(libc::EINVAL));
+        }
+        let handler_address = handler as *const () as usize;
+        unsafe {
+            // the previous handler is recorded before ours is installed, so it's there as soon as ours is called
+            let mut previous: libc::sigaction = mem::zeroed();
+            if libc::sigaction(signal, ptr::null(), &mut previous) != 0 {
+                return Err(IOError::last_os_error());
+            }
+            if previous.sa_sigaction == handler_address {
+                return Ok(true);
+            }
+            if only_if_handled && (previous.sa_sigaction == libc::SIG_DFL || previous.sa_sigaction == libc::SIG_IGN) {
+                return Ok(false);
+            }
+            PREVIOUS[index].store(previous.sa_sigaction, Ordering::SeqCst);
+            PREVIOUS_SIGINFO[index].store(previous.sa_flags & libc::SA_SIGINFO != 0, Ordering::SeqCst);
+            installed.replaced[index] = Some(previous);
+
+            // the previous action's flags and mask are kept, so e.g. without `SA_RESTART` the signal still interrupts
+            // blocking calls, and python's handler still gets to raise `KeyboardInterrupt` from them
+            let mut action = previous;
+            action.sa_sigaction = handler_address;
+            action.sa_flags = (previous.sa_flags | libc::SA_SIGINFO) & !libc::SA_RESETHAND;
+            if libc::sigaction(signal, &action, ptr::null_mut()) != 0 {
+                return Err(IOError::last_os_error());
+            }
+        }
+        Ok(true)
+    }
+
+    /// Marks `watch()` as waiting for signals, until dropped.
+    #[derive(Debug)]
+    pub(crate) struct Watching {
+        signals: Vec<i32>,
+    }
+
+    impl Drop for Watching {
+        fn drop(&mut self) {
+            for signal in &self.signals {
+                WATCHING[*signal as usize].fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Mark `watch()` as waiting for `signals`, so while the returned guard is held they're recorded rather than
+    /// terminating the process.
+    pub(crate) fn watching(signals: &[i32]) -> Watching {
+        let signals: Vec<i32> = signals
+            .iter()
+            .copied()
+            .filter(|signal| (*signal as usize) < MAX_SIGNAL)
+            .collect();
+        for signal in &signals {
+            WATCHING[*signal as usize].fetch_add(1, Ordering::SeqCst);
+        }
+        Watching { signals }
+    }
+
+    /// Whether `signal` has been received since this was last called for it.
+    pub(crate) fn take(signal: i32) -> bool {
+        let bit = 1 << (signal as usize % MAX_SIGNAL);
+        RECEIVED.fetch_and(!bit, Ordering::SeqCst) & bit != 0
+    }
+
+    /// Wake `wakeup` as soon as any signal our handler is installed for is received, so `watch()` can return
+    /// `"signal"` straight away rather than at the end of its step. If this fails, signals are still checked on
+    /// each step.
+    pub(crate) fn wake_on_signal(wakeup: &Arc<Wakeup>) {
+        let mut listeners = LISTENERS.lock().unwrap();
+        if WAKE_FD.load(Ordering::SeqCst) < 0 {
+            let Ok((reader, writer)) = UnixStream::pair() else {
+                return;
+            };
+            if writer.set_nonblocking(true).is_err() {
+                return;
+            }
+            spawn(move || wake_listeners(reader));
+            WAKE_FD.store(writer.into_raw_fd(), Ordering::SeqCst);
+        }
+        listeners.retain(|listener| listener.strong_count() > 0);
+        listeners.push(Arc::downgrade(wakeup));
+    }
+
+    /// While held in the main thread, our socket is python's wakeup fd, so signals which python handles itself
+    /// wake `watch()` to run their handlers, see `python_wakeup`.
+    #[derive(Debug)]
+    pub(crate) struct PythonWakeup {
+        // python's wakeup fd before ours, `None` outside the main thread where nothing was replaced
+        previous: Option<i32>,
+    }
+
+    impl Drop for PythonWakeup {
+        fn drop(&mut self) {
+            let Some(previous) = self.previous else {
+                return;
+            };
+            FORWARD_FD.store(-1, Ordering::SeqCst);
+            Python::with_gil(|py| {
+                let _ = py
+                    .import_bound("signal")
+                    .and_then(|signal| signal.call_method1("set_wakeup_fd", (previous,)));
+            });
+        }
+    }
+
+    /// Make our socket python's wakeup fd until the returned guard is dropped, the fd it replaces, e.g. asyncio's,
+    /// is still written to. `None` if signals python handles can't wake `watch()` in this thread, so they must be
+    /// checked for on each step, outside the main thread python doesn't run signal handlers, so there's nothing to
+    /// wake.
+    pub(crate) fn python_wakeup(py: Python) -> Option<PythonWakeup> {
+        let threading = py.import_bound("threading").ok()?;
+        let main_thread = threading.call_method0("main_thread").ok()?;
+        if !threading.call_method0("current_thread").ok()?.is(&main_thread) {
+            return Some(PythonWakeup { previous: None });
+        }
+        let wake_fd = WAKE_FD.load(Ordering::SeqCst);
+        if wake_fd < 0 {
+            return None;
+        }
+        let signal = py.import_bound("signal").ok()?;
+        let previous: i32 = signal.call_method1("set_wakeup_fd", (wake_fd,)).ok()?.extract().ok()?;
+        FORWARD_FD.store(previous, Ordering::SeqCst);
+        Some(PythonWakeup {
+            previous: Some(previous),
+        })
+    }
+
+    fn wake_listeners(mut reader: UnixStream) {
+        let mut buf = [0u8; 64];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return,
+                Ok(n) => {
+                    let forward_fd = FORWARD_FD.load(Ordering::SeqCst);
+                    if forward_fd >= 0 {
+                        unsafe {
+                            libc::write(forward_fd, buf.as_ptr() as *const libc::c_void, n);
+                        }
+                    }
+                    for wakeup in LISTENERS.lock().unwrap().iter().filter_map(Weak::upgrade) {
+                        wakeup.signal();
+                    }
+                }
+                Err(e) if e.kind() == IOErrorKind::Interrupted => (),
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+#[cfg(not(all(unix, feature = "signals")))]
+mod imp {
+    use std::io::{Error as IOError, ErrorKind as IOErrorKind, Result as IOResult};
+    use std::sync::Arc;
+
+    use pyo3::prelude::*;
+
+    use crate::wakeup::Wakeup;
+
+    // not used, since nothing can be installed
+    pub(crate) const SIGINT: i32 = 2;
+    pub(crate) const SIGTERM: i32 = 15;
+
+    #[derive(Debug)]
+    pub(crate) struct SignalClaim;
+
+    impl SignalClaim {
+        pub fn reinstall(&self) -> IOResult<()> {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn claim(_signal: i32) -> IOResult<SignalClaim> {
+        Err(IOError::new(
+            IOErrorKind::Unsupported,
+            "handling signals requires unix and the `signals` feature",
+        ))
+    }
+
+    pub(crate) fn claim_if_handled(signal: i32) -> IOResult<Option<SignalClaim>> {
+        claim(signal).map(Some)
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct Watching;
+
+    pub(crate) fn watching(_signals: &[i32]) -> Watching {
+        Watching
+    }
+
+    pub(crate) fn take(_signal: i32) -> bool {
+        false
+    }
+
+    // signals are only checked on each step
+    pub(crate) fn wake_on_signal(_wakeup: &Arc<Wakeup>) {}
+
+    #[derive(Debug)]
+    pub(crate) struct PythonWakeup;
+
+    pub(crate) fn python_wakeup(_py: Python) -> Option<PythonWakeup> {
+        None
+    }
+}
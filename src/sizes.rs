@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::memory::map_bytes;
+use crate::snapshot::Snapshot;
+use crate::{CHANGE_ADDED, CHANGE_DELETED, CHANGE_MODIFIED};
+
+/// How the size of a modified file changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SizeChange {
+    pub old_size: Option<u64>,
+    pub new_size: u64,
+}
+
+impl SizeChange {
+    /// We only know sizes, not content, so "appended" means the file grew and "replaced" that it was rewritten
+    /// without getting smaller.
+    pub fn kind(&self) -> Option<&'static str> {
+        match self.old_size {
+            Some(old_size) if self.new_size < old_size => Some("truncated"),
+            Some(old_size) if self.new_size > old_size => Some("appended"),
+            Some(_) => Some("replaced"),
+            None => None,
+        }
+    }
+}
+
+/// Tracks file sizes so modifications reported by the poll watcher can be described.
+#[derive(Debug, Default)]
+pub(crate) struct SizeTracker {
+    // only polling-derived changes are tracked, this is set once a poll watcher is created
+    active: AtomicBool,
+    sizes: Mutex<HashMap<String, u64>>,
+    // size changes for files modified in the current batch
+    changes: Mutex<HashMap<String, SizeChange>>,
+}
+
+impl SizeTracker {
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    pub fn activate(&self, watch_paths: &[String], recursive: bool) {
+        *self.sizes.lock().unwrap() = Snapshot::scan(watch_paths, recursive).file_sizes();
+        self.active.store(true, Ordering::SeqCst);
+    }
+
+    pub fn record(&self, change: u8, path: &str) {
+        if !self.active.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut sizes = self.sizes.lock().unwrap();
+        match change {
+            CHANGE_DELETED => {
+                sizes.remove(path);
+            }
+            CHANGE_ADDED | CHANGE_MODIFIED => {
+                let new_size = match fs::metadata(path) {
+                    Ok(metadata) if metadata.is_file() => metadata.len(),
+                    _ => return,
+                };
+                let old_size = sizes.insert(path.to_string(), new_size);
+                if change == CHANGE_MODIFIED {
+                    // if the file is modified more than once in a batch, keep the size from before the first change
+                    let mut changes = self.changes.lock().unwrap();
+                    let size_change = changes
+                        .entry(path.to_string())
+                        .or_insert(SizeChange { old_size, new_size });
+                    size_change.new_size = new_size;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn take_changes(&self) -> HashMap<String, SizeChange> {
+        std::mem::take(&mut *self.changes.lock().unwrap())
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        map_bytes(&self.sizes.lock().unwrap(), |path, _| path.capacity())
+            + map_bytes(&self.changes.lock().unwrap(), |path, _| path.capacity())
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+use walkdir::WalkDir;
+
+use crate::memory::map_bytes;
+use crate::{CHANGE_ADDED, CHANGE_DELETED, CHANGE_MODIFIED};
+
+/// The subset of metadata we compare between two scans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub is_dir: bool,
+    pub size: u64,
+    pub mtime: Option<SystemTime>,
+}
+
+/// A point-in-time view of every path under a set of watch roots, used to find changes the
+/// underlying watcher failed to report.
+#[derive(Debug, Default)]
+pub(crate) struct Snapshot {
+    entries: HashMap<String, Entry>,
+}
+
+impl Snapshot {
+    pub fn scan(roots: &[String], recursive: bool) -> Self {
+        let mut entries = HashMap::new();
+        for root in roots {
+            let max_depth = if recursive { usize::MAX } else { 1 };
+            // errors (permission denied, paths vanishing mid-walk) just leave the entry out of the snapshot
+            for dir_entry in WalkDir::new(root).max_depth(max_depth).into_iter().flatten() {
+                let metadata = match dir_entry.metadata() {
+                    Ok(m) => m,
+                    Err(_) => continue,
+                };
+                if let Some(path) = dir_entry.path().to_str() {
+                    let entry = Entry {
+                        is_dir: metadata.is_dir(),
+                        size: metadata.len(),
+                        mtime: metadata.modified().ok(),
+                    };
+                    entries.insert(path.to_string(), entry);
+                }
+            }
+        }
+        Snapshot { entries }
+    }
+
+    /// Every path below `dir` (but not `dir` itself) as `(path, is_dir)`.
+    pub fn entries_below(dir: &str) -> Vec<(String, bool)> {
+        WalkDir::new(dir)
+            .min_depth(1)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| Some((entry.path().to_str()?.to_string(), entry.file_type().is_dir())))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        map_bytes(&self.entries, |path, _| path.capacity())
+    }
+
+    #[cfg_attr(not(feature = "poll"), allow(dead_code))]
+    pub fn file_sizes(self) -> HashMap<String, u64> {
+        self.entries
+            .into_iter()
+            .filter(|(_, entry)| !entry.is_dir)
+            .map(|(path, entry)| (path, entry.size))
+            .collect()
+    }
+
+    /// Changes required to get from `self` to `new` as `(change, path, is_dir)`.
+    ///
+    /// Directory mtimes are ignored since they change whenever a child is added or removed, which is
+    /// already reported via the child itself.
+    pub fn diff(&self, new: &Snapshot) -> Vec<(u8, String, bool)> {
+        let mut changes = Vec::new();
+        for (path, new_entry) in new.entries.iter() {
+            match self.entries.get(path) {
+                None => changes.push((CHANGE_ADDED, path.clone(), new_entry.is_dir)),
+                Some(old_entry) if old_entry.is_dir != new_entry.is_dir => {
+                    changes.push((CHANGE_DELETED, path.clone(), old_entry.is_dir));
+                    changes.push((CHANGE_ADDED, path.clone(), new_entry.is_dir));
+                }
+                Some(old_entry) if !new_entry.is_dir && old_entry != new_entry => {
+                    changes.push((CHANGE_MODIFIED, path.clone(), false))
+                }
+                _ => (),
+            }
+        }
+        for (path, old_entry) in self.entries.iter() {
+            if !new.entries.contains_key(path) && !Path::new(path).exists() {
+                changes.push((CHANGE_DELETED, path.clone(), old_entry.is_dir));
+            }
+        }
+        changes
+    }
+}
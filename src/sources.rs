@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::memory::map_bytes;
+use crate::pending::PendingChanges;
+
+// sources of changes other than the watcher backends themselves
+pub(crate) const SOURCE_RESCAN: &str = "rescan";
+pub(crate) const SOURCE_SCAN: &str = "scan";
+pub(crate) const SOURCE_ROOT_CHECK: &str = "root_check";
+pub(crate) const SOURCE_SWITCH: &str = "switch";
+pub(crate) const SOURCE_SHARED: &str = "shared";
+
+/// Records which part of the watcher first reported each pending change.
+#[derive(Debug, Default)]
+pub(crate) struct SourceTracker {
+    sources: Mutex<HashMap<(u8, String), &'static str>>,
+}
+
+impl SourceTracker {
+    pub fn record(&self, change: u8, path: &str, source: &'static str) {
+        self.sources
+            .lock()
+            .unwrap()
+            .entry((change, path.to_string()))
+            .or_insert(source);
+    }
+
+    pub fn take(&self) -> HashMap<(u8, String), &'static str> {
+        std::mem::take(&mut *self.sources.lock().unwrap())
+    }
+
+    /// As `take`, but only for `changes`.
+    pub fn take_for(&self, changes: &PendingChanges) -> HashMap<(u8, String), &'static str> {
+        changes.take_annotations(&mut self.sources.lock().unwrap())
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        map_bytes(&self.sources.lock().unwrap(), |(_, path), _| path.capacity())
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Result as IOResult, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::pending::PendingChanges;
+
+static NEXT_SPILL_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Write a change as the change type as a byte, then the length of the path as a little-endian u32, then the path.
+pub(crate) fn write_record(writer: &mut impl Write, change: u8, path: &str) -> IOResult<()> {
+    writer.write_all(&[change])?;
+    writer.write_all(&(path.len() as u32).to_le_bytes())?;
+    writer.write_all(path.as_bytes())
+}
+
+/// Read a change written by `write_record`.
+pub(crate) fn read_record(reader: &mut impl Read) -> IOResult<(u8, String)> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    let mut path = vec![0u8; len];
+    reader.read_exact(&mut path)?;
+    let path = String::from_utf8(path).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).to_string());
+    Ok((header[0], path))
+}
+
+/// Append-only file of changes which didn't fit in memory, read back in order as the buffer is drained.
+///
+/// Each record is written by `write_record`.
+#[derive(Debug)]
+pub(crate) struct Spill {
+    max_pending: usize,
+    file: Mutex<SpillFile>,
+}
+
+#[derive(Debug)]
+struct SpillFile {
+    path: PathBuf,
+    file: File,
+    // offset of the first record which hasn't been read back yet
+    read_offset: u64,
+    pending: usize,
+}
+
+impl Spill {
+    pub fn new(max_pending: usize, dir: &Path) -> IOResult<Self> {
+        let id = NEXT_SPILL_ID.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("watchfiles-spill-{}-{}", std::process::id(), id));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        Ok(Spill {
+            max_pending,
+            file: Mutex::new(SpillFile {
+                path,
+                file,
+                read_offset: 0,
+                pending: 0,
+            }),
+        })
+    }
+
+    /// Whether `changes` is full, new changes should be spilled rather than added to it.
+    pub fn is_full(&self, changes: &PendingChanges) -> bool {
+        changes.len() >= self.max_pending || self.pending() > 0
+    }
+
+    pub fn pending(&self) -> usize {
+        self.file.lock().unwrap().pending
+    }
+
+    pub fn push(&self, change: u8, path: &str) -> IOResult<()> {
+        let mut spill_file = self.file.lock().unwrap();
+        let mut writer = BufWriter::new(&spill_file.file);
+        writer.seek(SeekFrom::End(0))?;
+        write_record(&mut writer, change, path)?;
+        writer.flush()?;
+        drop(writer);
+        spill_file.pending += 1;
+        Ok(())
+    }
+
+    /// Move spilled changes back into `changes` until it's full or there are none left.
+    pub fn refill(&self, changes: &mut PendingChanges) -> IOResult<()> {
+        let mut spill_file = self.file.lock().unwrap();
+        if spill_file.pending == 0 {
+            return Ok(());
+        }
+        let mut reader = BufReader::new(&spill_file.file);
+        reader.seek(SeekFrom::Start(spill_file.read_offset))?;
+        let mut read = 0;
+        while changes.len() < self.max_pending && read < spill_file.pending {
+            let (change, path) = read_record(&mut reader)?;
+            // the insert hooks were called as the change was spilled
+            changes.restore(change, path);
+            read += 1;
+        }
+        let read_offset = reader.stream_position()?;
+        drop(reader);
+        spill_file.read_offset = read_offset;
+        spill_file.pending -= read;
+        if spill_file.pending == 0 {
+            spill_file.reset()?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self) -> IOResult<()> {
+        self.file.lock().unwrap().reset()
+    }
+
+    /// Delete the spill file, nothing more will be read from it.
+    pub fn close(&self) {
+        let mut spill_file = self.file.lock().unwrap();
+        let _ = spill_file.reset();
+        let _ = remove_file(&spill_file.path);
+    }
+}
+
+impl SpillFile {
+    // once everything has been read back the file is truncated so it doesn't grow forever
+    fn reset(&mut self) -> IOResult<()> {
+        self.file.set_len(0)?;
+        self.read_offset = 0;
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
+    }
+}
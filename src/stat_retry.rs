@@ -0,0 +1,55 @@
+use std::collections::HashSet;
+use std::io::ErrorKind as IOErrorKind;
+use std::sync::Mutex;
+use std::thread::sleep;
+
+use crate::memory::set_bytes;
+use crate::WatchRetry;
+
+/// Confirms deletions reported while polling.
+///
+/// A transient failure stat-ing a path during a scan (e.g. `ESTALE` or `EIO` on a network mount) makes the poll
+/// watcher report the path as deleted, then as created again once a later scan succeeds.
+#[derive(Debug)]
+pub(crate) struct StatRetry {
+    retry: WatchRetry,
+    // paths whose deletion wasn't confirmed, so the next creation event for them is the watcher finding them again
+    recovered: Mutex<HashSet<String>>,
+}
+
+impl StatRetry {
+    pub fn new(retry: WatchRetry) -> Self {
+        Self {
+            retry,
+            recovered: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Whether `path` has really been deleted, it's stat-ed again with backoff while errors other than
+    /// "not found" occur, and only counts as deleted if every attempt fails.
+    pub fn confirm_deleted(&self, path: &str) -> bool {
+        let mut delays = self.retry.delays();
+        loop {
+            match std::fs::metadata(path) {
+                Ok(_) => {
+                    self.recovered.lock().unwrap().insert(path.to_string());
+                    return false;
+                }
+                Err(e) if e.kind() == IOErrorKind::NotFound => return true,
+                Err(_) => match delays.next() {
+                    Some(delay) => sleep(delay),
+                    None => return true,
+                },
+            }
+        }
+    }
+
+    /// Whether a creation event for `path` is just the poll watcher finding it again after a transient failure.
+    pub fn take_recovered(&self, path: &str) -> bool {
+        self.recovered.lock().unwrap().remove(path)
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        set_bytes(&self.recovered.lock().unwrap(), |path| path.capacity())
+    }
+}
@@ -0,0 +1,68 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::config::{EffectiveConfig, FilterConfig};
+
+/// How a watch path is being watched, as reported by `watch_state()`.
+#[derive(Debug)]
+pub(crate) struct RootState {
+    pub backend: &'static str,
+    pub recursive: bool,
+    // `None` unless polled
+    pub poll_delay_ms: Option<f64>,
+    // "watched", "nested", "aliased", "skipped" or "waiting"
+    pub status: &'static str,
+    // the path covering a nested or aliased path, or why a path was skipped
+    pub reason: Option<String>,
+    pub filters: FilterConfig,
+}
+
+impl RootState {
+    fn to_py(&self, py: Python) -> PyResult<PyObject> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("backend", self.backend)?;
+        dict.set_item("recursive", self.recursive)?;
+        dict.set_item("poll_delay_ms", self.poll_delay_ms)?;
+        dict.set_item("status", self.status)?;
+        dict.set_item("reason", &self.reason)?;
+        dict.set_item("filters", self.filters.to_py(py)?)?;
+        Ok(dict.into())
+    }
+}
+
+/// Snapshot of what a `RustNotify` instance is doing, for debugging missing events.
+#[derive(Debug)]
+pub(crate) struct WatchState {
+    pub backend: &'static str,
+    pub paused: bool,
+    pub pending_changes: usize,
+    pub steps: u64,
+    pub roots: Vec<(String, RootState)>,
+}
+
+impl WatchState {
+    /// `config` provides the filters which apply to every watch path.
+    pub fn to_py(&self, py: Python, config: &EffectiveConfig) -> PyResult<PyObject> {
+        let roots = PyDict::new_bound(py);
+        for (path, root) in self.roots.iter() {
+            roots.set_item(path, root.to_py(py)?)?;
+        }
+        let filters = PyDict::new_bound(py);
+        filters.set_item("gitignore", config.gitignore)?;
+        filters.set_item("include_globs", config.include_globs.clone())?;
+        filters.set_item("exclude_globs", config.exclude_globs.clone())?;
+        filters.set_item("allow_regexes", config.allow_regexes.clone())?;
+        filters.set_item("deny_regexes", config.deny_regexes.clone())?;
+        filters.set_item("change_mask", config.change_mask)?;
+        filters.set_item("max_depth", config.max_depth)?;
+        filters.set_item("follow_symlinks", config.follow_symlinks)?;
+        let dict = PyDict::new_bound(py);
+        dict.set_item("backend", self.backend)?;
+        dict.set_item("paused", self.paused)?;
+        dict.set_item("pending_changes", self.pending_changes)?;
+        dict.set_item("steps", self.steps)?;
+        dict.set_item("roots", roots)?;
+        dict.set_item("filters", filters)?;
+        Ok(dict.into())
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use notify::RecursiveMode;
+
+use crate::roots::Roots;
+
+/// Watch paths which are files, watched via the directory containing them with `file_targets`.
+///
+/// Editors often save by writing a new file and renaming it over the old one, which removes an OS watch on the
+/// file itself, while a watch on its directory sees the replacement arrive.
+#[derive(Debug, Clone)]
+pub(crate) struct FileTargets {
+    // each file mapped to its directory
+    parents: HashMap<String, PathBuf>,
+    dirs: HashSet<PathBuf>,
+    roots: Roots,
+}
+
+impl FileTargets {
+    pub fn new(watch_paths: &[String]) -> Self {
+        let parents: HashMap<String, PathBuf> = watch_paths
+            .iter()
+            .filter(|path| Path::new(path).is_file())
+            .filter_map(|path| Some((path.clone(), Path::new(path).parent()?.to_path_buf())))
+            .collect();
+        FileTargets {
+            dirs: parents.values().cloned().collect(),
+            parents,
+            roots: Roots::new(watch_paths),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// What to register with the watcher for the watch path `path`, and how.
+    pub fn registration(&self, path: &str, mode: RecursiveMode) -> (PathBuf, RecursiveMode) {
+        match self.parents.get(path) {
+            Some(parent) => (parent.clone(), RecursiveMode::NonRecursive),
+            None => (PathBuf::from(path), mode),
+        }
+    }
+
+    /// What to unwatch to stop watching the watch path `path`, `None` if another file target is watched via the
+    /// same directory.
+    pub fn unregistration(&self, path: &str) -> Option<PathBuf> {
+        match self.parents.get(path) {
+            Some(parent) if self.parents.iter().any(|(other, dir)| other != path && dir == parent) => None,
+            Some(parent) => Some(parent.clone()),
+            None => Some(PathBuf::from(path)),
+        }
+    }
+
+    /// Whether a change to `path` is wanted, rather than to a sibling of a file target or the directory itself.
+    pub fn allows(&self, path: &str) -> bool {
+        let path = Path::new(path);
+        if self.roots.owning_root(path).is_some() {
+            return true;
+        }
+        !self.dirs.contains(path) && !path.parent().map_or(false, |parent| self.dirs.contains(parent))
+    }
+}
@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::memory::map_bytes;
+use crate::pending::PendingChanges;
+
+/// The current time in seconds since the epoch.
+pub(crate) fn epoch_seconds() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since_epoch| since_epoch.as_secs_f64())
+}
+
+/// Records when each pending change first arrived.
+#[derive(Debug, Default)]
+pub(crate) struct TimestampTracker {
+    timestamps: Mutex<HashMap<(u8, String), f64>>,
+}
+
+impl TimestampTracker {
+    pub fn record(&self, change: u8, path: &str) {
+        self.timestamps
+            .lock()
+            .unwrap()
+            .entry((change, path.to_string()))
+            .or_insert_with(epoch_seconds);
+    }
+
+    pub fn take(&self) -> HashMap<(u8, String), f64> {
+        std::mem::take(&mut *self.timestamps.lock().unwrap())
+    }
+
+    /// As `take`, but only for `changes`.
+    pub fn take_for(&self, changes: &PendingChanges) -> HashMap<(u8, String), f64> {
+        changes.take_annotations(&mut self.timestamps.lock().unwrap())
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        map_bytes(&self.timestamps.lock().unwrap(), |(_, path), _| path.capacity())
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+use std::io::Result as IOResult;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+use pyo3::prelude::*;
+
+use crate::ready::ReadySignal;
+
+#[derive(Debug, Default)]
+struct WakeupState {
+    // how many changes have arrived, so waiters can tell whether any arrived since they last looked
+    arrivals: u64,
+    // whether changes are pending, and how many times that's started being the case
+    ready: bool,
+    batches: u64,
+    // the threads started by `call_soon_on_batch` which haven't been stopped, and the ID of the last one
+    listeners: HashSet<u64>,
+    last_listener: u64,
+    // set by `stop()` until `watch()` returns due to it
+    stopped: bool,
+    // set when a signal is received or an error reported until `watch()` next wakes to check for them
+    woken: bool,
+    // how many times `stop()` has been called or an error reported, so waiting event loops can be woken to see
+    alerts: u64,
+}
+
+/// Signalled each time a new change is added, so `watch()` can wake as soon as the first change of a batch
+/// arrives rather than finding it on its next step.
+#[derive(Debug, Default)]
+pub(crate) struct Wakeup {
+    state: Mutex<WakeupState>,
+    changed: Condvar,
+    // only created once `fileno()` or `event_handle()` is called
+    ready_signal: Mutex<Option<ReadySignal>>,
+}
+
+impl Wakeup {
+    pub fn notify(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.arrivals += 1;
+        if !state.ready {
+            state.ready = true;
+            state.batches += 1;
+        }
+        drop(state);
+        self.changed.notify_all();
+        self.set_signal(true);
+    }
+
+    /// Update whether changes are pending, should be called with the lock on the pending changes held, as
+    /// `notify` is, so the two can't disagree.
+    pub fn set_ready(&self, ready: bool) {
+        let mut state = self.state.lock().unwrap();
+        if ready && !state.ready {
+            state.batches += 1;
+        }
+        state.ready = ready;
+        drop(state);
+        self.changed.notify_all();
+        self.set_signal(ready);
+    }
+
+    fn set_signal(&self, ready: bool) {
+        if let Some(ready_signal) = self.ready_signal.lock().unwrap().as_mut() {
+            ready_signal.set(ready);
+        }
+    }
+
+    /// The file descriptor or event handle of the readiness signal, created the first time it's needed,
+    /// `pending` is whether there are already changes pending.
+    pub fn ready_handle(&self, pending: bool) -> IOResult<i64> {
+        let mut ready_signal = self.ready_signal.lock().unwrap();
+        if ready_signal.is_none() {
+            let mut new_signal = ReadySignal::new()?;
+            new_signal.set(pending);
+            *ready_signal = Some(new_signal);
+        }
+        Ok(ready_signal.as_ref().map_or(-1, |ready_signal| ready_signal.handle()))
+    }
+
+    pub fn arrivals(&self) -> u64 {
+        self.state.lock().unwrap().arrivals
+    }
+
+    /// Wait up to `timeout` for a change to arrive after `seen` arrivals, or just `timeout` with `seen=None`,
+    /// either way returning early if `stop()` is called, a signal is received or an error is reported, returns
+    /// the number of arrivals so far.
+    pub fn wait(&self, seen: Option<u64>, timeout: Duration) -> u64 {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .changed
+            .wait_timeout_while(state, timeout, |state| {
+                !state.stopped && !state.woken && seen.map_or(true, |seen| state.arrivals == seen)
+            })
+            .unwrap();
+        state.arrivals
+    }
+
+    /// Make `watch()` return `"stop"`, waking it straight away.
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.stopped = true;
+        state.alerts += 1;
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    /// Wake `watch()` to raise an error reported by one of the watcher's threads.
+    pub fn error(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.woken = true;
+        state.alerts += 1;
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    /// Whether `stop()` has been called since this last returned `true`.
+    pub fn take_stopped(&self) -> bool {
+        std::mem::take(&mut self.state.lock().unwrap().stopped)
+    }
+
+    /// Wake `watch()` to check for signals, called once a signal has been received.
+    #[cfg_attr(not(all(unix, feature = "signals")), allow(dead_code))]
+    pub fn signal(&self) {
+        self.state.lock().unwrap().woken = true;
+        self.changed.notify_all();
+    }
+
+    /// Whether `signal()` or `error()` has been called since this last returned `true`.
+    pub fn take_woken(&self) -> bool {
+        std::mem::take(&mut self.state.lock().unwrap().woken)
+    }
+
+    /// Call `call_soon_threadsafe(callback)` from a new thread each time changes start being pending, including
+    /// straight away if they already are, until the returned `LoopWakeups` is dropped. With `alerts`, it's also
+    /// called each time `stop()` is called or an error is reported.
+    pub fn call_soon_on_batch(
+        self: &Arc<Self>,
+        call_soon_threadsafe: PyObject,
+        callback: PyObject,
+        alerts: bool,
+        debug: bool,
+    ) -> LoopWakeups {
+        let mut state = self.state.lock().unwrap();
+        state.last_listener += 1;
+        let listener = state.last_listener;
+        state.listeners.insert(listener);
+        let mut seen = match state.ready {
+            true => state.batches - 1,
+            false => state.batches,
+        };
+        let mut seen_alerts = state.alerts;
+        drop(state);
+
+        let wakeup = self.clone();
+        spawn(move || {
+            while let Some((batches, alert_count)) = wakeup.wait_for_batch(seen, seen_alerts, alerts, listener) {
+                seen = batches;
+                seen_alerts = alert_count;
+                if let Err(err) = Python::with_gil(|py| call_soon_threadsafe.call1(py, (&callback,))) {
+                    // most likely the event loop has been closed
+                    if debug {
+                        eprintln!("error waking event loop, no longer waking it: {}", err);
+                    }
+                    break;
+                }
+            }
+        });
+        LoopWakeups {
+            wakeup: self.clone(),
+            listener,
+        }
+    }
+
+    // the numbers of batches and alerts so far, or `None` once `listener` has been stopped
+    fn wait_for_batch(&self, seen: u64, seen_alerts: u64, alerts: bool, listener: u64) -> Option<(u64, u64)> {
+        let state = self.state.lock().unwrap();
+        let state = self
+            .changed
+            .wait_while(state, |state| {
+                state.batches == seen && (!alerts || state.alerts == seen_alerts) && state.listeners.contains(&listener)
+            })
+            .unwrap();
+        match state.listeners.contains(&listener) {
+            true => Some((state.batches, state.alerts)),
+            false => None,
+        }
+    }
+}
+
+/// Stops the thread started by `Wakeup::call_soon_on_batch` when dropped.
+#[derive(Debug)]
+pub(crate) struct LoopWakeups {
+    wakeup: Arc<Wakeup>,
+    listener: u64,
+}
+
+impl Drop for LoopWakeups {
+    fn drop(&mut self) {
+        let mut state = self.wakeup.state.lock().unwrap();
+        if state.listeners.remove(&self.listener) {
+            drop(state);
+            self.wakeup.changed.notify_all();
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use notify::ErrorKind as NotifyErrorKind;
+use pyo3::prelude::*;
+use walkdir::WalkDir;
+
+use crate::paths::watched_path;
+use crate::WatchLimitError;
+
+const ENOSPC: i32 = 28;
+const EMFILE: i32 = 24;
+
+/// What happens when the inotify limit on watches is reached while registering a watch path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WatchLimitPolicy {
+    Error,
+    Poll,
+}
+
+impl WatchLimitPolicy {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "error" => Ok(WatchLimitPolicy::Error),
+            "poll" => Ok(WatchLimitPolicy::Poll),
+            _ => Err(format!("Invalid watch_limit value {:?}, must be 'error' or 'poll'", s)),
+        }
+    }
+}
+
+/// The inotify limits which, once exhausted, mean no more directories can be watched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WatchLimit {
+    // `fs.inotify.max_user_watches`, the number of directories a user can watch across all their instances
+    Watches,
+    // `fs.inotify.max_user_instances`, the number of inotify instances a user can create
+    Instances,
+}
+
+impl WatchLimit {
+    /// Which limit `error` means has been reached, if any, only inotify reports these.
+    pub fn reached(error: &notify::Error) -> Option<Self> {
+        if !cfg!(target_os = "linux") {
+            return None;
+        }
+        match &error.kind {
+            NotifyErrorKind::MaxFilesWatch => Some(WatchLimit::Watches),
+            NotifyErrorKind::Io(io_error) if io_error.raw_os_error() == Some(EMFILE) => Some(WatchLimit::Instances),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            WatchLimit::Watches => "max_user_watches",
+            WatchLimit::Instances => "max_user_instances",
+        }
+    }
+
+    fn errno(self) -> i32 {
+        match self {
+            WatchLimit::Watches => ENOSPC,
+            WatchLimit::Instances => EMFILE,
+        }
+    }
+
+    /// The configured limit, read from `/proc/sys/fs/inotify`.
+    fn configured(self) -> Option<u64> {
+        let limit = fs::read_to_string(format!("/proc/sys/fs/inotify/{}", self.name())).ok()?;
+        limit.trim().parse().ok()
+    }
+
+    /// How many watches or instances are in use by the processes we can inspect, which are normally all those of
+    /// the current user, who the limit applies to.
+    fn usage(self) -> Option<u64> {
+        let mut usage = 0;
+        for process in fs::read_dir("/proc").ok()?.flatten() {
+            let process = process.path();
+            let is_pid = process
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| name.bytes().all(|b| b.is_ascii_digit()));
+            if is_pid {
+                usage += self.process_usage(&process);
+            }
+        }
+        Some(usage)
+    }
+
+    /// How many watches or instances are in use by the process whose directory in `/proc` is `process`, `0` if it
+    /// can't be inspected.
+    pub fn process_usage(self, process: &Path) -> u64 {
+        let Ok(fds) = fs::read_dir(process.join("fd")) else {
+            return 0;
+        };
+        let mut usage = 0;
+        for fd in fds.flatten() {
+            let is_inotify = fs::read_link(fd.path()).map_or(false, |target| target == Path::new("anon_inode:inotify"));
+            if !is_inotify {
+                continue;
+            }
+            usage += match self {
+                WatchLimit::Instances => 1,
+                WatchLimit::Watches => fs::read_to_string(process.join("fdinfo").join(fd.file_name()))
+                    .map_or(0, |info| {
+                        info.lines().filter(|line| line.starts_with("inotify wd:")).count() as u64
+                    }),
+            };
+        }
+        usage
+    }
+
+    /// The error to raise once the limit's been reached while watching `watch_paths`.
+    pub fn to_py(self, py: Python, watch_paths: &[String], recursive: bool) -> PyErr {
+        let limit = self.configured();
+        let usage = self.usage();
+        let directories = count_directories(watch_paths, recursive);
+        let describe = |value: Option<u64>| value.map_or_else(|| "unknown".to_string(), |value| value.to_string());
+        let message = match self {
+            WatchLimit::Watches => format!(
+                "inotify watch limit reached: {} of fs.inotify.max_user_watches={} watches in use, watchfiles \
+                 tried to watch {} directories; increase the limit, e.g. with \
+                 `sudo sysctl fs.inotify.max_user_watches=524288`, watch fewer directories or use `force_polling`",
+                describe(usage),
+                describe(limit),
+                directories,
+            ),
+            WatchLimit::Instances => format!(
+                "inotify instance limit reached: {} of fs.inotify.max_user_instances={} instances in use, \
+                 watchfiles tried to watch {} directories; increase the limit, e.g. with \
+                 `sudo sysctl fs.inotify.max_user_instances=1024`, close unused watchers or use `force_polling`",
+                describe(usage),
+                describe(limit),
+                directories,
+            ),
+        };
+        let err = WatchLimitError::new_err((self.errno(), message));
+        let value = err.value_bound(py);
+        let attrs = [
+            ("limit_name", format!("fs.inotify.{}", self.name()).to_object(py)),
+            ("limit", limit.to_object(py)),
+            ("usage", usage.to_object(py)),
+            ("directories", directories.to_object(py)),
+        ];
+        for (name, attr) in attrs {
+            if let Err(e) = value.setattr(name, attr) {
+                return e;
+            }
+        }
+        err
+    }
+}
+
+/// The directories under `target` which weren't watched when registering it failed at `failed`, as the roots of
+/// the fewest subtrees covering them.
+///
+/// notify registers a recursive watch path one directory at a time in walk order, and keeps the watches it added
+/// before failing, so everything from `failed` onwards in the same walk is unwatched.
+pub(crate) fn unwatched_subtrees(target: &Path, failed: Option<&Path>, recursive: bool) -> Vec<String> {
+    let target = watched_path(target);
+    let mut subtrees: Vec<PathBuf> = Vec::new();
+    if let (Some(failed), true) = (failed, recursive) {
+        let mut walker = WalkDir::new(&target).follow_links(true).into_iter();
+        while let Some(entry) = walker.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            if !entry.file_type().is_dir() || (subtrees.is_empty() && entry.path() != failed) {
+                continue;
+            }
+            // the rest of the walk is outside this subtree, so each directory it finds starts another
+            subtrees.push(entry.into_path());
+            walker.skip_current_dir();
+        }
+    }
+    // e.g. the tree changed since notify walked it
+    if subtrees.is_empty() {
+        subtrees.push(target);
+    }
+    subtrees.into_iter().map(|p| p.to_string_lossy().to_string()).collect()
+}
+
+/// How many directories watching `watch_paths` requires a watch for.
+fn count_directories(watch_paths: &[String], recursive: bool) -> u64 {
+    let mut directories = 0;
+    for watch_path in watch_paths {
+        let walker = match recursive {
+            true => WalkDir::new(watch_path),
+            false => WalkDir::new(watch_path).max_depth(0),
+        };
+        directories += walker
+            .into_iter()
+            .flatten()
+            .filter(|entry| entry.file_type().is_dir())
+            .count() as u64;
+    }
+    directories
+}
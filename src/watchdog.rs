@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Checks natively watched paths are still receiving events while `watch()` runs, so a watcher which has
+/// silently stopped (e.g. after its inotify descriptor was invalidated) can be rebuilt.
+///
+/// Each check writes and deletes a probe file in every watch path, the next check looks for events from those
+/// probes before writing new ones.
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    interval: Duration,
+    next_check: Instant,
+    // watch paths and the probes written in them by the last check
+    probes: Vec<(String, String)>,
+    // how many times each watch path's watcher has been rebuilt
+    restarts: HashMap<String, u32>,
+}
+
+impl Watchdog {
+    pub fn new(interval: Duration) -> Self {
+        Watchdog {
+            interval,
+            next_check: Instant::now() + interval,
+            probes: Vec::new(),
+            restarts: HashMap::new(),
+        }
+    }
+
+    /// If a check is due, the probes written by the last check, the caller should then write new ones
+    /// with `set_probes`.
+    pub fn due(&mut self) -> Option<Vec<(String, String)>> {
+        let now = Instant::now();
+        if now < self.next_check {
+            return None;
+        }
+        self.next_check = now + self.interval;
+        Some(std::mem::take(&mut self.probes))
+    }
+
+    pub fn set_probes(&mut self, probes: Vec<(String, String)>) {
+        self.probes = probes;
+    }
+
+    /// Forget the probes written by the last check, so the next check doesn't look for their events.
+    pub fn take_probes(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.probes)
+    }
+
+    /// Record `path`'s watcher being rebuilt, returns how many times it has been rebuilt including this one.
+    pub fn record_restart(&mut self, path: &str) -> u32 {
+        let restarts = self.restarts.entry(path.to_string()).or_default();
+        *restarts += 1;
+        *restarts
+    }
+
+    pub fn restarts(&self) -> HashMap<String, u32> {
+        self.restarts.clone()
+    }
+}
@@ -1,12 +1,27 @@
+import asyncio
+import gc
 import os
 import re
+import select
+import signal
+import subprocess
 import sys
+import weakref
 from pathlib import Path
-from typing import TYPE_CHECKING
+from threading import Thread, Timer, main_thread
+from time import perf_counter, sleep, time
+from typing import TYPE_CHECKING, List, Set, Tuple
 
 import pytest
 
-from watchfiles._rust_notify import RustNotify
+from watchfiles._rust_notify import (
+    ManualClock,
+    RustNotify,
+    WatchfilesRustInternalError,
+    WatchLimitError,
+    WatchResult,
+    backend_capabilities,
+)
 from watchfiles.main import _default_ignore_permission_denied
 
 if TYPE_CHECKING:
@@ -321,6 +336,14 @@ def test_ignore_permission_denied():
         RustNotify([path], False, False, 0, True, False)
 
 
+def test_ignore_permission_denied_skipped_paths():
+    path = os.getenv('WATCHFILES_TEST_PERMISSION_DENIED_PATH') or '/'
+
+    watcher = RustNotify([path], False, False, 0, True, True)
+    assert list(watcher.skipped_paths.keys()) == [path]
+    assert 'Permission denied' in watcher.skipped_paths[path]
+
+
 @pytest.mark.parametrize(
     'env_var,arg,expected',
     [
@@ -339,3 +362,2519 @@ def test_default_ignore_permission_denied(env: 'SetEnv', env_var, arg, expected)
     if env_var is not None:
         env('WATCHFILES_IGNORE_PERMISSION_DENIED', env_var)
     assert _default_ignore_permission_denied(arg) == expected
+
+
+@pytest.mark.parametrize('linger_ms,expected', [(0, {'foo.txt'}), (500, {'foo.txt', 'bar.txt'})])
+def test_linger(tmp_path: Path, linger_ms: int, expected: Set[str]):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    Timer(0.2, (tmp_path / 'bar.txt').write_text, args=('foobar',)).start()
+
+    changes = watcher.watch(50, 10, 1000, None, linger_ms=linger_ms)
+    assert {Path(path).name for _, path in changes} == expected
+
+
+def test_rescan_catches_missed(tmp_path: Path):
+    # the poll watcher won't scan again within the test, so only the rescan can see the new file
+    watcher = RustNotify([str(tmp_path)], False, True, 60_000, True, False, rescan_interval_ms=50)
+
+    (tmp_path / 'missed.txt').write_text('foobar')
+
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(tmp_path / 'missed.txt'))}
+
+
+def test_max_file_size(tmp_path: Path):
+    big = tmp_path / 'big.txt'
+    big.write_text('x' * 100)
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, max_file_size=10)
+
+    (tmp_path / 'small.txt').write_text('foobar')
+    with big.open('a') as f:
+        f.write('more')
+
+    assert watcher.watch(200, 50, 500, None) == {(1, str(tmp_path / 'small.txt'))}
+
+
+@skip_unless_linux
+def test_files_only(tmp_path: Path):
+    (tmp_path / 'old_dir').mkdir()
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, files_only=True)
+
+    (tmp_path / 'new_dir').mkdir()
+    (tmp_path / 'old_dir').rmdir()
+    (tmp_path / 'foo.txt').write_text('foobar')
+
+    assert watcher.watch(200, 50, 500, None) == {(1, str(tmp_path / 'foo.txt'))}
+
+
+@skip_unless_linux
+@pytest.mark.parametrize(
+    'file_types,expected',
+    [('regular', {'foo.txt'}), ('regular_and_symlinks', {'foo.txt', 'link'}), ('all', {'foo.txt', 'link', 'fifo'})],
+)
+def test_file_types(tmp_path: Path, file_types: str, expected: Set[str]):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, file_types=file_types)
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    (tmp_path / 'link').symlink_to(tmp_path / 'foo.txt')
+    os.mkfifo(tmp_path / 'fifo')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert {Path(path).name for _, path in changes} == expected
+
+
+@skip_unless_linux
+def test_file_types_deleted(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, file_types='regular')
+
+    os.mkfifo(tmp_path / 'fifo')
+    (tmp_path / 'foo.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 500, None) == {(1, str(tmp_path / 'foo.txt'))}
+
+    (tmp_path / 'fifo').unlink()
+    (tmp_path / 'foo.txt').unlink()
+    assert watcher.watch(200, 50, 500, None) == {(3, str(tmp_path / 'foo.txt'))}
+
+
+def test_file_types_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='Invalid file_types value "foobar"'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, file_types='foobar')
+
+
+@skip_unless_linux
+@pytest.mark.parametrize(
+    'collapse_renames,expected',
+    [(False, {(3, 'a.txt'), (1, 'b.txt'), (3, 'b.txt'), (1, 'c.txt')}), (True, {(3, 'a.txt'), (1, 'c.txt')})],
+)
+def test_collapse_renames(tmp_path: Path, collapse_renames: bool, expected: Set[Tuple[int, str]]):
+    (tmp_path / 'a.txt').write_text('foobar')
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, collapse_renames=collapse_renames)
+
+    (tmp_path / 'a.txt').rename(tmp_path / 'b.txt')
+    (tmp_path / 'b.txt').rename(tmp_path / 'c.txt')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert {(change, Path(path).name) for change, path in changes} == expected
+
+
+@skip_unless_linux
+@pytest.mark.parametrize(
+    'collapse_renames,expected',
+    [(False, {(4, 'a.txt', 'b.txt'), (4, 'b.txt', 'c.txt')}), (True, {(4, 'a.txt', 'c.txt')})],
+)
+def test_rename_pairs(tmp_path: Path, collapse_renames: bool, expected: Set[Tuple[int, str, str]]):
+    (tmp_path / 'a.txt').write_text('foobar')
+    watcher = RustNotify(
+        [str(tmp_path)], False, False, 0, True, False, rename_pairs=True, collapse_renames=collapse_renames
+    )
+
+    (tmp_path / 'a.txt').rename(tmp_path / 'b.txt')
+    (tmp_path / 'b.txt').rename(tmp_path / 'c.txt')
+    (tmp_path / 'd.txt').write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    renames = {(change, Path(old).name, Path(new).name) for change, old, new in (c for c in changes if len(c) == 3)}
+    assert renames == expected
+    assert (1, str(tmp_path / 'd.txt')) in changes
+    assert not any(Path(path).name in {'a.txt', 'b.txt', 'c.txt'} for _, path in (c for c in changes if len(c) == 2))
+
+
+@skip_windows
+def test_gitignore(tmp_path: Path):
+    (tmp_path / '.git').mkdir()
+    (tmp_path / '.gitignore').write_text('target/\n*.log\n!keep.log\n/build\n')
+    src = tmp_path / 'src'
+    src.mkdir()
+    (src / '.gitignore').write_text('*.tmp\n')
+    (tmp_path / 'target').mkdir()
+    watcher = RustNotify([str(src), str(tmp_path / 'target')], False, False, 0, True, False, gitignore=True)
+
+    (tmp_path / 'target' / 'out.txt').write_text('foobar')
+    (src / 'debug.log').write_text('foobar')
+    (src / 'keep.log').write_text('foobar')
+    (src / 'scratch.tmp').write_text('foobar')
+    (src / 'build').write_text('foobar')
+    (src / 'main.rs').write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert {Path(path).name for _, path in changes} == {'keep.log', 'build', 'main.rs'}
+
+
+@skip_windows
+@pytest.mark.parametrize('configured', [False, True])
+def test_gitignore_global(tmp_path: Path, configured: bool):
+    home, repo = tmp_path / 'home', tmp_path / 'repo'
+    (home / '.config' / 'git').mkdir(parents=True)
+    (home / '.config' / 'git' / 'ignore').write_text('*.log\n')
+    (home / 'excludes').write_text('*.tmp\n')
+    (repo / '.git').mkdir(parents=True)
+    if configured:
+        # core.excludesFile replaces the default global excludes file
+        (repo / '.git' / 'config').write_text('[core]\n\texcludesFile = ~/excludes\n')
+    environ = {k: os.environ.get(k) for k in ('HOME', 'XDG_CONFIG_HOME')}
+    os.environ['HOME'] = str(home)
+    os.environ.pop('XDG_CONFIG_HOME', None)
+    try:
+        watcher = RustNotify([str(repo)], False, False, 0, True, False, gitignore=True)
+    finally:
+        for k, v in environ.items():
+            if v is None:
+                os.environ.pop(k, None)
+            else:
+                os.environ[k] = v
+
+    for name in ('debug.log', 'scratch.tmp', 'main.rs'):
+        (repo / name).write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    expected = {'debug.log', 'main.rs'} if configured else {'scratch.tmp', 'main.rs'}
+    assert {Path(path).name for _, path in changes} == expected
+
+
+@skip_windows
+@pytest.mark.parametrize('with_git', [True, False])
+def test_gitignore_global_include(tmp_path: Path, with_git: bool):
+    home, repo = tmp_path / 'home', tmp_path / 'repo'
+    home.mkdir()
+    # read by git when it can be run, otherwise by us
+    (home / '.gitconfig').write_text('[include]\n\tpath = extra.gitconfig\n')
+    (home / 'extra.gitconfig').write_text('[core]\n\texcludesFile = "~/my excludes" ; quoted\n')
+    (home / 'my excludes').write_text('*.tmp\n')
+    (repo / '.git').mkdir(parents=True)
+    environ = {k: os.environ.get(k) for k in ('HOME', 'XDG_CONFIG_HOME', 'PATH')}
+    os.environ['HOME'] = str(home)
+    os.environ.pop('XDG_CONFIG_HOME', None)
+    if not with_git:
+        os.environ['PATH'] = str(tmp_path / 'bin')
+    try:
+        watcher = RustNotify([str(repo)], False, False, 0, True, False, gitignore=True)
+    finally:
+        for k, v in environ.items():
+            if v is None:
+                os.environ.pop(k, None)
+            else:
+                os.environ[k] = v
+
+    for name in ('scratch.tmp', 'main.rs'):
+        (repo / name).write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert {Path(path).name for _, path in changes} == {'main.rs'}
+
+
+@skip_windows
+def test_gitignore_escapes_and_deleted_dirs(tmp_path: Path):
+    (tmp_path / '.git').mkdir()
+    # unescaped trailing spaces are dropped, an escaped one is part of the pattern
+    (tmp_path / '.gitignore').write_text('spaced\\ \ncache/  \n')
+    # when polling, deletions don't say whether the path was a directory
+    watcher = RustNotify([str(tmp_path)], False, True, 50, True, False, gitignore=True)
+
+    (tmp_path / 'cache').mkdir()
+    (tmp_path / 'spaced ').write_text('foobar')
+    (tmp_path / 'spaced').write_text('foobar')
+    changes = watcher.watch(200, 50, 1000, None)
+    assert {Path(path).name for _, path in changes} == {'spaced'}
+
+    (tmp_path / 'cache').rmdir()
+    (tmp_path / 'main.rs').write_text('foobar')
+    changes = watcher.watch(200, 50, 1000, None)
+    assert {Path(path).name for _, path in changes} == {'main.rs'}
+
+
+def test_globs(tmp_path: Path):
+    (tmp_path / 'node_modules').mkdir()
+    (tmp_path / 'src').mkdir()
+    watcher = RustNotify(
+        [str(tmp_path)],
+        False,
+        False,
+        0,
+        True,
+        False,
+        include_globs=['*.py', 'docs/*.md'],
+        exclude_globs=['node_modules', 'test_*.py'],
+    )
+
+    (tmp_path / 'node_modules' / 'foo.py').write_text('foobar')
+    (tmp_path / 'src' / 'main.py').write_text('foobar')
+    (tmp_path / 'src' / 'test_main.py').write_text('foobar')
+    (tmp_path / 'src' / 'main.rs').write_text('foobar')
+    (tmp_path / 'README.md').write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert {Path(path).name for _, path in changes} == {'main.py'}
+
+
+@skip_windows
+@pytest.mark.parametrize(
+    'pattern,matched,unmatched',
+    [
+        ('**/foo.txt', ['foo.txt', 'a/b/foo.txt'], ['a/xfoo.txt', 'a/foo.txt.bak']),
+        ('a/**', ['a/x.txt', 'a/b/y.txt'], ['ab/z.txt', 'x.txt']),
+        ('[!ab]*.txt', ['c.txt', 'a/c1.txt'], ['a.txt', 'b1.txt']),
+        ('[]x].txt', ['].txt', 'x.txt'], ['y.txt']),
+        (r'\*.txt', ['*.txt'], ['x.txt']),
+        (r'\[x\].txt', ['[x].txt'], ['x.txt']),
+        (r'[\]]?.txt', [']1.txt'], ['\\1.txt', 'a1.txt']),
+        # `**` other than as a whole path component doesn't match `/`
+        ('a/**b.txt', ['a/b.txt', 'a/xb.txt'], ['a/c/xb.txt']),
+        ('a**/b.txt', ['a/b.txt', 'ax/b.txt'], ['ax/c/b.txt']),
+        ('a/**/b.txt', ['a/b.txt', 'a/c/d/b.txt'], ['ax/b.txt']),
+    ],
+)
+def test_globs_edge_cases(tmp_path: Path, pattern: str, matched: List[str], unmatched: List[str]):
+    for name in matched + unmatched:
+        (tmp_path / name).parent.mkdir(parents=True, exist_ok=True)
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, include_globs=[pattern])
+
+    for name in matched + unmatched:
+        (tmp_path / name).write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert {Path(path).relative_to(tmp_path).as_posix() for _, path in changes} == set(matched)
+
+
+def test_globs_invalid():
+    with pytest.raises(ValueError, match='Invalid glob'):
+        RustNotify(['.'], False, False, 0, True, False, exclude_globs=['[abc'])
+
+
+@skip_windows
+def test_regexes(tmp_path: Path):
+    watcher = RustNotify(
+        [str(tmp_path)],
+        False,
+        False,
+        0,
+        True,
+        False,
+        allow_regexes=[r'\.py$', r'^.*/settings\.toml$'],
+        deny_regexes=[r'_v\d+\.py$'],
+    )
+
+    (tmp_path / 'main.py').write_text('foobar')
+    (tmp_path / 'migration_v2.py').write_text('foobar')
+    (tmp_path / 'settings.toml').write_text('foobar')
+    (tmp_path / 'main.rs').write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert {Path(path).name for _, path in changes} == {'main.py', 'settings.toml'}
+
+
+def test_regexes_invalid():
+    with pytest.raises(ValueError, match='Invalid deny_regexes pattern'):
+        RustNotify(['.'], False, False, 0, True, False, deny_regexes=['(foo'])
+
+
+def test_change_mask(tmp_path: Path):
+    (tmp_path / 'a.txt').write_text('foobar')
+    (tmp_path / 'b.txt').write_text('foobar')
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, change_mask=(1 << 1) | (1 << 3))
+
+    (tmp_path / 'a.txt').write_text('changed')
+    (tmp_path / 'b.txt').unlink()
+    (tmp_path / 'c.txt').write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert changes == {(3, str(tmp_path / 'b.txt')), (1, str(tmp_path / 'c.txt'))}
+
+
+@pytest.mark.parametrize('change_mask', [0, 1, 1 << 7])
+def test_change_mask_invalid(change_mask: int):
+    with pytest.raises(ValueError, match='Invalid change_mask value'):
+        RustNotify(['.'], False, False, 0, True, False, change_mask=change_mask)
+
+
+def test_timestamp(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, timestamp=True, source=True)
+
+    start = time()
+    (tmp_path / 'foo.txt').write_text('foobar')
+    changes = watcher.watch(200, 50, 500, None)
+    end = time()
+
+    assert len(changes) == 1
+    change, path, source, timestamp = changes.pop()
+    assert (change, path) == (1, str(tmp_path / 'foo.txt'))
+    # the timestamp comes after the source
+    assert isinstance(source, str)
+    assert start <= timestamp <= end
+
+
+@skip_unless_linux
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_is_dir(tmp_path: Path, force_polling: bool):
+    # when polling, deletions don't say whether the path was a directory
+    watcher = RustNotify([str(tmp_path)], False, force_polling, 50, True, False, is_dir=True)
+
+    (tmp_path / 'foo').mkdir()
+    (tmp_path / 'foo' / 'bar.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 500, None) == {
+        (1, str(tmp_path / 'foo'), True),
+        (1, str(tmp_path / 'foo' / 'bar.txt'), False),
+    }
+
+    (tmp_path / 'foo' / 'bar.txt').unlink()
+    (tmp_path / 'foo').rmdir()
+    assert watcher.watch(200, 50, 500, None) == {
+        (3, str(tmp_path / 'foo'), True),
+        (3, str(tmp_path / 'foo' / 'bar.txt'), False),
+    }
+
+
+@skip_unless_linux
+@pytest.mark.parametrize(
+    'keep_duplicates,expected',
+    [
+        (False, [(2, 'c.txt'), (2, 'a.txt'), (2, 'b.txt')]),
+        (True, [(2, 'c.txt'), (2, 'a.txt'), (2, 'b.txt'), (2, 'c.txt')]),
+    ],
+)
+def test_ordered(tmp_path: Path, keep_duplicates: bool, expected: List[Tuple[int, str]]):
+    for name in 'a.txt', 'b.txt', 'c.txt':
+        (tmp_path / name).write_text('foobar')
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, ordered=True, keep_duplicates=keep_duplicates)
+
+    for name in 'c.txt', 'a.txt', 'b.txt', 'c.txt':
+        with (tmp_path / name).open('a') as f:
+            f.write('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert isinstance(changes, list)
+    assert [(change, Path(path).name) for change, path in changes] == expected
+
+
+def test_keep_duplicates_not_ordered():
+    with pytest.raises(ValueError, match='`keep_duplicates` requires `ordered=True`'):
+        RustNotify(['.'], False, False, 0, True, False, keep_duplicates=True)
+
+
+@skip_unless_linux
+def test_close_write(tmp_path: Path):
+    watcher = RustNotify(
+        [str(tmp_path)], False, False, 0, True, False, close_write=True, change_mask=(1 << 1) | (1 << 5)
+    )
+
+    with (tmp_path / 'foo.txt').open('w') as f:
+        for _ in range(3):
+            f.write('foobar')
+            f.flush()
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert changes == {(1, str(tmp_path / 'foo.txt')), (5, str(tmp_path / 'foo.txt'))}
+
+
+@skip_unless_linux
+@pytest.mark.parametrize('metadata_changes,expected', [('modified', {2}), ('attribute', {6}), ('ignore', set())])
+def test_metadata_changes(tmp_path: Path, metadata_changes: str, expected: Set[int]):
+    (tmp_path / 'foo.txt').write_text('foobar')
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, metadata_changes=metadata_changes)
+
+    (tmp_path / 'foo.txt').chmod(0o600)
+    os.utime(tmp_path / 'foo.txt', (1, 1))
+
+    changes = watcher.watch(200, 50, 200, None)
+    if expected:
+        assert changes == {(change, str(tmp_path / 'foo.txt')) for change in expected}
+    else:
+        assert changes == 'timeout'
+
+
+def test_metadata_changes_invalid():
+    with pytest.raises(ValueError, match='Invalid metadata_changes value "foo"'):
+        RustNotify(['.'], False, False, 0, True, False, metadata_changes='foo')
+
+
+@skip_unless_linux
+@pytest.mark.skipif(sys.platform == 'linux' and os.geteuid() != 0, reason='fanotify requires CAP_SYS_ADMIN')
+def test_fanotify_process(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, fanotify=True, process=True)
+    assert watcher.effective_config['backend'] == 'fanotify'
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert changes == {(1, str(tmp_path / 'foo.txt'), (os.getpid(), os.geteuid()))}
+
+
+def test_fanotify_force_polling():
+    with pytest.raises(ValueError, match="`fanotify` can't be used with `force_polling`"):
+        RustNotify(['.'], False, True, 0, True, False, fanotify=True)
+
+
+@skip_unless_linux
+@pytest.mark.parametrize('backend,expected', [('poll', 'poll'), ('native', 'inotify'), ('inotify', 'inotify')])
+def test_backend(tmp_path: Path, backend: str, expected: str):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, backend=backend, source=True)
+    assert watcher.effective_config['backend'] == expected
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+
+    assert (1, str(tmp_path / 'foo.txt'), expected) in watcher.watch(200, 50, 1000, None)
+
+
+def test_backend_invalid():
+    with pytest.raises(ValueError, match='Backend "foobar" is not available'):
+        RustNotify(['.'], False, False, 0, True, False, backend='foobar')
+    with pytest.raises(ValueError, match="`backend` can't be combined with `force_polling` or `fanotify`"):
+        RustNotify(['.'], False, True, 0, True, False, backend='native')
+
+
+def test_collapse_save_storms(tmp_path: Path):
+    target = tmp_path / 'foo.py'
+    target.write_text('old')
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, collapse_save_storms=True)
+
+    # mimic a JetBrains safe-write
+    tmp = tmp_path / 'foo.py___jb_tmp___'
+    tmp.write_text('new')
+    old = tmp_path / 'foo.py___jb_old___'
+    target.rename(old)
+    tmp.rename(target)
+    old.unlink()
+
+    assert watcher.watch(200, 50, 500, None) == {(2, str(target))}
+
+
+@skip_windows
+def test_modify_kind(tmp_path: Path):
+    log = tmp_path / 'log.txt'
+    log.write_text('first line\n')
+    rotated = tmp_path / 'rotated.txt'
+    rotated.write_text('lots of old content\n')
+    # the poll watcher compares mtimes in whole seconds
+    for f in log, rotated:
+        os.utime(f, (0, 0))
+    watcher = RustNotify([str(tmp_path)], False, True, 50, True, False, modify_kind=True)
+
+    with log.open('a') as f:
+        f.write('second line\n')
+    rotated.write_text('new\n')
+
+    assert watcher.watch(200, 50, 1000, None) == {
+        (2, str(log), 'appended'),
+        (2, str(rotated), 'truncated'),
+    }
+
+
+@skip_windows
+def test_modify_sizes(tmp_path: Path):
+    log = tmp_path / 'log.txt'
+    log.write_text('first line\n')
+    os.utime(log, (0, 0))
+    watcher = RustNotify([str(tmp_path)], False, True, 50, True, False, modify_kind=True, modify_sizes=True)
+
+    with log.open('a') as f:
+        f.write('second line\n')
+
+    assert watcher.watch(200, 50, 1000, None) == {(2, str(log), 'appended', (11, 23))}
+
+
+def test_poll_delay_ms_overrides(tmp_path: Path):
+    fast = tmp_path / 'fast'
+    fast.mkdir()
+    slow = tmp_path / 'slow'
+    slow.mkdir()
+    watcher = RustNotify(
+        [str(fast), str(slow)], False, True, 50, True, False, poll_delay_ms_overrides={str(slow): 60_000}
+    )
+    assert repr(watcher).startswith('RustNotify(MultiPoll(\n')
+    assert 'delay: Some( 60s, )' in re.sub(r'\s+', ' ', repr(watcher))
+    # each poll thread scans once as soon as it starts, wait for that to happen
+    sleep(0.1)
+
+    (fast / 'foo.txt').write_text('foobar')
+    (slow / 'foo.txt').write_text('foobar')
+
+    changes = watcher.watch(200, 50, 1000, None)
+    assert (1, str(fast / 'foo.txt')) in changes  # sometimes has a modify event for the directory too
+    assert not any(str(slow) in p for c, p in changes)
+
+
+def test_poll_delay_ms_overrides_unknown(tmp_path: Path):
+    with pytest.raises(ValueError, match='poll_delay_ms_overrides contains paths which are not being watched'):
+        RustNotify([str(tmp_path)], False, True, 50, True, False, poll_delay_ms_overrides={'/foo/bar': 100})
+
+
+@skip_unless_linux
+def test_virtual_filesystem():
+    # watched by default, e.g. for /proc/<pid> or /sys/class files whose writers do notify
+    watcher = RustNotify(['/proc'], False, False, 0, False, False)
+    assert watcher.effective_config['virtual_filesystems'] == 'allow'
+    with pytest.raises(OSError, match='Unable to watch "/proc", it is on a virtual "proc" filesystem'):
+        RustNotify(['/proc'], False, False, 0, False, False, virtual_filesystems='error')
+
+
+@skip_unless_linux
+def test_virtual_filesystem_skip(tmp_path: Path):
+    with pytest.warns(RuntimeWarning, match='Not watching "/sys", it is on a virtual "sysfs" filesystem'):
+        watcher = RustNotify([str(tmp_path), '/sys'], False, False, 0, False, False, virtual_filesystems='skip')
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+
+    assert watcher.watch(200, 50, 500, None) == {(1, str(tmp_path / 'foo.txt'))}
+
+
+def test_virtual_filesystem_invalid(tmp_path: Path):
+    msg = "Invalid virtual_filesystems value \"foo\", must be 'error', 'skip' or 'allow'"
+    with pytest.raises(ValueError, match=msg):
+        RustNotify([str(tmp_path)], False, False, 0, False, False, virtual_filesystems='foo')
+
+
+@skip_unless_linux
+def test_network_filesystems_local(tmp_path: Path):
+    # tmp_path is on a local filesystem, so isn't moved to polling
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, network_filesystems='poll')
+    assert watcher.root_backends == {str(tmp_path): 'inotify'}
+    assert watcher.effective_config['root_backends'] == {}
+
+
+def test_network_filesystems_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match="Invalid network_filesystems value \"foo\", must be 'native' or 'poll'"):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, network_filesystems='foo')
+
+
+def test_scan_new_dirs(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, scan_new_dirs=True)
+    assert watcher.effective_config['scan_new_dirs'] is True
+
+    nested = tmp_path / 'a' / 'b' / 'c'
+    nested.mkdir(parents=True)
+    (nested / 'foo.txt').write_text('foobar')
+
+    changes = watcher.watch(200, 50, 500, None)
+    assert changes >= {
+        (1, str(tmp_path / 'a')),
+        (1, str(tmp_path / 'a' / 'b')),
+        (1, str(nested)),
+        (1, str(nested / 'foo.txt')),
+    }
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_watch_retries(tmp_path: Path, force_polling: bool):
+    p = tmp_path / 'late'
+    timer = Timer(0.1, p.mkdir)
+    timer.start()
+
+    watcher = RustNotify([str(p)], False, force_polling, 50, True, False, watch_retries=5, watch_retry_delay_ms=50)
+    timer.join()
+
+    assert watcher.retried_paths.keys() == {str(p)}
+    assert watcher.retried_paths[str(p)] >= 1
+
+
+def test_watch_retries_exhausted(tmp_path: Path):
+    p = tmp_path / 'missing'
+    with pytest.raises(FileNotFoundError):
+        RustNotify([str(p)], False, False, 0, True, False, watch_retries=2, watch_retry_delay_ms=1)
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_partial_ok(tmp_path: Path, force_polling: bool):
+    missing = tmp_path / 'missing'
+    present = tmp_path / 'present'
+    present.mkdir()
+    watcher = RustNotify([str(missing), str(present)], False, force_polling, 50, True, False, partial_ok=True)
+
+    report = watcher.registration_report
+    assert report.keys() == {str(missing), str(present)}
+    assert report[str(present)] is None
+    assert 'No such file or directory' in report[str(missing)]
+
+    (present / 'foo.txt').write_text('foobar')
+    assert (1, str(present / 'foo.txt')) in watcher.watch(200, 50, 1000, None)
+
+
+def test_partial_ok_all_fail(tmp_path: Path):
+    with pytest.raises(OSError, match='Unable to watch any of the watch paths'):
+        RustNotify([str(tmp_path / 'missing')], False, False, 0, True, False, partial_ok=True)
+
+
+def test_root_deleted_raise(tmp_path: Path):
+    root = tmp_path / 'root'
+    root.mkdir()
+    watcher = RustNotify([str(root)], False, False, 0, True, False, root_deleted='raise')
+
+    root.rmdir()
+    with pytest.raises(FileNotFoundError, match='was deleted'):
+        watcher.watch(200, 50, 1000, None)
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_root_deleted_reattach(tmp_path: Path, force_polling: bool):
+    root = tmp_path / 'root'
+    root.mkdir()
+    watcher = RustNotify([str(root)], False, force_polling, 50, True, False, root_deleted='reattach')
+
+    root.rmdir()
+    assert (3, str(root)) in watcher.watch(200, 50, 1000, None)
+
+    root.mkdir()
+    assert (1, str(root)) in watcher.watch(200, 50, 1000, None)
+
+    (root / 'foo.txt').write_text('foobar')
+    assert (1, str(root / 'foo.txt')) in watcher.watch(200, 50, 1000, None)
+
+
+@skip_unless_linux
+def test_root_deleted_reattach_replaced(tmp_path: Path):
+    root = tmp_path / 'root'
+    root.mkdir()
+    watcher = RustNotify([str(root)], False, False, 0, True, False, root_deleted='reattach')
+
+    # replaced before the watcher checks for deleted watch paths
+    root.rmdir()
+    root.mkdir()
+    changes = watcher.watch(200, 50, 1000, None)
+    assert {(3, str(root)), (1, str(root))} <= changes
+
+    (root / 'foo.txt').write_text('foobar')
+    assert (1, str(root / 'foo.txt')) in watcher.watch(200, 50, 1000, None)
+
+
+def test_root_deleted_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='Invalid root_deleted value "foobar"'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, root_deleted='foobar')
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_wait_for_creation(tmp_path: Path, force_polling: bool):
+    root = tmp_path / 'root'
+    watcher = RustNotify([str(root)], False, force_polling, 50, True, False, wait_for_creation=True)
+    assert watcher.waiting_paths == [str(root)]
+    assert watcher.watch_state()['roots'][str(root)]['status'] == 'waiting'
+    assert watcher.watch(200, 50, 300, None) == 'timeout'
+
+    root.mkdir()
+    assert (1, str(root)) in watcher.watch(200, 50, 1000, None)
+    assert watcher.waiting_paths == []
+
+    (root / 'foo.txt').write_text('foobar')
+    assert (1, str(root / 'foo.txt')) in watcher.watch(200, 50, 1000, None)
+
+
+def test_max_pending_changes(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, max_pending_changes=2)
+
+    for i in range(5):
+        (tmp_path / f'{i}.txt').touch()
+
+    changes = set()
+    for _ in range(10):
+        batch = watcher.watch(200, 50, 1000, None)
+        if batch == 'timeout':
+            break
+        assert len(batch) <= 2
+        changes |= batch
+    assert changes == {(1, str(tmp_path / f'{i}.txt')) for i in range(5)}
+
+
+def test_max_pending_changes_timeout(tmp_path: Path):
+    watcher = RustNotify(
+        [str(tmp_path)], False, False, 0, True, False, max_pending_changes=1, backpressure_timeout_ms=50
+    )
+
+    for i in range(3):
+        (tmp_path / f'{i}.txt').touch()
+    sleep(0.3)
+
+    with pytest.raises(WatchfilesRustInternalError, match='pending changes buffer still full'):
+        watcher.watch(200, 50, 1000, None)
+
+
+def test_max_pending_changes_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='max_pending_changes must be greater than 0'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, max_pending_changes=0)
+
+
+def test_watcher_error_details(tmp_path: Path):
+    root = tmp_path / 'root'
+    root.mkdir()
+    watcher = RustNotify([str(root)], False, True, 50, True, False)
+
+    root.rmdir()
+    with pytest.raises(WatchfilesRustInternalError, match='error in underlying watcher') as exc_info:
+        watcher.watch(200, 50, 1000, None)
+    assert exc_info.value.backend == 'poll'
+    assert isinstance(exc_info.value.paths, tuple)
+
+
+def test_watcher_error_details_defaults():
+    e = WatchfilesRustInternalError('foobar')
+    assert e.errno is None
+    assert e.strerror is None
+    assert e.paths == ()
+    assert e.backend is None
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_health_check(tmp_path: Path, force_polling: bool):
+    file = tmp_path / 'foo.txt'
+    file.touch()
+    watcher = RustNotify([str(tmp_path), str(file)], False, force_polling, 50, True, False)
+
+    assert watcher.health_check(1000) == {str(tmp_path): True, str(file): None}
+    # events for the probe file aren't reported
+    assert watcher.watch(200, 50, 300, None) == 'timeout'
+    assert list(tmp_path.iterdir()) == [file]
+
+
+def test_manual_clock_timeout(tmp_path: Path):
+    clock = ManualClock()
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, clock=clock)
+
+    start = perf_counter()
+    assert watcher.watch(1_000, 100, 60_000, None) == 'timeout'
+    assert perf_counter() - start < 5
+    assert 60_000 < clock.now_ms <= 60_100
+
+
+def test_manual_clock_debounce(tmp_path: Path):
+    clock = ManualClock()
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, clock=clock)
+
+    (tmp_path / 'foo.txt').touch()
+    sleep(0.1)
+    assert watcher.watch(1_000, 100, 60_000, None) == {(1, str(tmp_path / 'foo.txt'))}
+    # one step to see the change, another to see there are no more
+    assert clock.now_ms == 200
+
+    clock.advance(50)
+    assert clock.now_ms == 250
+    assert repr(clock) == 'ManualClock(now_ms=250)'
+
+
+def test_fractional_ms(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0.5, True, False)
+
+    start = perf_counter()
+    assert watcher.watch(1, 0.1, 1.5, None) == 'timeout'
+    assert perf_counter() - start < 0.5
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    assert (1, str(tmp_path / 'foo.txt')) in watcher.watch(50.5, 0.25, 1000, None)
+
+
+def test_wakes_on_change(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    Timer(0.1, (tmp_path / 'foo.txt').write_text, args=('foobar',)).start()
+
+    start = perf_counter()
+    assert (1, str(tmp_path / 'foo.txt')) in watcher.watch(5_000, 1_000, 10_000, None)
+    # woken by the change rather than the end of a step, then one step to see there are no more
+    assert perf_counter() - start < 1.8
+
+
+@skip_windows
+def test_fileno(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    fd = watcher.fileno()
+    assert select.select([fd], [], [], 0.1)[0] == []
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    assert select.select([fd], [], [], 1)[0] == [fd]
+    assert watcher.fileno() == fd
+
+    assert (1, str(tmp_path / 'foo.txt')) in watcher.watch(200, 50, 1000, None)
+    assert select.select([fd], [], [], 0.1)[0] == []
+
+
+@pytest.mark.skipif(sys.platform != 'win32', reason='Windows only')
+def test_event_handle(tmp_path: Path):
+    import _winapi
+
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    handle = watcher.event_handle()
+    assert _winapi.WaitForSingleObject(handle, 100) == _winapi.WAIT_TIMEOUT
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    assert _winapi.WaitForSingleObject(handle, 1000) == _winapi.WAIT_OBJECT_0
+
+    assert (1, str(tmp_path / 'foo.txt')) in watcher.watch(200, 50, 1000, None)
+    assert _winapi.WaitForSingleObject(handle, 100) == _winapi.WAIT_TIMEOUT
+
+
+@skip_windows
+def test_event_handle_unsupported(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    with pytest.raises(OSError, match=r'event_handle\(\) is only supported on windows'):
+        watcher.event_handle()
+
+
+def test_register_loop(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+
+    async def main():
+        woken = asyncio.Event()
+        watcher.register_loop(asyncio.get_running_loop(), woken.set)
+        assert not woken.is_set()
+
+        (tmp_path / 'foo.txt').write_text('foobar')
+        await asyncio.wait_for(woken.wait(), 1)
+        changes = watcher.watch(200, 50, 1000, None)
+
+        # only called again once the next batch of changes arrives
+        woken.clear()
+        await asyncio.sleep(0.1)
+        assert not woken.is_set()
+        (tmp_path / 'bar.txt').write_text('foobar')
+        await asyncio.wait_for(woken.wait(), 1)
+
+        watcher.unregister_loop()
+        return changes
+
+    assert (1, str(tmp_path / 'foo.txt')) in asyncio.run(main())
+
+
+def test_watch_async(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+
+    async def main():
+        assert await watcher.watch_async(200, 50, 100) == 'timeout'
+
+        Timer(0.1, (tmp_path / 'foo.txt').write_text, args=('foobar',)).start()
+        return await watcher.watch_async(200, 50, 1000)
+
+    assert (1, str(tmp_path / 'foo.txt')) in asyncio.run(main())
+
+
+def test_watch_async_cancel(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+
+    async def main():
+        with pytest.raises(asyncio.TimeoutError):
+            await asyncio.wait_for(watcher.watch_async(200, 50, 10_000), 0.1)
+        # give the cancelled watch a step to stop
+        await asyncio.sleep(0.1)
+
+        (tmp_path / 'foo.txt').write_text('foobar')
+        return await watcher.watch_async(200, 50, 1000)
+
+    assert (1, str(tmp_path / 'foo.txt')) in asyncio.run(main())
+
+
+def test_watch_async_concurrent(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+
+    async def main():
+        first = watcher.watch_async(200, 50, 1000)
+        with pytest.raises(RuntimeError, match='watch_async\\(\\) is already being awaited'):
+            watcher.watch_async(200, 50, 1000)
+
+        (tmp_path / 'foo.txt').write_text('foobar')
+        changes = await first
+        # once the first call has a result, another can be made
+        assert await watcher.watch_async(200, 50, 100) == 'timeout'
+        return changes
+
+    assert (1, str(tmp_path / 'foo.txt')) in asyncio.run(main())
+
+
+def test_watch_async_register_loop(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+
+    async def main():
+        woken = asyncio.Event()
+        watcher.register_loop(asyncio.get_running_loop(), woken.set)
+        Timer(0.1, (tmp_path / 'foo.txt').write_text, args=('foobar',)).start()
+        changes = await watcher.watch_async(200, 50, 1000)
+        # both are woken by the same batch
+        assert woken.is_set()
+        watcher.unregister_loop()
+        return changes
+
+    assert (1, str(tmp_path / 'foo.txt')) in asyncio.run(main())
+
+
+@skip_unless_linux
+def test_watch_async_threads(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+
+    async def main():
+        assert await watcher.watch_async(50, 10, 20) == 'timeout'
+        threads = len(os.listdir('/proc/self/task'))
+        threads_awaiting = []
+        for i in range(5):
+            future = watcher.watch_async(50, 10, 1000)
+            threads_awaiting.append(len(os.listdir('/proc/self/task')))
+            (tmp_path / f'{i}.txt').touch()
+            assert (1, str(tmp_path / f'{i}.txt')) in await future
+        return threads, threads_awaiting
+
+    # the thread waking the loop is started by the first call, after that no thread is started per call
+    threads, threads_awaiting = asyncio.run(main())
+    assert threads_awaiting == [threads] * 5
+
+
+def test_watch_async_no_loop(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    with pytest.raises(RuntimeError, match='no running event loop'):
+        watcher.watch_async(200, 50, 100)
+
+
+def test_set_callback(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    batches = []
+    watcher.set_callback(batches.append, debounce_ms=200, step_ms=50)
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    sleep(0.5)
+    (tmp_path / 'bar.txt').write_text('foobar')
+    sleep(0.5)
+    watcher.set_callback(None)
+    assert batches == [{(1, str(tmp_path / 'foo.txt'))}, {(1, str(tmp_path / 'bar.txt'))}]
+
+    (tmp_path / 'spam.txt').write_text('foobar')
+    sleep(0.3)
+    assert len(batches) == 2
+
+
+def test_set_callback_collected(tmp_path: Path):
+    # the callback's thread doesn't keep the watcher alive, it stops once the watcher is collected
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    batches = []
+    watcher.set_callback(batches.append, debounce_ms=50, step_ms=10)
+    (tmp_path / 'foo.txt').write_text('foobar')
+    sleep(0.3)
+    assert batches == [{(1, str(tmp_path / 'foo.txt'))}]
+
+    watcher_ref = weakref.ref(watcher)
+    del watcher
+    for _ in range(50):
+        gc.collect()
+        if watcher_ref() is None:
+            break
+        sleep(0.1)
+    assert watcher_ref() is None
+
+
+def test_stop(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    Timer(0.1, watcher.stop).start()
+
+    start = perf_counter()
+    assert watcher.watch(200, 5_000, 10_000, None) == 'stop'
+    assert perf_counter() - start < 1
+
+    # only stops one call
+    watcher.stop()
+    assert watcher.watch(200, 50, 10_000, None) == 'stop'
+    assert watcher.watch(200, 50, 100, None) == 'timeout'
+
+
+def test_flush_on_timeout(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    assert watcher.watch(5_000, 50, 200, None, flush_on_timeout=True) == 'timeout'
+
+    def write_files():
+        for i in range(50):
+            (tmp_path / f'{i}.txt').touch()
+            sleep(0.01)
+
+    writer = Timer(0.05, write_files)
+    writer.start()
+    start = perf_counter()
+    changes = watcher.watch(5_000, 100, 300, None, flush_on_timeout=True)
+    assert perf_counter() - start < 0.45
+    assert (1, str(tmp_path / '0.txt')) in changes
+
+    # changes arriving after the timeout are kept for the next call
+    writer.join()
+    changes |= watcher.watch(200, 50, 1000, None)
+    assert {(1, str(tmp_path / f'{i}.txt')) for i in range(50)} <= changes
+
+
+def test_structured(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    result = watcher.watch(200, 50, 100, None, structured=True)
+    assert isinstance(result, WatchResult)
+    assert result.reason == 'timeout'
+    assert result.changes == set()
+    assert result.duration_ms == 0
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    result = watcher.watch(200, 50, 1000, None, structured=True)
+    assert result.reason == 'changes'
+    assert (1, str(tmp_path / 'foo.txt')) in result.changes
+    assert result.duration_ms > 0
+    assert result.raw_event_count >= len(result.changes)
+    assert repr(result).startswith("WatchResult(reason=\"changes\", changes={")
+
+    watcher.stop()
+    result = watcher.watch(200, 50, 1000, None, structured=True)
+    assert result.reason == 'stop'
+    assert result.raw_event_count == 0
+
+
+def test_structured_flush_on_timeout(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+
+    def write_files():
+        for i in range(50):
+            (tmp_path / f'{i}.txt').touch()
+            sleep(0.01)
+
+    writer = Timer(0.05, write_files)
+    writer.start()
+    result = watcher.watch(5_000, 100, 300, None, flush_on_timeout=True, structured=True)
+    writer.join()
+    assert result.reason == 'timeout'
+    assert (1, str(tmp_path / '0.txt')) in result.changes
+
+
+def test_acknowledge(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, acknowledge=True)
+    with pytest.raises(ValueError, match='`acknowledge=True` requires `structured=True`'):
+        watcher.watch(200, 50, 100, None)
+    result = watcher.watch(200, 50, 100, None, structured=True)
+    assert result.reason == 'timeout'
+    assert result.token is None
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    first = watcher.watch(200, 50, 1000, None, structured=True)
+    assert (1, str(tmp_path / 'foo.txt')) in first.changes
+    assert first.token is not None
+    first.changes.clear()
+
+    # not acknowledged, so delivered again
+    (tmp_path / 'bar.txt').write_text('foobar')
+    sleep(0.2)
+    again = watcher.watch(200, 50, 1000, None, structured=True)
+    assert again.token == first.token
+    assert (1, str(tmp_path / 'foo.txt')) in again.changes
+    assert (1, str(tmp_path / 'bar.txt')) not in again.changes
+
+    watcher.ack(first.token)
+    with pytest.raises(ValueError, match=f'`token` {first.token} isn\'t the token of the batch'):
+        watcher.ack(first.token)
+    second = watcher.watch(200, 50, 1000, None, structured=True)
+    assert (1, str(tmp_path / 'bar.txt')) in second.changes
+    assert second.token != first.token
+
+
+def test_ack_without_acknowledge(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    with pytest.raises(RuntimeError, match="Batches aren't acknowledged"):
+        watcher.ack(1)
+    (tmp_path / 'foo.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 1000, None, structured=True).token is None
+
+
+def test_structured_ordered(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, ordered=True)
+    assert watcher.watch(200, 50, 100, None, structured=True).changes == []
+
+
+@skip_windows
+def test_sigint_wakes(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    Timer(0.1, os.kill, (os.getpid(), signal.SIGINT)).start()
+
+    start = perf_counter()
+    assert watcher.watch(200, 5_000, 10_000, None) == 'signal'
+    assert perf_counter() - start < 1
+
+
+@skip_windows
+def test_handle_sigterm(tmp_path: Path):
+    received = []
+    signal.signal(signal.SIGTERM, lambda signum, frame: received.append(signum))
+    try:
+        watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, handle_sigterm=True)
+        assert watcher.effective_config['handle_sigterm'] is True
+        Timer(0.1, os.kill, (os.getpid(), signal.SIGTERM)).start()
+        start = perf_counter()
+        assert watcher.watch(200, 5_000, 10_000, None) == 'signal'
+        assert perf_counter() - start < 1
+        # the handler already installed is still called
+        assert received == [signal.SIGTERM]
+        with pytest.raises(RuntimeError, match='RustNotify watcher closed'):
+            watcher.watch(200, 50, 100, None)
+    finally:
+        signal.signal(signal.SIGTERM, signal.SIG_DFL)
+
+
+class Interrupted(Exception):
+    pass
+
+
+def raise_interrupted(signum, frame):
+    raise Interrupted(signum)
+
+
+def interrupted_read_time(signum: int) -> float:
+    """
+    How long a blocking read takes to be interrupted by `signum` once it's sent, its handler must raise `Interrupted`.
+    """
+    r, w = os.pipe()
+    # ends the read if the signal doesn't, the handler then raises once it returns
+    unblock = Timer(2, os.write, (w, b'x'))
+    try:
+        Timer(0.1, signal.pthread_kill, (main_thread().ident, signum)).start()
+        unblock.start()
+        start = perf_counter()
+        with pytest.raises(Interrupted):
+            os.read(r, 1)
+        return perf_counter() - start - 0.1
+    finally:
+        unblock.cancel()
+        unblock.join()
+        os.close(r)
+        os.close(w)
+
+
+@skip_windows
+def test_handle_sigterm_interrupts_reads(tmp_path: Path):
+    signal.signal(signal.SIGTERM, raise_interrupted)
+    try:
+        watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, handle_sigterm=True)
+        # our handler is installed, but blocking calls are still interrupted as they were by python's
+        assert interrupted_read_time(signal.SIGTERM) < 1
+        watcher.close()
+        assert interrupted_read_time(signal.SIGTERM) < 1
+    finally:
+        signal.signal(signal.SIGTERM, signal.SIG_DFL)
+
+
+@skip_windows
+def test_sigint_restored_after_watch(tmp_path: Path):
+    signal.signal(signal.SIGINT, raise_interrupted)
+    try:
+        watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+        Timer(0.1, signal.pthread_kill, (main_thread().ident, signal.SIGINT)).start()
+        assert watcher.watch(200, 5_000, 10_000, None) == 'signal'
+        # python's handler is back, so blocking calls are interrupted as they were before `watch()`
+        assert interrupted_read_time(signal.SIGINT) < 1
+        watcher.close()
+        assert interrupted_read_time(signal.SIGINT) < 1
+    finally:
+        signal.signal(signal.SIGINT, signal.default_int_handler)
+
+
+@skip_windows
+@pytest.mark.parametrize('close', [False, True])
+def test_handle_sigterm_not_watching(tmp_path: Path, close: bool):
+    # outside `watch()`, and once the watcher is closed, SIGTERM still terminates the process
+    code = f"""
+import os, signal
+from watchfiles._rust_notify import RustNotify
+watcher = RustNotify([{str(tmp_path)!r}], False, False, 0, True, False, handle_sigterm=True)
+if {close}:
+    watcher.close()
+os.kill(os.getpid(), signal.SIGTERM)
+"""
+    env = {**os.environ, 'PYTHONPATH': os.pathsep.join(sys.path)}
+    result = subprocess.run([sys.executable, '-c', code], env=env, timeout=30)
+    assert result.returncode == -signal.SIGTERM
+
+
+@skip_windows
+def test_interrupt_signals(tmp_path: Path):
+    received = []
+    signal.signal(signal.SIGUSR1, lambda signum, frame: received.append(signum))
+    watcher = RustNotify(
+        [str(tmp_path)], False, False, 0, True, False, interrupt_signals=[signal.SIGUSR1, signal.SIGHUP, signal.SIGHUP]
+    )
+    try:
+        assert watcher.effective_config['interrupt_signals'] == sorted([signal.SIGHUP, signal.SIGUSR1])
+        Timer(0.1, os.kill, (os.getpid(), signal.SIGHUP)).start()
+        start = perf_counter()
+        assert watcher.watch(200, 5_000, 10_000, None) == 'signal'
+        assert perf_counter() - start < 1
+
+        # received while not watching, with a handler already installed, the watcher isn't closed
+        os.kill(os.getpid(), signal.SIGUSR1)
+        assert received == [signal.SIGUSR1]
+        assert watcher.watch(200, 50, 10_000, None) == 'signal'
+        assert watcher.watch(200, 50, 100, None) == 'timeout'
+    finally:
+        signal.signal(signal.SIGHUP, signal.SIG_DFL)
+        signal.signal(signal.SIGUSR1, signal.SIG_DFL)
+
+
+@skip_windows
+def test_interrupt_signals_interrupt_reads(tmp_path: Path):
+    signal.signal(signal.SIGUSR1, raise_interrupted)
+    try:
+        watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, interrupt_signals=[signal.SIGUSR1])
+        assert interrupted_read_time(signal.SIGUSR1) < 1
+        watcher.close()
+        assert interrupted_read_time(signal.SIGUSR1) < 1
+    finally:
+        signal.signal(signal.SIGUSR1, signal.SIG_DFL)
+
+
+@skip_windows
+@pytest.mark.parametrize('close', [False, True])
+def test_interrupt_signals_not_watching(tmp_path: Path, close: bool):
+    # outside `watch()`, and once the watcher is closed, the signals have their default action
+    code = f"""
+import os, signal
+from watchfiles._rust_notify import RustNotify
+watcher = RustNotify([{str(tmp_path)!r}], False, False, 0, True, False, interrupt_signals=[signal.SIGUSR1])
+if {close}:
+    watcher.close()
+os.kill(os.getpid(), signal.SIGUSR1)
+"""
+    env = {**os.environ, 'PYTHONPATH': os.pathsep.join(sys.path)}
+    result = subprocess.run([sys.executable, '-c', code], env=env, timeout=30)
+    assert result.returncode == -signal.SIGUSR1
+
+
+@skip_windows
+def test_interrupt_signals_closed(tmp_path: Path):
+    # the handler installed before the watcher is restored when it's closed
+    received = []
+    signal.signal(signal.SIGUSR2, lambda signum, frame: received.append(signum))
+    try:
+        with RustNotify([str(tmp_path)], False, False, 0, True, False, interrupt_signals=[signal.SIGUSR2]) as watcher:
+            Timer(0.1, os.kill, (os.getpid(), signal.SIGUSR2)).start()
+            assert watcher.watch(200, 5_000, 10_000, None) == 'signal'
+        assert received == [signal.SIGUSR2]
+        # only the handler restored sees this, so it isn't waiting to end the next watcher's `watch()`
+        os.kill(os.getpid(), signal.SIGUSR2)
+        assert received == [signal.SIGUSR2, signal.SIGUSR2]
+        with RustNotify([str(tmp_path)], False, False, 0, True, False, interrupt_signals=[signal.SIGUSR2]) as watcher:
+            assert watcher.watch(200, 50, 100, None) == 'timeout'
+    finally:
+        signal.signal(signal.SIGUSR2, signal.SIG_DFL)
+
+
+@skip_windows
+def test_interrupt_signals_invalid(tmp_path: Path):
+    with pytest.raises(OSError, match='error installing handler for `interrupt_signals` 9'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, interrupt_signals=[signal.SIGKILL])
+
+
+def test_set_callback_not_callable(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    with pytest.raises(TypeError, match="'callback' must be callable"):
+        watcher.set_callback('foobar')
+
+
+def test_register_loop_not_callable(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    loop = asyncio.new_event_loop()
+    with pytest.raises(TypeError, match="'callback' must be callable"):
+        watcher.register_loop(loop, 'foobar')
+    loop.close()
+
+
+def test_invalid_ms(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    with pytest.raises(ValueError, match='step_ms must be a non-negative number, not -1'):
+        watcher.watch(1, -1, 1, None)
+
+
+def test_overflow_spill(tmp_path: Path):
+    watch_dir = tmp_path / 'watch'
+    watch_dir.mkdir()
+    spill_dir = tmp_path / 'spill'
+    spill_dir.mkdir()
+    watcher = RustNotify(
+        [str(watch_dir)],
+        False,
+        False,
+        0,
+        True,
+        False,
+        max_pending_changes=2,
+        overflow='spill',
+        spill_dir=str(spill_dir),
+    )
+    assert len(list(spill_dir.iterdir())) == 1
+
+    for i in range(5):
+        (watch_dir / f'{i}.txt').touch()
+    sleep(0.1)
+
+    batches = [watcher.watch(200, 50, 1000, None) for _ in range(3)]
+    assert [len(b) for b in batches] == [2, 2, 1]
+    assert set().union(*batches) == {(1, str(watch_dir / f'{i}.txt')) for i in range(5)}
+    assert watcher.watch(200, 50, 100, None) == 'timeout'
+
+    watcher.close()
+    assert list(spill_dir.iterdir()) == []
+
+
+def test_overflow_spill_annotations(tmp_path: Path):
+    watcher = RustNotify(
+        [str(tmp_path)],
+        False,
+        False,
+        0,
+        True,
+        False,
+        max_pending_changes=2,
+        overflow='spill',
+        timestamp=True,
+        source=True,
+    )
+    start = time()
+    for i in range(5):
+        (tmp_path / f'{i}.txt').touch()
+    end = time()
+    sleep(0.2)
+
+    batches = [watcher.watch(200, 50, 1000, None) for _ in range(3)]
+    assert [len(b) for b in batches] == [2, 2, 1]
+    # spilled changes are annotated once, as they arrive, rather than again as they're read back
+    for change, path, source, timestamp in set().union(*batches):
+        assert change == 1
+        assert isinstance(source, str)
+        assert start <= timestamp <= end
+
+
+def test_overflow_rescan(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, max_pending_changes=3, overflow='rescan')
+    (tmp_path / 'foo.txt').touch()
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(tmp_path / 'foo.txt'))}
+
+    for i in range(10):
+        (tmp_path / f'{i}.txt').touch()
+    sleep(0.1)
+    assert watcher.watch(200, 50, 1000, None) == {(7, str(tmp_path))}
+    assert watcher.watch(200, 50, 100, None) == 'timeout'
+
+    # collecting changes again
+    (tmp_path / 'bar.txt').touch()
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(tmp_path / 'bar.txt'))}
+
+
+def test_overflow_rescan_change_mask(tmp_path: Path):
+    watcher = RustNotify(
+        [str(tmp_path)], False, False, 0, True, False, max_pending_changes=1, overflow='rescan', change_mask=1 << 1
+    )
+    for i in range(3):
+        (tmp_path / f'{i}.txt').touch()
+    assert watcher.watch(200, 50, 1000, None) == {(7, str(tmp_path))}
+
+
+@skip_unless_linux
+@pytest.mark.skipif(
+    not os.access('/proc/sys/fs/inotify/max_queued_events', os.W_OK), reason='requires changing the inotify queue size'
+)
+def test_queue_overflow_rescan(tmp_path: Path):
+    # the queue size is fixed when the inotify instance is created, so can be restored straight away
+    max_queued_events = Path('/proc/sys/fs/inotify/max_queued_events')
+    original = max_queued_events.read_text()
+    max_queued_events.write_text('16')
+    try:
+        watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    finally:
+        max_queued_events.write_text(original)
+
+    for i in range(1000):
+        (tmp_path / f'{i}.txt').touch()
+    assert (7, str(tmp_path)) in watcher.watch(200, 50, 2000, None)
+
+
+@skip_unless_linux
+@pytest.mark.skipif(
+    not os.access('/proc/sys/fs/inotify/max_user_watches', os.W_OK), reason='requires changing the inotify watch limit'
+)
+def test_watch_limit(tmp_path: Path):
+    for i in range(20):
+        (tmp_path / f'dir_{i}').mkdir()
+    max_user_watches = Path('/proc/sys/fs/inotify/max_user_watches')
+    original = max_user_watches.read_text()
+    max_user_watches.write_text('10')
+    try:
+        with pytest.raises(WatchLimitError, match='inotify watch limit reached') as exc_info:
+            RustNotify([str(tmp_path)], False, False, 0, True, False)
+    finally:
+        max_user_watches.write_text(original)
+
+    e = exc_info.value
+    assert isinstance(e, OSError)
+    assert e.errno == 28
+    assert e.limit_name == 'fs.inotify.max_user_watches'
+    assert e.limit == 10
+    assert e.usage is not None
+    assert e.directories == 21
+
+
+def inotify_watches_in_use() -> int:
+    watches = 0
+    for pid in filter(str.isdigit, os.listdir('/proc')):
+        try:
+            for fd in os.listdir(f'/proc/{pid}/fd'):
+                if os.readlink(f'/proc/{pid}/fd/{fd}') == 'anon_inode:inotify':
+                    fdinfo = Path(f'/proc/{pid}/fdinfo/{fd}').read_text()
+                    watches += sum(line.startswith('inotify wd:') for line in fdinfo.splitlines())
+        except OSError:
+            pass
+    return watches
+
+
+@skip_unless_linux
+@pytest.mark.skipif(
+    not os.access('/proc/sys/fs/inotify/max_user_watches', os.W_OK), reason='requires changing the inotify watch limit'
+)
+def test_watch_limit_poll(tmp_path: Path):
+    for i in range(5):
+        for j in range(5):
+            (tmp_path / f'dir_{i}' / f'sub_{j}').mkdir(parents=True)
+    max_user_watches = Path('/proc/sys/fs/inotify/max_user_watches')
+    original = max_user_watches.read_text()
+    # enough for the watch path and a few of the 30 directories under it
+    max_user_watches.write_text(str(inotify_watches_in_use() + 10))
+    try:
+        watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, watch_limit='poll')
+    finally:
+        max_user_watches.write_text(original)
+    assert watcher.effective_config['watch_limit'] == 'poll'
+
+    subtrees = watcher.polled_subtrees[str(tmp_path)]
+    assert str(tmp_path) not in subtrees
+    polled = Path(subtrees[0]) / 'polled.txt'
+    polled.touch()
+    assert watcher.watch(200, 50, 3000, None) == {(1, str(polled))}
+
+    # the watch path itself is still watched natively
+    (tmp_path / 'native.txt').touch()
+    assert watcher.watch(200, 50, 3000, None) == {(1, str(tmp_path / 'native.txt'))}
+
+
+@skip_unless_linux
+def test_lazy_depth(tmp_path: Path):
+    for i in range(3):
+        for j in range(3):
+            (tmp_path / f'dir_{i}' / f'sub_{j}').mkdir(parents=True)
+    inodes = {f'{p.stat().st_ino:x}' for p in tmp_path.glob('**')}
+
+    # watches for directories in tmp_path, other watchers in this process may be watching other directories
+    def watched_dirs() -> int:
+        watched = 0
+        for fd in os.listdir('/proc/self/fdinfo'):
+            try:
+                fdinfo = Path(f'/proc/self/fdinfo/{fd}').read_text()
+            except OSError:
+                continue
+            for line in fdinfo.splitlines():
+                m = re.match(r'inotify wd:\w+ ino:(\w+) ', line)
+                watched += bool(m and m.group(1) in inodes)
+        return watched
+
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, lazy_depth=1)
+    assert watcher.effective_config['lazy_depth'] == 1
+    # the watch path and the three directories in it, rather than all 13 directories
+    assert watched_dirs() == 4
+
+    # activity in dir_0 registers the directories inside it
+    (tmp_path / 'dir_0' / 'foo.txt').touch()
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(tmp_path / 'dir_0' / 'foo.txt'))}
+    assert watched_dirs() == 7
+    (tmp_path / 'dir_0' / 'sub_0' / 'bar.txt').touch()
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(tmp_path / 'dir_0' / 'sub_0' / 'bar.txt'))}
+
+    # as are directories created inside it from then on
+    (tmp_path / 'dir_0' / 'new').mkdir()
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(tmp_path / 'dir_0' / 'new'))}
+    (tmp_path / 'dir_0' / 'new' / 'spam.txt').touch()
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(tmp_path / 'dir_0' / 'new' / 'spam.txt'))}
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_registration_threads(tmp_path: Path, force_polling: bool):
+    roots = [tmp_path / f'root_{i}' for i in range(4)]
+    for root in roots:
+        (root / 'sub').mkdir(parents=True)
+    nested = roots[0] / 'sub'
+    watch_paths = [str(p) for p in roots + [nested]]
+    watcher = RustNotify(watch_paths, False, force_polling, 50, True, False, registration_threads=3)
+    assert watcher.effective_config['registration_threads'] == 3
+    backend = 'poll' if force_polling else watcher.effective_config['backend']
+    assert watcher.root_backends == {p: backend for p in watch_paths}
+    assert watcher.nested_paths == {str(nested): str(roots[0])}
+
+    for root in roots:
+        (root / 'sub' / 'foo.txt').touch()
+    # each watch path is polled separately, so steps longer than the poll interval keep the changes in one batch
+    changes = watcher.watch(200, 100, 3000, None)
+    assert {(change, path) for change, path in changes if change == 1} == {
+        (1, str(root / 'sub' / 'foo.txt')) for root in roots
+    }
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_progress(tmp_path: Path, force_polling: bool):
+    for i in range(3):
+        (tmp_path / f'dir_{i}' / 'sub').mkdir(parents=True)
+    counts = []
+    watcher = RustNotify([str(tmp_path)], False, force_polling, 50, True, False, progress=counts.append)
+    assert watcher.effective_config['progress'] is True
+    assert counts == sorted(counts)
+    if sys.platform == 'linux' or force_polling:
+        assert counts[-1] == 7
+    else:
+        assert counts[-1] == 0
+
+    # only registration is counted
+    (tmp_path / 'dir_0' / 'new').mkdir()
+    watcher.watch(200, 50, 1000, None)
+    assert counts[-1] in (0, 7)
+
+
+def test_progress_error(tmp_path: Path):
+    def progress(count: int):
+        raise RuntimeError('boom')
+
+    unraisable = []
+    sys.unraisablehook, hook = unraisable.append, sys.unraisablehook
+    try:
+        RustNotify([str(tmp_path)], False, False, 0, True, False, progress=progress)
+    finally:
+        sys.unraisablehook = hook
+    assert [repr(u.exc_value) for u in unraisable] == ["RuntimeError('boom')"]
+
+
+def test_registration_threads_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='`registration_threads` must be at least 1'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, registration_threads=0)
+    with pytest.raises(ValueError, match="`registration_threads` can't be used with `fanotify` or `lazy_depth`"):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, registration_threads=2, lazy_depth=1)
+
+
+def test_lazy_depth_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='`lazy_depth` requires `recursive=True` and native notifications'):
+        RustNotify([str(tmp_path)], False, True, 0, True, False, lazy_depth=1)
+    with pytest.raises(ValueError, match='`lazy_depth` requires `recursive=True` and native notifications'):
+        RustNotify([str(tmp_path)], False, False, 0, False, False, lazy_depth=1)
+
+
+def test_watch_limit_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='Invalid watch_limit value "foobar"'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, watch_limit='foobar')
+
+
+def test_overflow_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='Invalid overflow value "foobar"'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, max_pending_changes=1, overflow='foobar')
+
+
+@skip_unless_linux
+def test_root_filters(tmp_path: Path):
+    web = tmp_path / 'web'
+    web.mkdir()
+    src = tmp_path / 'src'
+    src.mkdir()
+    (web / 'big.txt').write_text('x' * 10)
+    (src / 'big.txt').write_text('x' * 10)
+    watcher = RustNotify(
+        [str(web), str(src)],
+        False,
+        False,
+        0,
+        True,
+        False,
+        files_only=True,
+        root_filters={str(web): {'max_file_size': 5, 'files_only': False}},
+    )
+
+    with (web / 'big.txt').open('a') as f:
+        f.write('x')
+    (web / 'dir').mkdir()
+    with (src / 'big.txt').open('a') as f:
+        f.write('x')
+    (src / 'dir').mkdir()
+    sleep(0.1)
+
+    changes = watcher.watch(200, 50, 1000, None)
+    assert changes == {(1, str(web / 'dir')), (2, str(src / 'big.txt'))}
+
+
+def test_root_filters_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='root_filters contains a path which is not being watched'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, root_filters={'/foo': {}})
+    with pytest.raises(ValueError, match='Invalid root_filters key "foobar"'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, root_filters={str(tmp_path): {'foobar': 1}})
+
+
+def test_effective_config(tmp_path: Path):
+    watcher = RustNotify(
+        [str(tmp_path)],
+        False,
+        True,
+        123,
+        False,
+        False,
+        files_only=True,
+        root_filters={str(tmp_path): {'max_file_size': 100}},
+        max_pending_changes=10,
+    )
+    config = watcher.effective_config
+    assert config['backend'] == 'poll'
+    assert config['watch_paths'] == [str(tmp_path)]
+    assert config['recursive'] is False
+    assert config['poll_delay_ms'] == 123
+    assert config['files_only'] is True
+    assert config['max_file_size'] is None
+    assert config['root_filters'] == {
+        str(tmp_path): {'max_file_size': 100, 'files_only': True, 'file_types': 'all'}
+    }
+    assert config['max_pending_changes'] == 10
+    assert config['overflow'] == 'block'
+    assert config['backpressure_timeout_ms'] == 5_000
+    assert config['spill_dir'] is None
+    assert config['clock'] == 'system'
+    assert config['advanced_options'] == {}
+    assert config['path_containment'] == 'off'
+    assert config['history_size'] == 0
+    assert config['merge_aliases'] is False
+    assert config['ignore_cloud_sync'] is False
+    assert config['rename_pairs'] is False
+    assert config['gitignore'] is False
+    assert config['include_globs'] is None
+    assert config['exclude_globs'] is None
+    assert config['allow_regexes'] is None
+    assert config['deny_regexes'] is None
+    assert config['change_mask'] == 0b1111110
+    assert config['timestamp'] is False
+    assert config['is_dir'] is False
+    assert config['ordered'] is False
+    assert config['keep_duplicates'] is False
+    assert config['close_write'] is False
+    assert config['metadata_changes'] == 'modified'
+    assert config['fanotify'] is False
+    assert config['process'] is False
+    assert config['root_backends'] == {}
+    assert config['network_filesystems'] == 'native'
+    assert config['scan_new_dirs'] is False
+    assert config['watchdog_interval_ms'] == 0
+    assert config['max_depth'] is None
+    assert config['follow_symlinks'] is True
+    assert config['canonical_paths'] is False
+    assert config['relative_paths'] is False
+    assert config['root'] is False
+    assert config['group_by_root'] is False
+    assert config['file_targets'] is False
+    assert config['wait_for_creation'] is False
+    assert config['handle_sigterm'] is False
+    assert config['interrupt_signals'] == []
+    assert config['acknowledge'] is False
+    assert config['watch_limit'] == 'error'
+    assert config['lazy_depth'] is None
+    assert config['registration_threads'] == 1
+    assert config['progress'] is False
+    assert config['compare_contents'] is False
+    assert config['poll_threads'] == 1
+    assert config['max_poll_delay_ms'] is None
+    assert config['compare_contents_max_size'] is None
+
+
+@skip_unless_linux
+def test_effective_config_native(tmp_path: Path):
+    config = RustNotify([str(tmp_path)], False, False, 123, True, False).effective_config
+    assert config['backend'] == 'inotify'
+    assert config['poll_delay_ms'] is None
+    assert config['overflow'] is None
+    assert config['compare_contents'] is None
+
+
+def test_advanced_options(tmp_path: Path):
+    watcher = RustNotify(
+        [str(tmp_path)],
+        False,
+        True,
+        10_000,
+        True,
+        False,
+        advanced_options={'poll_interval_ms': 20, 'compare_contents': True},
+    )
+    assert watcher.effective_config['advanced_options'] == {'poll_interval_ms': 20, 'compare_contents': True}
+    sleep(0.1)
+
+    # poll_delay_ms of 10s would miss this, poll_interval_ms takes precedence
+    (tmp_path / 'foo.txt').write_text('foobar')
+    assert (1, str(tmp_path / 'foo.txt')) in watcher.watch(200, 50, 1000, None)
+
+
+def test_compare_contents(tmp_path: Path):
+    path = tmp_path / 'foo.txt'
+    path.write_text('spam')
+    mtime = path.stat().st_mtime_ns
+    watcher = RustNotify([str(tmp_path)], False, True, 20, True, False, compare_contents=True)
+    assert watcher.effective_config['compare_contents'] is True
+
+    path.write_text('eggs')
+    # e.g. a network share which doesn't update modification times
+    os.utime(path, ns=(mtime, mtime))
+    assert watcher.watch(200, 50, 1000, None) == {(2, str(path))}
+
+
+def test_compare_contents_max_size(tmp_path: Path):
+    small, large = tmp_path / 'small.txt', tmp_path / 'large.txt'
+    small.write_text('spam')
+    large.write_text('spam' * 10)
+    mtimes = {path: path.stat().st_mtime_ns for path in (small, large)}
+    watcher = RustNotify(
+        [str(tmp_path)], False, True, 20, True, False, compare_contents=True, compare_contents_max_size=10
+    )
+    assert watcher.effective_config['compare_contents_max_size'] == 10
+
+    # modification times aren't updated, only the small file's contents are compared
+    small.write_text('eggs')
+    large.write_text('eggs' * 10)
+    for path, mtime in mtimes.items():
+        os.utime(path, ns=(mtime, mtime))
+    assert watcher.watch(200, 50, 1000, None) == {(2, str(small))}
+
+    # larger files are still compared by size
+    large.write_text('eggs' * 11)
+    os.utime(large, ns=(mtimes[large], mtimes[large]))
+    assert watcher.watch(200, 50, 1000, None) == {(2, str(large))}
+
+
+def test_compare_contents_max_size_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='`compare_contents_max_size` requires `compare_contents=True`'):
+        RustNotify([str(tmp_path)], False, True, 20, True, False, compare_contents_max_size=10)
+
+
+def test_poll_threads(tmp_path: Path):
+    for name in 'abcd':
+        (tmp_path / name / 'sub').mkdir(parents=True)
+    watcher = RustNotify([str(tmp_path)], False, True, 20, True, False, poll_threads=3)
+    assert watcher.effective_config['poll_threads'] == 3
+
+    for name in 'abcd':
+        (tmp_path / name / 'sub' / 'foo.txt').touch()
+    (tmp_path / 'bar.txt').touch()
+    changes = watcher.watch(200, 50, 1000, None)
+    assert {(c, p) for c, p in changes if c == 1} == {(1, str(tmp_path / n / 'sub' / 'foo.txt')) for n in 'abcd'} | {
+        (1, str(tmp_path / 'bar.txt'))
+    }
+
+    # new directories inside the watch path are polled from the next step
+    (tmp_path / 'e').mkdir()
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(tmp_path / 'e'))}
+    (tmp_path / 'e' / 'spam.txt').touch()
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(tmp_path / 'e' / 'spam.txt'))}
+
+    watcher.remove_path(str(tmp_path))
+    (tmp_path / 'a' / 'sub' / 'eggs.txt').touch()
+    assert watcher.watch(200, 50, 300, None) == 'timeout'
+
+
+def test_poll_threads_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='`poll_threads` must be at least 1'):
+        RustNotify([str(tmp_path)], False, True, 20, True, False, poll_threads=0)
+    with pytest.raises(ValueError, match='`poll_threads` requires `recursive=True`'):
+        RustNotify([str(tmp_path)], False, True, 20, False, False, poll_threads=2)
+
+
+def test_max_poll_delay(tmp_path: Path):
+    for i in range(20):
+        (tmp_path / f'dir_{i}').mkdir()
+        for j in range(100):
+            (tmp_path / f'dir_{i}' / f'{j}.txt').touch()
+    watcher = RustNotify([str(tmp_path)], False, True, 1, True, False, max_poll_delay_ms=500)
+    assert watcher.effective_config['max_poll_delay_ms'] == 500
+    assert watcher.poll_delay_ms == 1
+
+    # scanning 2,000 files takes longer than 1ms
+    sleep(0.5)
+    assert 1 < watcher.poll_delay_ms <= 500
+
+    (tmp_path / 'dir_0' / 'foo.txt').touch()
+    changes = watcher.watch(200, 50, 3000, None)
+    assert (1, str(tmp_path / 'dir_0' / 'foo.txt')) in changes
+
+
+def test_max_poll_delay_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='`max_poll_delay_ms` must be at least `poll_delay_ms`'):
+        RustNotify([str(tmp_path)], False, True, 100, True, False, max_poll_delay_ms=50)
+
+
+def test_poll_delay_ms(tmp_path: Path):
+    assert RustNotify([str(tmp_path)], False, True, 123, True, False).poll_delay_ms == 123
+    assert RustNotify([str(tmp_path)], False, False, 123, True, False).poll_delay_ms is None
+
+
+def test_advanced_options_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='Invalid advanced_options key "follow_links"'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, advanced_options={'follow_links': True})
+
+
+@skip_windows
+def test_stat_retries_transient(tmp_path: Path):
+    foo = tmp_path / 'foo.txt'
+    foo.write_text('foobar')
+    watcher = RustNotify(
+        [str(tmp_path)], False, True, 20, True, False, files_only=True, stat_retries=4, stat_retry_delay_ms=100
+    )
+    sleep(0.1)
+
+    # a symlink to itself can't be stat-ed, the file is restored before the retries are exhausted
+    foo.unlink()
+    foo.symlink_to(foo)
+    sleep(0.2)
+    foo.unlink()
+    foo.write_text('foobar')
+
+    assert watcher.watch(200, 50, 1500, None) == 'timeout'
+
+
+@skip_windows
+def test_stat_retries_exhausted(tmp_path: Path):
+    foo = tmp_path / 'foo.txt'
+    foo.write_text('foobar')
+    watcher = RustNotify([str(tmp_path)], False, True, 20, True, False, stat_retries=2, stat_retry_delay_ms=10)
+    sleep(0.1)
+
+    foo.unlink()
+    foo.symlink_to(foo)
+
+    assert (3, str(foo)) in watcher.watch(200, 50, 1000, None)
+
+
+@skip_unless_linux
+def test_switch_backend(tmp_path: Path):
+    other = tmp_path / 'other'
+    sub = tmp_path / 'sub'
+    other.mkdir()
+    sub.mkdir()
+    watcher = RustNotify([str(other), str(sub)], False, False, 0, True, False, source=True)
+    assert watcher.root_backends == {str(other): 'inotify', str(sub): 'inotify'}
+
+    watcher.switch_backend(str(sub), 'poll')
+    assert watcher.root_backends == {str(other): 'inotify', str(sub): 'poll'}
+    sleep(0.1)
+
+    (sub / 'foo.txt').write_text('foobar')
+    (other / 'bar.txt').write_text('foobar')
+    changes = watcher.watch(200, 50, 2000, None)
+    assert (1, str(sub / 'foo.txt'), 'poll') in changes
+    assert (1, str(other / 'bar.txt'), 'inotify') in changes
+
+    watcher.switch_backend(str(sub), 'native')
+    assert watcher.root_backends == {str(other): 'inotify', str(sub): 'inotify'}
+    (sub / 'foo.txt').unlink()
+    assert watcher.watch(200, 50, 1000, None) == {(3, str(sub / 'foo.txt'), 'inotify')}
+
+
+def test_switch_backend_while_watching(tmp_path: Path):
+    for i in range(5_000):
+        (tmp_path / f'{i}.txt').touch()
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    results = []
+
+    def watch():
+        try:
+            results.append(watcher.watch(50, 1, 3000, None))
+        except BaseException as e:
+            results.append(e)
+
+    thread = Thread(target=watch)
+    thread.start()
+    sleep(0.1)
+    # the tree is scanned twice while switching, `watch()` keeps running meanwhile
+    watcher.switch_backend(str(tmp_path), 'poll')
+    (tmp_path / 'new.txt').touch()
+    thread.join()
+    assert results == [{(1, str(tmp_path / 'new.txt'))}]
+
+
+def test_add_path_while_returning(tmp_path: Path):
+    watched = tmp_path / 'watched'
+    other = tmp_path / 'other'
+    watched.mkdir()
+    other.mkdir()
+    watcher = RustNotify([str(watched)], False, False, 0, True, False)
+    for i in range(20_000):
+        (watched / f'{i}.txt').touch()
+    sleep(0.1)
+    results = []
+
+    def watch():
+        results.append(watcher.watch(50, 1, 5000, None))
+
+    thread = Thread(target=watch)
+    thread.start()
+    # the batch is prepared without the GIL, the watcher mustn't be borrowed meanwhile
+    while thread.is_alive():
+        watcher.add_path(str(other))
+        watcher.remove_path(str(other))
+    thread.join()
+    assert len(results[0]) == 20_000
+
+
+@skip_unless_linux
+def test_root_backends(tmp_path: Path):
+    local, mounted = tmp_path / 'local', tmp_path / 'mounted'
+    local.mkdir()
+    mounted.mkdir()
+    watcher = RustNotify(
+        [str(local), str(mounted)], False, False, 0, True, False, root_backends={str(mounted): 'poll'}, source=True
+    )
+    assert watcher.root_backends == {str(local): 'inotify', str(mounted): 'poll'}
+    assert watcher.effective_config['root_backends'] == {str(mounted): 'poll'}
+
+    (local / 'foo.txt').write_text('foobar')
+    (mounted / 'bar.txt').write_text('foobar')
+
+    changes = watcher.watch(200, 50, 2000, None)
+    assert (1, str(local / 'foo.txt'), 'inotify') in changes
+    assert (1, str(mounted / 'bar.txt'), 'poll') in changes
+    assert not any(source == 'inotify' for _, path, source in changes if path.startswith(str(mounted)))
+
+
+def test_root_backends_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='root_backends contains a path which is not being watched'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, root_backends={'/foo': 'poll'})
+    with pytest.raises(ValueError, match='Backend "foobar" is not available'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, root_backends={str(tmp_path): 'foobar'})
+
+
+@skip_unless_linux
+def test_watchdog(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, watchdog_interval_ms=100)
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 500, None) == {(1, str(tmp_path / 'foo.txt'))}
+    assert watcher.watchdog_restarts == {}
+
+    # replacing the inotify file descriptor removes it from notify's epoll set, so the watcher silently stops
+    null_fd = os.open(os.devnull, os.O_RDONLY)
+    for fd in os.listdir('/proc/self/fd'):
+        try:
+            if os.readlink(f'/proc/self/fd/{fd}') == 'anon_inode:inotify':
+                os.dup2(null_fd, int(fd))
+        except OSError:
+            pass
+    os.close(null_fd)
+
+    assert watcher.watch(200, 50, 500, None) == 'timeout'
+    assert watcher.watchdog_restarts == {str(tmp_path): 1}
+    (tmp_path / 'bar.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 500, None) == {(1, str(tmp_path / 'bar.txt'))}
+
+
+def test_add_remove_path(tmp_path: Path):
+    first, second = tmp_path / 'first', tmp_path / 'second'
+    first.mkdir()
+    second.mkdir()
+    watcher = RustNotify([str(first)], False, False, 0, True, False)
+
+    # paths can be added while another thread is waiting in `watch()`
+    Timer(0.1, watcher.add_path, (str(second),)).start()
+    Timer(0.3, (second / 'foo.txt').write_text, ('foobar',)).start()
+    assert watcher.watch(200, 50, 2000, None) == {(1, str(second / 'foo.txt'))}
+    assert str(second) in watcher.registration_report
+
+    watcher.remove_path(str(first))
+    (first / 'bar.txt').write_text('foobar')
+    (second / 'bar.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 500, None) == {(1, str(second / 'bar.txt'))}
+    assert watcher.effective_config['watch_paths'] == [str(second)]
+
+
+def test_add_remove_path_invalid(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    with pytest.raises(ValueError, match='is already a watch path'):
+        watcher.add_path(str(tmp_path))
+    with pytest.raises(FileNotFoundError):
+        watcher.add_path(str(tmp_path / 'missing'))
+    with pytest.raises(ValueError, match='is not a watch path'):
+        watcher.remove_path(str(tmp_path / 'missing'))
+    watcher.close()
+    with pytest.raises(RuntimeError, match='RustNotify watcher closed'):
+        watcher.add_path(str(tmp_path / 'foo'))
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_pause_resume(tmp_path: Path, force_polling: bool):
+    watcher = RustNotify([str(tmp_path)], False, force_polling, 50, True, False)
+    watcher.pause()
+    assert watcher.paused is True
+    (tmp_path / 'paused.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 500, None) == 'timeout'
+
+    watcher.resume()
+    assert watcher.paused is False
+    (tmp_path / 'resumed.txt').write_text('foobar')
+    # polling may also report the directory as modified
+    changes = watcher.watch(200, 50, 2000, None)
+    assert (1, str(tmp_path / 'resumed.txt')) in changes
+    assert not any(path == str(tmp_path / 'paused.txt') for _, path in changes)
+
+    watcher.close()
+    with pytest.raises(RuntimeError, match='RustNotify watcher closed'):
+        watcher.pause()
+
+
+def test_watch_state(tmp_path: Path):
+    (tmp_path / 'a' / 'b').mkdir(parents=True)
+    watcher = RustNotify(
+        [str(tmp_path / 'a'), str(tmp_path / 'a' / 'b')],
+        False,
+        True,
+        123,
+        True,
+        False,
+        root_filters={str(tmp_path / 'a'): {'files_only': True}},
+        exclude_globs=['*.tmp'],
+    )
+    watcher.pause()
+    assert watcher.watch_state() == {
+        'backend': 'poll',
+        'paused': True,
+        'pending_changes': 0,
+        'steps': 0,
+        'roots': {
+            str(tmp_path / 'a'): {
+                'backend': 'poll',
+                'recursive': True,
+                'poll_delay_ms': 123,
+                'status': 'watched',
+                'reason': None,
+                'filters': {'max_file_size': None, 'files_only': True, 'file_types': 'all'},
+            },
+            str(tmp_path / 'a' / 'b'): {
+                'backend': 'poll',
+                'recursive': True,
+                'poll_delay_ms': 123,
+                'status': 'nested',
+                'reason': str(tmp_path / 'a'),
+                'filters': {'max_file_size': None, 'files_only': True, 'file_types': 'all'},
+            },
+        },
+        'filters': {
+            'gitignore': False,
+            'include_globs': None,
+            'exclude_globs': ['*.tmp'],
+            'allow_regexes': None,
+            'deny_regexes': None,
+            'change_mask': 126,
+            'max_depth': None,
+            'follow_symlinks': True,
+        },
+    }
+    watcher.close()
+    with pytest.raises(RuntimeError, match='RustNotify watcher closed'):
+        watcher.watch_state()
+
+
+def test_watch_idle_steps(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    assert watcher.watch(50, 10, 500, None) == 'timeout'
+    # while nothing is pending it isn't woken every `step_ms`, only by the timeout
+    assert watcher.watch_state()['steps'] <= 3
+
+    (tmp_path / 'foo.txt').touch()
+    assert watcher.watch(50, 10, 5_000, None) == {(1, str(tmp_path / 'foo.txt'))}
+
+
+@skip_windows
+def test_watch_idle_python_handler(tmp_path: Path):
+    # handlers installed in python still run, and can end `watch()`, while it waits for a change
+    signal.signal(signal.SIGUSR1, raise_interrupted)
+    try:
+        watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+        Timer(0.1, os.kill, (os.getpid(), signal.SIGUSR1)).start()
+        start = perf_counter()
+        assert watcher.watch(50, 10, 10_000, None) == 'signal'
+        assert perf_counter() - start < 1
+        assert watcher.watch_state()['steps'] <= 3
+    finally:
+        signal.signal(signal.SIGUSR1, signal.SIG_DFL)
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_max_depth(tmp_path: Path, force_polling: bool):
+    (tmp_path / 'a' / 'b').mkdir(parents=True)
+    watcher = RustNotify([str(tmp_path)], False, force_polling, 50, True, False, max_depth=2)
+    assert watcher.effective_config['max_depth'] == 2
+
+    (tmp_path / 'top.txt').write_text('foobar')
+    (tmp_path / 'a' / 'middle.txt').write_text('foobar')
+    (tmp_path / 'a' / 'b' / 'deep.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 2000, None) == {
+        (1, str(tmp_path / 'top.txt')),
+        (1, str(tmp_path / 'a' / 'middle.txt')),
+    }
+
+
+def test_switch_backend_invalid(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, True, 0, True, False)
+    with pytest.raises(ValueError, match='is not a watch path'):
+        watcher.switch_backend(str(tmp_path / 'foo'), 'poll')
+    with pytest.raises(ValueError, match='Backend "foobar" is not available'):
+        watcher.switch_backend(str(tmp_path), 'foobar')
+    watcher.close()
+    with pytest.raises(RuntimeError, match='RustNotify watcher closed'):
+        watcher.switch_backend(str(tmp_path), 'poll')
+
+
+def test_capabilities_poll(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, True, 0, True, False)
+    assert watcher.capabilities() == backend_capabilities('poll')
+    assert watcher.capabilities() == {
+        'event_driven': False,
+        'native_recursion': False,
+        'rename_cookies': False,
+        'close_write': False,
+        'follow_symlinks': True,
+        'compare_contents': True,
+    }
+    watcher.close()
+    with pytest.raises(RuntimeError, match='RustNotify watcher closed'):
+        watcher.capabilities()
+
+
+@skip_unless_linux
+def test_capabilities_inotify(tmp_path: Path):
+    capabilities = RustNotify([str(tmp_path)], False, False, 0, True, False).capabilities()
+    assert capabilities['event_driven'] is True
+    assert capabilities['rename_cookies'] is True
+
+
+def test_backend_capabilities_invalid():
+    assert backend_capabilities('fsevent')['native_recursion'] is True
+    with pytest.raises(ValueError, match='Invalid backend "foobar"'):
+        backend_capabilities('foobar')
+
+
+@skip_windows
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_symlink_loop(tmp_path: Path, force_polling: bool):
+    sub = tmp_path / 'sub'
+    sub.mkdir()
+    (sub / 'loop').symlink_to(tmp_path)
+    watcher = RustNotify([str(tmp_path)], False, force_polling, 50, True, False)
+    sleep(0.1)
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    assert (1, str(tmp_path / 'foo.txt')) in watcher.watch(200, 50, 1000, None)
+    if force_polling:
+        assert watcher.symlink_loops == {str(sub / 'loop'): str(tmp_path)}
+    else:
+        assert watcher.symlink_loops == {}
+
+
+@skip_windows
+@pytest.mark.parametrize('force_polling', [False, True])
+@pytest.mark.parametrize('follow_symlinks', [True, False])
+def test_follow_symlinks(tmp_path: Path, force_polling: bool, follow_symlinks: bool):
+    watched, target = tmp_path / 'watched', tmp_path / 'target'
+    watched.mkdir()
+    target.mkdir()
+    (watched / 'link').symlink_to(target)
+    watcher = RustNotify(
+        [str(watched)], False, force_polling, 50, True, False, follow_symlinks=follow_symlinks, files_only=True
+    )
+    assert watcher.effective_config['follow_symlinks'] is follow_symlinks
+    sleep(0.1)
+
+    (target / 'foo.txt').write_text('foobar')
+    (watched / 'bar.txt').write_text('foobar')
+    changes = watcher.watch(200, 50, 2000, None)
+    assert (1, str(watched / 'bar.txt')) in changes
+    assert ((1, str(watched / 'link' / 'foo.txt')) in changes) is follow_symlinks
+
+
+@skip_windows
+def test_canonical_paths(tmp_path: Path):
+    target = tmp_path / 'target'
+    target.mkdir()
+    (target / 'bar.txt').write_text('foobar')
+    (tmp_path / 'link').symlink_to(target)
+    watcher = RustNotify([str(tmp_path / 'link' / '..' / 'link')], False, False, 0, True, False, canonical_paths=True)
+
+    (target / 'foo.txt').write_text('foobar')
+    (target / 'bar.txt').unlink()
+    assert watcher.watch(200, 50, 2000, None) == {
+        (1, os.path.realpath(target / 'foo.txt')),
+        (3, os.path.realpath(target / 'bar.txt')),
+    }
+
+
+def test_relative_paths(tmp_path: Path):
+    sub = tmp_path / 'sub'
+    (sub / 'dir').mkdir(parents=True)
+    watcher = RustNotify([str(tmp_path), str(sub)], False, False, 0, True, False, relative_paths=True)
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    (sub / 'dir' / 'bar.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 2000, None) == {(1, 'foo.txt'), (1, os.path.join('dir', 'bar.txt'))}
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_root(tmp_path: Path, force_polling: bool):
+    first, second = tmp_path / 'first', tmp_path / 'second'
+    (first / 'nested').mkdir(parents=True)
+    second.mkdir()
+    watch_paths = [str(first), str(first / 'nested'), str(second)]
+    watcher = RustNotify(watch_paths, False, force_polling, 50, True, False, root=True, relative_paths=True)
+
+    (first / 'a.txt').write_text('foobar')
+    (first / 'nested' / 'b.txt').write_text('foobar')
+    (second / 'c.txt').write_text('foobar')
+    changes = watcher.watch(200, 50, 2000, None)
+    assert (1, 'a.txt', str(first)) in changes
+    assert (1, 'b.txt', str(first / 'nested')) in changes
+    assert (1, 'c.txt', str(second)) in changes
+
+
+@pytest.mark.parametrize('ordered', [False, True])
+def test_group_by_root(tmp_path: Path, ordered: bool):
+    first, second, third = tmp_path / 'first', tmp_path / 'second', tmp_path / 'third'
+    for path in (first, second, third):
+        path.mkdir()
+    watcher = RustNotify(
+        [str(first), str(second), str(third)], False, False, 0, True, False, group_by_root=True, ordered=ordered
+    )
+
+    (first / 'a.txt').write_text('foobar')
+    (second / 'b.txt').write_text('foobar')
+    (second / 'b.txt').unlink()
+    changes = watcher.watch(200, 50, 2000, None)
+    if ordered:
+        assert changes == {
+            str(first): [(1, str(first / 'a.txt'))],
+            str(second): [(1, str(second / 'b.txt')), (3, str(second / 'b.txt'))],
+        }
+    else:
+        assert changes == {
+            str(first): {(1, str(first / 'a.txt'))},
+            str(second): {(1, str(second / 'b.txt')), (3, str(second / 'b.txt'))},
+        }
+
+
+def test_file_targets(tmp_path: Path):
+    target = tmp_path / 'foo.txt'
+    target.write_text('original')
+    (tmp_path / 'bar.txt').write_text('original')
+    watcher = RustNotify([str(target)], False, False, 0, True, False, file_targets=True)
+
+    # each save writes a new file and renames it over the target, as many editors do
+    for content in ('first', 'second'):
+        (tmp_path / 'foo.txt.tmp').write_text(content)
+        os.replace(tmp_path / 'foo.txt.tmp', target)
+        (tmp_path / 'bar.txt').write_text(content)
+        changes = watcher.watch(200, 50, 2000, None)
+        assert {path for _, path in changes} == {str(target)}
+
+
+def test_relative_paths_canonical(tmp_path: Path):
+    with pytest.raises(ValueError, match="`canonical_paths` and `relative_paths` can't be used together"):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, canonical_paths=True, relative_paths=True)
+
+
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_nested_paths(tmp_path: Path, force_polling: bool):
+    sub = tmp_path / 'sub'
+    sub.mkdir()
+    watcher = RustNotify([str(sub), str(tmp_path), str(tmp_path)], False, force_polling, 50, True, False, depth=True)
+    assert watcher.nested_paths == {str(sub): str(tmp_path)}
+    assert watcher.registration_report == {str(sub): None, str(tmp_path): None}
+    sleep(0.1)
+
+    (sub / 'foo.txt').write_text('foobar')
+    assert watcher.watch(200, 50, 1000, None) == {(1, str(sub / 'foo.txt'), 1)}
+
+
+def test_nested_paths_not_recursive(tmp_path: Path):
+    sub = tmp_path / 'sub'
+    sub.mkdir()
+    watcher = RustNotify([str(tmp_path), str(sub)], False, False, 0, False, False)
+    assert watcher.nested_paths == {}
+
+
+@skip_windows
+@pytest.mark.parametrize('force_polling', [False, True])
+def test_merge_aliases(tmp_path: Path, force_polling: bool):
+    real = tmp_path / 'real'
+    real.mkdir()
+    alias = tmp_path / 'alias'
+    alias.symlink_to(real)
+    watcher = RustNotify([str(real), str(alias)], False, force_polling, 50, True, False, merge_aliases=True)
+    assert watcher.aliased_paths == {str(alias): str(real)}
+    sleep(0.1)
+
+    (alias / 'foo.txt').write_text('foobar')
+    changes = watcher.watch(200, 50, 1000, None)
+    assert (1, str(real / 'foo.txt')) in changes
+    assert not any(path.startswith(str(alias)) for _, path in changes)
+
+
+def test_depth_first_component(tmp_path: Path):
+    pkg = tmp_path / 'pkg'
+    (pkg / 'sub').mkdir(parents=True)
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, depth=True, first_component=True)
+
+    (tmp_path / 'foo.txt').touch()
+    (pkg / 'sub' / 'bar.py').touch()
+    sleep(0.1)
+
+    changes = watcher.watch(200, 50, 1000, None)
+    assert (1, str(tmp_path / 'foo.txt'), 1, 'foo.txt') in changes
+    assert (1, str(pkg / 'sub' / 'bar.py'), 3, 'pkg') in changes
+
+
+@skip_unless_linux
+@pytest.mark.parametrize('force_polling,backend', [(False, 'inotify'), (True, 'poll')])
+def test_source(tmp_path: Path, force_polling: bool, backend: str):
+    root = tmp_path / 'root'
+    root.mkdir()
+    watcher = RustNotify([str(root)], False, force_polling, 50, True, False, source=True, root_deleted='emit')
+    sleep(0.1)
+
+    (root / 'foo.txt').touch()
+    assert (1, str(root / 'foo.txt'), backend) in watcher.watch(200, 50, 1000, None)
+
+    (root / 'foo.txt').unlink()
+    watcher.watch(200, 50, 1000, None)
+    root.rmdir()
+    changes = watcher.watch(200, 50, 1000, None)
+    assert {source for _, _, source in changes} <= {backend, 'root_check'}
+    assert (3, str(root), 'root_check') in changes or (3, str(root), backend) in changes
+
+
+@skip_windows
+def test_share(tmp_path: Path):
+    root = tmp_path / 'root'
+    root.mkdir()
+    socket = str(tmp_path / 'watch.sock')
+    host = RustNotify([str(root)], False, False, 0, True, False, share=socket)
+    client = RustNotify([str(root)], False, False, 0, True, False, share=socket, source=True)
+    assert host.effective_config['share_role'] == 'host'
+    assert client.effective_config['share_role'] == 'client'
+    assert client.effective_config['backend'] == host.effective_config['backend']
+    sleep(0.1)
+
+    (root / 'foo.txt').write_text('foobar')
+    # clients are sent each batch once the host's `watch()` returns it
+    assert client.watch(200, 50, 300, None) == 'timeout'
+    assert (1, str(root / 'foo.txt')) in host.watch(200, 50, 1000, None)
+    assert (1, str(root / 'foo.txt'), 'shared') in client.watch(200, 50, 1000, None)
+
+    host.close()
+    with pytest.raises(WatchfilesRustInternalError, match='lost connection to the shared watcher'):
+        client.watch(200, 50, 1000, None)
+
+
+@skip_windows
+def test_share_mismatch(tmp_path: Path):
+    socket = str(tmp_path / 'watch.sock')
+    host = RustNotify([str(tmp_path)], False, False, 0, True, False, share=socket)
+    with pytest.raises(OSError, match="watch paths, recursive or the options filtering changes don't match the host"):
+        RustNotify([str(tmp_path)], False, False, 0, False, False, share=socket)
+    host.close()
+
+
+@skip_windows
+def test_share_filters_mismatch(tmp_path: Path):
+    socket = str(tmp_path / 'watch.sock')
+    host = RustNotify([str(tmp_path)], False, False, 0, True, False, share=socket, exclude_globs=['*.log'])
+    with pytest.raises(OSError, match="the options filtering changes don't match the host"):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, share=socket)
+    client = RustNotify([str(tmp_path)], False, False, 0, True, False, share=socket, exclude_globs=['*.log'])
+    assert client.effective_config['share_role'] == 'client'
+    host.close()
+
+
+@skip_windows
+@pytest.mark.parametrize('path_containment,expected', [('drop', {'foo.txt'}), ('flag', {'foo.txt', 'link.txt'})])
+def test_path_containment(tmp_path: Path, path_containment: str, expected: Set[str]):
+    root = tmp_path / 'root'
+    root.mkdir()
+    outside = tmp_path / 'outside.txt'
+    outside.write_text('secret')
+    watcher = RustNotify([str(root)], False, False, 0, True, False, path_containment=path_containment)
+
+    (root / 'foo.txt').write_text('foobar')
+    (root / 'link.txt').symlink_to(outside)
+
+    changes = watcher.watch(200, 50, 1000, None)
+    assert {Path(path).name for _, path in changes} == expected
+    assert watcher.escaped_paths == {str(root / 'link.txt'): 'resolves outside the watch paths via a symlink'}
+
+
+def test_path_containment_invalid(tmp_path: Path):
+    with pytest.raises(ValueError, match='Invalid path_containment value "foobar"'):
+        RustNotify([str(tmp_path)], False, False, 0, True, False, path_containment='foobar')
+
+
+def test_peek(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, source=True)
+    assert watcher.peek() == set()
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    sleep(0.2)
+    peeked = watcher.peek()
+    assert (1, str(tmp_path / 'foo.txt')) in peeked
+    # peeking doesn't consume the changes, which are peeked without the extra elements `watch()` adds
+    changes = watcher.watch(200, 50, 1000, None)
+    assert all(len(change) == 3 for change in changes)
+    assert {change[:2] for change in changes} == peeked
+    assert watcher.peek() == set()
+
+
+def test_clear(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    assert watcher.clear() == 0
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    sleep(0.2)
+    pending = watcher.peek()
+    assert (1, str(tmp_path / 'foo.txt')) in pending
+    assert watcher.clear() == len(pending)
+    assert watcher.peek() == set()
+    assert watcher.watch(200, 50, 100, None) == 'timeout'
+
+    (tmp_path / 'bar.txt').write_text('foobar')
+    assert (1, str(tmp_path / 'bar.txt')) in watcher.watch(200, 50, 1000, None)
+
+
+def test_changes_pending(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    assert watcher.changes_pending() == {'changes': 0, 'raw_events': 0}
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    (tmp_path / 'foo.txt').write_text('spam')
+    sleep(0.2)
+    pending = watcher.changes_pending()
+    assert pending['changes'] == len(watcher.peek())
+    assert pending['raw_events'] > pending['changes']
+
+    watcher.watch(200, 50, 1000, None)
+    assert watcher.changes_pending() == {'changes': 0, 'raw_events': 0}
+
+
+def test_memory_stats(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, rescan_interval_ms=50, source=True)
+    stats = watcher.memory_stats()
+    assert stats['pending_changes'] == 0
+    assert stats['total'] == sum(v for k, v in stats.items() if k != 'total')
+
+    (tmp_path / 'foo.txt').write_text('foobar')
+    sleep(0.2)
+    stats = watcher.memory_stats()
+    assert stats['pending_changes'] > 0
+    assert stats['change_sources'] > 0
+    assert stats['rescan_snapshot'] > 0
+
+
+def test_changes_between(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False, files_only=True, history_size=2)
+
+    start = time()
+    for name in ('a.txt', 'b.txt', 'c.txt'):
+        (tmp_path / name).write_text('foobar')
+        assert (1, str(tmp_path / name)) in watcher.watch(200, 50, 1000, None)
+    end = time()
+
+    history = watcher.changes_between(start, end)
+    assert [(change, Path(path).name) for _, change, path in history] == [(1, 'b.txt'), (1, 'c.txt')]
+    assert all(start <= timestamp <= end for timestamp, _, _ in history)
+    assert watcher.changes_between(end + 1, end + 2) == []
+
+
+def test_changes_between_no_history(tmp_path: Path):
+    watcher = RustNotify([str(tmp_path)], False, False, 0, True, False)
+    with pytest.raises(RuntimeError, match="Change history isn't kept"):
+        watcher.changes_between(0, time())
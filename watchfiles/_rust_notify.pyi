@@ -1,6 +1,14 @@
-from typing import Any, Literal, Protocol
+import asyncio
+from typing import Any, Callable, Literal, Protocol
 
-__all__ = 'RustNotify', 'WatchfilesRustInternalError'
+__all__ = (
+    'RustNotify',
+    'WatchfilesRustInternalError',
+    'WatchLimitError',
+    'ManualClock',
+    'WatchResult',
+    'backend_capabilities',
+)
 
 __version__: str
 """The package version as defined in `Cargo.toml`, modified to match python's versioning semantics."""
@@ -8,6 +16,9 @@ __version__: str
 class AbstractEvent(Protocol):
     def is_set(self) -> bool: ...
 
+class AbstractEventLoop(Protocol):
+    def call_soon_threadsafe(self, callback: Callable[..., Any], *args: Any) -> Any: ...
+
 class RustNotify:
     """
     Interface to the Rust [notify](https://crates.io/crates/notify) crate which does
@@ -19,49 +30,441 @@ class RustNotify:
         watch_paths: list[str],
         debug: bool,
         force_polling: bool,
-        poll_delay_ms: int,
+        poll_delay_ms: float,
         recursive: bool,
         ignore_permission_denied: bool,
+        *,
+        rescan_interval_ms: int = 0,
+        max_file_size: int | None = None,
+        files_only: bool = False,
+        file_types: Literal['all', 'regular', 'regular_and_symlinks'] = 'all',
+        collapse_save_storms: bool = False,
+        collapse_renames: bool = False,
+        modify_kind: bool = False,
+        modify_sizes: bool = False,
+        poll_delay_ms_overrides: dict[str, float] | None = None,
+        virtual_filesystems: Literal['error', 'skip', 'allow'] = 'allow',
+        scan_new_dirs: bool = False,
+        watch_retries: int = 0,
+        watch_retry_delay_ms: int = 50,
+        stat_retries: int = 0,
+        stat_retry_delay_ms: int = 10,
+        partial_ok: bool = False,
+        root_deleted: Literal['ignore', 'raise', 'emit', 'reattach'] = 'ignore',
+        max_pending_changes: int | None = None,
+        overflow: Literal['block', 'spill', 'rescan'] = 'block',
+        backpressure_timeout_ms: int = 5_000,
+        spill_dir: str | None = None,
+        clock: ManualClock | None = None,
+        root_filters: dict[str, dict[str, Any]] | None = None,
+        depth: bool = False,
+        first_component: bool = False,
+        source: bool = False,
+        advanced_options: dict[str, Any] | None = None,
+        share: str | None = None,
+        path_containment: Literal['off', 'drop', 'flag'] = 'off',
+        history_size: int = 0,
+        merge_aliases: bool = False,
+        ignore_cloud_sync: bool = False,
+        rename_pairs: bool = False,
+        gitignore: bool = False,
+        include_globs: list[str] | None = None,
+        exclude_globs: list[str] | None = None,
+        allow_regexes: list[str] | None = None,
+        deny_regexes: list[str] | None = None,
+        change_mask: int | None = None,
+        timestamp: bool = False,
+        is_dir: bool = False,
+        ordered: bool = False,
+        keep_duplicates: bool = False,
+        close_write: bool = False,
+        metadata_changes: Literal['modified', 'attribute', 'ignore'] = 'modified',
+        fanotify: bool = False,
+        process: bool = False,
+        backend: Literal['poll', 'native', 'inotify', 'fsevent', 'kqueue', 'windows', 'fanotify'] | None = None,
+        root_backends: dict[str, Literal['poll', 'native', 'inotify', 'fsevent', 'kqueue', 'windows']] | None = None,
+        network_filesystems: Literal['native', 'poll'] = 'native',
+        watchdog_interval_ms: int = 0,
+        max_depth: int | None = None,
+        follow_symlinks: bool | None = None,
+        canonical_paths: bool = False,
+        relative_paths: bool = False,
+        root: bool = False,
+        group_by_root: bool = False,
+        file_targets: bool = False,
+        wait_for_creation: bool = False,
+        handle_sigterm: bool = False,
+        interrupt_signals: list[int] | None = None,
+        acknowledge: bool = False,
+        watch_limit: Literal['error', 'poll'] = 'error',
+        lazy_depth: int | None = None,
+        registration_threads: int = 1,
+        progress: Callable[[int], Any] | None = None,
+        compare_contents: bool = False,
+        poll_threads: int = 1,
+        max_poll_delay_ms: float | None = None,
+        compare_contents_max_size: int | None = None,
     ) -> None:
         """
         Create a new `RustNotify` instance and start a thread to watch for changes.
 
-        `FileNotFoundError` is raised if any of the paths do not exist.
+        `FileNotFoundError` is raised if any of the paths do not exist, unless `wait_for_creation` is `True`.
+        [`WatchLimitError`][watchfiles._rust_notify.WatchLimitError] is raised on Linux if the inotify limit on
+        instances is reached, or the limit on watches with `watch_limit='error'`.
 
         Args:
             watch_paths: file system paths to watch for changes, can be directories or files
             debug: if true, print details about all events to stderr
             force_polling: if true, always use polling instead of file system notifications
-            poll_delay_ms: delay between polling for changes, only used if `force_polling=True`
+            poll_delay_ms: delay between polling for changes, only used if `force_polling=True`, fractional values
+                are allowed for sub-millisecond delays
             recursive: if `True`, watch for changes in sub-directories recursively, otherwise watch only for changes in
                 the top-level directory, default is `True`.
-            ignore_permission_denied: if `True`, permission denied errors are ignored while watching changes.
+            ignore_permission_denied: if `True`, permission denied errors are ignored while watching changes,
+                paths which couldn't be watched are available via
+                [`skipped_paths`][watchfiles._rust_notify.RustNotify.skipped_paths].
+            rescan_interval_ms: if non-zero, rescan all watched paths on a background thread at this interval,
+                diffing against the previous scan and adding any changes the watcher failed to report.
+            max_file_size: if set, added and modified events for files larger than this many bytes are ignored.
+            files_only: if `True`, changes to directories are ignored and only changes to files are reported.
+            file_types: `'regular'` to only report changes to regular files, ignoring directories, symlinks, sockets,
+                FIFOs and device nodes, or `'regular_and_symlinks'` to report symlinks too. Deletions are reported
+                unless the path was seen to be another type of file before it was deleted.
+            collapse_save_storms: if `True`, the sequences of changes editors produce when "safely" saving a file
+                (writing a temporary file and renaming it over the original) are collapsed into a single
+                modified change for the file being saved.
+            collapse_renames: if `True`, when a path is renamed more than once in a batch, e.g. `A -> B -> C`, the
+                intermediate names are dropped so only `A` being deleted and `C` being added are reported. Only
+                applies with backends which link both paths of a rename, see
+                [`capabilities`][watchfiles._rust_notify.RustNotify.capabilities].
+            modify_kind: if `True`, when polling, file sizes are tracked and each change tuple gets a third element
+                describing modifications: `'truncated'` if the file got smaller, `'appended'` if it grew, or
+                `'replaced'` if its size is unchanged. The element is `None` for other changes, or if the previous
+                size of the file is unknown.
+            modify_sizes: if `True`, when polling, each change tuple gets an extra element which is
+                `(old_size, new_size)` for modifications, or `None` for other changes. `old_size` is `None` if the
+                previous size of the file is unknown. If both `modify_kind` and `modify_sizes` are set, the kind
+                comes first.
+            poll_delay_ms_overrides: mapping of watch paths to the delay to use when polling them, paths not included
+                use `poll_delay_ms`. Paths with different delays are polled by separate threads.
+            virtual_filesystems: what to do with watch paths on virtual filesystems like `/proc` and `/sys`
+                (Linux only), where file metadata doesn't reflect changes and native notifications are rarely emitted:
+                `'allow'` watches them anyway, `'skip'` doesn't watch those paths and emits a `RuntimeWarning` for
+                each, `'error'` raises an `OSError`. Only the watch paths themselves are checked, not their
+                subdirectories.
+            scan_new_dirs: if `True` and `recursive=True`, when a directory is created its contents are scanned and
+                reported as added, so files created in the directory before the watcher starts watching it
+                aren't missed. The scan walks the whole new tree on the backend's event thread, so is off by default.
+            watch_retries: number of times to retry watching a path if it fails, e.g. because the path is briefly
+                missing while being recreated. Paths which needed retries are available via
+                [`retried_paths`][watchfiles._rust_notify.RustNotify.retried_paths].
+            watch_retry_delay_ms: delay before the first retry, the delay doubles with each retry up to one second.
+            stat_retries: when polling, number of times to retry stat-ing a path which the watcher reports as deleted
+                after failing to read it, e.g. due to `ESTALE` or `EIO` on a flaky network mount. The deletion is
+                only reported if every retry fails, and errors reading paths no longer stop the watcher. With the
+                default of `0` such errors are raised by `watch()`.
+            stat_retry_delay_ms: delay before the first stat retry, the delay doubles with each retry up to one
+                second.
+            partial_ok: if `True`, paths which can't be watched (including missing paths) are skipped rather than
+                raising an error, as long as at least one path can be watched. Which paths were watched is available
+                via [`registration_report`][watchfiles._rust_notify.RustNotify.registration_report].
+            root_deleted: what to do when a watch path is deleted while it's being watched, checked on each step of
+                [`watch`][watchfiles._rust_notify.RustNotify.watch]: `'ignore'` leaves it to the backend, which may
+                or may not report the deletion, `'raise'` raises a `FileNotFoundError`, `'emit'` reports a deleted
+                change for the path, `'reattach'` reports a deleted change, then when the path is recreated watches it
+                again and reports an added change. A path deleted and recreated between checks is handled the same
+                way, provided the backend reported the deletion or (on unix) the new directory has a different inode.
+            max_pending_changes: if set, at most this many changes are buffered in memory between calls to
+                [`watch`][watchfiles._rust_notify.RustNotify.watch], `overflow` decides what happens to further
+                changes, none are dropped except with `overflow='rescan'`.
+            overflow: what to do once `max_pending_changes` are buffered: `'block'` blocks the watcher until `watch`
+                collects the changes, `'spill'` appends further changes to a file which is read back as `watch`
+                collects changes, so each call to `watch` returns at most `max_pending_changes` changes. `'rescan'`
+                replaces the buffered changes with a [`Change.rescan`][watchfiles.Change.rescan] change for each
+                watch path, dropping further changes until `watch` collects them, so memory use stays bounded
+                however far behind the consumer falls, at the cost of having to rescan.
+            backpressure_timeout_ms: how long the watcher blocks waiting for room in the buffer with
+                `overflow='block'`, if the buffer is still full after this time the next call to `watch`
+                raises a [`WatchfilesRustInternalError`][watchfiles._rust_notify.WatchfilesRustInternalError].
+            spill_dir: directory to create the spill file in with `overflow='spill'`, defaults to the system's
+                temporary directory. The file is deleted when the `RustNotify` instance is closed or dropped.
+            clock: if set, [`watch`][watchfiles._rust_notify.RustNotify.watch] uses this clock rather than the system
+                clock when waiting, debouncing and timing out, intended for tests.
+            root_filters: mapping of watch paths to filter options used for changes under that path instead of the
+                arguments above, options are `'max_file_size'`, `'files_only'` and `'file_types'`, options not
+                included use the values above. Where watch paths are nested, the options for the innermost path are
+                used.
+            depth: if `True`, each change tuple gets an extra element which is the number of levels the path is below
+                the watch path containing it, e.g. `1` for a file directly inside the watch path. Where watch paths
+                are nested, the innermost is used. Comes after any `modify_kind` and `modify_sizes` elements.
+            first_component: if `True`, each change tuple gets an extra element which is the first component of the
+                path below the watch path containing it, e.g. `'foo'` for `<watch path>/foo/bar.py`, or `None` for
+                the watch path itself. Comes after any `depth` element.
+            source: if `True`, each change tuple gets a final extra element naming what first reported the change:
+                the watcher backend (`'poll'`, `'inotify'`, `'fsevent'`, `'kqueue'`, `'windows'` or `'fanotify'`),
+                `'rescan'` for changes found by `rescan_interval_ms` rescans, `'scan'` for the contents of new
+                directories found via `scan_new_dirs`, `'root_check'` for changes reported due to `root_deleted`, or
+                `'shared'` for changes received from the host of a `share` socket.
+            advanced_options: options passed directly to the underlying notify watcher configuration, applied after
+                the options above so they take precedence. Supported keys are `'poll_interval_ms'` (overrides
+                `poll_delay_ms` and `poll_delay_ms_overrides`) and `'compare_contents'` (when polling, compare file
+                contents as well as modification times). `ValueError` is raised for any other key.
+            share: path of a unix socket used to share one watcher between processes watching the same paths. The
+                first process to use the socket hosts the watcher, later processes connect to it as clients and
+                receive each batch of changes as the host's `watch()` returns it rather than watching the paths
+                themselves; clients still apply their own options to those changes. `watch_paths`, `recursive` and
+                the options filtering which changes are reported (e.g. `exclude_globs`, `gitignore`, `change_mask`)
+                must match the host's, otherwise `OSError` is raised. If the host closes, its clients' `watch()` raises
+                `WatchfilesRustInternalError`. `health_check()` and `switch_backend()` aren't supported by clients.
+                Not supported on Windows.
+            path_containment: check every changed path is inside one of `watch_paths`, both once `.` and `..`
+                components are removed and once symlinks are resolved, for consumers which pass changed paths on to
+                e.g. shell commands. With `'drop'` changes to paths which escape the watch paths are removed, with
+                `'flag'` they're kept. Either way such paths are listed in
+                [`escaped_paths`][watchfiles._rust_notify.RustNotify.escaped_paths].
+            history_size: number of the most recent changes returned by `watch()` to keep, so they can be queried with
+                [`changes_between`][watchfiles._rust_notify.RustNotify.changes_between]. `0` keeps no history.
+            merge_aliases: if `True`, watch paths which refer to the same directory as an earlier watch path, e.g. via
+                a symlink or bind mount, aren't watched separately, so each change is reported once via the earlier
+                path rather than once per path. See
+                [`aliased_paths`][watchfiles._rust_notify.RustNotify.aliased_paths].
+            ignore_cloud_sync: if `True`, modifications to cloud storage placeholders (OneDrive, Dropbox etc.) which
+                only reflect the file being downloaded, pinned or freed up, rather than its contents changing, are
+                ignored. A modification is ignored if the file's size and modification time are unchanged since the
+                last event for it, so the first event for each placeholder is always reported. Only has an effect on
+                Windows.
+            rename_pairs: if `True`, a rename is reported as a single change `(4, old_path, new_path)` (`4` being
+                [`Change.renamed`][watchfiles.Change.renamed]) rather than the old path being deleted and the new path
+                being added, any extra elements follow the new path. With `collapse_renames` a path renamed more than
+                once is reported as one rename from its first to its last name. Only applies with backends which link
+                both paths of a rename, see [`capabilities`][watchfiles._rust_notify.RustNotify.capabilities].
+            gitignore: if `True`, changes to paths ignored by `.gitignore` files are ignored before they reach
+                python. Rules are read from the `.gitignore` files in the git repository containing each watch path
+                (or the watch path itself outside a repository), `.git/info/exclude` and the global excludes file,
+                `core.excludesFile` or `~/.config/git/ignore`. `core.excludesFile` is read by running `git config`,
+                or from the config files directly if git isn't installed. Changes to `.gitignore` files and
+                `.git/info/exclude` take effect immediately.
+            include_globs: if set, only changes to paths matching one of these glob patterns are reported.
+                Patterns are matched against the path relative to its watch path, using `/` as the separator,
+                patterns without a `/` match the file name at any depth. `*` and `?` don't match `/`, `**` does
+                as a whole path component (e.g. `a/**/b`), elsewhere it's the same as `*`, `[abc]` and `[!abc]`
+                match character sets, `\\` escapes the next character.
+            exclude_globs: changes to paths matching one of these glob patterns, or inside a directory which
+                matches one, are ignored. Patterns are matched as with `include_globs`, exclusion takes precedence
+                over inclusion.
+            allow_regexes: if set, only changes to paths where one of these regular expressions matches are reported.
+                Expressions are searched for anywhere in the full path, use `^` and `$` to anchor them. The syntax is
+                that of the rust [`regex`](https://docs.rs/regex/latest/regex/#syntax) crate, which doesn't support
+                lookaround or backreferences.
+            deny_regexes: changes to paths where one of these regular expressions matches are ignored, takes
+                precedence over `allow_regexes`.
+            change_mask: if set, only these kinds of change are reported, as a bitmask with `1 << change` set for each
+                kind, e.g. `(1 << Change.added) | (1 << Change.deleted)` to ignore modifications. Other changes are
+                discarded as they happen rather than when `watch` returns.
+            timestamp: if `True`, each change tuple gets a final extra element which is when the change arrived, in
+                seconds since the epoch as returned by `time.time()`. Where a path has the same change several times
+                in a batch, this is when the first arrived. Comes after any `source` element.
+            is_dir: if `True`, each change tuple gets a final extra element which is whether the path is a directory,
+                taken from the event where the backend reports it and otherwise from checking the path when the change
+                arrives. Deleted paths are classified from when they were last seen, `None` is used where it can't be
+                determined, e.g. for a path deleted before any change to it was seen. Comes after any `timestamp`
+                element.
+            ordered: if `True`, `watch` returns a `list` of changes in the order they arrived rather than a `set`.
+                Renames reported with `rename_pairs` are placed where the new path was added.
+            keep_duplicates: if `True`, a change which arrives again while it's pending is repeated in the list each
+                time it arrived, rather than only appearing where it first arrived. Requires `ordered=True`.
+            close_write: if `True`, a file opened for writing being closed is reported as a `(5, path)` change (`5`
+                being [`Change.written`][watchfiles.Change.written]), so consumers can wait for a writer to finish
+                rather than reacting to each modification, combine with `change_mask` to ignore the modifications.
+                Only applies with backends which report it, see
+                [`capabilities`][watchfiles._rust_notify.RustNotify.capabilities].
+            metadata_changes: how changes to only a path's metadata (e.g. `chmod`, `chown` or `touch`) are reported:
+                `'modified'` reports them as modifications, `'attribute'` as `(6, path)` changes (`6` being
+                [`Change.attribute`][watchfiles.Change.attribute]) and `'ignore'` drops them. Polling can't tell
+                metadata changes from other modifications, so they're always reported as modifications when polling.
+            fanotify: if `True`, watch with fanotify rather than inotify, which reports the process behind each
+                change, see `process`. Linux only, requires the `CAP_SYS_ADMIN` capability (`PermissionError` is
+                raised without it) and Linux 5.9 or later. The whole filesystem containing each watch path is watched
+                and changes outside the watch paths discarded, so there's no limit on the number of directories
+                watched, but symlinks aren't followed. Can't be combined with `force_polling`.
+            process: if `True`, each change tuple gets a final extra element which is `(pid, uid)`, the ID of the
+                process which made the change and the effective ID of the user it was running as, or `None` where the
+                backend doesn't report it, currently only `fanotify` does. `uid` is `None` if the process exited
+                before it could be looked up. Where a path has the same change several times in a batch, this is the
+                process behind the first. Comes after any `is_dir` element.
+            backend: the backend to watch with, overriding the choice of native backend for this platform, e.g.
+                `'poll'` is the same as `force_polling=True`. `'native'` or the name of this platform's native backend
+                uses it without falling back to polling if it's unavailable. `ValueError` is raised if the backend
+                isn't available on this platform or in this build, or if `force_polling` or `fanotify` is also set.
+                On macOS, `'kqueue'` requires watchfiles to be built with the `macos_kqueue` cargo feature, which
+                replaces FSEvents, as notify can't use both in one build. The backend in use is
+                `effective_config['backend']`.
+            root_backends: watch paths mapped to the backend to watch them with, `'poll'` or `'native'` (or the name
+                of the native backend for this platform), e.g. to poll a network mount while watching local paths
+                natively. Each gets its own watcher, as with
+                [`switch_backend`][watchfiles._rust_notify.RustNotify.switch_backend], other watch paths use the
+                backend chosen by `backend`, `force_polling` or `fanotify`. `ValueError` is raised if a path isn't a
+                watch path or a backend isn't available. Not supported by clients of a `share` socket.
+            network_filesystems: how to watch paths on network, shared-folder and overlay filesystems (NFS, SMB, 9p,
+                virtiofs, VirtualBox shared folders, sshfs and overlayfs, Linux only), where changes made by other
+                machines, the VM host or an overlay's lower layers don't produce native notifications: `'native'`
+                watches them like any other path, `'poll'` polls them while other paths are watched natively, as if
+                they were in `root_backends`. Only the watch paths themselves are checked, not their subdirectories.
+                Paths moved to polling are included in `effective_config['root_backends']`.
+            watchdog_interval_ms: if non-zero, while `watch` is running, check at this interval that each natively
+                watched directory still receives events, by writing and deleting a probe file in it as
+                [`health_check`][watchfiles._rust_notify.RustNotify.health_check] does. A path whose probe isn't
+                seen by the next check gets a new native watcher, if that also stops receiving events, or can't be
+                created, the path is polled instead, see
+                [`watchdog_restarts`][watchfiles._rust_notify.RustNotify.watchdog_restarts]. Changes made while a
+                watcher was stopped aren't reported, combine with `rescan_interval_ms` to find them.
+            max_depth: if set, ignore changes more than this many levels below the watch path containing them, e.g.
+                with `max_depth=1` only changes to the watch path's direct children are reported. Changes are filtered
+                out after they're received, the whole tree below each watch path is still watched.
+            follow_symlinks: whether to report changes inside directories reached via symlinks below the watch paths,
+                `None` leaves this to the backend, see
+                [`capabilities`][watchfiles._rust_notify.RustNotify.capabilities]. With `False` changes below
+                symlinks are filtered out after they're received, as for `max_depth`, changes to the symlinks
+                themselves are still reported. `True` raises `ValueError` with backends which can't follow symlinks
+                (e.g. FSEvents). Either way, symlinks pointing to one of their ancestors aren't followed, see
+                [`symlink_loops`][watchfiles._rust_notify.RustNotify.symlink_loops].
+            canonical_paths: whether to return canonical paths, with symlinks and `..` segments in the directories
+                containing each changed path resolved, e.g. `/private/var/...` rather than `/var/...` on macOS. The
+                changed path itself isn't resolved, so changes to symlinks are reported as the symlink, and deleted
+                paths are resolved via whichever of their ancestors still exist.
+            relative_paths: whether to return paths relative to the innermost watch path containing them, e.g.
+                `'src/main.py'` rather than `'/project/src/main.py'`, a change to a watch path itself is returned as
+                `'.'`. Can't be combined with `canonical_paths`, use `first_component` or `depth` to find which watch
+                path a change is below.
+            root: if `True`, each change tuple gets a final extra element which is the watch path containing the
+                changed path, as passed to `__init__` or [`add_path`][watchfiles._rust_notify.RustNotify.add_path],
+                or `None` for paths outside every watch path. Where watch paths are nested, the innermost is used.
+                Comes after any `process` element.
+            group_by_root: if `True`, [`watch`][watchfiles._rust_notify.RustNotify.watch] returns changes grouped in a
+                `dict` mapping the watch path containing them to their `set` (or `list` with `ordered=True`), only
+                watch paths with changes are included. Where watch paths are nested, changes are grouped under the
+                innermost, changes outside every watch path are grouped under `None`.
+            file_targets: if `True`, watch paths which are files are watched via the directory containing them, with
+                changes to anything else in that directory ignored, so they're still watched after being replaced,
+                e.g. by editors which save by renaming a new file over the old one, which removes a watch on the file
+                itself with most native backends. Only applies to watch paths passed here, not to those added by
+                [`add_path`][watchfiles._rust_notify.RustNotify.add_path].
+            wait_for_creation: if `True`, watch paths which don't exist yet are accepted, and checked for on each step
+                of [`watch`][watchfiles._rust_notify.RustNotify.watch] until they're created, at which point they're
+                watched and an added change is reported for the path. Changes within the path before it's watched
+                aren't reported. See [`waiting_paths`][watchfiles._rust_notify.RustNotify.waiting_paths].
+            handle_sigterm: if `True`, a handler for `SIGTERM` is installed, after which
+                [`watch`][watchfiles._rust_notify.RustNotify.watch] returns `'signal'` when the process receives
+                `SIGTERM`, closing this watcher first so its backend threads stop, rather than the process being
+                terminated with them still running. Any handler already installed for `SIGTERM`, e.g. via
+                `signal.signal()`, is still called. While `watch` isn't running, `SIGTERM` terminates the process as
+                it would otherwise, and the previous handler is restored when this watcher is closed. Unix only,
+                requires the `signals` feature, which is on by default.
+            interrupt_signals: signal numbers, e.g. `signal.SIGHUP`, which make
+                [`watch`][watchfiles._rust_notify.RustNotify.watch] return `'signal'` as soon as they're received,
+                e.g. to reload straight away, handled the same way as `SIGTERM` with `handle_sigterm`, but
+                without closing this watcher. A signal received while `watch` isn't running has its usual effect,
+                and if a handler was already installed for it, also makes the next call return `'signal'`. The
+                previous handlers are restored when this watcher is closed.
+                `OSError` is raised for signals which can't be handled, e.g. `SIGKILL`.
+            acknowledge: if `True`, each batch of changes returned by
+                [`watch`][watchfiles._rust_notify.RustNotify.watch] comes with a
+                [`token`][watchfiles._rust_notify.WatchResult.token], and is returned again by each call to `watch`
+                until the token is passed to [`ack`][watchfiles._rust_notify.RustNotify.ack], so a consumer which
+                fails part way through processing a batch gets it again, giving at-least-once delivery. Requires
+                `structured=True` when calling `watch`, so isn't supported by `watch_async` or `set_callback`.
+            watch_limit: what to do when the inotify limit on watches (`fs.inotify.max_user_watches`) is reached
+                part way through watching a directory tree on Linux: `'error'` raises
+                [`WatchLimitError`][watchfiles._rust_notify.WatchLimitError], `'poll'` keeps the directories
+                already watched and polls the rest, see
+                [`polled_subtrees`][watchfiles._rust_notify.RustNotify.polled_subtrees]. New directories
+                created inside natively watched directories once the limit is reached aren't watched. Builds
+                without polling always raise.
+            lazy_depth: if set, directories more than this many levels below each watch path aren't watched
+                straight away, but once their parent directory reports a change, so watching a very large tree
+                starts quickly and doesn't use inotify watches for directories which never change. Changes
+                inside a directory before it's watched are missed. `0` watches just the watch paths to start
+                with. Requires `recursive=True` and native notifications.
+            registration_threads: if greater than `1`, each watch path gets its own watcher, and up to this many
+                are created at once, so registering watches for several large trees, or scanning them for the
+                first time when polling, takes as long as the largest rather than all of them together. Watch
+                paths inside another watch path share its watcher. On Linux, each watcher uses an inotify instance,
+                with `watch_limit='poll'` a watch path is polled if the limit on instances or watches is reached.
+                Can't be used with `fanotify` or `lazy_depth`.
+            progress: called with the number of directories registered so far, about every half second while
+                watch paths are being registered, then once registration is finished, so setting up a very large
+                tree can be followed. Exceptions it raises are reported with `sys.unraisablehook`. With
+                `debug=True` the count is printed too. Only inotify watches and directories found when polling
+                are counted, so on macOS and Windows a natively watched tree is reported as `0`.
+            compare_contents: when polling, hash the contents of every file on each poll and report a modification
+                if they've changed, even if the modification time hasn't, e.g. on network shares with coarse or
+                stale modification times. Every file is read on every poll, so this is slow for large trees.
+                Overridden by `'compare_contents'` in `advanced_options`.
+            poll_threads: when polling, if greater than `1`, the subdirectories of each watch path are shared between
+                this many poll watchers, each scanning its share on its own thread, so a poll of a large tree, e.g.
+                on a network filesystem, takes a fraction of the time. Directories created directly inside a watch
+                path are polled from the next `watch` step, so changes inside them before then are missed. Watch
+                paths added with `add_path` or polled via `root_backends` are polled by a single thread. Requires
+                `recursive=True`, can't be used with `registration_threads` or `poll_delay_ms_overrides`.
+            max_poll_delay_ms: when polling, if set, each poll watcher times its scans and waits twice as long as
+                the last scan took before the next, between `poll_delay_ms` and this, so polling a tree which takes
+                longer to scan than `poll_delay_ms` doesn't keep a CPU busy. The current interval is available via
+                [`poll_delay_ms`][watchfiles._rust_notify.RustNotify.poll_delay_ms]. Can't be used with
+                `poll_delay_ms_overrides`.
+            compare_contents_max_size: with `compare_contents`, only files no larger than this many bytes have their
+                contents hashed, larger files are compared by size and modification time, so multi-GB files aren't
+                read on every poll. Applies to the watch paths polled when this watcher is created.
         """
     def watch(
         self,
-        debounce_ms: int,
-        step_ms: int,
-        timeout_ms: int,
+        debounce_ms: float,
+        step_ms: float,
+        timeout_ms: float,
         stop_event: AbstractEvent | None,
-    ) -> set[tuple[int, str]] | Literal['signal', 'stop', 'timeout']:
+        *,
+        linger_ms: float = 0,
+        flush_on_timeout: bool = False,
+        structured: bool = False,
+    ) -> (
+        set[tuple[Any, ...]]
+        | list[tuple[Any, ...]]
+        | dict[str | None, set[tuple[Any, ...]] | list[tuple[Any, ...]]]
+        | Literal['signal', 'stop', 'timeout']
+        | WatchResult
+    ):
         """
         Watch for changes.
 
         This method will wait `timeout_ms` milliseconds for changes, but once a change is detected,
         it will group changes and return in no more than `debounce_ms` milliseconds.
 
-        The GIL is released during a `step_ms` sleep on each iteration to avoid
-        blocking python.
+        The GIL is released while waiting on each iteration to avoid blocking python. Until the first change
+        arrives, each iteration ends as soon as one does, or after `step_ms` to check for signals and `stop_event`.
+        On unix, `SIGINT`, `SIGTERM` with `handle_sigterm=True`, and `interrupt_signals` end the iteration as soon as
+        they're received.
+
+        All times are in milliseconds, fractional values are allowed for sub-millisecond precision.
 
         Args:
             debounce_ms: maximum time in milliseconds to group changes over before returning.
             step_ms: time to wait for new changes in milliseconds, if no changes are detected
-                in this time, and at least one change has been detected, the changes are yielded.
+                in this time, and at least one change has been detected, the changes are yielded. Also how often
+                `stop_event`, and signals other than those which end the iteration straight away, are checked while
+                waiting for the first change.
             timeout_ms: maximum time in milliseconds to wait for changes before returning,
                 `0` means wait indefinitely, `debounce_ms` takes precedence over `timeout_ms` once
-                a change is detected.
+                a change is detected, unless `flush_on_timeout` is `True`.
             stop_event: event to check on every iteration to see if this function should return early.
                 The event should be an object which has an `is_set()` method which returns a boolean.
+            linger_ms: time to keep collecting changes after the batch has settled, so changes which trail
+                slightly behind the rest, e.g. the rename at the end of an editor's save, are returned in the
+                same batch rather than the next one.
+            flush_on_timeout: if `True`, once `timeout_ms` is exceeded the changes collected so far are returned
+                straight away, even if more are still arriving, rather than waiting for the batch to settle, and
+                `'timeout'` is only returned if there are none. Changes arriving after the timeout are kept for the
+                next call rather than discarded.
+            structured: if `True`, a [`WatchResult`][watchfiles._rust_notify.WatchResult] is returned rather than
+                either changes or a string, so changes returned due to `flush_on_timeout` can be told apart.
 
         Returns:
             See below.
@@ -69,10 +472,398 @@ class RustNotify:
         Return values have the following meanings:
 
         * Change details as a `set` of `(event_type, path)` tuples, the event types are ints which match
-          [`Change`][watchfiles.Change], `path` is a string representing the path of the file that changed
-        * `'signal'` string, if a signal was received
+          [`Change`][watchfiles.Change], `path` is a string representing the path of the file that changed,
+          extra elements are appended to each tuple depending on the options passed when creating `RustNotify`,
+          or a `list` in the order the changes arrived with `ordered=True`, grouped in a `dict` by watch path
+          with `group_by_root=True`
+        * `'signal'` string, if a signal was received, `SIGINT`, `SIGTERM` with `handle_sigterm=True`, or one
+          of `interrupt_signals`
         * `'stop'` string, if the `stop_event` was set
         * `'timeout'` string, if `timeout_ms` was exceeded
+
+        With `structured=True`, a [`WatchResult`][watchfiles._rust_notify.WatchResult] whose `reason` is `'changes'`
+        or one of the strings above.
+        """
+    def watch_async(
+        self,
+        debounce_ms: float,
+        step_ms: float,
+        timeout_ms: float,
+        *,
+        linger_ms: float = 0,
+        flush_on_timeout: bool = False,
+    ) -> asyncio.Future[
+        set[tuple[Any, ...]]
+        | list[tuple[Any, ...]]
+        | dict[str | None, set[tuple[Any, ...]] | list[tuple[Any, ...]]]
+        | Literal['timeout']
+    ]:
+        """
+        Asynchronous equivalent of [`watch`][watchfiles._rust_notify.RustNotify.watch], which must be called from
+        a running asyncio event loop. Rather than a thread waiting for changes, each step `watch` would take is
+        taken as a callback in the loop's thread, which is woken as soon as the first change of a batch arrives, and
+        the returned future is resolved with the result.
+
+        Cancelling the future stops waiting, as setting `stop_event` would, and any pending changes are discarded.
+
+        Arguments and results match those of [`watch`][watchfiles._rust_notify.RustNotify.watch], except that there's
+        no `stop_event`, so neither `'stop'` nor `'signal'` are returned. Errors are raised when the future is
+        awaited.
+
+        Only one call can be awaited at a time, another can be made once the future has a result or has been
+        cancelled.
+
+        `RuntimeError` is raised if there's no running event loop, the watcher has been closed or another call is
+        being awaited, `ValueError` if any of the durations is invalid.
+        """
+    def fileno(self) -> int:
+        """
+        A file descriptor which is readable while changes are pending, so `RustNotify` can be used with `select`
+        or an event loop's `add_reader`, calling [`watch`][watchfiles._rust_notify.RustNotify.watch] once it's
+        readable, rather than blocking a thread in `watch`.
+
+        The descriptor is owned by `RustNotify` and must not be closed or read from. It may occasionally be readable
+        with no changes pending, e.g. after changes are removed by collapsing.
+
+        `OSError` is raised on platforms other than unix, where
+        [`event_handle`][watchfiles._rust_notify.RustNotify.event_handle] can be used instead, `RuntimeError` if the
+        watcher has been closed.
+        """
+    def event_handle(self) -> int:
+        """
+        The Windows equivalent of [`fileno`][watchfiles._rust_notify.RustNotify.fileno], a handle to a manual-reset
+        event which is signaled while changes are pending, for use with `WaitForMultipleObjects` or
+        `ProactorEventLoop` integrations, calling [`watch`][watchfiles._rust_notify.RustNotify.watch] once it's
+        signaled.
+
+        The handle is owned by `RustNotify` and must not be closed, set or reset. As with `fileno`, it may
+        occasionally be signaled with no changes pending.
+
+        `OSError` is raised on platforms other than Windows, `RuntimeError` if the watcher has been closed.
+        """
+    def register_loop(self, event_loop: AbstractEventLoop, callback: Callable[[], Any]) -> None:
+        """
+        Call `event_loop.call_soon_threadsafe(callback)` from a thread owned by `RustNotify` each time the first
+        change of a batch arrives, or straight away if changes are already pending, so an asyncio program can wait
+        for changes without a thread blocked in [`watch`][watchfiles._rust_notify.RustNotify.watch].
+
+        Once the callback has been called, it isn't called again until
+        [`watch`][watchfiles._rust_notify.RustNotify.watch] has returned the pending changes and another change
+        arrives. Registering another loop replaces this one, the callback is no longer called once
+        [`unregister_loop`][watchfiles._rust_notify.RustNotify.unregister_loop] or
+        [`close`][watchfiles._rust_notify.RustNotify.close] is called, or the loop is closed.
+
+        Args:
+            event_loop: the loop to wake, anything with a `call_soon_threadsafe` method can be used.
+            callback: called with no arguments in the loop's thread.
+
+        `TypeError` is raised if `callback` isn't callable, `RuntimeError` if the watcher has been closed.
+        """
+    def set_callback(
+        self,
+        callback: Callable[[set[tuple[Any, ...]] | list[tuple[Any, ...]] | dict[str | None, Any]], Any] | None,
+        *,
+        debounce_ms: float = 1_600,
+        step_ms: float = 50,
+        linger_ms: float = 0,
+    ) -> None:
+        """
+        Call `callback` with each batch of changes, from a thread owned by `RustNotify` which calls
+        [`watch`][watchfiles._rust_notify.RustNotify.watch] in a loop, so no thread of your own is needed to
+        consume changes, e.g. in GUI applications.
+
+        Exceptions raised by `callback` are reported via `sys.unraisablehook` and don't stop further calls, an
+        error from `watch` is reported the same way but stops them.
+
+        The thread stops when `set_callback(None)` or [`close`][watchfiles._rust_notify.RustNotify.close] is called,
+        or once the `RustNotify` instance is garbage collected, it doesn't keep the instance alive. Setting another
+        callback replaces this one.
+        Changes pending when the callback is replaced or removed are discarded.
+
+        Args:
+            callback: called with each batch of changes in the same form `watch` returns them, `None` to stop.
+            debounce_ms: as for `watch`.
+            step_ms: as for `watch`.
+            linger_ms: as for `watch`.
+
+        `TypeError` is raised if `callback` isn't callable, `RuntimeError` if the watcher has been closed.
+        """
+    def stop(self) -> None:
+        """
+        Make [`watch`][watchfiles._rust_notify.RustNotify.watch] return `'stop'` straight away, rather than on its
+        next step as with `stop_event`. This can be called from any thread, and if `watch` isn't running, the next
+        call returns `'stop'` instead. As with `stop_event`, pending changes are discarded.
+
+        This also stops the thread calling the callback passed to
+        [`set_callback`][watchfiles._rust_notify.RustNotify.set_callback].
+        """
+    def unregister_loop(self) -> None:
+        """
+        Stop calling the callback passed to [`register_loop`][watchfiles._rust_notify.RustNotify.register_loop].
+        """
+    def switch_backend(
+        self, path: str, backend: Literal['poll', 'native', 'inotify', 'fsevent', 'kqueue', 'windows']
+    ) -> None:
+        """
+        Move a watch path to another backend while watching, e.g. to poll a path which has moved to a network mount.
+
+        The new backend starts watching the path before the old one stops, and snapshots of the path taken before
+        and after the switch are compared so changes made while switching aren't lost.
+
+        Args:
+            path: one of the paths passed to `__init__`.
+            backend: `'poll'`, or `'native'` (or the name of the native backend for this platform) to stop polling.
+
+        `ValueError` is raised if `path` isn't a watch path or `backend` isn't available, `RuntimeError` if the
+        watcher has been closed.
+        """
+    def add_path(self, path: str, recursive: bool | None = None) -> None:
+        """
+        Start watching another path, this can be called while [`watch`][watchfiles._rust_notify.RustNotify.watch]
+        is running in another thread.
+
+        Args:
+            path: the path to watch.
+            recursive: whether to watch `path` recursively, defaults to `recursive` as passed to `__init__`.
+
+        Options which are applied relative to the paths passed to `__init__`, i.e. `gitignore`, `include_globs`,
+        `exclude_globs`, `max_depth` and `rescan_interval_ms` rescans, don't apply to added paths.
+
+        `ValueError` is raised if `path` is already a watch path, `FileNotFoundError` if it doesn't exist and
+        `RuntimeError` if the watcher has been closed or is a client of a `share` socket.
+        """
+    def remove_path(self, path: str) -> None:
+        """
+        Stop watching one of the watch paths, this can be called while
+        [`watch`][watchfiles._rust_notify.RustNotify.watch] is running in another thread. Changes already found
+        below `path` are still returned.
+
+        `ValueError` is raised if `path` isn't a watch path, `RuntimeError` if the watcher has been closed.
+        """
+    def pause(self) -> None:
+        """
+        Stop collecting changes until [`resume`][watchfiles._rust_notify.RustNotify.resume] is called, e.g.
+        to avoid a storm of events while code is regenerated. Native watches are removed while paused, paths
+        watched by several poll watchers or a shared watcher are still watched but their changes are dropped.
+
+        Changes made while paused are never returned, changes found before pausing still are.
+
+        `RuntimeError` is raised if the watcher has been closed or is the host of a `share` socket.
+        """
+    def resume(self) -> None:
+        """
+        Watch the watch paths again after [`pause`][watchfiles._rust_notify.RustNotify.pause].
+
+        `RuntimeError` is raised if the watcher has been closed.
+        """
+    def watch_state(self) -> dict[str, Any]:
+        """
+        How each watch path is being watched, for debugging why changes aren't being reported. Unlike
+        [`effective_config`][watchfiles._rust_notify.RustNotify.effective_config] this reflects changes made
+        since `__init__`, e.g. by [`add_path`][watchfiles._rust_notify.RustNotify.add_path] or the watchdog.
+
+        Returns a dict with keys:
+
+        * `'backend'`: the backend of the main watcher, as in `effective_config['backend']`
+        * `'paused'`: whether the watcher is [paused][watchfiles._rust_notify.RustNotify.pause]
+        * `'pending_changes'`: the number of changes found but not yet returned by
+          [`watch`][watchfiles._rust_notify.RustNotify.watch]
+        * `'steps'`: how many times `watch` or `watch_async` has woken to check for changes, while nothing is
+          pending they only wake once a change arrives, `stop` is called, a signal is received or they time out
+        * `'roots'`: each watch path mapped to a dict of its `'backend'`, `'recursive'`, `'poll_delay_ms'` (`None`
+          unless polled), `'filters'` (`'max_file_size'`, `'files_only'` and `'file_types'` after `root_filters`
+          are applied) and `'status'`: `'watched'`, `'nested'` or `'aliased'` if it's covered by another watch
+          path's watch, `'skipped'` if it couldn't be watched, or `'waiting'` if it's waiting to be created with
+          `wait_for_creation`, with `'reason'` giving the covering watch path or why it was skipped
+        * `'filters'`: the filters applied to every watch path, `'gitignore'`, `'include_globs'`, `'exclude_globs'`,
+          `'allow_regexes'`, `'deny_regexes'`, `'change_mask'`, `'max_depth'` and `'follow_symlinks'`
+
+        `RuntimeError` is raised if the watcher has been closed.
+        """
+    @property
+    def paused(self) -> bool:
+        """
+        Whether the watcher is paused, see [`pause`][watchfiles._rust_notify.RustNotify.pause].
+        """
+    @property
+    def poll_delay_ms(self) -> float | None:
+        """
+        How long paths are currently polled every, with `max_poll_delay_ms` the longest interval any poll watcher
+        has backed off to, `None` if nothing is polled.
+        """
+    @property
+    def root_backends(self) -> dict[str, str]:
+        """
+        Every watch path mapped to the backend watching it, see
+        [`switch_backend`][watchfiles._rust_notify.RustNotify.switch_backend].
+        """
+    @property
+    def watchdog_restarts(self) -> dict[str, int]:
+        """
+        Watch paths whose watcher has been rebuilt by `watchdog_interval_ms` checks, mapped to how many times.
+        """
+    def capabilities(self) -> dict[str, bool]:
+        """
+        Feature flags for the backend in use, see
+        [`backend_capabilities`][watchfiles._rust_notify.backend_capabilities].
+
+        `RuntimeError` is raised if the watcher has been closed.
+        """
+    def health_check(self, timeout_ms: float = 1_000) -> dict[str, bool | None]:
+        """
+        Check the watcher is still receiving events, e.g. after a system suspend or remount, by creating then deleting
+        a probe file in each watched directory and waiting for the corresponding events.
+
+        Events for the probe files are not reported by [`watch`][watchfiles._rust_notify.RustNotify.watch].
+
+        Args:
+            timeout_ms: maximum time in milliseconds to wait for all the events, when polling this needs to be
+                longer than `poll_delay_ms`.
+
+        Returns:
+            Every watch path mapped to `True` if the events were received, `False` if they weren't (or the probe
+                file couldn't be created), or `None` if the path wasn't checked because it's a file or isn't being
+                watched.
+        """
+    @property
+    def effective_config(self) -> dict[str, Any]:
+        """
+        The configuration actually in use after defaults and fallbacks have been applied, intended for logging.
+
+        Keys match the arguments to `__init__`, plus `'backend'` which is the mechanism used to watch for changes:
+        `'poll'`, `'inotify'`, `'fsevent'`, `'kqueue'` or `'windows'`. `watch_paths` excludes any paths skipped due
+        to `virtual_filesystems`, `poll_delay_ms` is `None` unless polling, and options which only apply with other
+        options set (e.g. `spill_dir` with `overflow='spill'`) are `None` when they don't apply.
+        """
+    @property
+    def symlink_loops(self) -> dict[str, str]:
+        """
+        Symlinks found while scanning watched directories which point to one of their own ancestors, mapped to that
+        ancestor. These symlinks aren't followed, rather than scanning the same directories forever.
+
+        Loops are only reported when polling, native watchers skip them silently.
+        """
+    def changes_between(self, start: float, end: float) -> list[tuple[float, int, str]]:
+        """
+        Changes returned by `watch()` between `start` and `end` inclusive, from the last `history_size` changes, as
+        `(timestamp, change, path)` tuples oldest first. Times are seconds since the epoch as returned by
+        `time.time()`, and each change's timestamp is when `watch()` returned it, e.g. for changes in the last 30
+        seconds use `changes_between(time.time() - 30, time.time())`.
+
+        `RuntimeError` is raised if `history_size` wasn't set.
+        """
+    def peek(self) -> set[tuple[int, str]]:
+        """
+        The changes currently waiting to be returned by [`watch`][watchfiles._rust_notify.RustNotify.watch],
+        without removing them, so monitoring code can see what's queued without affecting the consumer.
+
+        Changes are `(event_type, path)` tuples as they were recorded, so without any extra elements requested
+        when creating `RustNotify`, and before the processing `watch` applies when returning them, e.g.
+        `collapse_renames`. Changes spilled to disk with `overflow='spill'` aren't included.
+        """
+    def ack(self, token: int) -> None:
+        """
+        With `acknowledge=True`, mark the batch returned by [`watch`][watchfiles._rust_notify.RustNotify.watch] with
+        `token` as processed, so the next call to `watch` returns new changes rather than the same batch again.
+
+        `ValueError` is raised if `token` isn't the token of the batch awaiting acknowledgement, e.g. if it's
+        already been acknowledged, `RuntimeError` is raised without `acknowledge=True`.
+
+        Args:
+            token: the [`token`][watchfiles._rust_notify.WatchResult.token] of the batch.
+        """
+    def clear(self) -> int:
+        """
+        Discard the changes waiting to be returned by [`watch`][watchfiles._rust_notify.RustNotify.watch], e.g.
+        once they're stale after performing a full rescan, rather than calling `watch` and ignoring the result.
+        Changes spilled to disk with `overflow='spill'` are also discarded.
+
+        Returns:
+            The number of changes discarded, not including any spilled to disk.
+        """
+    def changes_pending(self) -> dict[str, int]:
+        """
+        How far behind the consumer of [`watch`][watchfiles._rust_notify.RustNotify.watch] is, e.g. to export as a
+        gauge, can be called from any thread, including while another is in `watch`. Keys are:
+
+        * `'changes'`: changes waiting to be returned by `watch`, each `(event_type, path)` pair counted once
+          however many events reported it
+        * `'raw_events'`: events the backend has reported since `watch` last returned, including those which were
+          filtered out or merged with others, as for `raw_event_count` on
+          [`WatchResult`][watchfiles._rust_notify.WatchResult]
+        """
+    def memory_stats(self) -> dict[str, int]:
+        """
+        Estimated bytes of memory used by the structures this instance maintains, for monitoring long-running
+        watchers of large trees. Keys are:
+
+        * `'pending_changes'`: changes waiting to be returned by `watch()`
+        * `'change_sources'`: what reported each pending change, with `source=True`
+        * `'rescan_snapshot'`: the snapshot of the watched paths kept between rescans, with `rescan_interval_ms`
+        * `'rescan_seen'`: paths reported since the last rescan
+        * `'file_sizes'`: file sizes kept to report `modify_kind` and `modify_sizes` while polling
+        * `'renames'`: renames kept for `collapse_renames`
+        * `'filter_cache'`: the types of paths seen, kept so deleted paths can be filtered
+        * `'stat_retry'`: paths whose deletion wasn't confirmed by `stat_retries`
+        * `'escaped_paths'`: paths found by `path_containment`
+        * `'history'`: changes kept for `changes_between()`, with `history_size`
+        * `'timestamps'`: when each pending change arrived, with `timestamp=True`
+        * `'dirs'`: whether paths are directories, with `is_dir=True`
+        * `'processes'`: the process behind each pending change, with `process=True`
+        * `'total'`: the sum of the above
+
+        Memory used inside the underlying notify watcher, e.g. the poll watcher's own record of every path, isn't
+        included.
+        """
+    @property
+    def escaped_paths(self) -> dict[str, str]:
+        """
+        Changed paths found outside the watch paths by `path_containment`, mapped to why: `'outside the watch paths'`
+        or `'resolves outside the watch paths via a symlink'`.
+        """
+    @property
+    def retried_paths(self) -> dict[str, int]:
+        """
+        Watch paths which were only watched successfully after retrying, mapped to the number of retries needed.
+        """
+    @property
+    def skipped_paths(self) -> dict[str, str]:
+        """
+        Watch paths which couldn't be watched, mapped to the error which occurred, when `ignore_permission_denied`
+        is `True`.
+
+        With `recursive=True`, a path is included if watching any directory within it failed, in which case
+        it may only be partially watched.
+        """
+    @property
+    def nested_paths(self) -> dict[str, str]:
+        """
+        Watch paths inside another watch path, mapped to the outer path. With `recursive=True` these aren't watched
+        separately since the outer path's watch already covers them; changes within them are still attributed to
+        the innermost watch path, e.g. for `depth`.
+        """
+    @property
+    def aliased_paths(self) -> dict[str, str]:
+        """
+        With `merge_aliases=True`, watch paths which refer to the same directory as an earlier watch path, mapped to
+        that path. Changes within these paths are reported via the path they're mapped to.
+        """
+    @property
+    def polled_subtrees(self) -> dict[str, list[str]]:
+        """
+        With `watch_limit='poll'`, watch paths only partly watched natively because the inotify limit on watches
+        was reached, mapped to the directories under them which are polled instead, each with everything inside it.
+        """
+    @property
+    def waiting_paths(self) -> list[str]:
+        """
+        With `wait_for_creation=True`, watch paths which haven't been created yet, so aren't watched.
+        """
+    @property
+    def registration_report(self) -> dict[str, str | None]:
+        """
+        Every watch path mapped to `None` if it was watched successfully, or the error which occurred if it was
+        skipped due to `partial_ok` or `ignore_permission_denied`.
         """
     def __enter__(self) -> RustNotify:
         """
@@ -102,10 +893,119 @@ class RustNotify:
             the watching thread.
         """
 
+def backend_capabilities(
+    backend: Literal['poll', 'inotify', 'fsevent', 'kqueue', 'windows', 'fanotify']
+) -> dict[str, bool]:
+    """
+    Feature flags for a backend, whether or not it's available on this platform.
+
+    Keys are:
+
+    * `'event_driven'`: changes are pushed by the OS rather than found by scanning
+    * `'native_recursion'`: one OS watch covers a whole directory tree, rather than one watch per directory
+    * `'rename_cookies'`: both sides of a rename are linked by the OS
+    * `'close_write'`: the OS reports when a file opened for writing is closed
+    * `'follow_symlinks'`: symlinks to directories are followed when watching recursively
+    * `'compare_contents'`: file contents can be compared via `advanced_options={'compare_contents': True}`
+
+    `ValueError` is raised for an unknown backend.
+    """
+
+class ManualClock:
+    """
+    A clock which only moves forward when [`watch`][watchfiles._rust_notify.RustNotify.watch] sleeps between steps
+    or [`advance`][watchfiles._rust_notify.ManualClock.advance] is called, so timeouts and debouncing happen
+    instantly and deterministically in tests.
+    """
+
+    def __init__(self) -> None: ...
+    @property
+    def now_ms(self) -> float:
+        """
+        Milliseconds elapsed on this clock since it was created.
+        """
+    def advance(self, ms: float) -> None:
+        """
+        Move the clock forward by `ms` milliseconds.
+        """
+
+class WatchResult:
+    """
+    Returned by [`watch`][watchfiles._rust_notify.RustNotify.watch] with `structured=True`.
+    """
+
+    @property
+    def reason(self) -> Literal['changes', 'signal', 'stop', 'timeout']:
+        """
+        Why `watch` returned, `'changes'` once a batch of changes settled, otherwise as for the strings `watch`
+        returns. With `flush_on_timeout=True`, `'timeout'` may come with changes.
+        """
+    @property
+    def changes(
+        self,
+    ) -> set[tuple[Any, ...]] | list[tuple[Any, ...]] | dict[str | None, set[tuple[Any, ...]] | list[tuple[Any, ...]]]:
+        """
+        The changes, in the same form `watch` would return them, empty if there are none.
+        """
+    @property
+    def duration_ms(self) -> float:
+        """
+        Milliseconds from the first change of the batch being seen to `watch` returning, `0` without changes.
+        """
+    @property
+    def raw_event_count(self) -> int:
+        """
+        How many events the backend reported since `watch` last returned, including those which were filtered out
+        or merged with others, so may be larger than the number of changes.
+        """
+    @property
+    def token(self) -> int | None:
+        """
+        With `acknowledge=True`, the token to pass to [`ack`][watchfiles._rust_notify.RustNotify.ack] once the
+        changes have been processed, the same each time a batch is returned again. `None` for results without
+        changes and without `acknowledge=True`.
+        """
+
 class WatchfilesRustInternalError(RuntimeError):
     """
     Raised when RustNotify encounters an unknown error.
 
     If you get this a lot, please check [github](https://github.com/samuelcolvin/watchfiles/issues) issues
     and create a new issue if your problem is not discussed.
+
+    When raised by [`watch`][watchfiles._rust_notify.RustNotify.watch] due to an error from the underlying watcher,
+    the following attributes describe the error, otherwise they keep their default values.
+    """
+
+    errno: int | None
+    """The OS error number, if the error was caused by an OS error."""
+    strerror: str | None
+    """The OS error message, if the error was caused by an OS error."""
+    paths: tuple[str, ...]
+    """Paths affected by the error, if known."""
+    backend: str | None
+    """
+    The watcher backend which raised the error: `'poll'`, `'inotify'`, `'fsevent'`, `'kqueue'`, `'windows'` or
+    `'fanotify'`.
+    """
+
+class WatchLimitError(OSError):
+    """
+    Raised on Linux when no more directories can be watched because the `fs.inotify.max_user_watches` limit
+    (`errno` is `ENOSPC`) or the `fs.inotify.max_user_instances` limit (`errno` is `EMFILE`) has been reached.
+
+    Either raise the limit, e.g. `sudo sysctl fs.inotify.max_user_watches=524288`, watch fewer directories,
+    or use `force_polling`, or `watch_limit='poll'` to poll the directories which couldn't be watched.
+    """
+
+    limit_name: str | None
+    """The limit reached, `'fs.inotify.max_user_watches'` or `'fs.inotify.max_user_instances'`."""
+    limit: int | None
+    """The configured value of the limit, read from `/proc/sys/fs/inotify`, `None` if it couldn't be read."""
+    usage: int | None
+    """
+    How many watches or instances are currently in use by the processes watchfiles can inspect, normally all
+    those of the current user, who the limit applies to. `None` if `/proc` couldn't be read.
     """
+    directories: int
+    """The number of directories watchfiles tried to watch."""
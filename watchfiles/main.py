@@ -4,7 +4,7 @@ import sys
 import warnings
 from enum import IntEnum
 from pathlib import Path
-from typing import TYPE_CHECKING, AsyncGenerator, Callable, Generator, Optional, Set, Tuple, Union
+from typing import TYPE_CHECKING, AsyncGenerator, Callable, Generator, Iterable, Optional, Set, Tuple, Union
 
 import anyio
 
@@ -26,6 +26,28 @@ class Change(IntEnum):
     """A file or directory was modified, can be either a metadata or data change."""
     deleted = 3
     """A file or directory was deleted."""
+    renamed = 4
+    """
+    A file or directory was renamed, only reported by [`RustNotify`][watchfiles._rust_notify.RustNotify] with
+    `rename_pairs=True`, whose change tuples for renames are `(Change.renamed, old_path, new_path)`.
+    """
+    written = 5
+    """
+    A file opened for writing was closed, so the writer has finished with it, only reported by
+    [`RustNotify`][watchfiles._rust_notify.RustNotify] with `close_write=True`.
+    """
+    attribute = 6
+    """
+    Only a file or directory's metadata, e.g. permissions, ownership or timestamps, changed, only reported by
+    [`RustNotify`][watchfiles._rust_notify.RustNotify] with `metadata_changes='attribute'`.
+    """
+    rescan = 7
+    """
+    Changes under the path, usually a watch path, may have been missed, so anything derived from its contents
+    should be rebuilt by rescanning it. Reported when the backend drops events, e.g. when inotify's queue
+    overflows, and by [`RustNotify`][watchfiles._rust_notify.RustNotify] with `overflow='rescan'` once
+    `max_pending_changes` is reached.
+    """
 
     def raw_str(self) -> str:
         return self.name
@@ -277,7 +299,7 @@ async def awatch(  # C901
 
 
 def _prep_changes(
-    raw_changes: Set[Tuple[int, str]], watch_filter: Optional[Callable[[Change, str], bool]]
+    raw_changes: Iterable[Tuple[int, str]], watch_filter: Optional[Callable[[Change, str], bool]]
 ) -> Set[FileChange]:
     # if we wanted to be really snazzy, we could move this into rust
     changes = {(Change(change), path) for change, path in raw_changes}